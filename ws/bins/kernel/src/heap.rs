@@ -0,0 +1,136 @@
+//! The heap of the kernel.
+//!
+//! By default, the heap is backed by a static array of [`HEAP_SIZE`] bytes.
+//! With the `bump-heap` feature, only a small static buffer of
+//! [`EARLY_HEAP_SIZE`] bytes serves the allocations of the earliest boot
+//! phase, which keeps the kernel image small. [`switch_to_full_heap`] then
+//! switches in a heap of [`HEAP_SIZE`] bytes of RAM from the memory map; see
+//! [`BumpAllocator`] for the protocol.
+
+use core::fmt::Write;
+use core::ops::Range;
+#[cfg(feature = "bump-heap")]
+use kernel_lib::{DirectMap, MemoryMap, MemoryMapEntryType};
+use util::heap::AllocFailure;
+#[cfg(not(feature = "bump-heap"))]
+use util::heap::Allocator;
+#[cfg(feature = "bump-heap")]
+use util::heap::BumpAllocator;
+use util::logging::raw_debugcon_print;
+#[cfg(not(feature = "bump-heap"))]
+use util::paging::Page;
+#[cfg(feature = "bump-heap")]
+use util::paging::PhysAddress;
+use util::paging::{PAGE_SIZE, VirtAddress};
+#[cfg(not(feature = "bump-heap"))]
+use util::sizes::{bytes_to_pages, pages_to_bytes};
+
+/// Size of the kernel heap in bytes.
+pub const HEAP_SIZE: usize = 32 * 1024 * 1024;
+const _: () = assert!(HEAP_SIZE.is_multiple_of(PAGE_SIZE));
+
+#[cfg(not(feature = "bump-heap"))]
+const HEAP_PAGES: usize = bytes_to_pages(HEAP_SIZE);
+
+/// Backing memory of the kernel heap.
+///
+/// Unmangled, so that the loader's ELF checker can verify that it is in a
+/// writable segment.
+#[cfg(not(feature = "bump-heap"))]
+#[unsafe(no_mangle)]
+static mut HEAP_MEM: [Page; HEAP_PAGES] = [Page::ZERO; HEAP_PAGES];
+
+#[cfg(not(feature = "bump-heap"))]
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new().with_failure_hook(report_alloc_failure);
+
+/// Size of the static buffer for the earliest allocations in bytes.
+#[cfg(feature = "bump-heap")]
+pub const EARLY_HEAP_SIZE: usize = 64 * 1024;
+
+#[cfg(feature = "bump-heap")]
+#[global_allocator]
+static ALLOCATOR: BumpAllocator<EARLY_HEAP_SIZE> =
+    BumpAllocator::new().with_failure_hook(report_alloc_failure);
+
+/// Reports a failed allocation directly to the debugcon device, as the
+/// panic that follows doesn't contain the layout and the logger might need
+/// the heap itself.
+fn report_alloc_failure(failure: AllocFailure) {
+    /// Writes via [`raw_debugcon_print`], which skips an absent device.
+    struct RawDebugcon;
+
+    impl Write for RawDebugcon {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            raw_debugcon_print(s);
+            Ok(())
+        }
+    }
+
+    let _ = writeln!(RawDebugcon, "{failure}");
+}
+
+/// Initializes the heap.
+///
+/// This must be called once before the first allocation.
+#[cfg(not(feature = "bump-heap"))]
+pub fn init() {
+    let heap_mem = &raw mut HEAP_MEM;
+    // SAFETY: We only read the length of the array.
+    let len = unsafe { (*heap_mem).len() };
+    // Claim exactly the memory of the backing array rather than
+    // `HEAP_SIZE` to prevent any drift between the two.
+    let size = pages_to_bytes(len);
+
+    // SAFETY: The memory is valid, exclusively owned by the heap, and this
+    // function is only called once.
+    unsafe { ALLOCATOR.init_from_span(heap_mem.cast(), size) }
+}
+
+/// Returns the virtual address range of the backing memory of the heap.
+#[cfg(not(feature = "bump-heap"))]
+#[must_use]
+pub fn range() -> Range<VirtAddress> {
+    let heap_mem = &raw const HEAP_MEM;
+    // SAFETY: We only read the length of the array.
+    let len = unsafe { (*heap_mem).len() };
+    let start = VirtAddress(heap_mem as u64);
+    start..start + pages_to_bytes(len) as u64
+}
+
+/// Initializes the heap.
+///
+/// The bump allocator needs no initialization; this only exists for parity
+/// with the default configuration.
+#[cfg(feature = "bump-heap")]
+pub const fn init() {}
+
+/// Switches from the static buffer to a heap of [`HEAP_SIZE`] bytes in the
+/// first available RAM region of the memory map that is large enough, and
+/// returns the virtual address range of the heap.
+///
+/// This must be called once, as soon as the memory map is available.
+///
+/// # Panics
+/// Panics if no available RAM region is large enough.
+#[cfg(feature = "bump-heap")]
+pub fn switch_to_full_heap(memory_map: &MemoryMap, direct_map: &DirectMap) -> Range<VirtAddress> {
+    let region = memory_map
+        .iter()
+        .find(|entry| {
+            entry.typ == MemoryMapEntryType::AvailableRam && entry.length >= HEAP_SIZE as u64
+        })
+        .expect("should have enough available RAM for the heap");
+    let start = direct_map.phys_to_virt(PhysAddress(region.from));
+    // SAFETY: Available RAM is covered by the direct map and not used by
+    // anything else, as the kernel doesn't manage physical memory yet.
+    unsafe { ALLOCATOR.switch_to_heap(start.0 as *mut u8, HEAP_SIZE) };
+    start..start + HEAP_SIZE as u64
+}
+
+/// Checks the heap with a few allocations of varying sizes and alignments.
+/// See [`util::heap::self_test`].
+#[cfg(debug_assertions)]
+pub fn self_test() -> Result<(), util::heap::HeapError> {
+    util::heap::self_test(&ALLOCATOR)
+}
@@ -0,0 +1,44 @@
+//! Logging of the kernel.
+
+use core::fmt;
+use kernel_lib::{BootInformation, DirectMap};
+use log::{LevelFilter, warn};
+use util::drivers::{DebugCon, VgaText};
+use util::logging::{DebugconLogger, LogFormat, LoggerFacade, LoggerFacadeInner, VgaTextLogger};
+use util::paging::PhysAddress;
+
+static LOGGER: LoggerFacade = LoggerFacade::new();
+
+/// Inits the logger with the debugcon and the VGA text mode backend.
+///
+/// The VGA text buffer is accessed via the direct map. It is only used if the
+/// loader reported legacy VGA text mode, as the buffer is neither guaranteed
+/// to exist nor to be shown otherwise. The debugcon backend is skipped if the
+/// device is not present.
+pub fn init(boot_info: &BootInformation, direct_map: &DirectMap) {
+    let mut logger = LoggerFacadeInner::new();
+    let debugcon_present = DebugCon::is_present();
+    if debugcon_present {
+        logger.set_debugcon(DebugconLogger::new(LogFormat::Full));
+    }
+    let vga_present = boot_info.vga_text_mode();
+    if vga_present {
+        let vga_buffer = direct_map.phys_to_virt(PhysAddress(VgaText::PHYS_ADDR));
+        // SAFETY: The loader reported that the text buffer exists. The direct
+        // map covers the VGA region and nothing else in the kernel accesses
+        // it.
+        let vga = unsafe { VgaText::new(vga_buffer.0 as *mut u16) };
+        logger.set_vga_text(VgaTextLogger::new(vga, LogFormat::LevelOnly));
+    }
+    LOGGER.init(logger, LevelFilter::Trace);
+    match (debugcon_present, vga_present) {
+        (true, _) => {}
+        (false, true) => warn!("debugcon not present; logging to VGA text mode only"),
+        (false, false) => warn!("neither debugcon nor VGA text mode present; logs are lost"),
+    }
+}
+
+/// Clears all text consoles and shows `msg` prominently.
+pub fn show_fatal(msg: fmt::Arguments<'_>) {
+    LOGGER.with_text_consoles(|console| console.show_fatal(msg));
+}
@@ -14,19 +14,34 @@
 #![deny(missing_debug_implementations)]
 #![deny(rustdoc::all)]
 
+#[cfg(not(feature = "bump-heap"))]
+use core::ops::Range;
+use kernel_lib::{BOOT_INFO_VADDR, BootInformation, DirectMap, MemoryMapEntryType};
+#[cfg(not(feature = "bump-heap"))]
+use kernel_lib::{SeparationError, check_separation};
 use log::info;
+#[cfg(not(feature = "bump-heap"))]
+use log::{debug, warn};
+use util::cpu::ControlRegisters;
+#[cfg(not(feature = "bump-heap"))]
+use util::paging::{PAGE_SIZE, VirtAddress};
 
+mod heap;
+mod logger;
 mod panic_handler;
+mod stack;
 
 /// Entry into the kernel.
 ///
-/// Set's up the stack before jumping into the Rust code.
+/// Set's up the stack before jumping into the Rust code. The loader passes the
+/// [`BootInformation`] in `rdi`, which is forwarded untouched to [`main`].
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".text.entry")]
 pub unsafe extern "sysv64" fn kernel_entry() -> ! {
     core::arch::naked_asm!(
-        // todo stack aufsetzen
+        // Set up stack
+        "lea {stack_mem}+{stack_span}(%rip), %rsp",
 
         // Jump to Kernel
         "mov $0xdeadbeef, %rax",
@@ -34,16 +49,127 @@ pub unsafe extern "sysv64" fn kernel_entry() -> ! {
         "hlt",
         "jmp main",
         "ud2",
+        stack_mem = sym stack::STACK_MEM,
+        stack_span = const stack::STACK_SPAN,
         options(att_syntax)
     )
 }
 
 #[unsafe(no_mangle)]
-fn main() -> ! {
-    let mut data = core::hint::black_box([1, 2, 3, 4]);
-    data[3] = 7;
-    info!("Hello world from kernel: {:?}", data);
+extern "sysv64" fn main(boot_info: *const BootInformation) -> ! {
+    // Heartbeat that doesn't depend on the heap or the logger.
+    util::logging::raw_debugcon_print("kernel: entered main\n");
+    stack::fill_unused();
+    // SAFETY: The kernel runs in ring 0.
+    unsafe { util::cpu::assert_long_mode_paging() };
+    heap::init();
+
+    assert_eq!(
+        boot_info as u64, BOOT_INFO_VADDR.0,
+        "loader should pass the boot information at BOOT_INFO_VADDR"
+    );
+    // SAFETY: The loader maps the boot information at this address and it
+    // stays mapped.
+    let boot_info_bytes = unsafe {
+        core::slice::from_raw_parts(BOOT_INFO_VADDR.0 as *const u8, size_of::<BootInformation>())
+    };
+    let boot_info =
+        <&BootInformation>::try_from(boot_info_bytes).expect("boot information should be valid");
+    let direct_map = DirectMap::from_boot_info(boot_info);
+    logger::init(boot_info, &direct_map);
+    // SAFETY: The kernel runs in ring 0.
+    let control_registers = unsafe { ControlRegisters::read() };
+    info!("Control registers: {control_registers}");
+    let stack = stack::range();
+    info!(
+        "Kernel stack at {:#x}..{:#x} ({} KiB)",
+        stack.start.0,
+        stack.end.0,
+        stack::size() / 1024
+    );
+    #[cfg(not(feature = "bump-heap"))]
+    check_stack_heap_separation(&stack);
+    info!(
+        "Direct map of physical memory at {:#x}",
+        direct_map.offset()
+    );
+    // SAFETY: The loader's direct map is active, and the kernel doesn't
+    // reclaim loader memory.
+    let memory_map =
+        unsafe { boot_info.memory_map(&direct_map) }.expect("memory map should be valid");
+    info!(
+        "Memory map with {} entries, {} MiB of available RAM",
+        memory_map.len(),
+        memory_map
+            .iter()
+            .filter(|entry| entry.typ == MemoryMapEntryType::AvailableRam)
+            .map(|entry| entry.length)
+            .sum::<u64>()
+            / (1024 * 1024)
+    );
+    #[cfg(feature = "bump-heap")]
+    {
+        let heap = heap::switch_to_full_heap(memory_map, &direct_map);
+        info!(
+            "Switched to kernel heap at {:#x}..{:#x} ({} KiB)",
+            heap.start.0,
+            heap.end.0,
+            heap::HEAP_SIZE / 1024
+        );
+    }
+    #[cfg(debug_assertions)]
+    {
+        heap::self_test().expect("heap should pass the self test");
+        log::debug!("Heap self test passed");
+    }
+
+    // SAFETY: We run in long mode, and the loader sets `cr3` to the bare
+    // address of the root table.
+    let pcids = unsafe { util::paging::pcid::enable() };
+    info!("PCIDs {}", if pcids { "enabled" } else { "not supported" });
+
+    let (page_tables_base, page_tables_size) = boot_info.page_tables();
+    info!(
+        "Loader page tables (reclaimable) at {:#x} ({} KiB)",
+        page_tables_base.0,
+        page_tables_size / 1024
+    );
+
+    info!(
+        "Kernel stack high-water mark: {} of {} KiB",
+        stack::used_bytes().div_ceil(1024),
+        stack::size() / 1024
+    );
+    info!("Hello world from kernel");
     loop {
         core::hint::spin_loop();
     }
 }
+
+/// Logs the placement of the kernel stack and the static heap and ensures
+/// that an overflow of the stack can't silently run into the heap.
+///
+/// Both are statics placed by the linker, so nothing else guarantees their
+/// separation.
+///
+/// # Panics
+/// Panics if the stack and the heap overlap.
+#[cfg(not(feature = "bump-heap"))]
+fn check_stack_heap_separation(stack: &Range<VirtAddress>) {
+    let heap = heap::range();
+    info!(
+        "Kernel heap at {:#x}..{:#x} ({} KiB)",
+        heap.start.0,
+        heap.end.0,
+        (heap.end.0 - heap.start.0) / 1024
+    );
+    let stack = stack.start.0..stack.end.0;
+    let heap = heap.start.0..heap.end.0;
+    match check_separation(&stack, &heap, PAGE_SIZE as u64) {
+        Ok(gap) => debug!("{gap:#x} bytes between kernel stack and heap"),
+        Err(e @ SeparationError::GapTooSmall { .. }) => {
+            warn!("No guard page between kernel stack and heap: {e}");
+        }
+        Err(e @ SeparationError::Overlap) => panic!("kernel stack and heap overlap: {e}"),
+    }
+}
@@ -4,6 +4,7 @@ use log::error;
 #[panic_handler]
 fn handle_panic(panic_info: &PanicInfo) -> ! {
     error!("Kernel panic! {}", panic_info);
+    crate::logger::show_fatal(format_args!("Kernel panic! {panic_info}"));
     loop {
         core::hint::spin_loop()
     }
@@ -0,0 +1,122 @@
+//! The stack of the kernel.
+
+use core::arch::asm;
+use core::ops::Range;
+use util::paging::{PAGE_SIZE, Page, VirtAddress};
+use util::sizes::{bytes_to_pages, pages_to_bytes};
+
+/// Default size of the kernel stack in bytes.
+const DEFAULT_STACK_SIZE: usize = 128 * 1024;
+
+/// Size of the kernel stack in bytes.
+///
+/// Can be overridden at build time via the environment variable
+/// `PHIPSOS_KERNEL_STACK_SIZE` (decimal number of bytes), e.g., for debugging
+/// deep recursions.
+pub const STACK_SIZE: usize = match option_env!("PHIPSOS_KERNEL_STACK_SIZE") {
+    Some(size) if !size.is_empty() => parse_size(size),
+    _ => DEFAULT_STACK_SIZE,
+};
+const _: () = assert!(STACK_SIZE > 0);
+const _: () = assert!(STACK_SIZE.is_multiple_of(PAGE_SIZE));
+
+const STACK_PAGES: usize = bytes_to_pages(STACK_SIZE);
+
+/// Size of the memory that is actually used as stack.
+///
+/// This is derived from the backing array rather than from [`STACK_SIZE`], so
+/// that the stack top can never be outside the backing memory.
+pub const STACK_SPAN: usize = pages_to_bytes(STACK_PAGES);
+
+/// Backing memory of the kernel stack.
+///
+/// Only accessed via its symbol in [`crate::kernel_entry`]. As the memory is
+/// page-aligned, the stack top is also 16-byte aligned, as required by the
+/// System V ABI. Unmangled, so that the loader's ELF checker can verify that
+/// it is in a writable segment.
+#[unsafe(no_mangle)]
+pub static mut STACK_MEM: [Page; STACK_PAGES] = [Page::ZERO; STACK_PAGES];
+
+/// Byte pattern the unused part of the stack is filled with by
+/// [`fill_unused`], so that [`used_bytes`] can find the high-water mark.
+pub const STACK_FILL_PATTERN: u8 = 0xaa;
+
+/// Bytes directly below the current stack pointer that [`fill_unused`] leaves
+/// alone. Matches the size of the System V red zone.
+const FILL_MARGIN: usize = 128;
+
+/// Parses a decimal number at compile time.
+const fn parse_size(size: &str) -> usize {
+    let bytes = size.as_bytes();
+    let mut value = 0_usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        let digit = bytes[i];
+        assert!(
+            digit.is_ascii_digit(),
+            "PHIPSOS_KERNEL_STACK_SIZE must be a decimal number"
+        );
+        value = value * 10 + (digit - b'0') as usize;
+        i += 1;
+    }
+    value
+}
+
+/// Returns the size of the kernel stack in bytes.
+#[must_use]
+pub const fn size() -> usize {
+    STACK_SPAN
+}
+
+/// Returns the virtual address range of the kernel stack, from the bottom
+/// (lowest address) to the top (initial stack pointer).
+#[must_use]
+pub fn range() -> Range<VirtAddress> {
+    let bottom = VirtAddress((&raw const STACK_MEM) as u64);
+    bottom..bottom + STACK_SPAN as u64
+}
+
+/// Fills the unused part of the stack, from its bottom up to shortly below
+/// the current stack pointer, with [`STACK_FILL_PATTERN`].
+///
+/// The stack is in use while this runs. Hence, reading `rsp` and filling the
+/// memory below it happens in a single `asm!` block, which doesn't push
+/// anything, rather than in Rust code whose callees could have their frames
+/// overwritten.
+///
+/// Should be called early, before the stack has grown deep.
+pub fn fill_unused() {
+    let bottom = (&raw mut STACK_MEM).cast::<u8>();
+    // SAFETY: Only memory between the stack bottom and the current stack
+    // pointer (minus a margin) is written, which is no live stack frame.
+    // `rep stosb` counts upwards, as the System V ABI guarantees a cleared
+    // direction flag.
+    unsafe {
+        asm!(
+            "mov rcx, rsp",
+            "sub rcx, rdi",
+            "sub rcx, {margin}",
+            "rep stosb",
+            margin = const FILL_MARGIN,
+            inout("rdi") bottom => _,
+            out("rcx") _,
+            in("al") STACK_FILL_PATTERN,
+            options(nostack),
+        );
+    }
+}
+
+/// Returns the maximum number of stack bytes used since [`fill_unused`] ran.
+#[must_use]
+pub fn used_bytes() -> usize {
+    let bottom = (&raw const STACK_MEM).cast::<u8>();
+    // No slice is created over the memory, as the live part of the stack,
+    // including the frame of this function, is written concurrently. The
+    // scan ends at the first used byte.
+    let bytes = (0..STACK_SPAN).map(|offset| {
+        // SAFETY: The address is within the stack memory, which is always
+        // valid for reads.
+        unsafe { bottom.add(offset).read_volatile() }
+    });
+    util::mem::stack_used_bytes(STACK_SPAN, bytes, STACK_FILL_PATTERN)
+}
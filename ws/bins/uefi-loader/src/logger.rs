@@ -1,23 +1,54 @@
 use crate::UEFI_BOOT_SERVICES_EXITED;
-use log::{LevelFilter, Log, Metadata, Record};
+use log::{LevelFilter, Log, Metadata, Record, warn};
 use std::fmt::Write;
 use std::sync::atomic::Ordering;
-use util::logging::{DebugconLogger, LoggerFacade, LoggerFacadeInner, fmt_and_write_msg};
+use util::drivers::DebugCon;
+use util::io::LineBuffered;
+use util::logging::{
+    BackendKind, DebugconLogger, LogFormat, LoggerFacade, LoggerFacadeInner, fmt_and_write_msg,
+    select_backends,
+};
 
 static LOGGER: LoggerFacade = LoggerFacade::new();
 
-/// Inits the logger.
-pub fn init() {
+/// The log backends the loader supports.
+const SUPPORTED_BACKENDS: [BackendKind; 2] = [BackendKind::Debugcon, BackendKind::Stdout];
+
+/// Inits the logger with the backends of the given names.
+///
+/// Unknown names and backends not supported by the loader are ignored with a
+/// warning. The debugcon backend is skipped if the device is not present, so
+/// that the loader doesn't write to an unrelated device on real hardware.
+pub fn init(backends: &[String]) {
     let mut logger = LoggerFacadeInner::new();
-    logger.set_debugcon(DebugconLogger);
-    logger.set_stdout_logger(Box::new(StdOutLogger));
+    let (kinds, ignored) =
+        select_backends(backends.iter().map(String::as_str), &SUPPORTED_BACKENDS);
+    let mut debugcon_absent = false;
+    for kind in kinds {
+        match kind {
+            BackendKind::Debugcon if !DebugCon::is_present() => debugcon_absent = true,
+            BackendKind::Debugcon => logger.set_debugcon(DebugconLogger::new(LogFormat::Full)),
+            BackendKind::Stdout => logger.set_stdout_logger(Box::new(StdOutLogger {
+                format: LogFormat::Full,
+            })),
+            BackendKind::VgaText => unreachable!("should only select supported backends"),
+        }
+    }
     LOGGER.init(logger, LevelFilter::Trace);
+    for name in ignored {
+        warn!("Ignoring unknown or unsupported log backend '{name}'");
+    }
+    if debugcon_absent {
+        warn!("Ignoring log backend 'debugcon': device not present");
+    }
 }
 
 /// Removes any logging functionality using UEFI boot services.
 pub fn exit_boot_services() {}
 
-struct StdOutLogger;
+struct StdOutLogger {
+    format: LogFormat,
+}
 
 impl Log for StdOutLogger {
     fn enabled(&self, _metadata: &Metadata) -> bool {
@@ -30,10 +61,13 @@ impl Log for StdOutLogger {
         }
 
         uefi::system::with_stdout(|out| {
-            fmt_and_write_msg(out, record)
+            // Each write is a call into the firmware; write whole lines.
+            let mut out = LineBuffered::<_, 256>::new(out);
+            fmt_and_write_msg(&mut out, record, self.format)
                 .expect("should not failed to format and write log message");
             out.write_char('\r').unwrap();
             out.write_char('\n').unwrap();
+            out.flush().unwrap();
         })
     }
 
@@ -20,19 +20,52 @@ mod logger;
 static UEFI_BOOT_SERVICES_EXITED: AtomicBool = AtomicBool::new(false);
 
 use anyhow::Context;
-use loader_lib::KernelFile;
-use log::{debug, error, info};
+use kernel_lib::{
+    BOOT_INFO_VADDR, BootInformation, FramebufferInfo, MemoryMapEntry, MemoryMapEntryType,
+    PixelFormat,
+};
+use loader_lib::{
+    Config, ErrorChain, KernelFile, MemoryMapEntryTypeExt, PageTablePool, jump_to_kernel_trampoline,
+};
+use log::{debug, error, info, warn};
+use std::borrow::Cow;
 use std::mem::ManuallyDrop;
 use std::os::uefi as uefi_std;
 use std::sync::atomic::{AtomicBool, Ordering};
+use uefi::boot::{AllocateType, MemoryType, OpenProtocolAttributes, OpenProtocolParams};
 use uefi::fs::FileSystem;
-use uefi::mem::memory_map::MemoryMapOwned;
+use uefi::mem::memory_map::{MemoryMap, MemoryMapOwned};
+use uefi::proto::console::gop::{GraphicsOutput, PixelFormat as GopPixelFormat};
 use uefi::{CStr16, Handle, cstr16};
-use util::paging::VirtAddress;
+use util::drivers::{X86PortIo, pit};
+use util::paging::{PAGE_SIZE, Page, PageTable, PhysAddress};
+use util::sizes;
 
 /// The path on the boot volume where we expect the kernel file to be.
+#[cfg_attr(feature = "embedded-kernel", allow(dead_code))]
 const KERNEL_PATH: &CStr16 = cstr16!("kernel.elf64");
 
+/// Size of the virtual window reserved for the kernel, starting at its link
+/// address.
+///
+/// The kernel is mapped via a single level-2 page table and must not reach
+/// the end of the 1 GiB region covered by it.
+const MAX_KERNEL_WINDOW: usize = 512 * 1024 * 1024;
+
+/// Size of the stack the trampoline switches to before jumping to the kernel.
+const HANDOFF_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+/// Number of memory map entries reserved in addition to the entries of the
+/// UEFI memory map at the time the buffer is allocated.
+///
+/// The final memory map is only known after exiting the boot services, and
+/// the firmware might split descriptors until then, e.g., for the buffer
+/// itself.
+const MEMORY_MAP_SLACK: usize = 32;
+
+/// The path on the boot volume where we expect the optional config file to be.
+const CONFIG_PATH: &CStr16 = cstr16!("phipsos.cfg");
+
 /// Performs the necessary setup code for the [`uefi`] crate.
 fn setup_uefi_crate() {
     let st = uefi_std::env::system_table();
@@ -47,50 +80,156 @@ fn setup_uefi_crate() {
     }
 }
 
+/// The kernel ELF embedded at build time.
+///
+/// The path is taken from the environment variable `PHIPSOS_EMBEDDED_KERNEL`.
+/// It should be absolute, as relative paths are resolved relative to this
+/// file.
+#[cfg(feature = "embedded-kernel")]
+static EMBEDDED_KERNEL: &[u8] = include_bytes!(env!("PHIPSOS_EMBEDDED_KERNEL"));
+
+/// Returns the raw bytes of the kernel ELF.
+///
+/// With the `embedded-kernel` feature, these are the bytes embedded into the
+/// loader at build time. Otherwise, the kernel is read from [`KERNEL_PATH`] on
+/// the boot volume.
+fn load_kernel() -> anyhow::Result<Cow<'static, [u8]>> {
+    #[cfg(feature = "embedded-kernel")]
+    {
+        info!("Using embedded kernel ({} bytes)", EMBEDDED_KERNEL.len());
+        Ok(Cow::Borrowed(EMBEDDED_KERNEL))
+    }
+    #[cfg(not(feature = "embedded-kernel"))]
+    {
+        load_kernel_elf_from_disk().map(|bytes| Cow::Owned(bytes.into_vec()))
+    }
+}
+
 /// Loads the ELF as raw bytes from disk.
+#[cfg(not(feature = "embedded-kernel"))]
 fn load_kernel_elf_from_disk() -> anyhow::Result<Box<[u8]>> {
     let handle = uefi::boot::image_handle();
     let fs = uefi::boot::get_image_file_system(handle)?;
     let mut fs = FileSystem::new(fs);
+
+    // The read is synchronous and might hang on flaky firmware. Log before,
+    // so that a hang is at least attributable.
+    let size = fs
+        .metadata(KERNEL_PATH)
+        .map_err(|e: uefi::fs::Error| anyhow::Error::new(e))?
+        .file_size();
+    info!("Reading kernel ({size} bytes) ...");
+    let begin = std::time::Instant::now();
     let bytes: Vec<u8> = fs
         .read(KERNEL_PATH)
         .map_err(|e: uefi::fs::Error| anyhow::Error::new(e))?;
+    debug!("Read kernel in {:?}", begin.elapsed());
     Ok(bytes.into_boxed_slice())
 }
 
-/// Trampoline in UEFI loader to jump to kernel.
-///
-/// This is the only part of the loader that will be mapped in the initial page
-/// tables of the loader.
-///
-/// # Alignment
-/// The trampoline is aligned to `8` bytes to prevent its instructions from
-/// crossing a page boundary. The `n` (`8`) must be less or equal to the size
-/// of the function.
+/// Loads the [`Config`] from disk, or the default config if there is no
+/// config file.
 ///
-/// One can check the disassembly with `objdump` to verify this.
+/// This runs before the logger is initialized and therefore doesn't log.
+fn load_config_from_disk() -> anyhow::Result<Config> {
+    let handle = uefi::boot::image_handle();
+    let fs = uefi::boot::get_image_file_system(handle)?;
+    let mut fs = FileSystem::new(fs);
+    if !fs.try_exists(CONFIG_PATH)? {
+        return Ok(Config::default());
+    }
+    let config = fs
+        .read_to_string(CONFIG_PATH)
+        .map_err(|e: uefi::fs::Error| anyhow::Error::new(e))?;
+    Ok(Config::parse(&config)?)
+}
+
+/// Returns the end of the physical address space backed by memory, according
+/// to the UEFI memory map. MMIO regions are not considered.
+fn phys_memory_end() -> anyhow::Result<u64> {
+    let mmap = uefi::boot::memory_map(MemoryType::LOADER_DATA)?;
+    let end = mmap
+        .entries()
+        .filter(|desc| MemoryMapEntryType::from_uefi(desc.ty) != MemoryMapEntryType::Mmio)
+        .map(|desc| desc.phys_start + desc.page_count * PAGE_SIZE as u64)
+        .max()
+        .unwrap_or(0);
+    Ok(end)
+}
+
+/// Returns the linear framebuffer of the current GOP mode, if any.
 ///
-/// # Arguments
+/// Returns `None` if there is no GOP or if its framebuffer can't be accessed
+/// directly or has a pixel format the kernel doesn't support.
+fn framebuffer_info() -> Option<FramebufferInfo> {
+    let handle = uefi::boot::get_handle_for_protocol::<GraphicsOutput>().ok()?;
+    // SAFETY: The protocol is only queried; the firmware's console may keep
+    // using it, so it is not opened exclusively.
+    let mut gop = unsafe {
+        uefi::boot::open_protocol::<GraphicsOutput>(
+            OpenProtocolParams {
+                handle,
+                agent: uefi::boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .ok()?;
+    let mode = gop.current_mode_info();
+    let format = match mode.pixel_format() {
+        GopPixelFormat::Rgb => PixelFormat::Rgb,
+        GopPixelFormat::Bgr => PixelFormat::Bgr,
+        format => {
+            warn!("Ignoring GOP framebuffer with unsupported pixel format {format:?}");
+            return None;
+        }
+    };
+    let (width, height) = mode.resolution();
+    // Both supported formats use 32 bits per pixel.
+    let pitch = mode.stride() * size_of::<u32>();
+    let base = gop.frame_buffer().as_mut_ptr() as u64;
+    Some(FramebufferInfo::new(
+        PhysAddress(base),
+        width as u32,
+        height as u32,
+        pitch as u32,
+        32,
+        format,
+    ))
+}
+
+/// Allocates memory for the kernel at the preferred physical base from the
+/// config, if any.
 ///
-/// The arguments passed using the SystemV ABI calling convention.
-/// - `new_cr3`: the new root page table
-/// - `kernel_addr`: the entry point of the kernel
-#[unsafe(naked)]
-#[unsafe(no_mangle)]
-pub unsafe extern "sysv64" fn jump_to_kernel_trampoline(
-    new_cr3: u64,
-    kernel_addr: VirtAddress,
-) -> ! {
-    core::arch::naked_asm!(
-        // align:
-        ".balign 8",
-        "mov %rdi, %cr3",
-        "jmp *%rsi",
-        "ud2",
-        options(att_syntax)
-    )
+/// Returns `None` if no base is configured or if the memory is not available,
+/// in which case the kernel can be placed anywhere.
+fn allocate_kernel_at_phys_base(config: &Config, len: usize) -> Option<&'static mut [u8]> {
+    let base = config.kernel_phys_base?;
+    let pages = sizes::bytes_to_pages(len);
+    match uefi::boot::allocate_pages(AllocateType::Address(base), MemoryType::LOADER_DATA, pages) {
+        Ok(ptr) => {
+            debug!("Placing kernel at preferred physical base {base:#x}");
+            // SAFETY: The memory was just allocated and is never freed.
+            Some(unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr(), len) })
+        }
+        Err(e) => {
+            warn!(
+                "Can't place kernel at preferred physical base {base:#x} ({e}); placing it anywhere"
+            );
+            None
+        }
+    }
 }
 
+/// Exits the UEFI boot services and returns the final memory map.
+///
+/// Exiting fails with `INVALID_PARAMETER` if the memory map changed between
+/// fetching it and the exit call. [`uefi::boot::exit_boot_services`] already
+/// handles this like Linux does: it fetches the map again and retries once.
+/// If that fails as well, it resets the machine, as the state of the boot
+/// services is undefined then. Hence, this never returns an error, and the
+/// returned map is always the one that was current at the exit.
 fn exit_boot_services() -> ManuallyDrop<MemoryMapOwned> {
     UEFI_BOOT_SERVICES_EXITED.store(true, Ordering::SeqCst);
 
@@ -102,42 +241,203 @@ fn exit_boot_services() -> ManuallyDrop<MemoryMapOwned> {
 }
 
 fn main_inner() -> anyhow::Result<()> {
+    // Heartbeat that doesn't depend on the logger.
+    util::logging::raw_debugcon_print("uefi-loader: entered main\n");
+
     // Early init of runtime.
     {
         setup_uefi_crate();
-        logger::init();
+    }
+
+    // The config selects the log backends. If it can't be loaded, the
+    // default backends are used to report the error.
+    let config = load_config_from_disk();
+    {
+        let backends = config.as_ref().map_or_else(
+            |_| Config::default().log_backends,
+            |config| config.log_backends.clone(),
+        );
+        logger::init(&backends);
         std::panic::set_hook(Box::new(|panic_info| {
             error!("PANIC: {panic_info}");
         }));
     }
+    let config = config.context("should be able to load config")?;
+    let file = load_kernel().context("should be able to load kernel file")?;
+    let kernel = KernelFile::from_bytes(&file).with_context(|| {
+        // Shows what was actually read, e.g., if the file is not an ELF.
+        let mut head = String::new();
+        let _ = util::fmt::hexdump(&file[..file.len().min(16)], 0, &mut head);
+        format!(
+            "should be valid kernel ({} bytes, starting with {})",
+            file.len(),
+            head.trim_end()
+        )
+    })?;
+    if !config.segment_hashes.is_empty() {
+        kernel
+            .verify_segments(&config.segment_hashes)
+            .context("kernel should match the segment hashes of the config")?;
+        info!("Verified hashes of all kernel LOAD segments");
+    }
+    let trampoline_addr = jump_to_kernel_trampoline as *const () as u64;
+    // Covered by the tests of loader-lib; this only guards against a
+    // different code generation of the loader's target.
+    debug_assert_eq!(
+        // SAFETY: The trampoline is part of the loaded image.
+        loader_lib::verify_trampoline(unsafe {
+            loader_lib::trampoline_page_bytes(trampoline_addr)
+        })
+        .map(|_| ()),
+        Ok(()),
+        "trampoline should be stack-free and fit into one page"
+    );
+
+    let phys_end = phys_memory_end()?;
+    let placement = config.segment_placement;
+    let mut page_table_pool = PageTablePool::new(
+        PageTablePool::required_tables(phys_end) + placement.extra_page_tables(&kernel),
+    );
 
-    let file =
-        load_kernel_elf_from_disk().context("should be able to load kernel file from volume")?;
-    let kernel = KernelFile::from_bytes(&file).context("should be valid kernel")?;
-    let trampoline_addr = jump_to_kernel_trampoline as u64;
+    // Leaked, as the memory must stay valid for the kernel. Page-aligned, as
+    // it is mapped at `BOOT_INFO_VADDR`. Filled once the page tables are
+    // known.
+    let boot_info_page = Box::leak(Box::new(Page::ZERO));
+    let boot_info_addr = PhysAddress(boot_info_page.as_ptr() as u64);
 
-    let new_cr3 = loader_lib::setup_page_tables(&kernel, trampoline_addr)?;
+    let setup = loader_lib::setup_page_tables(
+        &kernel,
+        trampoline_addr,
+        boot_info_addr,
+        size_of::<BootInformation>(),
+        BOOT_INFO_VADDR,
+        MAX_KERNEL_WINDOW,
+        allocate_kernel_at_phys_base(&config, kernel.required_phys_memory(placement.page_size())),
+        placement,
+        &mut page_table_pool,
+    )?;
+    let new_cr3 = setup.cr3;
+    debug!("Page tables (without direct map): {}", setup.stats);
+    let kernel_region = setup.kernel_memory_map_entry();
+    debug!(
+        "Kernel at phys {:#x}..{:#x} ({:?})",
+        kernel_region.from,
+        kernel_region.from + kernel_region.length,
+        kernel_region.typ
+    );
+    let stack_top = {
+        // SAFETY: The page tables are identity-mapped in the loader and not yet
+        // in use.
+        let root = unsafe { &mut *(new_cr3.0 as *mut PageTable) };
+        let kernel_window = kernel.virt_start().0..kernel.virt_start().0 + MAX_KERNEL_WINDOW as u64;
+        loader_lib::setup_direct_map(
+            root,
+            &mut page_table_pool,
+            config.hhdm_offset(),
+            phys_end,
+            kernel_window,
+            BOOT_INFO_VADDR,
+        )?;
+        // Leaked, as the kernel runs on this stack until it has set up its own.
+        let stack = Box::leak(Box::new([Page::ZERO; HANDOFF_STACK_SIZE / PAGE_SIZE]));
+        loader_lib::setup_handoff_stack(
+            root,
+            &mut page_table_pool,
+            PhysAddress(stack.as_ptr() as u64),
+            HANDOFF_STACK_SIZE,
+        )
+        .context("should be able to map the handoff stack")?
+    };
+    let (page_tables_base, page_tables_size) = page_table_pool.region();
+    debug!(
+        "Page-table pool: {} of {} tables used, region at {:#x} ({} KiB)",
+        page_table_pool.len(),
+        sizes::bytes_to_pages(page_tables_size),
+        page_tables_base.0,
+        page_tables_size / 1024
+    );
+    let mut boot_info = loader_lib::create_boot_information(&config, &page_table_pool);
+    if let Some(framebuffer) = framebuffer_info() {
+        debug!(
+            "Framebuffer at {:#x}: {}x{}, pitch {}, {:?}",
+            framebuffer.base().0,
+            framebuffer.width(),
+            framebuffer.height(),
+            framebuffer.pitch(),
+            framebuffer.format()
+        );
+        boot_info = boot_info.with_framebuffer(framebuffer);
+    } else {
+        debug!("No framebuffer");
+    }
+    // Leaked, as the kernel reads the memory map. Filled once the boot
+    // services are exited and the memory map is final.
+    let memory_map_buffer = {
+        let entries = uefi::boot::memory_map(MemoryType::LOADER_DATA)?.len() + MEMORY_MAP_SLACK;
+        Box::leak(vec![0_u8; entries * MemoryMapEntry::SIZE].into_boxed_slice())
+    };
     let entry = kernel.entry();
+    info!(
+        "Kernel entry '{}' at {:#x}",
+        kernel.entry_symbol_name().unwrap_or("<unknown>"),
+        entry.0
+    );
     drop(kernel);
     drop(file);
 
+    for msg in config.boot_delay_countdown() {
+        info!("{msg}");
+        // SAFETY: The loader is the only user of the PIT.
+        let mut io = unsafe { X86PortIo::new() };
+        pit::busy_wait_ms(&mut io, 1000);
+    }
+
     // -------------------------------------------------------------------------
     // No allocations etc. beyond this point.
 
     debug!("Exiting UEFI boot services");
-    exit_boot_services();
+    let uefi_memory_map = exit_boot_services();
     info!("Exited UEFI boot services");
 
+    let memory_map = loader_lib::write_memory_map(
+        memory_map_buffer,
+        kernel_region,
+        uefi_memory_map.entries().map(|desc| {
+            MemoryMapEntry::with_default_flags(
+                desc.phys_start,
+                desc.page_count * PAGE_SIZE as u64,
+                MemoryMapEntryType::from_uefi(desc.ty),
+            )
+        }),
+    )
+    .expect("memory map buffer should have room for the final memory map");
+    debug!("Memory map with {} entries", memory_map.len());
+    let boot_info = boot_info.with_memory_map(
+        PhysAddress(memory_map.entries().as_ptr() as u64),
+        memory_map.len(),
+    );
+    loader_lib::write_boot_information(boot_info_page, boot_info);
+
     info!("Jumping to kernel");
-    debug!("  new cr3     : {:#x}", new_cr3);
+    debug!("  new cr3     : {:#x}", new_cr3.0);
     debug!("  kernel entry: {:#x}", entry.0);
+    debug!(
+        "  boot info   : {:#x} (phys {:#x})",
+        BOOT_INFO_VADDR.0, boot_info_addr.0
+    );
+    debug!("  direct map  : {:#x}", config.hhdm_offset());
+    debug!("  stack top   : {:#x}", stack_top.0);
     unsafe {
-        jump_to_kernel_trampoline(new_cr3, entry);
+        jump_to_kernel_trampoline(new_cr3.0, entry, BOOT_INFO_VADDR.0 as *const _, stack_top);
     }
 }
 
 fn main() -> ! {
-    main_inner().unwrap();
+    if let Err(e) = main_inner() {
+        // anyhow only shows the outermost context by default.
+        error!("{}", ErrorChain(e.as_ref()));
+        panic!("loader failed");
+    }
     loop {
         core::hint::spin_loop();
     }
@@ -0,0 +1,205 @@
+//! Allocation-free bitmap, e.g., to track the state of physical frames.
+
+use core::fmt;
+
+/// Number of bits per backing word.
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// Bitmap over a caller-provided backing storage.
+///
+/// A set bit marks the corresponding index as used, a cleared bit as free.
+/// The bitmap does not allocate memory, so the backing storage can live in
+/// any memory region, such as a region carved from RAM.
+///
+/// A frame allocator is supposed to layer the conversion between frame
+/// numbers and physical addresses on top of this.
+pub struct Bitmap<'a> {
+    words: &'a mut [u64],
+}
+
+impl<'a> Bitmap<'a> {
+    /// Creates a new bitmap over the provided backing storage.
+    ///
+    /// The existing content of `words` is used as is.
+    pub const fn new(words: &'a mut [u64]) -> Self {
+        Self { words }
+    }
+
+    /// Returns the number of bits (indices) in the bitmap.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.words.len() * BITS_PER_WORD
+    }
+
+    /// Returns whether the bitmap has no bits at all.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Returns the word index and the bit mask for the given bit index.
+    ///
+    /// # Panics
+    /// Panics if `idx` is out of range.
+    fn location(&self, idx: usize) -> (usize, u64) {
+        assert!(
+            idx < self.len(),
+            "index {idx} out of range (len={})",
+            self.len()
+        );
+        (idx / BITS_PER_WORD, 1 << (idx % BITS_PER_WORD))
+    }
+
+    /// Marks the given index as used.
+    ///
+    /// # Panics
+    /// Panics if `idx` is out of range.
+    pub fn set(&mut self, idx: usize) {
+        let (word, mask) = self.location(idx);
+        self.words[word] |= mask;
+    }
+
+    /// Marks the given index as free.
+    ///
+    /// # Panics
+    /// Panics if `idx` is out of range.
+    pub fn clear(&mut self, idx: usize) {
+        let (word, mask) = self.location(idx);
+        self.words[word] &= !mask;
+    }
+
+    /// Returns whether the given index is used.
+    ///
+    /// # Panics
+    /// Panics if `idx` is out of range.
+    #[must_use]
+    pub fn get(&self, idx: usize) -> bool {
+        let (word, mask) = self.location(idx);
+        self.words[word] & mask != 0
+    }
+
+    /// Returns the first free index, if any.
+    #[must_use]
+    pub fn find_first_free(&self) -> Option<usize> {
+        self.words
+            .iter()
+            .enumerate()
+            // Fast path: skip fully used words.
+            .find(|(_, word)| **word != u64::MAX)
+            .map(|(i, word)| i * BITS_PER_WORD + word.trailing_ones() as usize)
+    }
+
+    /// Returns the first index of a range of `n` contiguous free indices,
+    /// if any.
+    ///
+    /// The search does not wrap around at the end of the bitmap. For `n == 0`,
+    /// this returns `None`.
+    #[must_use]
+    pub fn find_contiguous_free(&self, n: usize) -> Option<usize> {
+        if n == 0 || n > self.len() {
+            return None;
+        }
+
+        let mut run_begin = 0;
+        let mut run_len = 0;
+        for idx in 0..self.len() {
+            if self.get(idx) {
+                run_len = 0;
+                continue;
+            }
+
+            if run_len == 0 {
+                run_begin = idx;
+            }
+            run_len += 1;
+            if run_len == n {
+                return Some(run_begin);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Debug for Bitmap<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bitmap")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_get_clear() {
+        let mut words = [0; 2];
+        let mut bitmap = Bitmap::new(&mut words);
+        assert_eq!(bitmap.len(), 128);
+
+        bitmap.set(0);
+        bitmap.set(64);
+        bitmap.set(127);
+        assert!(bitmap.get(0));
+        assert!(!bitmap.get(1));
+        assert!(bitmap.get(64));
+        assert!(bitmap.get(127));
+
+        bitmap.clear(64);
+        assert!(!bitmap.get(64));
+        assert_eq!(words, [1, 1 << 63]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_out_of_range() {
+        let mut words = [0; 1];
+        let bitmap = Bitmap::new(&mut words);
+        let _ = bitmap.get(64);
+    }
+
+    #[test]
+    fn test_find_first_free() {
+        let mut words = [u64::MAX, 0b0111];
+        let mut bitmap = Bitmap::new(&mut words);
+        assert_eq!(bitmap.find_first_free(), Some(67));
+
+        bitmap.clear(5);
+        assert_eq!(bitmap.find_first_free(), Some(5));
+
+        let mut words = [u64::MAX; 2];
+        let bitmap = Bitmap::new(&mut words);
+        assert_eq!(bitmap.find_first_free(), None);
+    }
+
+    #[test]
+    fn test_find_contiguous_free() {
+        let mut words = [0; 2];
+        let mut bitmap = Bitmap::new(&mut words);
+        assert_eq!(bitmap.find_contiguous_free(0), None);
+        assert_eq!(bitmap.find_contiguous_free(128), Some(0));
+        assert_eq!(bitmap.find_contiguous_free(129), None);
+
+        // Run crosses the word boundary.
+        bitmap.set(60);
+        assert_eq!(bitmap.find_contiguous_free(61), Some(61));
+    }
+
+    #[test]
+    fn test_find_contiguous_free_exact_fit_at_end() {
+        let mut words = [u64::MAX, u64::MAX >> 4];
+        let bitmap = Bitmap::new(&mut words);
+        assert_eq!(bitmap.find_contiguous_free(4), Some(124));
+        assert_eq!(bitmap.find_contiguous_free(5), None);
+    }
+
+    #[test]
+    fn test_find_contiguous_free_no_wrap_around() {
+        // Free: 0..2 and 126..128; together 4 but only when wrapping.
+        let mut words = [!0b11, u64::MAX >> 2];
+        let bitmap = Bitmap::new(&mut words);
+        assert_eq!(bitmap.find_contiguous_free(2), Some(0));
+        assert_eq!(bitmap.find_contiguous_free(3), None);
+    }
+}
@@ -0,0 +1,617 @@
+//! Boot information passed from the OS loader to the kernel.
+//!
+//! The types in this module are part of the binary contract between the
+//! loader and the kernel and therefore have a stable ABI.
+
+use crate::{DirectMap, MemoryMap, MemoryMapEntry};
+use thiserror::Error;
+use util::paging::{PhysAddress, VirtAddress};
+
+/// Virtual address at which the loader maps the [`BootInformation`] into the
+/// address space of the kernel.
+///
+/// The loader additionally passes a pointer to the boot information in `rdi`
+/// to the kernel. Both must agree; the kernel asserts this.
+pub const BOOT_INFO_VADDR: VirtAddress = VirtAddress(0xffff_ffff_8000_0000);
+
+/// Possible errors when interpreting raw bytes as [`BootInformation`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum BootInformationError {
+    /// The slice is smaller than the boot information.
+    #[error("boot information is too short ({0} bytes)")]
+    TooShort(usize),
+    /// The slice is not properly aligned.
+    #[error("boot information at {0:#x} is misaligned")]
+    Misaligned(usize),
+    /// The magic doesn't match [`BootInformation::MAGIC`].
+    #[error("boot information has invalid magic {0:#x}")]
+    InvalidMagic(u64),
+    /// The version doesn't match [`BootInformation::VERSION`].
+    #[error("boot information has unsupported version {0}")]
+    UnsupportedVersion(u32),
+    /// The checksum doesn't match the content.
+    #[error("boot information has invalid checksum")]
+    InvalidChecksum,
+    /// The framebuffer has an unknown pixel format.
+    #[error("framebuffer has unknown pixel format {0}")]
+    InvalidPixelFormat(u8),
+}
+
+/// Order of the color channels of a pixel in a framebuffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum PixelFormat {
+    /// Red in the lowest byte, followed by green and blue.
+    Rgb = 0,
+    /// Blue in the lowest byte, followed by green and red.
+    Bgr = 1,
+}
+
+impl PixelFormat {
+    /// Returns the pixel format for its raw ABI value.
+    #[must_use]
+    pub const fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Rgb),
+            1 => Some(Self::Bgr),
+            _ => None,
+        }
+    }
+}
+
+/// Linear framebuffer the loader obtained from the firmware.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FramebufferInfo {
+    base: u64,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: u8,
+    /// Raw [`PixelFormat`]; stored as integer so that all bit patterns are
+    /// valid.
+    format: u8,
+    _reserved: [u8; 2],
+}
+
+impl FramebufferInfo {
+    /// Placeholder for a missing framebuffer.
+    const NONE: Self = Self {
+        base: 0,
+        width: 0,
+        height: 0,
+        pitch: 0,
+        bpp: 0,
+        format: 0,
+        _reserved: [0; 2],
+    };
+
+    /// Creates a new framebuffer description.
+    ///
+    /// `pitch` is the number of bytes per line and `bpp` the number of bits
+    /// per pixel.
+    #[must_use]
+    pub const fn new(
+        base: PhysAddress,
+        width: u32,
+        height: u32,
+        pitch: u32,
+        bpp: u8,
+        format: PixelFormat,
+    ) -> Self {
+        Self {
+            base: base.0,
+            width,
+            height,
+            pitch,
+            bpp,
+            format: format as u8,
+            _reserved: [0; 2],
+        }
+    }
+
+    /// Returns the physical base address.
+    #[must_use]
+    pub const fn base(&self) -> PhysAddress {
+        PhysAddress(self.base)
+    }
+
+    /// Returns the width in pixels.
+    #[must_use]
+    pub const fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height in pixels.
+    #[must_use]
+    pub const fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the number of bytes per line.
+    #[must_use]
+    pub const fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    /// Returns the number of bits per pixel.
+    #[must_use]
+    pub const fn bpp(&self) -> u8 {
+        self.bpp
+    }
+
+    /// Returns the pixel format.
+    ///
+    /// # Panics
+    /// Panics if the format is unknown, which [`BootInformation::validate`]
+    /// rules out.
+    #[must_use]
+    pub const fn format(&self) -> PixelFormat {
+        match PixelFormat::from_raw(self.format) {
+            Some(format) => format,
+            None => panic!("should have a valid pixel format"),
+        }
+    }
+
+    /// Returns the size of the framebuffer in bytes.
+    #[must_use]
+    pub const fn size(&self) -> u64 {
+        self.pitch as u64 * self.height as u64
+    }
+}
+
+/// Boot information passed from the OS loader to the kernel.
+///
+/// The loader maps the boot information read-only and non-executable at
+/// [`BOOT_INFO_VADDR`] into the address space of the kernel. The kernel must
+/// treat it as immutable.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct BootInformation {
+    magic: u64,
+    version: u32,
+    /// Chosen so that all 32-bit words of the structure sum up to zero.
+    checksum: u32,
+    hhdm_offset: u64,
+    page_tables_base: u64,
+    page_tables_size: u64,
+    /// Absent if its base address is zero.
+    framebuffer: FramebufferInfo,
+    /// See [`Self::FLAG_VGA_TEXT_MODE`].
+    flags: u64,
+    memory_map_base: u64,
+    /// Number of [`MemoryMapEntry`]s.
+    memory_map_len: u64,
+}
+
+impl BootInformation {
+    /// Magic value identifying a valid boot information.
+    pub const MAGIC: u64 = u64::from_le_bytes(*b"PHIPSOS\0");
+    /// The version of the boot information layout.
+    pub const VERSION: u32 = 5;
+
+    /// Flag indicating that the display is in legacy VGA text mode, i.e., the
+    /// text buffer at physical address `0xb8000` exists and is shown.
+    const FLAG_VGA_TEXT_MODE: u64 = 1 << 0;
+
+    /// Creates a new boot information.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            magic: Self::MAGIC,
+            version: Self::VERSION,
+            checksum: 0,
+            hhdm_offset: 0,
+            page_tables_base: 0,
+            page_tables_size: 0,
+            framebuffer: FramebufferInfo::NONE,
+            flags: 0,
+            memory_map_base: 0,
+            memory_map_len: 0,
+        }
+        .with_checksum()
+    }
+
+    /// Sets the virtual base address of the direct map of physical memory.
+    #[must_use]
+    pub const fn with_hhdm_offset(mut self, hhdm_offset: u64) -> Self {
+        self.hhdm_offset = hhdm_offset;
+        self.with_checksum()
+    }
+
+    /// Sets the physical region holding the page tables the loader built for
+    /// the kernel.
+    #[must_use]
+    pub const fn with_page_tables(mut self, base: PhysAddress, size: u64) -> Self {
+        self.page_tables_base = base.0;
+        self.page_tables_size = size;
+        self.with_checksum()
+    }
+
+    /// Sets the framebuffer the loader obtained from the firmware.
+    #[must_use]
+    pub const fn with_framebuffer(mut self, framebuffer: FramebufferInfo) -> Self {
+        self.framebuffer = framebuffer;
+        self.with_checksum()
+    }
+
+    /// Sets whether the display is in legacy VGA text mode.
+    ///
+    /// The loader must only set this if it knows that the text buffer exists,
+    /// as nothing else tells the kernel whether it is safe to access it.
+    #[must_use]
+    pub const fn with_vga_text_mode(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.flags |= Self::FLAG_VGA_TEXT_MODE;
+        } else {
+            self.flags &= !Self::FLAG_VGA_TEXT_MODE;
+        }
+        self.with_checksum()
+    }
+
+    /// Sets the physical location of the memory map, which consists of `len`
+    /// [`MemoryMapEntry`]s at `base`.
+    #[must_use]
+    pub const fn with_memory_map(mut self, base: PhysAddress, len: usize) -> Self {
+        self.memory_map_base = base.0;
+        self.memory_map_len = len as u64;
+        self.with_checksum()
+    }
+
+    /// Returns the wrapping sum of all 32-bit words except the checksum.
+    ///
+    /// This must consider all fields of the structure.
+    const fn sum(&self) -> u32 {
+        let words = [
+            self.magic as u32,
+            (self.magic >> 32) as u32,
+            self.version,
+            self.hhdm_offset as u32,
+            (self.hhdm_offset >> 32) as u32,
+            self.page_tables_base as u32,
+            (self.page_tables_base >> 32) as u32,
+            self.page_tables_size as u32,
+            (self.page_tables_size >> 32) as u32,
+            self.framebuffer.base as u32,
+            (self.framebuffer.base >> 32) as u32,
+            self.framebuffer.width,
+            self.framebuffer.height,
+            self.framebuffer.pitch,
+            u32::from_le_bytes([
+                self.framebuffer.bpp,
+                self.framebuffer.format,
+                self.framebuffer._reserved[0],
+                self.framebuffer._reserved[1],
+            ]),
+            self.flags as u32,
+            (self.flags >> 32) as u32,
+            self.memory_map_base as u32,
+            (self.memory_map_base >> 32) as u32,
+            self.memory_map_len as u32,
+            (self.memory_map_len >> 32) as u32,
+        ];
+        let mut sum = 0_u32;
+        let mut i = 0;
+        while i < words.len() {
+            sum = sum.wrapping_add(words[i]);
+            i += 1;
+        }
+        sum
+    }
+
+    /// Updates the checksum according to the current content.
+    const fn with_checksum(mut self) -> Self {
+        self.checksum = 0_u32.wrapping_sub(self.sum());
+        self
+    }
+
+    /// Validates magic, version, checksum, and the framebuffer.
+    pub const fn validate(&self) -> Result<(), BootInformationError> {
+        if self.magic != Self::MAGIC {
+            return Err(BootInformationError::InvalidMagic(self.magic));
+        }
+        if self.version != Self::VERSION {
+            return Err(BootInformationError::UnsupportedVersion(self.version));
+        }
+        if self.sum().wrapping_add(self.checksum) != 0 {
+            return Err(BootInformationError::InvalidChecksum);
+        }
+        if PixelFormat::from_raw(self.framebuffer.format).is_none() {
+            return Err(BootInformationError::InvalidPixelFormat(
+                self.framebuffer.format,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns whether magic, version, checksum, and the framebuffer are
+    /// valid.
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Returns the virtual base address of the direct map of physical memory
+    /// that the loader set up.
+    ///
+    /// Physical address `p` is mapped at virtual address `hhdm_offset + p`.
+    #[must_use]
+    pub const fn hhdm_offset(&self) -> u64 {
+        self.hhdm_offset
+    }
+
+    /// Returns the physical base address and the size in bytes of the region
+    /// holding the page tables the loader built for the kernel.
+    ///
+    /// The region is `LoaderData` memory that the kernel can reclaim once it
+    /// switched to its own page tables.
+    #[must_use]
+    pub const fn page_tables(&self) -> (PhysAddress, u64) {
+        (PhysAddress(self.page_tables_base), self.page_tables_size)
+    }
+
+    /// Returns the physical base address and the number of entries of the
+    /// memory map.
+    #[must_use]
+    pub const fn memory_map_region(&self) -> (PhysAddress, usize) {
+        (
+            PhysAddress(self.memory_map_base),
+            self.memory_map_len as usize,
+        )
+    }
+
+    /// Returns the memory map the loader created, accessed via the direct
+    /// map.
+    ///
+    /// Returns `None` if the memory map is malformed; see
+    /// [`MemoryMap::from_bytes`].
+    ///
+    /// # Safety
+    /// The direct map must be active and cover the memory map, which must
+    /// stay unmodified for `'a`. This holds for the memory map the loader
+    /// reports as long as the kernel doesn't reclaim loader memory.
+    #[must_use]
+    pub unsafe fn memory_map<'a>(&self, direct_map: &DirectMap) -> Option<&'a MemoryMap> {
+        let (base, len) = self.memory_map_region();
+        if len == 0 {
+            return Some(MemoryMap::new(&[]));
+        }
+        let size = len.checked_mul(MemoryMapEntry::SIZE)?;
+        let ptr = direct_map.phys_to_virt(base).0 as *const u8;
+        // SAFETY: Guaranteed by the caller.
+        let bytes = unsafe { core::slice::from_raw_parts(ptr, size) };
+        MemoryMap::from_bytes(bytes)
+    }
+
+    /// Returns whether the display is in legacy VGA text mode, as reported by
+    /// the loader.
+    #[must_use]
+    pub const fn vga_text_mode(&self) -> bool {
+        self.flags & Self::FLAG_VGA_TEXT_MODE != 0
+    }
+
+    /// Returns the framebuffer, if the loader found one.
+    #[must_use]
+    pub const fn framebuffer(&self) -> Option<&FramebufferInfo> {
+        if self.framebuffer.base == 0 {
+            None
+        } else {
+            Some(&self.framebuffer)
+        }
+    }
+}
+
+impl Default for BootInformation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for &'a BootInformation {
+    type Error = BootInformationError;
+
+    /// Interprets the raw handoff bytes as [`BootInformation`] after checking
+    /// its size and alignment and validating it, see [`BootInformation::validate`].
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < size_of::<BootInformation>() {
+            return Err(BootInformationError::TooShort(bytes.len()));
+        }
+        let ptr = bytes.as_ptr().cast::<BootInformation>();
+        if !ptr.is_aligned() {
+            return Err(BootInformationError::Misaligned(ptr as usize));
+        }
+        // SAFETY: Size and alignment are checked and all bit patterns are
+        // valid for the plain integer fields.
+        let boot_info = unsafe { &*ptr };
+        boot_info.validate()?;
+        Ok(boot_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::offset_of;
+
+    /// Buffer suitably aligned for [`BootInformation`].
+    #[repr(C, align(8))]
+    struct Buffer([u8; 128]);
+
+    /// Returns a buffer with the serialized boot information at `offset`.
+    fn serialize(boot_info: &BootInformation, offset: usize) -> Buffer {
+        let mut buffer = Buffer([0; 128]);
+        // SAFETY: The boot information consists of plain integers.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                core::ptr::from_ref(boot_info).cast::<u8>(),
+                size_of::<BootInformation>(),
+            )
+        };
+        buffer.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+        buffer
+    }
+
+    #[test]
+    fn test_abi() {
+        assert_eq!(size_of::<BootInformation>(), 88);
+        assert_eq!(align_of::<BootInformation>(), 8);
+        assert_eq!(offset_of!(BootInformation, hhdm_offset), 16);
+        assert_eq!(offset_of!(BootInformation, page_tables_base), 24);
+        assert_eq!(offset_of!(BootInformation, page_tables_size), 32);
+        assert_eq!(offset_of!(BootInformation, framebuffer), 40);
+        assert_eq!(offset_of!(BootInformation, flags), 64);
+        assert_eq!(offset_of!(BootInformation, memory_map_base), 72);
+        assert_eq!(offset_of!(BootInformation, memory_map_len), 80);
+
+        assert_eq!(size_of::<FramebufferInfo>(), 24);
+        assert_eq!(align_of::<FramebufferInfo>(), 8);
+        assert_eq!(offset_of!(FramebufferInfo, width), 8);
+        assert_eq!(offset_of!(FramebufferInfo, height), 12);
+        assert_eq!(offset_of!(FramebufferInfo, pitch), 16);
+        assert_eq!(offset_of!(FramebufferInfo, bpp), 20);
+        assert_eq!(offset_of!(FramebufferInfo, format), 21);
+    }
+
+    #[test]
+    fn test_boot_info_vaddr() {
+        assert!(BOOT_INFO_VADDR.is_canonical());
+        assert!(
+            BOOT_INFO_VADDR
+                .0
+                .is_multiple_of(util::paging::PAGE_SIZE as u64)
+        );
+    }
+
+    #[test]
+    fn test_is_valid() {
+        assert!(BootInformation::new().is_valid());
+    }
+
+    #[test]
+    fn test_hhdm_offset() {
+        let boot_info = BootInformation::new().with_hhdm_offset(0xffff_8000_0000_0000);
+        assert_eq!(boot_info.hhdm_offset(), 0xffff_8000_0000_0000);
+        assert!(boot_info.is_valid());
+    }
+
+    #[test]
+    fn test_page_tables() {
+        let boot_info = BootInformation::new().with_page_tables(PhysAddress(0x7000_0000), 0x9000);
+        assert_eq!(boot_info.page_tables(), (PhysAddress(0x7000_0000), 0x9000));
+        assert!(boot_info.is_valid());
+    }
+
+    #[test]
+    fn test_framebuffer() {
+        assert_eq!(BootInformation::new().framebuffer(), None);
+
+        let framebuffer = FramebufferInfo::new(
+            PhysAddress(0x8000_0000),
+            1280,
+            800,
+            5120,
+            32,
+            PixelFormat::Bgr,
+        );
+        let boot_info = BootInformation::new().with_framebuffer(framebuffer.clone());
+        assert_eq!(boot_info.framebuffer(), Some(&framebuffer));
+        assert_eq!(framebuffer.format(), PixelFormat::Bgr);
+        assert_eq!(framebuffer.size(), 5120 * 800);
+        assert!(boot_info.is_valid());
+
+        let mut framebuffer = framebuffer;
+        framebuffer.format = 2;
+        let boot_info = BootInformation::new().with_framebuffer(framebuffer);
+        assert_eq!(
+            boot_info.validate(),
+            Err(BootInformationError::InvalidPixelFormat(2))
+        );
+    }
+
+    #[test]
+    fn test_vga_text_mode() {
+        assert!(!BootInformation::new().vga_text_mode());
+        let boot_info = BootInformation::new().with_vga_text_mode(true);
+        assert!(boot_info.vga_text_mode());
+        assert!(boot_info.is_valid());
+        let boot_info = boot_info.with_vga_text_mode(false);
+        assert_eq!(boot_info, BootInformation::new());
+    }
+
+    #[test]
+    fn test_memory_map() {
+        use crate::MemoryMapEntryType;
+
+        let entries = [
+            MemoryMapEntry::with_default_flags(0x0, 0x8_0000, MemoryMapEntryType::AvailableRam),
+            MemoryMapEntry::with_default_flags(0x20_0000, 0x4000, MemoryMapEntryType::Kernel),
+        ];
+        let boot_info = BootInformation::new()
+            .with_memory_map(PhysAddress(entries.as_ptr() as u64), entries.len());
+        assert_eq!(
+            boot_info.memory_map_region(),
+            (PhysAddress(entries.as_ptr() as u64), 2)
+        );
+        assert!(boot_info.is_valid());
+
+        // In the tests, physical addresses are the host's virtual addresses.
+        // SAFETY: The entries outlive the view.
+        let map = unsafe { boot_info.memory_map(&DirectMap::new(0)) }.unwrap();
+        assert_eq!(map.entries(), &entries);
+
+        // SAFETY: An empty memory map is never accessed.
+        let map = unsafe { BootInformation::new().memory_map(&DirectMap::new(0)) }.unwrap();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_try_from_bytes() {
+        let boot_info = BootInformation::new().with_hhdm_offset(0xffff_8000_0000_0000);
+        let buffer = serialize(&boot_info, 0);
+        let parsed = <&BootInformation>::try_from(&buffer.0[..]).unwrap();
+        assert_eq!(parsed, &boot_info);
+    }
+
+    #[test]
+    fn test_try_from_bytes_too_short() {
+        let buffer = serialize(&BootInformation::new(), 0);
+        assert_eq!(
+            <&BootInformation>::try_from(&buffer.0[..87]),
+            Err(BootInformationError::TooShort(87))
+        );
+    }
+
+    #[test]
+    fn test_try_from_bytes_misaligned() {
+        let buffer = serialize(&BootInformation::new(), 1);
+        let bytes = &buffer.0[1..];
+        assert_eq!(
+            <&BootInformation>::try_from(bytes),
+            Err(BootInformationError::Misaligned(bytes.as_ptr() as usize))
+        );
+    }
+
+    #[test]
+    fn test_try_from_bytes_corrupted() {
+        let mut buffer = serialize(&BootInformation::new(), 0);
+        buffer.0[0] ^= 0xff;
+        assert!(matches!(
+            <&BootInformation>::try_from(&buffer.0[..]),
+            Err(BootInformationError::InvalidMagic(_))
+        ));
+
+        let mut buffer = serialize(&BootInformation::new(), 0);
+        buffer.0[8] = 2;
+        assert_eq!(
+            <&BootInformation>::try_from(&buffer.0[..]),
+            Err(BootInformationError::UnsupportedVersion(2))
+        );
+
+        let mut buffer = serialize(&BootInformation::new(), 0);
+        buffer.0[16] ^= 0x1;
+        assert_eq!(
+            <&BootInformation>::try_from(&buffer.0[..]),
+            Err(BootInformationError::InvalidChecksum)
+        );
+    }
+}
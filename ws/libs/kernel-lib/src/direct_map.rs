@@ -0,0 +1,69 @@
+//! Access to physical memory via the direct map set up by the loader.
+
+use crate::BootInformation;
+use util::paging::{PhysAddress, VirtAddress};
+
+/// The higher-half direct map (HHDM) of physical memory.
+///
+/// The loader maps all physical memory linearly at a fixed virtual offset,
+/// which it reports in [`BootInformation::hhdm_offset`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DirectMap {
+    offset: u64,
+}
+
+impl DirectMap {
+    /// Creates a direct map with the given virtual base address.
+    #[must_use]
+    pub const fn new(offset: u64) -> Self {
+        Self { offset }
+    }
+
+    /// Creates the direct map described by the boot information.
+    #[must_use]
+    pub const fn from_boot_info(boot_info: &BootInformation) -> Self {
+        Self::new(boot_info.hhdm_offset())
+    }
+
+    /// Returns the virtual base address of the direct map.
+    #[must_use]
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the virtual address at which `phys` is mapped.
+    #[must_use]
+    pub const fn phys_to_virt(&self, phys: PhysAddress) -> VirtAddress {
+        VirtAddress(self.offset + phys.0)
+    }
+
+    /// Returns the physical address behind `virt`, if `virt` is within the
+    /// direct map.
+    #[must_use]
+    pub const fn virt_to_phys(&self, virt: VirtAddress) -> Option<PhysAddress> {
+        match virt.0.checked_sub(self.offset) {
+            Some(phys) => Some(PhysAddress(phys)),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phys_virt_conversion() {
+        let boot_info = BootInformation::new().with_hhdm_offset(0xffff_8000_0000_0000);
+        let map = DirectMap::from_boot_info(&boot_info);
+        assert_eq!(
+            map.phys_to_virt(PhysAddress(0x1234)),
+            VirtAddress(0xffff_8000_0000_1234)
+        );
+        assert_eq!(
+            map.virt_to_phys(VirtAddress(0xffff_8000_0000_1234)),
+            Some(PhysAddress(0x1234))
+        );
+        assert_eq!(map.virt_to_phys(VirtAddress(0x1234)), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Checks of the placement of the kernel's memory regions.
+
+use core::ops::Range;
+use thiserror::Error;
+
+/// Possible problems of the placement of two memory regions, see
+/// [`check_separation`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum SeparationError {
+    /// The regions overlap.
+    #[error("regions overlap")]
+    Overlap,
+    /// The regions don't overlap, but are closer than required.
+    #[error("only {gap:#x} bytes between the regions, but {min_gap:#x} bytes are required")]
+    GapTooSmall {
+        /// Bytes between the regions.
+        gap: u64,
+        /// Bytes required between the regions.
+        min_gap: u64,
+    },
+}
+
+/// Checks that the address ranges `a` and `b` don't overlap and that at
+/// least `min_gap` bytes lie between them, in whatever order they are.
+///
+/// This is used, e.g., to ensure that an overflow of the kernel stack can't
+/// silently corrupt the heap. Returns the number of bytes between the
+/// regions. Empty ranges never overlap anything.
+pub const fn check_separation(
+    a: &Range<u64>,
+    b: &Range<u64>,
+    min_gap: u64,
+) -> Result<u64, SeparationError> {
+    let gap = if a.end <= b.start {
+        b.start - a.end
+    } else if b.end <= a.start {
+        a.start - b.end
+    } else if a.start >= a.end || b.start >= b.end {
+        return Ok(0);
+    } else {
+        return Err(SeparationError::Overlap);
+    };
+    if gap < min_gap {
+        return Err(SeparationError::GapTooSmall { gap, min_gap });
+    }
+    Ok(gap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_separation() {
+        let stack = 0x1000..0x3000;
+        assert_eq!(
+            check_separation(&stack, &(0x4000..0x8000), 0x1000),
+            Ok(0x1000)
+        );
+        assert_eq!(
+            check_separation(&(0x4000..0x8000), &stack, 0x1000),
+            Ok(0x1000)
+        );
+        // Adjacent regions are fine without a required gap.
+        assert_eq!(check_separation(&stack, &(0x3000..0x4000), 0), Ok(0));
+        assert_eq!(
+            check_separation(&stack, &(0x3000..0x4000), 0x1000),
+            Err(SeparationError::GapTooSmall {
+                gap: 0,
+                min_gap: 0x1000
+            })
+        );
+        assert_eq!(
+            check_separation(&(0x0..0x800), &stack, 0x1000),
+            Err(SeparationError::GapTooSmall {
+                gap: 0x800,
+                min_gap: 0x1000
+            })
+        );
+
+        for overlapping in [0x2fff..0x4000, 0x0..0x1001, 0x1800..0x2000, 0x0..0x4000] {
+            assert_eq!(
+                check_separation(&stack, &overlapping, 0),
+                Err(SeparationError::Overlap),
+                "{overlapping:x?}"
+            );
+        }
+        assert_eq!(check_separation(&stack, &(0x2000..0x2000), 0x1000), Ok(0));
+    }
+}
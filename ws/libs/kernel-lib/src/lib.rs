@@ -13,9 +13,31 @@
 #![deny(missing_debug_implementations)]
 #![deny(rustdoc::all)]
 
+extern crate alloc;
 #[cfg(test)]
 extern crate std;
 
+mod bitmap;
+mod boot_information;
+mod direct_map;
+mod layout;
+mod memory_map;
+mod memory_map_builder;
+mod owned_memory_map;
+
+pub use bitmap::Bitmap;
+pub use boot_information::{
+    BOOT_INFO_VADDR, BootInformation, BootInformationError, FramebufferInfo, PixelFormat,
+};
+pub use direct_map::DirectMap;
+pub use layout::{SeparationError, check_separation};
+pub use memory_map::{
+    BufferTooSmall, ConsistencyError, MemoryMap, MemoryMapEntry, MemoryMapEntryFlags,
+    MemoryMapEntryType, verify_kernel_regions,
+};
+pub use memory_map_builder::{MemoryMapBuilder, OverlapError};
+pub use owned_memory_map::OwnedMemoryMap;
+
 #[cfg(test)]
 mod tests {
     // use super::*;
@@ -0,0 +1,784 @@
+//! Memory map passed from the OS loader to the kernel.
+//!
+//! The types in this module are part of the binary contract between the
+//! loader and the kernel and therefore have a stable ABI.
+
+use bitflags::bitflags;
+use core::fmt::{self, Display, Formatter, Write};
+use core::ops::Range;
+use thiserror::Error;
+use util::paging::{PAGE_SIZE, PhysAddress};
+
+/// The type of memory described by a [`MemoryMapEntry`].
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum MemoryMapEntryType {
+    /// Free RAM usable by the kernel.
+    AvailableRam = 1,
+    /// RAM occupied by the loaded kernel image.
+    Kernel = 2,
+    /// RAM occupied by data of the OS loader that is still needed, such as
+    /// the boot information or the initial page tables.
+    LoaderData = 3,
+    /// ACPI tables that can be reclaimed once they were parsed.
+    AcpiReclaimable = 4,
+    /// Code and data of the firmware, such as UEFI runtime services.
+    Firmware = 5,
+    /// Memory-mapped I/O of devices.
+    Mmio = 6,
+    /// Memory that must not be touched.
+    Reserved = 7,
+}
+
+impl TryFrom<u16> for MemoryMapEntryType {
+    type Error = u16;
+
+    /// Converts the raw representation; returns the value if it is unknown.
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        let typ = match value {
+            1 => Self::AvailableRam,
+            2 => Self::Kernel,
+            3 => Self::LoaderData,
+            4 => Self::AcpiReclaimable,
+            5 => Self::Firmware,
+            6 => Self::Mmio,
+            7 => Self::Reserved,
+            _ => return Err(value),
+        };
+        Ok(typ)
+    }
+}
+
+impl MemoryMapEntryType {
+    /// Returns the conventional protection flags for memory of this type.
+    ///
+    /// The convention is:
+    /// - [`Self::AvailableRam`] is `rw-`
+    /// - [`Self::Kernel`] is `rwx`, as the region covers all kernel segments.
+    ///   The actual protection per segment follows from the kernel ELF.
+    /// - [`Self::LoaderData`] is `rw-`
+    /// - [`Self::AcpiReclaimable`] is `r--`
+    /// - [`Self::Firmware`] is `r-x`
+    /// - [`Self::Mmio`] is `rw-`
+    /// - [`Self::Reserved`] is `---`
+    #[must_use]
+    pub const fn default_flags(self) -> MemoryMapEntryFlags {
+        match self {
+            Self::AvailableRam | Self::LoaderData | Self::Mmio => {
+                MemoryMapEntryFlags::READ.union(MemoryMapEntryFlags::WRITE)
+            }
+            Self::Kernel => MemoryMapEntryFlags::all(),
+            Self::AcpiReclaimable => MemoryMapEntryFlags::READ,
+            Self::Firmware => MemoryMapEntryFlags::READ.union(MemoryMapEntryFlags::EXECUTE),
+            Self::Reserved => MemoryMapEntryFlags::empty(),
+        }
+    }
+}
+
+bitflags! {
+    /// Protection flags of a [`MemoryMapEntry`].
+    #[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+    #[repr(transparent)]
+    pub struct MemoryMapEntryFlags: u16 {
+        /// The memory is readable.
+        const READ = 1 << 0;
+        /// The memory is writeable.
+        const WRITE = 1 << 1;
+        /// The memory is executable.
+        const EXECUTE = 1 << 2;
+    }
+}
+
+/// Formats the flags in the compact `rwx` style, e.g., `r-x` or `rw-`.
+impl Display for MemoryMapEntryFlags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (flag, c) in [(Self::READ, 'r'), (Self::WRITE, 'w'), (Self::EXECUTE, 'x')] {
+            f.write_char(if self.contains(flag) { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+/// A single entry of the memory map describing a region of physical memory.
+///
+/// The derived [`PartialEq`] and [`Hash`] include the padding bytes, which are
+/// only meaningful if the padding is zero. All constructors, including
+/// [`Self::from_le_bytes`] for external data, guarantee this. Use
+/// [`Self::eq_ignoring_padding`] for entries of unknown origin.
+///
+/// The derived [`Ord`] compares `from` first and therefore agrees with the
+/// order by start address for maps without overlapping entries. Don't rely on
+/// this; use [`MemoryMap::sort_by_address`] to sort by start address
+/// explicitly.
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct MemoryMapEntry {
+    /// Physical begin address of the region.
+    pub from: u64,
+    /// Length of the region in bytes.
+    pub length: u64,
+    /// Type of the region.
+    pub typ: MemoryMapEntryType,
+    /// Protection flags of the region.
+    pub flags: MemoryMapEntryFlags,
+    _padding: [u8; 4],
+}
+
+impl MemoryMapEntry {
+    /// Creates a new entry.
+    #[must_use]
+    pub const fn new(
+        from: u64,
+        length: u64,
+        typ: MemoryMapEntryType,
+        flags: MemoryMapEntryFlags,
+    ) -> Self {
+        Self {
+            from,
+            length,
+            typ,
+            flags,
+            _padding: [0; 4],
+        }
+    }
+
+    /// Creates a new entry with the [default flags] of the given type.
+    ///
+    /// [default flags]: MemoryMapEntryType::default_flags
+    #[must_use]
+    pub const fn with_default_flags(from: u64, length: u64, typ: MemoryMapEntryType) -> Self {
+        Self::new(from, length, typ, typ.default_flags())
+    }
+
+    /// Size of the serialized entry in bytes.
+    pub const SIZE: usize = 24;
+
+    /// Parses an entry from its little-endian in-memory representation, as
+    /// found in data from an external source.
+    ///
+    /// The padding is zeroed. Returns `None` for an unknown type or unknown
+    /// flags.
+    #[must_use]
+    pub fn from_le_bytes(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let (from, rest) = bytes.split_first_chunk::<8>()?;
+        let (length, rest) = rest.split_first_chunk::<8>()?;
+        let (typ, rest) = rest.split_first_chunk::<2>()?;
+        let (flags, _padding) = rest.split_first_chunk::<2>()?;
+
+        let typ = MemoryMapEntryType::try_from(u16::from_le_bytes(*typ)).ok()?;
+        let flags = MemoryMapEntryFlags::from_bits(u16::from_le_bytes(*flags))?;
+        Some(Self::new(
+            u64::from_le_bytes(*from),
+            u64::from_le_bytes(*length),
+            typ,
+            flags,
+        ))
+    }
+
+    /// Compares only the semantic fields, ignoring the padding.
+    #[must_use]
+    pub fn eq_ignoring_padding(&self, other: &Self) -> bool {
+        self.from == other.from
+            && self.length == other.length
+            && self.typ == other.typ
+            && self.flags == other.flags
+    }
+
+    /// Returns the exclusive physical end address of the region.
+    ///
+    /// Returns `None` if the region exceeds the 64-bit address space, i.e.,
+    /// if the entry is malformed.
+    #[must_use]
+    pub const fn to(&self) -> Option<u64> {
+        self.from.checked_add(self.length)
+    }
+
+    /// Returns the physical address range of the region.
+    ///
+    /// # Panics
+    /// Panics if the region exceeds the 64-bit address space.
+    #[must_use]
+    pub const fn phys_range(&self) -> Range<u64> {
+        let to = self
+            .to()
+            .expect("region should not exceed the address space");
+        self.from..to
+    }
+
+    /// Returns the range of 4 KiB frame numbers of the region.
+    ///
+    /// # Panics
+    /// Panics if `from` or `length` are not page-aligned or if the region
+    /// exceeds the 64-bit address space.
+    #[must_use]
+    pub const fn frame_range(&self) -> Range<u64> {
+        assert!(self.from.is_multiple_of(PAGE_SIZE as u64));
+        assert!(self.length.is_multiple_of(PAGE_SIZE as u64));
+        let range = self.phys_range();
+        range.start / PAGE_SIZE as u64..range.end / PAGE_SIZE as u64
+    }
+
+    /// Returns the number of 4 KiB frames of the region.
+    ///
+    /// # Panics
+    /// Panics if `length` is not page-aligned.
+    #[must_use]
+    pub const fn frame_count(&self) -> u64 {
+        assert!(self.length.is_multiple_of(PAGE_SIZE as u64));
+        self.length / PAGE_SIZE as u64
+    }
+
+    /// Returns an iterator over the base addresses of all 4 KiB frames of the
+    /// region.
+    ///
+    /// # Panics
+    /// Panics under the same conditions as [`Self::frame_range`].
+    pub fn frames(&self) -> impl Iterator<Item = PhysAddress> + use<> {
+        self.frame_range()
+            .map(|frame| PhysAddress(frame * PAGE_SIZE as u64))
+    }
+
+    /// Returns whether the entry is well-formed, i.e., whether it is not
+    /// empty and doesn't exceed the 64-bit address space.
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        self.length > 0 && self.to().is_some()
+    }
+
+    /// Returns whether `addr` is within the region.
+    ///
+    /// This never overflows. For malformed entries exceeding the address
+    /// space, the region is considered to end at the top of the address space.
+    #[must_use]
+    pub const fn contains(&self, addr: u64) -> bool {
+        match addr.checked_sub(self.from) {
+            Some(offset) => offset < self.length,
+            None => false,
+        }
+    }
+
+    /// Returns whether the regions of both entries overlap.
+    ///
+    /// Empty regions never overlap. Like [`Self::contains`], this never
+    /// overflows.
+    #[must_use]
+    pub const fn overlaps(&self, other: &Self) -> bool {
+        if self.length == 0 || other.length == 0 {
+            return false;
+        }
+        if self.from <= other.from {
+            self.contains(other.from)
+        } else {
+            other.contains(self.from)
+        }
+    }
+}
+
+/// Borrowed view on the entries of a memory map.
+#[derive(Debug, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct MemoryMap([MemoryMapEntry]);
+
+impl MemoryMap {
+    /// Creates a memory map view on the given entries.
+    #[must_use]
+    pub const fn new(entries: &[MemoryMapEntry]) -> &Self {
+        // SAFETY: `Self` is a transparent wrapper around the slice.
+        unsafe { &*(core::ptr::from_ref(entries) as *const Self) }
+    }
+
+    /// Creates a mutable memory map view on the given entries.
+    #[must_use]
+    pub const fn new_mut(entries: &mut [MemoryMapEntry]) -> &mut Self {
+        // SAFETY: `Self` is a transparent wrapper around the slice.
+        unsafe { &mut *(core::ptr::from_mut(entries) as *mut Self) }
+    }
+
+    /// Returns a view on the serialized entries in `bytes`, e.g., as written
+    /// by [`crate::OwnedMemoryMap::as_bytes`], without copying.
+    ///
+    /// Returns `None` if `bytes` is not aligned for [`MemoryMapEntry`], its
+    /// length is not a multiple of [`MemoryMapEntry::SIZE`], or an entry has
+    /// an unknown type, unknown flags, or non-zero padding.
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8]) -> Option<&Self> {
+        if bytes.is_empty() {
+            return Some(Self::new(&[]));
+        }
+        if !bytes.as_ptr().cast::<MemoryMapEntry>().is_aligned()
+            || !bytes.len().is_multiple_of(MemoryMapEntry::SIZE)
+        {
+            return None;
+        }
+        let valid = bytes
+            .as_chunks::<{ MemoryMapEntry::SIZE }>()
+            .0
+            .iter()
+            .all(|chunk| MemoryMapEntry::from_le_bytes(chunk).is_some() && chunk[20..] == [0; 4]);
+        if !valid {
+            return None;
+        }
+        // SAFETY: The bytes are aligned and each chunk is a valid entry.
+        let entries = unsafe {
+            core::slice::from_raw_parts(
+                bytes.as_ptr().cast::<MemoryMapEntry>(),
+                bytes.len() / MemoryMapEntry::SIZE,
+            )
+        };
+        Some(Self::new(entries))
+    }
+
+    /// Copies `entries` into `buffer` and returns a view on the copy.
+    ///
+    /// This builds the memory map in pre-allocated memory, e.g., next to the
+    /// [`crate::BootInformation`], without allocating. If `buffer` is not
+    /// aligned for [`MemoryMapEntry`], the copy starts at the first aligned
+    /// byte.
+    pub fn write_into<'a>(
+        buffer: &'a mut [u8],
+        entries: &[MemoryMapEntry],
+    ) -> Result<&'a Self, BufferTooSmall> {
+        if entries.is_empty() {
+            return Ok(Self::new(&[]));
+        }
+        let offset = buffer.as_ptr().align_offset(align_of::<MemoryMapEntry>());
+        let needed = offset.saturating_add(size_of_val(entries));
+        if needed > buffer.len() {
+            return Err(BufferTooSmall {
+                needed,
+                len: buffer.len(),
+            });
+        }
+        // SAFETY: The aligned part of the buffer is large enough and doesn't
+        // overlap with `entries`, as it is borrowed mutably.
+        let copy = unsafe {
+            let dst = buffer.as_mut_ptr().add(offset).cast::<MemoryMapEntry>();
+            dst.copy_from_nonoverlapping(entries.as_ptr(), entries.len());
+            core::slice::from_raw_parts(dst, entries.len())
+        };
+        Ok(Self::new(copy))
+    }
+
+    /// Sorts the entries by their start address.
+    ///
+    /// The sort is stable, so entries with the same start address keep their
+    /// relative order.
+    pub fn sort_by_address(&mut self) {
+        self.0.sort_by_key(|entry| entry.from);
+    }
+
+    /// Returns the entries of the memory map.
+    #[must_use]
+    pub const fn entries(&self) -> &[MemoryMapEntry] {
+        &self.0
+    }
+
+    /// Returns an iterator over the entries of the memory map.
+    pub fn iter(&self) -> core::slice::Iter<'_, MemoryMapEntry> {
+        self.0.iter()
+    }
+
+    /// Returns the number of entries.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the memory map has no entries.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the highest exclusive physical end address of all entries, or
+    /// only of the entries of type `typ`, if given.
+    ///
+    /// This is the address space a frame allocator must cover, e.g., its
+    /// bitmap needs `max_phys_addr / PAGE_SIZE` bits. Malformed entries (see
+    /// [`MemoryMapEntry::is_valid`]) are ignored. Returns `0` if there is no
+    /// matching entry.
+    #[must_use]
+    pub fn max_phys_addr(&self, typ: Option<MemoryMapEntryType>) -> u64 {
+        self.iter()
+            .filter(|entry| typ.is_none_or(|typ| entry.typ == typ))
+            .filter(|entry| entry.is_valid())
+            .filter_map(MemoryMapEntry::to)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl<'a> IntoIterator for &'a MemoryMap {
+    type Item = &'a MemoryMapEntry;
+    type IntoIter = core::slice::Iter<'a, MemoryMapEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Error of [`MemoryMap::write_into`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+#[error("buffer has {len} bytes but the memory map needs {needed} bytes")]
+pub struct BufferTooSmall {
+    /// Bytes needed, including the bytes skipped for alignment.
+    pub needed: usize,
+    /// Size of the buffer.
+    pub len: usize,
+}
+
+/// Possible inconsistencies between a [`MemoryMap`] and what the loader
+/// actually did.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum ConsistencyError {
+    /// Memory of the kernel is not covered by a
+    /// [`MemoryMapEntryType::Kernel`] entry.
+    #[error("kernel memory at {0:#x} is not marked as kernel memory")]
+    KernelNotCovered(u64),
+    /// Memory of the kernel is also reported as available RAM.
+    #[error("kernel memory overlaps available RAM at {:#x}", .0.from)]
+    KernelOverlapsAvailableRam(MemoryMapEntry),
+    /// The memory of the kernel exceeds the physical address space.
+    #[error("kernel memory at {base:#x} with {len:#x} bytes exceeds the address space")]
+    KernelExceedsAddressSpace {
+        /// Physical base address of the kernel.
+        base: u64,
+        /// Length of the kernel's memory.
+        len: usize,
+    },
+}
+
+/// Verifies that the physical memory of the kernel at `kernel_phys_base` with
+/// length `len` is fully covered by [`MemoryMapEntryType::Kernel`] entries and
+/// not reported as [`MemoryMapEntryType::AvailableRam`].
+///
+/// This is a post-condition for the loader: otherwise, the kernel might hand
+/// out its own memory to the frame allocator. The entries don't need to be
+/// sorted.
+pub fn verify_kernel_regions(
+    map: &MemoryMap,
+    kernel_phys_base: PhysAddress,
+    len: usize,
+) -> Result<(), ConsistencyError> {
+    let end = kernel_phys_base.0.checked_add(len as u64).ok_or(
+        ConsistencyError::KernelExceedsAddressSpace {
+            base: kernel_phys_base.0,
+            len,
+        },
+    )?;
+    let kernel = MemoryMapEntry::with_default_flags(
+        kernel_phys_base.0,
+        len as u64,
+        MemoryMapEntryType::Kernel,
+    );
+    if let Some(entry) = map
+        .iter()
+        .find(|entry| entry.typ == MemoryMapEntryType::AvailableRam && entry.overlaps(&kernel))
+    {
+        return Err(ConsistencyError::KernelOverlapsAvailableRam(*entry));
+    }
+
+    let mut addr = kernel_phys_base.0;
+    while addr < end {
+        addr = map
+            .iter()
+            .filter(|entry| entry.typ == MemoryMapEntryType::Kernel && entry.contains(addr))
+            .find_map(MemoryMapEntry::to)
+            .ok_or(ConsistencyError::KernelNotCovered(addr))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type T = MemoryMapEntryType;
+
+    #[test]
+    fn test_abi() {
+        assert_eq!(size_of::<MemoryMapEntryType>(), 2);
+        assert_eq!(size_of::<MemoryMapEntryFlags>(), 2);
+        assert_eq!(size_of::<MemoryMapEntry>(), MemoryMapEntry::SIZE);
+        assert_eq!(align_of::<MemoryMapEntry>(), 8);
+    }
+
+    #[test]
+    fn test_default_flags() {
+        type F = MemoryMapEntryFlags;
+
+        assert_eq!(T::AvailableRam.default_flags(), F::READ | F::WRITE);
+        assert_eq!(T::Kernel.default_flags(), F::READ | F::WRITE | F::EXECUTE);
+        assert_eq!(T::LoaderData.default_flags(), F::READ | F::WRITE);
+        assert_eq!(T::AcpiReclaimable.default_flags(), F::READ);
+        assert_eq!(T::Firmware.default_flags(), F::READ | F::EXECUTE);
+        assert_eq!(T::Mmio.default_flags(), F::READ | F::WRITE);
+        assert_eq!(T::Reserved.default_flags(), F::empty());
+
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x2000, T::Firmware);
+        assert_eq!(entry.flags, F::READ | F::EXECUTE);
+    }
+
+    #[test]
+    fn test_flags_display() {
+        type F = MemoryMapEntryFlags;
+
+        assert_eq!(std::format!("{}", F::empty()), "---");
+        assert_eq!(std::format!("{}", F::all()), "rwx");
+        assert_eq!(std::format!("{}", F::READ | F::WRITE), "rw-");
+        assert_eq!(std::format!("{}", F::READ | F::EXECUTE), "r-x");
+    }
+
+    #[test]
+    fn test_eq_ignoring_padding() {
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x2000, T::AvailableRam);
+        let mut dirty = entry;
+        dirty._padding = [0xff; 4];
+        assert_ne!(entry, dirty);
+        assert!(entry.eq_ignoring_padding(&dirty));
+
+        let other = MemoryMapEntry::with_default_flags(0x1000, 0x2000, T::Mmio);
+        assert!(!entry.eq_ignoring_padding(&other));
+    }
+
+    #[test]
+    fn test_from_le_bytes() {
+        let mut bytes = [0_u8; MemoryMapEntry::SIZE];
+        bytes[0..8].copy_from_slice(&0x1000_u64.to_le_bytes());
+        bytes[8..16].copy_from_slice(&0x2000_u64.to_le_bytes());
+        bytes[16..18].copy_from_slice(&(T::Firmware as u16).to_le_bytes());
+        bytes[18..20].copy_from_slice(&0b101_u16.to_le_bytes());
+        bytes[20..24].copy_from_slice(&[0xaa; 4]);
+
+        let entry = MemoryMapEntry::from_le_bytes(&bytes).unwrap();
+        assert_eq!(
+            entry,
+            MemoryMapEntry::with_default_flags(0x1000, 0x2000, T::Firmware)
+        );
+
+        bytes[16] = 0;
+        assert_eq!(MemoryMapEntry::from_le_bytes(&bytes), None);
+        bytes[16] = T::Firmware as u8;
+        bytes[18] = 0xff;
+        assert_eq!(MemoryMapEntry::from_le_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn test_range_checks() {
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x2000, T::AvailableRam);
+        assert_eq!(entry.to(), Some(0x3000));
+        assert!(entry.is_valid());
+        assert!(!entry.contains(0xfff));
+        assert!(entry.contains(0x1000));
+        assert!(entry.contains(0x2fff));
+        assert!(!entry.contains(0x3000));
+
+        let other = MemoryMapEntry::with_default_flags(0x2fff, 0x1, T::Mmio);
+        assert!(entry.overlaps(&other));
+        assert!(other.overlaps(&entry));
+        let other = MemoryMapEntry::with_default_flags(0x3000, 0x1000, T::Mmio);
+        assert!(!entry.overlaps(&other));
+        assert!(!other.overlaps(&entry));
+        let empty = MemoryMapEntry::with_default_flags(0x1000, 0, T::Mmio);
+        assert!(!empty.is_valid());
+        assert!(!entry.overlaps(&empty));
+    }
+
+    #[test]
+    fn test_phys_and_frame_range() {
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x2000, T::AvailableRam);
+        assert_eq!(entry.phys_range(), 0x1000..0x3000);
+        assert_eq!(entry.frame_range(), 1..3);
+        assert_eq!(entry.frame_range().count(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_frame_range_unaligned() {
+        let entry = MemoryMapEntry::with_default_flags(0x1800, 0x2000, T::AvailableRam);
+        let _ = entry.frame_range();
+    }
+
+    #[test]
+    fn test_frames() {
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x2000, T::AvailableRam);
+        assert_eq!(entry.frame_count(), 2);
+        let frames = entry.frames().collect::<std::vec::Vec<_>>();
+        assert_eq!(frames, [PhysAddress(0x1000), PhysAddress(0x2000)]);
+
+        let empty = MemoryMapEntry::with_default_flags(0x1000, 0, T::AvailableRam);
+        assert_eq!(empty.frame_count(), 0);
+        assert_eq!(empty.frames().count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_frame_count_unaligned() {
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x1800, T::AvailableRam);
+        let _ = entry.frame_count();
+    }
+
+    #[test]
+    fn test_range_checks_dont_wrap() {
+        let entry = MemoryMapEntry::with_default_flags(u64::MAX - 0x1000, 0x2000, T::Reserved);
+        assert_eq!(entry.to(), None);
+        assert!(!entry.is_valid());
+        assert!(!entry.contains(0));
+        assert!(!entry.contains(0xfff));
+        assert!(entry.contains(u64::MAX));
+
+        let low = MemoryMapEntry::with_default_flags(0, 0x1000, T::AvailableRam);
+        assert!(!entry.overlaps(&low));
+        assert!(!low.overlaps(&entry));
+        let high = MemoryMapEntry::with_default_flags(u64::MAX, 1, T::AvailableRam);
+        assert!(entry.overlaps(&high));
+        assert!(high.overlaps(&entry));
+    }
+
+    #[test]
+    fn test_sort_by_address() {
+        let mut entries = [
+            MemoryMapEntry::with_default_flags(0x40_0000, 0x1000, T::Mmio),
+            MemoryMapEntry::with_default_flags(0x0, 0x1000, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(0x20_0000, 0x2000, T::Kernel),
+            MemoryMapEntry::with_default_flags(0x20_0000, 0x1000, T::Reserved),
+            MemoryMapEntry::with_default_flags(0x10_0000, 0x1000, T::LoaderData),
+        ];
+        MemoryMap::new_mut(&mut entries).sort_by_address();
+        let froms = entries.map(|entry| entry.from);
+        assert_eq!(froms, [0x0, 0x10_0000, 0x20_0000, 0x20_0000, 0x40_0000]);
+        // Stable: the derived `Ord` would put the shorter entry first.
+        assert_eq!(entries[2].typ, T::Kernel);
+        assert_eq!(entries[3].typ, T::Reserved);
+    }
+
+    #[test]
+    fn test_write_into() {
+        #[repr(align(8))]
+        struct Buffer([u8; 4 * MemoryMapEntry::SIZE]);
+
+        let entries = [
+            MemoryMapEntry::with_default_flags(0x1000, 0x9f000, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(0x20_0000, 0x20_0000, T::Kernel),
+            MemoryMapEntry::with_default_flags(0xfee0_0000, 0x1000, T::Mmio),
+        ];
+        let mut buffer = Buffer([0xaa; 4 * MemoryMapEntry::SIZE]);
+        let map = MemoryMap::write_into(&mut buffer.0, &entries).unwrap();
+        assert_eq!(map.entries(), entries);
+        assert_eq!(
+            map.entries().as_ptr().cast::<u8>(),
+            buffer.0.as_ptr(),
+            "aligned buffer should be used from its start"
+        );
+
+        // A misaligned buffer loses its first bytes.
+        let map = MemoryMap::write_into(&mut buffer.0[1..], &entries).unwrap();
+        assert_eq!(map.entries(), entries);
+        assert_eq!(map.entries().as_ptr().cast::<u8>(), buffer.0[8..].as_ptr());
+
+        let len = 3 * MemoryMapEntry::SIZE;
+        assert_eq!(
+            MemoryMap::write_into(&mut buffer.0[..len - 1], &entries),
+            Err(BufferTooSmall {
+                needed: len,
+                len: len - 1
+            })
+        );
+        assert_eq!(
+            MemoryMap::write_into(&mut buffer.0[1..=len], &entries),
+            Err(BufferTooSmall {
+                needed: len + 7,
+                len
+            })
+        );
+        assert!(MemoryMap::write_into(&mut [], &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        #[repr(align(8))]
+        struct Buffer([u8; 2 * MemoryMapEntry::SIZE]);
+
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x9f000, T::AvailableRam);
+        let mut buffer = Buffer([0; 2 * MemoryMapEntry::SIZE]);
+        let map = MemoryMap::write_into(&mut buffer.0, &[entry, entry]).unwrap();
+        assert_eq!(map.len(), 2);
+        let len = 2 * MemoryMapEntry::SIZE;
+        assert_eq!(
+            MemoryMap::from_bytes(&buffer.0).unwrap().entries(),
+            [entry, entry]
+        );
+        assert!(MemoryMap::from_bytes(&[]).unwrap().is_empty());
+        assert_eq!(MemoryMap::from_bytes(&buffer.0[..len - 1]), None);
+        assert_eq!(
+            MemoryMap::from_bytes(&buffer.0[1..=MemoryMapEntry::SIZE]),
+            None
+        );
+
+        // Unknown type and non-zero padding.
+        buffer.0[16] = 0xff;
+        assert_eq!(MemoryMap::from_bytes(&buffer.0), None);
+        buffer.0[16..18].copy_from_slice(&(T::AvailableRam as u16).to_le_bytes());
+        assert!(MemoryMap::from_bytes(&buffer.0).is_some());
+        buffer.0[23] = 1;
+        assert_eq!(MemoryMap::from_bytes(&buffer.0), None);
+    }
+
+    #[test]
+    fn test_max_phys_addr() {
+        assert_eq!(MemoryMap::new(&[]).max_phys_addr(None), 0);
+
+        let entries = [
+            MemoryMapEntry::with_default_flags(0x0, 0x9_f000, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(0x10_0000, 0xbff0_0000, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(0xfec0_0000, 0x1000, T::Mmio),
+            MemoryMapEntry::with_default_flags(0xc000_0000, 0x4000_0000, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(0x20_0000, 0x20_0000, T::Kernel),
+            // Malformed entries are ignored.
+            MemoryMapEntry::with_default_flags(0x2_0000_0000, 0, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(u64::MAX, 0x1000, T::Reserved),
+        ];
+        let map = MemoryMap::new(&entries);
+        let max = map.max_phys_addr(Some(T::AvailableRam));
+        assert_eq!(max, 0x1_0000_0000);
+        assert_eq!(map.max_phys_addr(None), 0x1_0000_0000);
+        assert_eq!(map.max_phys_addr(Some(T::Mmio)), 0xfec0_1000);
+        assert_eq!(map.max_phys_addr(Some(T::Firmware)), 0);
+
+        // A frame bitmap for 4 GiB of RAM needs 128 KiB.
+        assert_eq!(max / PAGE_SIZE as u64 / 8, 128 * 1024);
+    }
+
+    #[test]
+    fn test_verify_kernel_regions() {
+        let entries = [
+            MemoryMapEntry::with_default_flags(0x0, 0x20_0000, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(0x40_0000, 0x20_0000, T::Kernel),
+            MemoryMapEntry::with_default_flags(0x20_0000, 0x20_0000, T::Kernel),
+            MemoryMapEntry::with_default_flags(0x60_0000, 0x20_0000, T::LoaderData),
+        ];
+        let map = MemoryMap::new(&entries);
+        assert_eq!(map.len(), 4);
+        let base = PhysAddress(0x20_0000);
+        assert_eq!(verify_kernel_regions(map, base, 0x40_0000), Ok(()));
+        assert_eq!(
+            verify_kernel_regions(map, base, 0x40_0001),
+            Err(ConsistencyError::KernelNotCovered(0x60_0000))
+        );
+        assert_eq!(
+            verify_kernel_regions(map, PhysAddress(0x1f_f000), 0x1000),
+            Err(ConsistencyError::KernelOverlapsAvailableRam(entries[0]))
+        );
+        assert_eq!(
+            verify_kernel_regions(MemoryMap::new(&[]), base, 0x1000),
+            Err(ConsistencyError::KernelNotCovered(0x20_0000))
+        );
+        assert_eq!(
+            verify_kernel_regions(map, PhysAddress(u64::MAX - 0xfff), 0x2000),
+            Err(ConsistencyError::KernelExceedsAddressSpace {
+                base: u64::MAX - 0xfff,
+                len: 0x2000
+            })
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Incremental construction of a memory map.
+
+use crate::{MemoryMap, MemoryMapEntry};
+use alloc::vec::Vec;
+use thiserror::Error;
+
+/// Error of [`MemoryMapBuilder::try_from_iter`] for two overlapping entries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+#[error("memory map entries at {:#x} and {:#x} overlap", .0.from, .1.from)]
+pub struct OverlapError(pub MemoryMapEntry, pub MemoryMapEntry);
+
+/// Builder for a sorted and coalesced list of [`MemoryMapEntry`]s.
+///
+/// Entries are coalesced if they have the same type and flags and are
+/// contiguous. Only [`Self::try_from_iter`] detects overlapping entries.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MemoryMapBuilder {
+    entries: Vec<MemoryMapEntry>,
+}
+
+impl MemoryMapBuilder {
+    /// Creates a new, empty builder.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Appends an entry without sorting or coalescing. This is deferred to
+    /// [`Self::build`].
+    pub fn push(&mut self, entry: MemoryMapEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Inserts an entry at its sorted position and immediately coalesces it
+    /// with its neighbors, if possible.
+    ///
+    /// If only this is used, the entries are always sorted and minimal.
+    pub fn insert_sorted(&mut self, entry: MemoryMapEntry) {
+        let mut index = self.entries.partition_point(|e| e.from < entry.from);
+        self.entries.insert(index, entry);
+        if index > 0 && self.try_merge(index - 1) {
+            index -= 1;
+        }
+        if index + 1 < self.entries.len() {
+            self.try_merge(index);
+        }
+    }
+
+    /// Collects the entries into a builder whose entries are sorted by their
+    /// start address and coalesced, as if [`Self::insert_sorted`] was used
+    /// for each. Empty entries are dropped.
+    ///
+    /// This allows `MemoryMapBuilder::try_from_iter(descriptors.map(to_entry))`.
+    ///
+    /// Fails if two non-empty entries overlap.
+    pub fn try_from_iter(
+        iter: impl IntoIterator<Item = MemoryMapEntry>,
+    ) -> Result<Self, OverlapError> {
+        let mut this = Self {
+            entries: iter.into_iter().filter(|e| e.length > 0).collect(),
+        };
+        this.sort_by_address();
+        // After sorting, an entry that overlaps any later entry also overlaps
+        // its direct successor.
+        if let Some(pair) = this
+            .entries
+            .windows(2)
+            .find(|pair| pair[0].overlaps(&pair[1]))
+        {
+            return Err(OverlapError(pair[0], pair[1]));
+        }
+        this.sort_and_coalesce();
+        Ok(this)
+    }
+
+    /// Returns the entries in their current order.
+    #[must_use]
+    pub fn entries(&self) -> &[MemoryMapEntry] {
+        &self.entries
+    }
+
+    /// Sorts the entries by their start address. See
+    /// [`MemoryMap::sort_by_address`].
+    pub fn sort_by_address(&mut self) {
+        MemoryMap::new_mut(&mut self.entries).sort_by_address();
+    }
+
+    /// Sorts the entries by their start address, coalesces them, and returns
+    /// the result.
+    #[must_use]
+    pub fn build(mut self) -> Vec<MemoryMapEntry> {
+        self.sort_and_coalesce();
+        self.entries
+    }
+
+    /// Sorts the entries by their start address and coalesces them.
+    fn sort_and_coalesce(&mut self) {
+        self.sort_by_address();
+        let mut index = 0;
+        while index + 1 < self.entries.len() {
+            if !self.try_merge(index) {
+                index += 1;
+            }
+        }
+    }
+
+    /// Merges the entry at `index + 1` into the entry at `index` if both have
+    /// the same type and flags and are contiguous.
+    fn try_merge(&mut self, index: usize) -> bool {
+        let (entry, next) = (self.entries[index], self.entries[index + 1]);
+        let mergeable =
+            entry.typ == next.typ && entry.flags == next.flags && entry.to() == Some(next.from);
+        if mergeable {
+            self.entries[index].length += next.length;
+            self.entries.remove(index + 1);
+        }
+        mergeable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryMapEntryType as T;
+
+    fn entry(from: u64, length: u64, typ: T) -> MemoryMapEntry {
+        MemoryMapEntry::with_default_flags(from, length, typ)
+    }
+
+    #[test]
+    fn test_insert_sorted() {
+        let regions = [
+            entry(0x1000, 0x1000, T::AvailableRam),
+            entry(0x2000, 0x2000, T::AvailableRam),
+            entry(0x4000, 0x1000, T::AvailableRam),
+            entry(0x5000, 0x1000, T::Kernel),
+        ];
+        let expected = [
+            entry(0x1000, 0x4000, T::AvailableRam),
+            entry(0x5000, 0x1000, T::Kernel),
+        ];
+
+        let orders = [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1], [1, 3, 0, 2]];
+        for order in orders {
+            let mut builder = MemoryMapBuilder::new();
+            for i in order {
+                builder.insert_sorted(regions[i]);
+            }
+            assert_eq!(builder.entries(), expected, "{order:?}");
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let mut builder = MemoryMapBuilder::new();
+        builder.push(entry(0x4000, 0x1000, T::AvailableRam));
+        builder.push(entry(0x0, 0x1000, T::AvailableRam));
+        builder.push(entry(0x1000, 0x3000, T::AvailableRam));
+        builder.push(entry(0x6000, 0x1000, T::AvailableRam));
+        assert_eq!(
+            builder.build(),
+            [
+                entry(0x0, 0x5000, T::AvailableRam),
+                entry(0x6000, 0x1000, T::AvailableRam)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_try_from_iter() {
+        let builder = MemoryMapBuilder::try_from_iter([
+            entry(0x6000, 0x1000, T::AvailableRam),
+            entry(0x1000, 0x3000, T::AvailableRam),
+            entry(0x8000, 0, T::Reserved),
+            entry(0x5000, 0x1000, T::Kernel),
+            entry(0x0, 0x1000, T::AvailableRam),
+            entry(0x4000, 0x1000, T::AvailableRam),
+        ])
+        .unwrap();
+        assert_eq!(
+            builder.entries(),
+            [
+                entry(0x0, 0x5000, T::AvailableRam),
+                entry(0x5000, 0x1000, T::Kernel),
+                entry(0x6000, 0x1000, T::AvailableRam),
+            ]
+        );
+        assert_eq!(builder.clone().build(), builder.entries());
+    }
+
+    #[test]
+    fn test_try_from_iter_overlap() {
+        // Also if the overlapping entries are not neighbors after sorting.
+        let result = MemoryMapBuilder::try_from_iter([
+            entry(0x3000, 0x1000, T::AvailableRam),
+            entry(0x0, 0x4000, T::Kernel),
+            entry(0x1000, 0x1000, T::AvailableRam),
+        ]);
+        assert_eq!(
+            result,
+            Err(OverlapError(
+                entry(0x0, 0x4000, T::Kernel),
+                entry(0x1000, 0x1000, T::AvailableRam)
+            ))
+        );
+        // Empty entries can't overlap.
+        let result = MemoryMapBuilder::try_from_iter([
+            entry(0x0, 0x4000, T::Kernel),
+            entry(0x1000, 0, T::AvailableRam),
+        ]);
+        assert!(result.is_ok());
+    }
+}
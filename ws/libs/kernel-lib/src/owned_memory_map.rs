@@ -0,0 +1,151 @@
+//! Owned, growable memory map.
+
+use crate::{MemoryMap, MemoryMapEntry};
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+/// Owned memory map backed by a [`Vec`].
+///
+/// This is for code that builds a memory map, such as the loader. It derefs
+/// to the borrowed [`MemoryMap`] view, which the kernel uses without
+/// allocating. Use [`Self::as_bytes`] to serialize it and
+/// [`MemoryMap::from_bytes`] to get the view on the serialized form.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct OwnedMemoryMap(Vec<MemoryMapEntry>);
+
+impl OwnedMemoryMap {
+    /// Creates a new, empty memory map.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Appends an entry.
+    pub fn push(&mut self, entry: MemoryMapEntry) {
+        self.0.push(entry);
+    }
+
+    /// Returns the number of entries.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the memory map has no entries.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the in-memory representation of the entries, e.g., to copy
+    /// them into the buffer of the boot information.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: The entries are `repr(C)` without implicit padding, and all
+        // constructors zero the explicit padding, so all bytes are
+        // initialized.
+        unsafe { core::slice::from_raw_parts(self.0.as_ptr().cast(), size_of_val(&*self.0)) }
+    }
+}
+
+impl From<Vec<MemoryMapEntry>> for OwnedMemoryMap {
+    fn from(entries: Vec<MemoryMapEntry>) -> Self {
+        Self(entries)
+    }
+}
+
+impl FromIterator<MemoryMapEntry> for OwnedMemoryMap {
+    fn from_iter<I: IntoIterator<Item = MemoryMapEntry>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Deref for OwnedMemoryMap {
+    type Target = MemoryMap;
+
+    fn deref(&self) -> &Self::Target {
+        MemoryMap::new(&self.0)
+    }
+}
+
+impl DerefMut for OwnedMemoryMap {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        MemoryMap::new_mut(&mut self.0)
+    }
+}
+
+impl IntoIterator for OwnedMemoryMap {
+    type Item = MemoryMapEntry;
+    type IntoIter = alloc::vec::IntoIter<MemoryMapEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a OwnedMemoryMap {
+    type Item = &'a MemoryMapEntry;
+    type IntoIter = core::slice::Iter<'a, MemoryMapEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryMapEntryType as T;
+
+    #[test]
+    fn test_owned_memory_map() {
+        let mut map = OwnedMemoryMap::new();
+        assert!(map.is_empty());
+        assert!(map.as_bytes().is_empty());
+        map.push(MemoryMapEntry::with_default_flags(
+            0x20_0000,
+            0x20_0000,
+            T::Kernel,
+        ));
+        map.push(MemoryMapEntry::with_default_flags(
+            0x1000,
+            0x9f000,
+            T::AvailableRam,
+        ));
+        assert_eq!(map.len(), 2);
+
+        // The borrowed view on the owned map.
+        map.sort_by_address();
+        assert_eq!(map.entries()[0].from, 0x1000);
+        assert_eq!(map.max_phys_addr(None), 0x40_0000);
+
+        let bytes = map.as_bytes();
+        assert_eq!(bytes.len(), 2 * MemoryMapEntry::SIZE);
+        let parsed = MemoryMap::from_bytes(bytes).unwrap();
+        assert_eq!(parsed, &*map);
+
+        let entries = map.clone().into_iter().collect::<Vec<_>>();
+        assert_eq!(entries, map.entries());
+        assert_eq!(OwnedMemoryMap::from(entries), map);
+        assert_eq!((&map).into_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_serialize_into_buffer() {
+        let map = [
+            MemoryMapEntry::with_default_flags(0x1000, 0x9f000, T::AvailableRam),
+            MemoryMapEntry::with_default_flags(0xfee0_0000, 0x1000, T::Mmio),
+        ]
+        .into_iter()
+        .collect::<OwnedMemoryMap>();
+
+        // An 8-byte aligned buffer, as in the boot information.
+        #[repr(align(8))]
+        struct Buffer([u8; 4 * MemoryMapEntry::SIZE]);
+        let mut buffer = Buffer([0xaa; 4 * MemoryMapEntry::SIZE]);
+        let buffer_bytes = &mut buffer.0[..map.as_bytes().len()];
+        buffer_bytes.copy_from_slice(map.as_bytes());
+        let parsed = MemoryMap::from_bytes(buffer_bytes).unwrap();
+        assert_eq!(parsed.entries(), map.entries());
+    }
+}
@@ -1,8 +1,9 @@
-use loader_lib::KernelFile;
+use loader_lib::{KernelFile, SymbolError};
 use log::{LevelFilter, Log, Metadata, Record};
 use std::io::stdout;
 use std::{fmt, fs, io};
-use util::logging::fmt_and_write_msg;
+use util::logging::{LogFormat, fmt_and_write_msg};
+use util::paging::VirtAddress;
 
 struct IoToFmt<W: io::Write>(W);
 
@@ -29,7 +30,7 @@ impl Log for Logger {
     fn log(&self, record: &Record) {
         let stdout = stdout();
         let mut stdout = IoToFmt(stdout);
-        fmt_and_write_msg(&mut stdout, record).unwrap();
+        fmt_and_write_msg(&mut stdout, record, LogFormat::Full).unwrap();
     }
 
     fn flush(&self) {}
@@ -37,6 +38,9 @@ impl Log for Logger {
 
 /// Performs the kernel ELF checks on the file that was provided as first
 /// argument.
+///
+/// All further arguments are interpreted as virtual addresses (hex) for which
+/// the containing LOAD segment is printed.
 fn main() {
     log::set_logger(&Logger).unwrap();
     log::set_max_level(LevelFilter::Trace);
@@ -44,15 +48,39 @@ fn main() {
     let elf_path = std::env::args().nth(1).unwrap();
     let elf_bytes = fs::read(elf_path).unwrap();
 
+    // Printed before the other checks, as misaligned segments are rejected.
+    if let Ok(alignments) = KernelFile::alignment_of_segments(&elf_bytes) {
+        for alignment in alignments {
+            println!("ALIGNMENT: {alignment}");
+        }
+    }
+
     // This either returns success or panics.
     let kernel = KernelFile::from_bytes(&elf_bytes).unwrap();
 
-    for (pr_hdr, data) in kernel.segments() {
-        println!(
-            "SEGMENT: type={}, flags={:#x} payload_len={}",
-            pr_hdr.p_type,
-            pr_hdr.p_flags,
-            data.len()
-        );
+    for pr_hdr in kernel.program_headers() {
+        println!("SEGMENT: {pr_hdr}");
+    }
+    for hash in kernel.segment_hashes() {
+        println!("HASH: segment_hash = {hash}");
+    }
+    for name in KernelFile::WRITABLE_SYMBOLS {
+        match kernel.check_symbol_writable(name) {
+            Ok(()) => println!("WRITABLE: {name}"),
+            // E.g., stripped kernels or the heap with the `bump-heap` feature.
+            Err(e @ SymbolError::NotFound(_)) => println!("WRITABLE: skipped: {e}"),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    for addr in std::env::args().skip(2) {
+        let vaddr = u64::from_str_radix(addr.trim_start_matches("0x"), 16).unwrap();
+        match kernel.segment_containing(VirtAddress(vaddr)) {
+            Some(pr_hdr) => println!(
+                "ADDRESS: {vaddr:#x} in segment vaddr={:#x}, flags={:#x}",
+                pr_hdr.p_vaddr, pr_hdr.p_flags
+            ),
+            None => println!("ADDRESS: {vaddr:#x} not in any LOAD segment"),
+        }
     }
 }
@@ -0,0 +1,212 @@
+//! Creation of the boot information handed over to the kernel.
+//!
+//! The types of the loader-kernel contract are defined once in [`kernel_lib`];
+//! the tests in this module verify that the kernel reads exactly what the
+//! loader writes.
+
+use crate::{Config, PageTablePool};
+use kernel_lib::{BootInformation, BufferTooSmall, MemoryMap, MemoryMapEntry};
+use util::logging::BackendKind;
+use util::paging::{PAGE_SIZE, Page};
+
+/// Creates the boot information for the given configuration and the pool
+/// holding the kernel's initial page tables.
+///
+/// The loader can't detect whether the display is in legacy VGA text mode.
+/// Hence, the boot information only reports it if the `vga_text` log backend
+/// is configured.
+#[must_use]
+pub fn create_boot_information(config: &Config, page_tables: &PageTablePool) -> BootInformation {
+    let (base, size) = page_tables.region();
+    let vga_text_mode = config
+        .log_backends
+        .iter()
+        .any(|name| BackendKind::from_name(name) == Some(BackendKind::VgaText));
+    BootInformation::new()
+        .with_hhdm_offset(config.hhdm_offset())
+        .with_page_tables(base, size as u64)
+        .with_vga_text_mode(vga_text_mode)
+}
+
+/// Writes the memory map for the kernel to `buffer`.
+///
+/// The map starts with the entry of the kernel's memory (see
+/// [`crate::PageTableSetup::kernel_memory_map_entry`]), followed by
+/// `entries`, e.g., converted from the final UEFI memory map.
+///
+/// The kernel's memory is also covered by a loader entry of the UEFI memory
+/// map. The overlap is harmless, as the kernel only hands out
+/// [`kernel_lib::MemoryMapEntryType::AvailableRam`] memory.
+///
+/// This doesn't allocate, so that it can be used after exiting the UEFI boot
+/// services. If `buffer` is not aligned for [`MemoryMapEntry`], the map starts
+/// at the first aligned byte.
+pub fn write_memory_map(
+    buffer: &mut [u8],
+    kernel: MemoryMapEntry,
+    entries: impl IntoIterator<Item = MemoryMapEntry>,
+) -> Result<&MemoryMap, BufferTooSmall> {
+    let offset = buffer.as_ptr().align_offset(align_of::<MemoryMapEntry>());
+    let capacity = buffer.len().saturating_sub(offset) / MemoryMapEntry::SIZE;
+    let dst = buffer
+        .as_mut_ptr()
+        .wrapping_add(offset)
+        .cast::<MemoryMapEntry>();
+    let mut len = 0;
+    for entry in core::iter::once(kernel).chain(entries) {
+        if len < capacity {
+            // SAFETY: The entry is within the aligned part of the buffer.
+            unsafe { dst.add(len).write(entry) };
+        }
+        len += 1;
+    }
+    if len > capacity {
+        return Err(BufferTooSmall {
+            needed: offset + len * MemoryMapEntry::SIZE,
+            len: buffer.len(),
+        });
+    }
+    // SAFETY: The first `len` entries were just written, and the buffer is
+    // borrowed for the lifetime of the view.
+    let entries = unsafe { core::slice::from_raw_parts(dst, len) };
+    Ok(MemoryMap::new(entries))
+}
+
+/// Writes the boot information to the beginning of `page`, which is mapped
+/// into the address space of the kernel at [`kernel_lib::BOOT_INFO_VADDR`].
+pub fn write_boot_information(page: &mut Page, boot_info: BootInformation) -> &BootInformation {
+    const { assert!(size_of::<BootInformation>() <= PAGE_SIZE) };
+    let ptr = page.as_ptr_mut().cast::<BootInformation>();
+    // SAFETY: The page is large enough and page-aligned.
+    unsafe {
+        ptr.write(boot_info);
+        &*ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel_lib::{DirectMap, MemoryMapEntryType};
+    use util::paging::PhysAddress;
+
+    #[test]
+    fn test_kernel_reads_what_loader_writes() {
+        let config = Config::parse("hhdm_offset = 0xffff_c000_0000_0000").unwrap();
+        let mut page = Box::new(Page::ZERO);
+        let pool = PageTablePool::new(1);
+        let written =
+            write_boot_information(&mut page, create_boot_information(&config, &pool)).clone();
+
+        // This is what the kernel does with the raw bytes at BOOT_INFO_VADDR.
+        let read = <&BootInformation>::try_from(&page.0[..]).unwrap();
+        assert_eq!(read, &written);
+        assert_eq!(read.hhdm_offset(), 0xffff_c000_0000_0000);
+        assert_eq!(read.page_tables(), (pool.region().0, PAGE_SIZE as u64));
+        assert!(!read.vga_text_mode());
+    }
+
+    #[test]
+    fn test_vga_text_mode() {
+        let pool = PageTablePool::new(1);
+        let config = Config::parse("log_backends = debugcon, vga_text").unwrap();
+        assert!(create_boot_information(&config, &pool).vga_text_mode());
+        let config = Config::parse("log_backends = debugcon").unwrap();
+        assert!(!create_boot_information(&config, &pool).vga_text_mode());
+    }
+
+    #[test]
+    fn test_page_tables_contain_cr3() {
+        let bytes = crate::test_utils::kernel_fixture().build();
+        let kernel = crate::KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(Page::ZERO);
+        let boot_info = Box::new(Page::ZERO);
+        let mut pool = PageTablePool::new(16);
+        let setup = crate::setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            util::paging::PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            usize::MAX,
+            None,
+            crate::SegmentPlacement::Aligned,
+            &mut pool,
+        )
+        .unwrap();
+
+        let boot_info = create_boot_information(&Config::default(), &pool);
+        let (base, size) = boot_info.page_tables();
+        assert_eq!(size, 16 * PAGE_SIZE as u64);
+        // The kernel can reclaim the region only if all tables are in it.
+        assert!((base.0..base.0 + size).contains(&setup.cr3.0));
+        assert!(setup.stats.tables() <= pool.len());
+    }
+
+    #[test]
+    fn test_memory_map_contains_kernel() {
+        let bytes = crate::test_utils::kernel_fixture().build();
+        let kernel = crate::KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(Page::ZERO);
+        let boot_info_page = Box::new(Page::ZERO);
+        let setup = crate::setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info_page.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            usize::MAX,
+            None,
+            crate::SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        )
+        .unwrap();
+        let kernel_entry = setup.kernel_memory_map_entry();
+        let firmware = [
+            MemoryMapEntry::with_default_flags(0x0, 0x8_0000, MemoryMapEntryType::AvailableRam),
+            MemoryMapEntry::with_default_flags(
+                kernel_entry.from,
+                kernel_entry.length,
+                MemoryMapEntryType::LoaderData,
+            ),
+        ];
+
+        let mut buffer = Box::new(Page::ZERO);
+        let map = write_memory_map(&mut buffer.0, kernel_entry, firmware).unwrap();
+        let (base, len) = (PhysAddress(map.entries().as_ptr() as u64), map.len());
+        let boot_info = BootInformation::new().with_memory_map(base, len);
+
+        // In the tests, physical addresses are the host's virtual addresses.
+        // SAFETY: The buffer outlives the view and is not modified.
+        let map = unsafe { boot_info.memory_map(&DirectMap::new(0)) }.unwrap();
+        assert_eq!(map.len(), 3);
+        assert!(map.iter().any(|entry| *entry == kernel_entry));
+        assert_eq!(
+            kernel_lib::verify_kernel_regions(map, setup.kernel_phys_base, setup.kernel_phys_len),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_write_memory_map_buffer_too_small() {
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x1000, MemoryMapEntryType::Kernel);
+        let mut buffer = Box::new(Page::ZERO);
+        let buffer = &mut buffer.0[..2 * MemoryMapEntry::SIZE];
+        assert_eq!(
+            write_memory_map(buffer, entry, [entry, entry]),
+            Err(BufferTooSmall {
+                needed: 3 * MemoryMapEntry::SIZE,
+                len: 2 * MemoryMapEntry::SIZE,
+            })
+        );
+        assert_eq!(write_memory_map(buffer, entry, [entry]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_memory_map_entry_round_trip() {
+        let entry = MemoryMapEntry::with_default_flags(0x1000, 0x2000, MemoryMapEntryType::Kernel);
+        // SAFETY: The entry consists of plain data without padding bytes.
+        let bytes = unsafe { &*core::ptr::from_ref(&entry).cast::<[u8; MemoryMapEntry::SIZE]>() };
+        assert_eq!(MemoryMapEntry::from_le_bytes(bytes), Some(entry));
+    }
+}
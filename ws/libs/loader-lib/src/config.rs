@@ -0,0 +1,475 @@
+//! Configuration of the loader.
+//!
+//! The configuration file is a simple text file with one `key = value` pair
+//! per line. Empty lines and lines starting with `#` are ignored. Numbers can
+//! be specified in decimal or, with a `0x` prefix, in hexadecimal notation.
+//!
+//! Example:
+//! ```text
+//! # Version of the configuration schema. Defaults to `1`.
+//! config_version = 3
+//! # Base of the direct map of physical memory.
+//! hhdm_offset = 0xffff800000000000
+//! # Optional physical base address for the kernel.
+//! kernel_phys_base = 0x1000000
+//! # Optional hashes of the LOAD segments: virtual address and FNV-1a hash.
+//! # If any is given, the kernel is only loaded if all segments match.
+//! segment_hash = 0xffffffff88200000 0x1c5bcae0bd3b9a93
+//! # Comma-separated list of the log backends. Defaults to `debugcon`.
+//! log_backends = debugcon, stdout
+//! # Physical placement of the LOAD segments: `aligned` (default) or `packed`.
+//! segment_placement = packed
+//! # Seconds to wait before booting the kernel, e.g., to read the log.
+//! boot_delay_secs = 5
+//! ```
+//!
+//! The schema versions are:
+//! - `1`: `hhdm_offset` and `kernel_phys_base`
+//! - `2`: adds `segment_hash` and `log_backends`
+//! - `3`: adds `segment_placement` and `boot_delay_secs`
+//!
+//! Keys added by later versions are optional, so older configurations load
+//! with their defaults. Configurations of newer versions are rejected, as
+//! they may rely on semantics the loader doesn't know.
+
+use crate::{SegmentHash, SegmentPlacement};
+use thiserror::Error;
+use util::paging::VirtAddress;
+use util::sizes::{ONE_GIB, TWO_MIB};
+
+/// Possible errors when parsing or validating a [`Config`].
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum ConfigError {
+    /// A line is not a `key = value` pair.
+    #[error("line {0} is not a `key = value` pair")]
+    InvalidLine(usize),
+    /// The key is not known.
+    #[error("unknown key `{0}`")]
+    UnknownKey(String),
+    /// The value of a key can't be parsed.
+    #[error("invalid value `{value}` for key `{key}`")]
+    InvalidValue {
+        /// The key.
+        key: String,
+        /// The value that couldn't be parsed.
+        value: String,
+    },
+    /// The HHDM offset is not a canonical address.
+    #[error("hhdm_offset {0:#x} is not canonical")]
+    HhdmOffsetNotCanonical(u64),
+    /// The HHDM offset is canonical but in the lower half, which the loader
+    /// uses for identity mappings during the handoff.
+    #[error("hhdm_offset {0:#x} is not in the higher half")]
+    HhdmOffsetNotHigherHalf(u64),
+    /// The HHDM offset is not 1 GiB aligned.
+    #[error("hhdm_offset {0:#x} is not 1 GiB aligned")]
+    HhdmOffsetNotAligned(u64),
+    /// The configuration uses a newer schema than the loader supports.
+    #[error("config_version {version} is not supported (newest is {supported})")]
+    UnsupportedVersion {
+        /// The version of the configuration.
+        version: u32,
+        /// The newest version the loader supports.
+        supported: u32,
+    },
+    /// The physical base of the kernel is not 2 MiB aligned.
+    #[error("kernel_phys_base {0:#x} is not 2 MiB aligned")]
+    KernelPhysBaseNotAligned(u64),
+}
+
+/// Configuration of the loader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Version of the configuration schema.
+    ///
+    /// Use [`Self::config_version`] to get the effective value.
+    pub config_version: Option<u32>,
+    /// Virtual base address of the higher-half direct map (HHDM) of physical
+    /// memory.
+    ///
+    /// Use [`Self::hhdm_offset`] to get the effective value.
+    pub hhdm_offset: Option<u64>,
+    /// Preferred physical base address of the kernel.
+    ///
+    /// If set, the loader tries to load the kernel at this address and falls
+    /// back to any suitable address if the memory is not available.
+    pub kernel_phys_base: Option<u64>,
+    /// Expected hashes of the LOAD segments of the kernel.
+    ///
+    /// If not empty, the loader refuses to boot a kernel whose segments don't
+    /// match, see [`crate::KernelFile::verify_segments`].
+    pub segment_hashes: Vec<SegmentHash>,
+    /// Names of the log backends to use, such as `debugcon` or `stdout`.
+    ///
+    /// The names are not validated here; the loader ignores unknown names
+    /// with a warning. `vga_text` is not supported by the loader itself, but
+    /// tells the kernel that the display is in legacy VGA text mode. Defaults
+    /// to [`Self::DEFAULT_LOG_BACKENDS`]. An empty list is rejected, as it
+    /// would silently disable all logging.
+    pub log_backends: Vec<String>,
+    /// Placement of the kernel's LOAD segments in physical memory.
+    pub segment_placement: SegmentPlacement,
+    /// Seconds the loader waits before it exits the boot services, e.g., to
+    /// read the log or to attach a debugger. `0` disables the delay.
+    pub boot_delay_secs: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            config_version: None,
+            hhdm_offset: None,
+            kernel_phys_base: None,
+            segment_hashes: Vec::new(),
+            log_backends: Self::DEFAULT_LOG_BACKENDS
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            segment_placement: SegmentPlacement::default(),
+            boot_delay_secs: 0,
+        }
+    }
+}
+
+impl Config {
+    /// The newest schema version the loader supports.
+    pub const CONFIG_VERSION: u32 = 3;
+
+    /// The schema version of configurations that don't specify one.
+    pub const DEFAULT_CONFIG_VERSION: u32 = 1;
+
+    /// The default virtual base address of the direct map: the begin of the
+    /// higher half.
+    pub const DEFAULT_HHDM_OFFSET: u64 = 0xffff_8000_0000_0000;
+
+    /// The log backends used if the configuration doesn't specify any.
+    pub const DEFAULT_LOG_BACKENDS: &[&str] = &["debugcon"];
+
+    /// Parses and validates the configuration.
+    ///
+    /// If the configuration uses a newer schema version, this fails with
+    /// [`ConfigError::UnsupportedVersion`] rather than with an error about
+    /// a key the loader doesn't know yet.
+    pub fn parse(config: &str) -> Result<Self, ConfigError> {
+        let mut this = Self::default();
+        let mut unknown_key = None;
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(ConfigError::InvalidLine(i + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "config_version" => this.config_version = Some(parse_u32(key, value)?),
+                "hhdm_offset" => this.hhdm_offset = Some(parse_u64(key, value)?),
+                "kernel_phys_base" => this.kernel_phys_base = Some(parse_u64(key, value)?),
+                "segment_hash" => this.segment_hashes.push(parse_segment_hash(key, value)?),
+                "log_backends" => this.log_backends = parse_backends(key, value)?,
+                "boot_delay_secs" => this.boot_delay_secs = parse_u32(key, value)?,
+                "segment_placement" => {
+                    this.segment_placement =
+                        SegmentPlacement::from_name(value).ok_or_else(|| {
+                            ConfigError::InvalidValue {
+                                key: key.to_string(),
+                                value: value.to_string(),
+                            }
+                        })?;
+                }
+                _ => {
+                    unknown_key.get_or_insert_with(|| ConfigError::UnknownKey(key.to_string()));
+                }
+            }
+        }
+        this.validate_version()?;
+        if let Some(e) = unknown_key {
+            return Err(e);
+        }
+        this.validate()?;
+        Ok(this)
+    }
+
+    /// Validates the configuration.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_version()?;
+        let hhdm_offset = self.hhdm_offset();
+        if !VirtAddress(hhdm_offset).is_canonical() {
+            return Err(ConfigError::HhdmOffsetNotCanonical(hhdm_offset));
+        }
+        if hhdm_offset & (1 << 63) == 0 {
+            return Err(ConfigError::HhdmOffsetNotHigherHalf(hhdm_offset));
+        }
+        if !hhdm_offset.is_multiple_of(ONE_GIB as u64) {
+            return Err(ConfigError::HhdmOffsetNotAligned(hhdm_offset));
+        }
+        if let Some(base) = self.kernel_phys_base
+            && !base.is_multiple_of(TWO_MIB as u64)
+        {
+            return Err(ConfigError::KernelPhysBaseNotAligned(base));
+        }
+        Ok(())
+    }
+
+    fn validate_version(&self) -> Result<(), ConfigError> {
+        let version = self.config_version();
+        if version > Self::CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion {
+                version,
+                supported: Self::CONFIG_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the effective schema version.
+    #[must_use]
+    pub fn config_version(&self) -> u32 {
+        self.config_version.unwrap_or(Self::DEFAULT_CONFIG_VERSION)
+    }
+
+    /// Returns the messages of the countdown of the boot delay, one per
+    /// second, e.g., `"Booting kernel in 2 s"`.
+    ///
+    /// This is empty if [`Self::boot_delay_secs`] is `0`.
+    pub fn boot_delay_countdown(&self) -> impl Iterator<Item = String> {
+        (1..=self.boot_delay_secs)
+            .rev()
+            .map(|secs| format!("Booting kernel in {secs} s"))
+    }
+
+    /// Returns the effective virtual base address of the direct map.
+    #[must_use]
+    pub fn hhdm_offset(&self) -> u64 {
+        self.hhdm_offset.unwrap_or(Self::DEFAULT_HHDM_OFFSET)
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+fn parse_u64(key: &str, value: &str) -> Result<u64, ConfigError> {
+    let digits = value.replace('_', "");
+    let res = digits
+        .strip_prefix("0x")
+        .map_or_else(|| digits.parse(), |hex| u64::from_str_radix(hex, 16));
+    res.map_err(|_| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Parses the non-empty list of log backends.
+fn parse_backends(key: &str, value: &str) -> Result<Vec<String>, ConfigError> {
+    let backends = parse_list(value);
+    if backends.is_empty() {
+        return Err(ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+    Ok(backends)
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal 32-bit number.
+fn parse_u32(key: &str, value: &str) -> Result<u32, ConfigError> {
+    u32::try_from(parse_u64(key, value)?).map_err(|_| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Parses a comma-separated list. Empty elements are skipped.
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Parses a virtual address and a hash separated by whitespace.
+fn parse_segment_hash(key: &str, value: &str) -> Result<SegmentHash, ConfigError> {
+    let invalid = || ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    };
+    let (vaddr, hash) = value.split_once(char::is_whitespace).ok_or_else(invalid)?;
+    Ok(SegmentHash {
+        vaddr: VirtAddress(parse_u64(key, vaddr)?),
+        hash: parse_u64(key, hash.trim())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty() {
+        let config = Config::parse("\n# comment\n").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.hhdm_offset(), Config::DEFAULT_HHDM_OFFSET);
+    }
+
+    #[test]
+    fn test_parse_hhdm_offset() {
+        let config = Config::parse("hhdm_offset = 0xffff_c000_0000_0000").unwrap();
+        assert_eq!(config.hhdm_offset(), 0xffff_c000_0000_0000);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Config::parse("foo"), Err(ConfigError::InvalidLine(1)));
+        assert_eq!(
+            Config::parse("foo = 1"),
+            Err(ConfigError::UnknownKey("foo".to_string()))
+        );
+        assert_eq!(
+            Config::parse("hhdm_offset = xyz"),
+            Err(ConfigError::InvalidValue {
+                key: "hhdm_offset".to_string(),
+                value: "xyz".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_hhdm_offset() {
+        assert_eq!(
+            Config::parse("hhdm_offset = 0x0000_8000_0000_0000"),
+            Err(ConfigError::HhdmOffsetNotCanonical(0x0000_8000_0000_0000))
+        );
+        for offset in [0, 0x4000_0000, 0x0000_7fff_c000_0000] {
+            assert_eq!(
+                Config::parse(&format!("hhdm_offset = {offset:#x}")),
+                Err(ConfigError::HhdmOffsetNotHigherHalf(offset))
+            );
+        }
+        assert_eq!(
+            Config::parse("hhdm_offset = 0xffff_8000_0020_0000"),
+            Err(ConfigError::HhdmOffsetNotAligned(0xffff_8000_0020_0000))
+        );
+    }
+
+    #[test]
+    fn test_parse_kernel_phys_base() {
+        let config = Config::parse("kernel_phys_base = 0x100_0000").unwrap();
+        assert_eq!(config.kernel_phys_base, Some(0x100_0000));
+        assert_eq!(
+            Config::parse("kernel_phys_base = 0x10_1000"),
+            Err(ConfigError::KernelPhysBaseNotAligned(0x10_1000))
+        );
+    }
+
+    #[test]
+    fn test_parse_segment_hash() {
+        let config = Config::parse(
+            "segment_hash = 0xffffffff88200000 0x1234\nsegment_hash = 0xffffffff88400000   42",
+        )
+        .unwrap();
+        assert_eq!(
+            config.segment_hashes,
+            [
+                SegmentHash {
+                    vaddr: VirtAddress(0xffff_ffff_8820_0000),
+                    hash: 0x1234
+                },
+                SegmentHash {
+                    vaddr: VirtAddress(0xffff_ffff_8840_0000),
+                    hash: 42
+                }
+            ]
+        );
+        assert!(matches!(
+            Config::parse("segment_hash = 0x1000"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_config_version() {
+        assert_eq!(Config::default().config_version(), 1);
+
+        // A version 1 configuration gets the defaults of later keys.
+        let config =
+            Config::parse("config_version = 1\nhhdm_offset = 0xffff_c000_0000_0000").unwrap();
+        assert_eq!(config.config_version(), 1);
+        assert_eq!(config.hhdm_offset(), 0xffff_c000_0000_0000);
+        assert!(config.segment_hashes.is_empty());
+        assert_eq!(config.log_backends, Config::DEFAULT_LOG_BACKENDS);
+
+        let config = Config::parse("config_version = 2").unwrap();
+        assert_eq!(config.config_version(), 2);
+        assert_eq!(config.segment_placement, SegmentPlacement::Aligned);
+        let config = Config::parse("config_version = 3").unwrap();
+        assert_eq!(config.config_version(), Config::CONFIG_VERSION);
+
+        let unsupported = Err(ConfigError::UnsupportedVersion {
+            version: 99,
+            supported: Config::CONFIG_VERSION,
+        });
+        assert_eq!(Config::parse("config_version = 99"), unsupported);
+        // Unknown keys of the newer version don't hide the actual problem.
+        assert_eq!(
+            Config::parse("new_key = 1\nconfig_version = 99"),
+            unsupported
+        );
+        assert!(matches!(
+            Config::parse("config_version = 0x1_0000_0000"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_log_backends() {
+        assert_eq!(Config::default().log_backends, ["debugcon"]);
+        let config = Config::parse("log_backends = stdout,  serial ,").unwrap();
+        assert_eq!(config.log_backends, ["stdout", "serial"]);
+        for value in ["", " , "] {
+            assert_eq!(
+                Config::parse(&format!("log_backends = {value}")),
+                Err(ConfigError::InvalidValue {
+                    key: "log_backends".to_string(),
+                    value: value.trim().to_string(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_segment_placement() {
+        assert_eq!(
+            Config::default().segment_placement,
+            SegmentPlacement::Aligned
+        );
+        let config = Config::parse("segment_placement = packed").unwrap();
+        assert_eq!(config.segment_placement, SegmentPlacement::Packed);
+        assert_eq!(
+            Config::parse("segment_placement = tight"),
+            Err(ConfigError::InvalidValue {
+                key: "segment_placement".to_string(),
+                value: "tight".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_boot_delay_secs() {
+        assert_eq!(Config::default().boot_delay_secs, 0);
+        assert_eq!(Config::default().boot_delay_countdown().count(), 0);
+
+        let config = Config::parse("boot_delay_secs = 3").unwrap();
+        assert_eq!(config.boot_delay_secs, 3);
+        assert!(config.boot_delay_countdown().eq([
+            "Booting kernel in 3 s",
+            "Booting kernel in 2 s",
+            "Booting kernel in 1 s"
+        ]));
+        assert!(matches!(
+            Config::parse("boot_delay_secs = -1"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+}
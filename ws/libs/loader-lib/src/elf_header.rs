@@ -0,0 +1,131 @@
+//! Minimal validation of ELF64 headers, independent of the [`elf`] crate.
+//!
+//! This is a fast sanity check with clear errors for obviously wrong files
+//! before the actual parsing happens.
+
+use thiserror::Error;
+
+/// Size of the ELF64 file header.
+pub const ELF64_HEADER_SIZE: usize = 64;
+
+const MAGIC: [u8; 4] = *b"\x7fELF";
+const OFFSET_CLASS: usize = 4;
+const OFFSET_DATA: usize = 5;
+const OFFSET_TYPE: usize = 16;
+const OFFSET_MACHINE: usize = 18;
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const TYPE_DYN: u16 = 3;
+const MACHINE_X86_64: u16 = 62;
+
+/// Possible errors of [`validate_elf_header`].
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum HeaderError {
+    /// The file is smaller than an ELF64 header.
+    #[error("file is too small for an ELF64 header ({0} bytes)")]
+    Truncated(usize),
+    /// The file doesn't start with the ELF magic.
+    #[error("file has no ELF magic")]
+    InvalidMagic,
+    /// The file is not a 64-bit ELF.
+    #[error("ELF is not 64-bit (class={0})")]
+    Not64Bit(u8),
+    /// The file is not little-endian.
+    #[error("ELF is not little-endian (data={0})")]
+    NotLittleEndian(u8),
+    /// The file is not for x86_64.
+    #[error("ELF is not for x86_64 (machine={0})")]
+    NotX86_64(u16),
+    /// The file is neither an executable nor a shared object.
+    #[error("ELF is neither EXEC nor DYN (type={0})")]
+    NotExecutable(u16),
+}
+
+const fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Checks that `bytes` start with the header of a little-endian ELF64
+/// executable (`EXEC` or `DYN`) for x86_64.
+pub fn validate_elf_header(bytes: &[u8]) -> Result<(), HeaderError> {
+    if bytes.len() < ELF64_HEADER_SIZE {
+        return Err(HeaderError::Truncated(bytes.len()));
+    }
+    if bytes[..MAGIC.len()] != MAGIC {
+        return Err(HeaderError::InvalidMagic);
+    }
+
+    let class = bytes[OFFSET_CLASS];
+    if class != CLASS_64 {
+        return Err(HeaderError::Not64Bit(class));
+    }
+
+    let data = bytes[OFFSET_DATA];
+    if data != DATA_LITTLE_ENDIAN {
+        return Err(HeaderError::NotLittleEndian(data));
+    }
+
+    let machine = read_u16(bytes, OFFSET_MACHINE);
+    if machine != MACHINE_X86_64 {
+        return Err(HeaderError::NotX86_64(machine));
+    }
+
+    let typ = read_u16(bytes, OFFSET_TYPE);
+    if typ != TYPE_EXEC && typ != TYPE_DYN {
+        return Err(HeaderError::NotExecutable(typ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::kernel_fixture;
+
+    #[test]
+    fn test_valid() {
+        let bytes = kernel_fixture().build();
+        assert_eq!(validate_elf_header(&bytes), Ok(()));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = kernel_fixture().build();
+        assert_eq!(
+            validate_elf_header(&bytes[..63]),
+            Err(HeaderError::Truncated(63))
+        );
+        assert_eq!(validate_elf_header(&[]), Err(HeaderError::Truncated(0)));
+    }
+
+    #[test]
+    fn test_wrong_machine() {
+        let mut elf = kernel_fixture();
+        elf.e_machine = elf::abi::EM_AARCH64;
+        let bytes = elf.build();
+        assert_eq!(
+            validate_elf_header(&bytes),
+            Err(HeaderError::NotX86_64(elf::abi::EM_AARCH64))
+        );
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut elf = kernel_fixture();
+        elf.e_type = elf::abi::ET_REL;
+        let bytes = elf.build();
+        assert_eq!(
+            validate_elf_header(&bytes),
+            Err(HeaderError::NotExecutable(elf::abi::ET_REL))
+        );
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        let mut bytes = kernel_fixture().build();
+        bytes[0] = 0;
+        assert_eq!(validate_elf_header(&bytes), Err(HeaderError::InvalidMagic));
+    }
+}
@@ -0,0 +1,49 @@
+//! Formatting of errors together with their sources.
+
+use core::error::Error;
+use core::fmt::{self, Display, Formatter};
+
+/// Displays an error followed by each error in its [`Error::source`] chain,
+/// one per line.
+///
+/// The default display of most errors only shows the outermost message, so
+/// the actual reason of a failure, e.g., a [`crate::KernelFileError`] wrapped
+/// by some context, gets lost in the log.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorChain<'a>(pub &'a (dyn Error + 'static));
+
+impl Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(error) = source {
+            write!(f, "\n  caused by: {error}")?;
+            source = error.source();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SetupError;
+    use util::paging::{AlignError, PhysAddress};
+
+    #[test]
+    fn test_error_chain() {
+        let error = SetupError::BootInfoMisaligned(AlignError {
+            addr: PhysAddress(0x1234),
+            align: 0x1000,
+        });
+        assert_eq!(
+            ErrorChain(&error).to_string(),
+            format!(
+                "boot information is misaligned\n  caused by: {}",
+                error.source().unwrap()
+            )
+        );
+        let error = SetupError::KernelDestinationTooSmall { len: 1, needed: 2 };
+        assert_eq!(ErrorChain(&error).to_string(), error.to_string());
+    }
+}
@@ -1,27 +1,293 @@
 //! Abstraction over the ELF file of the kernel.
 
-use core::slice;
+use crate::elf_header::{HeaderError, validate_elf_header};
+use core::fmt::{self, Display, Formatter};
+use core::ops::Range;
 use elf::ElfBytes;
-use elf::abi::{PF_R, PF_W, PF_X, PT_LOAD};
+use elf::abi::{
+    PF_R, PF_W, PF_X, PT_DYNAMIC, PT_GNU_STACK, PT_LOAD, PT_NOTE, PT_PHDR, R_X86_64_RELATIVE,
+    STT_FUNC, STT_NOTYPE, STT_OBJECT,
+};
 use elf::endian::LittleEndian;
 use elf::segment::ProgramHeader;
 use log::error;
 use thiserror::Error;
-use util::paging::VirtAddress;
+use util::paging::{PageSize, VirtAddress};
 use util::sizes::TWO_MIB;
 
 /// Possible errors when creating a [`KernelFile`] via
 /// [`KernelFile::from_bytes`].
 #[derive(Debug, Error)]
 pub enum KernelFileError {
+    /// The file doesn't have a valid ELF64 x86_64 header.
+    #[error("kernel has an invalid ELF header")]
+    InvalidHeader(#[from] HeaderError),
     /// The file is not a valid ELF.
     #[error("kernel is not a valid ELF")]
     InvalidElf(#[from] elf::ParseError),
+    /// The kernel is not linked to the higher half of the address space.
+    #[error("kernel is linked to the lower half at {0:#x}, but must be a higher-half kernel")]
+    NotHigherHalf(u64),
     /// The LOAD segments have invalid or unexpected properties (e.g., no 2 MiB alignment).
     #[error("LOAD segments have invalid properties (e.g., no 2 MiB alignment)")]
     InvalidLoadSegments,
 }
 
+/// Expected hash of the data of a LOAD segment, see
+/// [`KernelFile::verify_segments`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SegmentHash {
+    /// Virtual address identifying the segment.
+    pub vaddr: VirtAddress,
+    /// 64-bit FNV-1a hash of the segment's data in the file.
+    pub hash: u64,
+}
+
+impl Display for SegmentHash {
+    /// Formats the hash as value for the `segment_hash` key of the
+    /// [`crate::Config`].
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x} {:#018x}", self.vaddr.0, self.hash)
+    }
+}
+
+/// Possible errors of [`KernelFile::verify_segments`].
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum VerifyError {
+    /// No hash was expected for a LOAD segment.
+    #[error("no expected hash for LOAD segment at {0:#x}")]
+    MissingHash(u64),
+    /// A hash was expected for a LOAD segment that doesn't exist.
+    #[error("expected hash for non-existing LOAD segment at {0:#x}")]
+    UnknownSegment(u64),
+    /// The data of a LOAD segment doesn't match the expected hash.
+    #[error("LOAD segment at {vaddr:#x} has hash {actual:#018x} but expected {expected:#018x}")]
+    Mismatch {
+        /// Virtual address of the segment.
+        vaddr: u64,
+        /// The expected hash.
+        expected: u64,
+        /// The hash of the segment's data.
+        actual: u64,
+    },
+}
+
+/// Possible errors of [`KernelFile::check_symbol_writable`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum SymbolError {
+    /// The file has no data symbol with that name, e.g., because it is
+    /// stripped.
+    #[error("no data symbol `{0}`")]
+    NotFound(String),
+    /// The symbol is not completely within a writable LOAD segment.
+    #[error("symbol `{name}` at {start:#x}..{end:#x} is not within a writable LOAD segment")]
+    NotWritable {
+        /// Name of the symbol.
+        name: String,
+        /// Virtual start address of the symbol.
+        start: u64,
+        /// Virtual end address (exclusive) of the symbol.
+        end: u64,
+    },
+    /// The symbol reaches beyond the end of the address space.
+    #[error("symbol `{name}` at {start:#x} with {size:#x} bytes exceeds the address space")]
+    InvalidSize {
+        /// Name of the symbol.
+        name: String,
+        /// Virtual start address of the symbol.
+        start: u64,
+        /// Size of the symbol.
+        size: u64,
+    },
+}
+
+/// Possible errors of [`KernelFile::relocate_to`].
+#[derive(Debug, Error)]
+pub enum RelocError {
+    /// The new base address is not 2 MiB aligned.
+    #[error("new base {0:#x} is not 2 MiB aligned")]
+    MisalignedBase(u64),
+    /// The output buffer can't hold the segments.
+    #[error("output buffer has {len} bytes but the kernel needs {needed} bytes")]
+    OutputTooSmall {
+        /// Bytes needed for all LOAD segments.
+        needed: usize,
+        /// Length of the output buffer.
+        len: usize,
+    },
+    /// The kernel has no `.rela.dyn`, so it can't be moved to another base.
+    #[error("kernel has no .rela.dyn section and can't be relocated")]
+    NotRelocatable,
+    /// The `.rela.dyn` section is invalid.
+    #[error("kernel has an invalid .rela.dyn section")]
+    InvalidRelocations(#[from] elf::ParseError),
+    /// A relocation has a type other than `R_X86_64_RELATIVE`.
+    #[error("unsupported relocation type {typ} at {offset:#x}")]
+    UnsupportedType {
+        /// Virtual address the relocation applies to.
+        offset: u64,
+        /// The relocation type.
+        typ: u32,
+    },
+    /// A relocation applies to memory outside of the LOAD segments.
+    #[error("relocation at {0:#x} is outside of the LOAD segments")]
+    OutOfRange(u64),
+}
+
+/// Returns the 64-bit FNV-1a hash of `bytes`.
+///
+/// This is no cryptographic hash; it only detects accidental corruption.
+const fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x100_0000_01b3;
+    let mut hash = OFFSET_BASIS;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(PRIME);
+        i += 1;
+    }
+    hash
+}
+
+/// Decoded program header of a segment of the [`KernelFile`].
+///
+/// This decouples consumers from the types of the `elf` crate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProgramHeaderInfo {
+    /// The raw segment type, such as `PT_LOAD`.
+    pub typ: u32,
+    /// The segment is readable.
+    pub read: bool,
+    /// The segment is writeable.
+    pub write: bool,
+    /// The segment is executable.
+    pub execute: bool,
+    /// Virtual address of the segment.
+    pub vaddr: VirtAddress,
+    /// Size of the segment in the file.
+    pub filesz: u64,
+    /// Size of the segment in memory.
+    pub memsz: u64,
+    /// Alignment of the segment.
+    pub align: u64,
+}
+
+impl ProgramHeaderInfo {
+    /// Returns the name of the segment type, if it is a common one.
+    #[must_use]
+    pub const fn type_name(&self) -> Option<&'static str> {
+        let name = match self.typ {
+            PT_LOAD => "LOAD",
+            PT_DYNAMIC => "DYNAMIC",
+            PT_NOTE => "NOTE",
+            PT_PHDR => "PHDR",
+            PT_GNU_STACK => "GNU_STACK",
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    /// Returns the permissions in the style of `ls -l`, e.g., `r-x`.
+    #[must_use]
+    pub const fn permissions(&self) -> &'static str {
+        const PERMISSIONS: [&str; 8] = ["---", "--x", "-w-", "-wx", "r--", "r-x", "rw-", "rwx"];
+        let index = (self.read as usize) << 2 | (self.write as usize) << 1 | self.execute as usize;
+        PERMISSIONS[index]
+    }
+}
+
+impl From<&ProgramHeader> for ProgramHeaderInfo {
+    fn from(pr_hdr: &ProgramHeader) -> Self {
+        Self {
+            typ: pr_hdr.p_type,
+            read: pr_hdr.p_flags & PF_R != 0,
+            write: pr_hdr.p_flags & PF_W != 0,
+            execute: pr_hdr.p_flags & PF_X != 0,
+            vaddr: VirtAddress(pr_hdr.p_vaddr),
+            filesz: pr_hdr.p_filesz,
+            memsz: pr_hdr.p_memsz,
+            align: pr_hdr.p_align,
+        }
+    }
+}
+
+impl Display for ProgramHeaderInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.type_name() {
+            Some(name) => write!(f, "{name:<9}")?,
+            None => write!(f, "{:#09x}", self.typ)?,
+        }
+        write!(
+            f,
+            " {} vaddr={:#018x} filesz={:#x} memsz={:#x} align={:#x}",
+            self.permissions(),
+            self.vaddr.0,
+            self.filesz,
+            self.memsz,
+            self.align
+        )
+    }
+}
+
+/// Alignment of a LOAD segment, see [`KernelFile::alignment_of_segments`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SegmentAlignment {
+    /// Virtual address of the segment.
+    pub vaddr: VirtAddress,
+    /// Alignment of the segment as stated in the program header.
+    pub p_align: u64,
+    /// Largest power of two the virtual address is actually aligned to.
+    pub actual: u64,
+}
+
+impl SegmentAlignment {
+    /// Alignment the loader needs for the huge-page mappings of the kernel.
+    pub const REQUIRED: u64 = TWO_MIB as u64;
+
+    /// Returns whether the segment is aligned as the loader needs it.
+    #[must_use]
+    pub const fn is_sufficient(&self) -> bool {
+        self.actual >= Self::REQUIRED
+    }
+}
+
+impl Display for SegmentAlignment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "segment at {:#x} (p_align={:#x}) is ",
+            self.vaddr.0, self.p_align
+        )?;
+        if self.is_sufficient() {
+            write!(f, "{} aligned", HumanSize(self.actual))
+        } else {
+            write!(
+                f,
+                "only {} aligned, needs {}",
+                HumanSize(self.actual),
+                HumanSize(Self::REQUIRED)
+            )
+        }
+    }
+}
+
+/// Formats a power of two of bytes with the largest fitting binary unit,
+/// e.g., `4 KiB`.
+struct HumanSize(u64);
+
+impl Display for HumanSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        const UNITS: [(u64, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+        for (size, unit) in UNITS {
+            if self.0 >= size && self.0.is_multiple_of(size) {
+                return write!(f, "{} {unit}", self.0 / size);
+            }
+        }
+        write!(f, "{} B", self.0)
+    }
+}
+
 /// Abstraction over the ELF file of the kernel.
 #[derive(Debug)]
 pub struct KernelFile<'a> {
@@ -35,7 +301,7 @@ impl<'a> KernelFile<'a> {
     /// Performs checks on the ELF.
     ///
     /// For example, this verifies the program header of each LOAD segment.
-    fn check_elf(elf: &ElfBytes<'a, LittleEndian>) -> Result<(), KernelFileError> {
+    fn check_elf(elf: &ElfBytes<'a, LittleEndian>, file_len: usize) -> Result<(), KernelFileError> {
         let segments = elf.segments().ok_or(KernelFileError::InvalidLoadSegments)?;
         let load_segments_iter = || {
             segments
@@ -44,6 +310,18 @@ impl<'a> KernelFile<'a> {
                 .filter(|pr_hdr| pr_hdr.p_type == PT_LOAD)
         };
 
+        // check: the data of all segments is within the file
+        if segments.iter().any(|pr_hdr| {
+            pr_hdr.p_filesz != 0
+                && pr_hdr
+                    .p_offset
+                    .checked_add(pr_hdr.p_filesz)
+                    .is_none_or(|end| end > file_len as u64)
+        }) {
+            error!("not all segments are within the file");
+            return Err(KernelFileError::InvalidLoadSegments);
+        }
+
         // check: have at least one rx, one rw, one ro segment
         {
             let has_rx = load_segments_iter().any(|pr_hdr| {
@@ -86,9 +364,14 @@ impl<'a> KernelFile<'a> {
             }
         };
 
-        // check: We have the expected link address.
+        // check: We have the expected link address. The loader only supports
+        // higher-half kernels, as the lower half is used for identity mappings
+        // during the handoff.
         {
             let first = load_segments_iter().next().unwrap();
+            if !Self::is_higher_half_addr(first.p_vaddr) {
+                return Err(KernelFileError::NotHigherHalf(first.p_vaddr));
+            }
             if first.p_vaddr != Self::EXPECTED_LINK_ADDR.0 {
                 error!(
                     "expected virtual address {:#x} but was {}",
@@ -105,19 +388,20 @@ impl<'a> KernelFile<'a> {
             return Err(KernelFileError::InvalidLoadSegments);
         }
 
-        // check: memsize == filesize.
-        // This makes the actual size of the kernel more clear and loading a
-        // bit easier.
-        if load_segments_iter().any(|pr_hdr| pr_hdr.p_filesz != pr_hdr.p_memsz) {
-            error!("not all LOAD segments have equal mem size and file size");
+        // check: memsize >= filesize.
+        // The remainder of a segment (BSS) is zero-filled by the loader.
+        if load_segments_iter().any(|pr_hdr| pr_hdr.p_filesz > pr_hdr.p_memsz) {
+            error!("not all LOAD segments have a mem size of at least their file size");
             return Err(KernelFileError::InvalidLoadSegments);
         }
 
         // check virtual address space is contiguous
         for (pr_hdr, pr_hdr_ne) in load_segments_iter().zip(load_segments_iter().skip(1)) {
-            let expected_next_vaddr = pr_hdr.p_vaddr + pr_hdr_ne.p_filesz;
-            let expected_next_vaddr = expected_next_vaddr.next_multiple_of(TWO_MIB as u64);
-            if expected_next_vaddr != pr_hdr_ne.p_vaddr {
+            let expected_next_vaddr = pr_hdr
+                .p_vaddr
+                .checked_add(pr_hdr.p_memsz)
+                .and_then(|end| end.checked_next_multiple_of(TWO_MIB as u64));
+            if expected_next_vaddr != Some(pr_hdr_ne.p_vaddr) {
                 error!("LOAD segments are not contiguous in virtual memory space");
                 return Err(KernelFileError::InvalidLoadSegments);
             }
@@ -129,11 +413,37 @@ impl<'a> KernelFile<'a> {
     /// Creates a new kernel file wrapper and performs checks on the provided
     /// ELF.
     pub fn from_bytes(elf_bytes: &'a [u8]) -> Result<Self, KernelFileError> {
+        validate_elf_header(elf_bytes)?;
         let elf: ElfBytes<LittleEndian> = ElfBytes::<LittleEndian>::minimal_parse(elf_bytes)?;
-        Self::check_elf(&elf)?;
+        Self::check_elf(&elf, elf_bytes.len())?;
         Ok(Self { elf_bytes, elf })
     }
 
+    /// Returns the alignment of each LOAD segment of the ELF in `elf_bytes`.
+    ///
+    /// Unlike [`Self::from_bytes`], this only requires a parseable ELF, so
+    /// it can explain why a kernel was rejected with
+    /// [`KernelFileError::InvalidLoadSegments`], e.g., "segment at
+    /// 0xffffffff88201000 (p_align=0x1000) is only 4 KiB aligned, needs
+    /// 2 MiB".
+    pub fn alignment_of_segments(
+        elf_bytes: &[u8],
+    ) -> Result<Vec<SegmentAlignment>, KernelFileError> {
+        validate_elf_header(elf_bytes)?;
+        let elf: ElfBytes<LittleEndian> = ElfBytes::<LittleEndian>::minimal_parse(elf_bytes)?;
+        let segments = elf.segments().ok_or(KernelFileError::InvalidLoadSegments)?;
+        let alignments = segments
+            .iter()
+            .filter(|pr_hdr| pr_hdr.p_type == PT_LOAD)
+            .map(|pr_hdr| SegmentAlignment {
+                vaddr: VirtAddress(pr_hdr.p_vaddr),
+                p_align: pr_hdr.p_align,
+                actual: 1 << pr_hdr.p_vaddr.trailing_zeros().min(63),
+            })
+            .collect();
+        Ok(alignments)
+    }
+
     /// Returns the segments of the ELF file.
     ///
     /// For all segments, the corresponding content is emitted as well.
@@ -141,28 +451,15 @@ impl<'a> KernelFile<'a> {
         // SAFETY: Earlier, we already checked that the segments are valid.
         let segments = unsafe { self.elf.segments().unwrap_unchecked() };
         segments.into_iter().map(move |pr_hdr| {
-            let data = if pr_hdr.p_offset != 0 {
-                // SAFETY: We know the pointer is valid.
-                let segment_ptr = unsafe {
-                    self.elf_bytes
-                        .as_ptr()
-                        .cast::<u8>()
-                        .add(pr_hdr.p_offset as usize)
-                };
-                // SAFETY: We will check the bounds in the next step before
-                // accessing the data.
-                let segment_ptr_end = unsafe { segment_ptr.add(pr_hdr.p_offset as usize) };
-
-                // Safety checks: Is the data in range?
-                {
-                    let ptr_begin = self.elf_bytes.as_ptr().cast::<u8>();
-                    let ptr_end = unsafe { ptr_begin.add(self.elf_bytes.len()) };
-                    let data_in_range = (ptr_begin..ptr_end).contains(&segment_ptr_end);
-                    assert!(data_in_range);
-                }
-
-                // SAFETY: We know the size is valid
-                unsafe { slice::from_raw_parts(segment_ptr, pr_hdr.p_filesz as usize) }
+            // A segment at file offset 0 is valid, e.g., one that contains
+            // the ELF header, so only the file size tells if it is empty.
+            // `check_elf()` ensured that the data is within the file.
+            let data = if pr_hdr.p_filesz != 0 {
+                let start = pr_hdr.p_offset as usize;
+                let end = start + pr_hdr.p_filesz as usize;
+                self.elf_bytes
+                    .get(start..end)
+                    .expect("segment data should be within the file")
             } else {
                 &[]
             };
@@ -171,6 +468,12 @@ impl<'a> KernelFile<'a> {
         })
     }
 
+    /// Returns the decoded program headers of all segments.
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeaderInfo> {
+        self.segments()
+            .map(|(pr_hdr, _)| ProgramHeaderInfo::from(&pr_hdr))
+    }
+
     /// Returns the LOAD segments of the ELF file.
     ///
     /// Filtered version of [`Self::segments`].
@@ -184,21 +487,76 @@ impl<'a> KernelFile<'a> {
     /// the same!
     #[must_use]
     pub fn virt_start(&self) -> VirtAddress {
-        // SAFETY; We checked in the constructor that we have valid segments.
+        // SAFETY: We checked in the constructor that we have valid segments.
         let vaddr = unsafe { self.load_segments().next().unwrap_unchecked().0.p_vaddr };
         VirtAddress(vaddr)
     }
 
-    /// Returns the total memsize the kernel will use at runtime when it is
-    /// mapped continuously into physical memory.
+    /// Returns the end (exclusive) of the last LOAD segment in virtual
+    /// memory, including its BSS.
+    ///
+    /// Saturates at `u64::MAX` for segments that exceed the address space.
+    #[must_use]
+    pub fn virt_end(&self) -> VirtAddress {
+        let end = self
+            .load_segments()
+            .map(|(pr_hdr, _)| pr_hdr.p_vaddr.saturating_add(pr_hdr.p_memsz))
+            .max()
+            .unwrap_or_else(|| self.virt_start().0);
+        VirtAddress(end)
+    }
+
+    const fn is_higher_half_addr(vaddr: u64) -> bool {
+        vaddr & (1 << 63) != 0
+    }
+
+    /// Returns whether the kernel is linked to the higher half of the address
+    /// space. This is always the case for a successfully created
+    /// [`KernelFile`].
+    #[must_use]
+    pub fn is_higher_half(&self) -> bool {
+        Self::is_higher_half_addr(self.virt_start().0)
+    }
+
+    /// Returns the size of the virtual range the kernel spans at runtime with
+    /// pages of the given size.
+    ///
+    /// This is the range from the start of the first to the end of the last
+    /// LOAD segment, rounded to `page_size`, including any gaps between the
+    /// segments. It is the size of the virtual window the kernel needs; the
+    /// physical memory to load it into is given by
+    /// [`Self::required_phys_memory`].
+    #[must_use]
+    pub fn total_runtime_memsize(&self, page_size: PageSize) -> usize {
+        let page_size = page_size.size() as u64;
+        let start = self.virt_start().0 & !(page_size - 1);
+        // Saturates for bogus segments, so that they exceed any window.
+        let end = self
+            .virt_end()
+            .0
+            .checked_next_multiple_of(page_size)
+            .unwrap_or(u64::MAX);
+        (end - start) as usize
+    }
+
+    /// Returns the physical memory the kernel needs when it is loaded
+    /// continuously into physical memory with pages of the given size.
+    ///
+    /// Each LOAD segment starts at a new page, so this is the sum of the
+    /// segment sizes in memory (including BSS), each rounded up to
+    /// `page_size`. See [`Self::segment_phys_size`].
     #[must_use]
-    pub fn total_runtime_memsize(&self) -> usize {
-        // we checked in the constructor that all LOAD segments are continuous
+    pub fn required_phys_memory(&self, page_size: PageSize) -> usize {
         self.load_segments()
-            .map(|(pr_hdr, _)| pr_hdr)
-            // We map them as huge pages.
-            .map(|pr_hdr| pr_hdr.p_memsz.next_multiple_of(TWO_MIB as u64))
-            .sum::<u64>() as usize
+            .map(|(pr_hdr, _)| Self::segment_phys_size(&pr_hdr, page_size))
+            .sum()
+    }
+
+    /// Returns the physical memory a single LOAD segment needs with pages of
+    /// the given size, i.e., its size in memory rounded up to `page_size`.
+    #[must_use]
+    pub const fn segment_phys_size(pr_hdr: &ProgramHeader, page_size: PageSize) -> usize {
+        (pr_hdr.p_memsz as usize).next_multiple_of(page_size.size())
     }
 
     /// Returns the address of the entry symbol.
@@ -206,4 +564,608 @@ impl<'a> KernelFile<'a> {
     pub fn entry(&self) -> VirtAddress {
         self.elf.ehdr.e_entry.into()
     }
+
+    /// Returns the hashes of the data of all LOAD segments.
+    #[must_use]
+    pub fn segment_hashes(&self) -> Vec<SegmentHash> {
+        self.load_segments()
+            .map(|(pr_hdr, data)| SegmentHash {
+                vaddr: VirtAddress(pr_hdr.p_vaddr),
+                hash: fnv1a(data),
+            })
+            .collect()
+    }
+
+    /// Verifies the data of each LOAD segment against the `expected` hashes.
+    ///
+    /// There must be exactly one expected hash per LOAD segment.
+    pub fn verify_segments(&self, expected: &[SegmentHash]) -> Result<(), VerifyError> {
+        let actual = self.segment_hashes();
+        if let Some(unknown) = expected
+            .iter()
+            .find(|e| !actual.iter().any(|a| a.vaddr == e.vaddr))
+        {
+            return Err(VerifyError::UnknownSegment(unknown.vaddr.0));
+        }
+        for actual in actual {
+            let expected = expected
+                .iter()
+                .find(|e| e.vaddr == actual.vaddr)
+                .ok_or(VerifyError::MissingHash(actual.vaddr.0))?;
+            if expected.hash != actual.hash {
+                return Err(VerifyError::Mismatch {
+                    vaddr: actual.vaddr.0,
+                    expected: expected.hash,
+                    actual: actual.hash,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the entry symbol for diagnostics.
+    ///
+    /// Returns `None` if the kernel is stripped, i.e., has no `.symtab`, or
+    /// if no named symbol is at the entry address.
+    #[must_use]
+    pub fn entry_symbol_name(&self) -> Option<&'a str> {
+        let (symtab, strtab) = self.elf.symbol_table().ok()??;
+        symtab
+            .iter()
+            .filter(|sym| sym.st_value == self.elf.ehdr.e_entry && sym.st_name != 0)
+            .filter(|sym| matches!(sym.st_symtype(), STT_FUNC | STT_NOTYPE))
+            .find_map(|sym| strtab.get(sym.st_name as usize).ok())
+    }
+
+    /// Names of the statics of the kernel that must be writable: the backing
+    /// memory of the heap and of the stack. The kernel exports them
+    /// unmangled. See [`Self::check_symbol_writable`].
+    pub const WRITABLE_SYMBOLS: &[&str] = &["HEAP_MEM", "STACK_MEM"];
+
+    /// Returns the virtual address range of the data symbol (e.g., a static)
+    /// with the given name, if the file has a symbol table with it.
+    ///
+    /// Returns `None` as well if the range exceeds the address space.
+    #[must_use]
+    pub fn object_symbol_range(&self, name: &str) -> Option<Range<u64>> {
+        let (start, size) = self.object_symbol(name)?;
+        Some(start..start.checked_add(size)?)
+    }
+
+    /// Returns the value and the size of the data symbol with the given name.
+    fn object_symbol(&self, name: &str) -> Option<(u64, u64)> {
+        let (symtab, strtab) = self.elf.symbol_table().ok()??;
+        symtab
+            .iter()
+            .filter(|sym| sym.st_symtype() == STT_OBJECT && sym.st_name != 0)
+            .find(|sym| strtab.get(sym.st_name as usize).is_ok_and(|n| n == name))
+            .map(|sym| (sym.st_value, sym.st_size))
+    }
+
+    /// Checks that the data symbol with the given name lies completely within
+    /// a writable LOAD segment.
+    ///
+    /// This catches linker-script mistakes that place writable statics, such
+    /// as the heap or the stack of the kernel, into read-only segments, which
+    /// would otherwise only show up as a page fault at runtime.
+    pub fn check_symbol_writable(&self, name: &str) -> Result<(), SymbolError> {
+        let (start, size) = self
+            .object_symbol(name)
+            .ok_or_else(|| SymbolError::NotFound(name.to_string()))?;
+        let range = start
+            .checked_add(size)
+            .map(|end| start..end)
+            .ok_or_else(|| SymbolError::InvalidSize {
+                name: name.to_string(),
+                start,
+                size,
+            })?;
+        let writable = self.load_segments().any(|(pr_hdr, _)| {
+            pr_hdr.p_flags & PF_W != 0
+                && pr_hdr.p_vaddr <= range.start
+                && pr_hdr
+                    .p_vaddr
+                    .checked_add(pr_hdr.p_memsz)
+                    .is_some_and(|end| range.end <= end)
+        });
+        if writable {
+            Ok(())
+        } else {
+            Err(SymbolError::NotWritable {
+                name: name.to_string(),
+                start: range.start,
+                end: range.end,
+            })
+        }
+    }
+
+    /// Copies the LOAD segments into `out` and relocates them to `new_base`.
+    ///
+    /// `out` is the image of the kernel's virtual memory from
+    /// [`Self::virt_start`] on: each segment lands at its offset from the
+    /// link address, and BSS as well as the gaps between segments are
+    /// zeroed. All `R_X86_64_RELATIVE` relocations of the `.rela.dyn` section
+    /// are then adjusted by `new_base - virt_start`. Other relocation types
+    /// are rejected, as they need a symbol lookup.
+    ///
+    /// A kernel without a `.rela.dyn` section can only be "relocated" to its
+    /// link address.
+    pub fn relocate_to(&self, new_base: VirtAddress, out: &mut [u8]) -> Result<(), RelocError> {
+        if !new_base.0.is_multiple_of(TWO_MIB as u64) {
+            return Err(RelocError::MisalignedBase(new_base.0));
+        }
+        let default_base = self.virt_start().0;
+        // SAFETY: We checked in the constructor that we have valid segments.
+        let last = unsafe { self.load_segments().last().unwrap_unchecked().0 };
+        let needed = (last.p_vaddr + last.p_memsz - default_base) as usize;
+        let len = out.len();
+        let out = out
+            .get_mut(..needed)
+            .ok_or(RelocError::OutputTooSmall { needed, len })?;
+
+        out.fill(0);
+        for (pr_hdr, data) in self.load_segments() {
+            let offset = (pr_hdr.p_vaddr - default_base) as usize;
+            out[offset..offset + data.len()].copy_from_slice(data);
+        }
+
+        let delta = new_base.0.wrapping_sub(default_base);
+        let Some(shdr) = self.elf.section_header_by_name(".rela.dyn")? else {
+            return if delta == 0 {
+                Ok(())
+            } else {
+                Err(RelocError::NotRelocatable)
+            };
+        };
+        for rela in self.elf.section_data_as_relas(&shdr)? {
+            if rela.r_type != R_X86_64_RELATIVE {
+                return Err(RelocError::UnsupportedType {
+                    offset: rela.r_offset,
+                    typ: rela.r_type,
+                });
+            }
+            let size = size_of::<u64>() as u64;
+            let in_segment = self
+                .segment_containing(VirtAddress(rela.r_offset))
+                .is_some_and(|pr_hdr| rela.r_offset + size <= pr_hdr.p_vaddr + pr_hdr.p_memsz);
+            if !in_segment {
+                return Err(RelocError::OutOfRange(rela.r_offset));
+            }
+            let offset = (rela.r_offset - default_base) as usize;
+            let value = (rela.r_addend as u64).wrapping_add(delta);
+            out[offset..offset + size as usize].copy_from_slice(&value.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Returns the LOAD segment that contains the given virtual address.
+    ///
+    /// This is useful for diagnostics, e.g., to find out if a faulting
+    /// address belongs to the RX, RO, or RW segment of the kernel.
+    #[must_use]
+    pub fn segment_containing(&self, vaddr: VirtAddress) -> Option<ProgramHeader> {
+        self.load_segments()
+            .map(|(pr_hdr, _)| pr_hdr)
+            .find(|pr_hdr| {
+                pr_hdr
+                    .p_vaddr
+                    .checked_add(pr_hdr.p_memsz)
+                    .is_some_and(|end| (pr_hdr.p_vaddr..end).contains(&vaddr.0))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf_header::ELF64_HEADER_SIZE;
+    use crate::test_utils::{LINK_ADDR, SegmentSpec, kernel_fixture};
+    use elf::abi::{PF_R, PF_W, PF_X, R_X86_64_64};
+
+    #[test]
+    fn test_segment_containing() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let two_mib = TWO_MIB as u64;
+
+        let flags = |vaddr| {
+            kernel
+                .segment_containing(VirtAddress(vaddr))
+                .map(|pr_hdr| pr_hdr.p_flags)
+        };
+
+        assert_eq!(flags(LINK_ADDR), Some(PF_R | PF_X));
+        assert_eq!(flags(LINK_ADDR + 0x17ff), Some(PF_R | PF_X));
+        assert_eq!(flags(LINK_ADDR + 0x1800), None);
+        assert_eq!(flags(LINK_ADDR + two_mib + 0x7ff), Some(PF_R));
+        assert_eq!(flags(LINK_ADDR + 2 * two_mib), Some(PF_R | PF_W));
+        assert_eq!(flags(LINK_ADDR + 2 * two_mib + 0x1000), None);
+        assert_eq!(flags(LINK_ADDR - 1), None);
+    }
+
+    #[test]
+    fn test_segment_at_offset_zero() {
+        let mut bytes = kernel_fixture().build();
+        // Let the RX segment start at the ELF header, like linkers do when
+        // the headers are part of the first LOAD segment.
+        let p_offset = ELF64_HEADER_SIZE + 8;
+        bytes[p_offset..p_offset + 8].copy_from_slice(&0_u64.to_le_bytes());
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+
+        let (pr_hdr, data) = kernel.load_segments().next().unwrap();
+        assert_eq!(pr_hdr.p_offset, 0);
+        assert_eq!(data.len(), 0x1800);
+        assert_eq!(data, &bytes[..0x1800]);
+        assert_eq!(&data[..4], b"\x7fELF");
+
+        // Segments without data in the file are empty.
+        let mut fixture = kernel_fixture();
+        fixture.segments[2].data.clear();
+        fixture.segments[2].p_memsz = 0x1000;
+        let bytes = fixture.build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let (_, data) = kernel.load_segments().nth(2).unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_empty_or_truncated() {
+        assert!(matches!(
+            KernelFile::from_bytes(&[]),
+            Err(KernelFileError::InvalidHeader(HeaderError::Truncated(0)))
+        ));
+        assert!(matches!(
+            KernelFile::from_bytes(&[0x7f; 10]),
+            Err(KernelFileError::InvalidHeader(HeaderError::Truncated(10)))
+        ));
+    }
+
+    #[test]
+    fn test_program_headers() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let headers = kernel.program_headers().collect::<Vec<_>>();
+        assert_eq!(headers.len(), 3);
+        let permissions = headers.iter().map(ProgramHeaderInfo::permissions);
+        assert!(permissions.eq(["r-x", "r--", "rw-"]));
+
+        let rx = headers[0];
+        assert_eq!(rx.type_name(), Some("LOAD"));
+        assert_eq!(rx.vaddr, VirtAddress(LINK_ADDR));
+        assert_eq!(rx.filesz, 0x1800);
+        assert!(
+            rx.to_string()
+                .starts_with("LOAD      r-x vaddr=0xffffffff88200000")
+        );
+    }
+
+    #[test]
+    fn test_higher_half() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert!(kernel.is_higher_half());
+
+        let mut fixture = kernel_fixture();
+        for segment in &mut fixture.segments {
+            segment.p_vaddr -= LINK_ADDR - 0x20_0000;
+        }
+        let bytes = fixture.build();
+        assert!(matches!(
+            KernelFile::from_bytes(&bytes),
+            Err(KernelFileError::NotHigherHalf(0x20_0000))
+        ));
+    }
+
+    #[test]
+    fn test_alignment_of_segments() {
+        let bytes = kernel_fixture().build();
+        let alignments = KernelFile::alignment_of_segments(&bytes).unwrap();
+        assert_eq!(alignments.len(), 3);
+        assert!(alignments.iter().all(SegmentAlignment::is_sufficient));
+        assert_eq!(alignments[0].actual, TWO_MIB as u64);
+        assert_eq!(
+            alignments[0].to_string(),
+            "segment at 0xffffffff88200000 (p_align=0x200000) is 2 MiB aligned"
+        );
+
+        let mut fixture = kernel_fixture();
+        fixture.segments[1].p_vaddr += 0x1000;
+        fixture.segments[1].p_align = 0x1000;
+        let bytes = fixture.build();
+        assert!(matches!(
+            KernelFile::from_bytes(&bytes),
+            Err(KernelFileError::InvalidLoadSegments)
+        ));
+        let alignments = KernelFile::alignment_of_segments(&bytes).unwrap();
+        assert_eq!(
+            alignments[1],
+            SegmentAlignment {
+                vaddr: VirtAddress(LINK_ADDR + TWO_MIB as u64 + 0x1000),
+                p_align: 0x1000,
+                actual: 0x1000,
+            }
+        );
+        assert!(!alignments[1].is_sufficient());
+        assert_eq!(
+            alignments[1].to_string(),
+            "segment at 0xffffffff88401000 (p_align=0x1000) is only 4 KiB aligned, needs 2 MiB"
+        );
+
+        assert!(matches!(
+            KernelFile::alignment_of_segments(&[]),
+            Err(KernelFileError::InvalidHeader(HeaderError::Truncated(0)))
+        ));
+    }
+
+    #[test]
+    fn test_total_runtime_memsize() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            kernel.total_runtime_memsize(PageSize::Size2MiB),
+            3 * TWO_MIB
+        );
+        // Includes the gaps between the segments.
+        assert_eq!(
+            kernel.total_runtime_memsize(PageSize::Size4KiB),
+            2 * TWO_MIB + 0x1000
+        );
+
+        // Segments larger than one 4 KiB page.
+        let mut fixture = kernel_fixture();
+        fixture.segments[0] = SegmentSpec::load(PF_R | PF_X, LINK_ADDR, vec![0xcc; 0x3001]);
+        let bytes = fixture.build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            kernel.total_runtime_memsize(PageSize::Size2MiB),
+            3 * TWO_MIB
+        );
+        assert_eq!(
+            kernel.total_runtime_memsize(PageSize::Size4KiB),
+            2 * TWO_MIB + 0x1000
+        );
+        // The physical memory doesn't include the gaps.
+        assert_eq!(
+            kernel.required_phys_memory(PageSize::Size4KiB),
+            0x4000 + 0x1000 + 0x1000
+        );
+    }
+
+    #[test]
+    fn test_required_phys_memory_with_bss() {
+        let two_mib = TWO_MIB as u64;
+        let mut fixture = kernel_fixture();
+        // 4 KiB of data followed by BSS spanning into a second 2 MiB page.
+        fixture.segments[1].p_memsz = two_mib + 0x1000;
+        fixture.segments[2].p_vaddr = LINK_ADDR + 3 * two_mib;
+        let bytes = fixture.build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert_eq!(kernel.required_phys_memory(PageSize::Size2MiB), 4 * TWO_MIB);
+        assert_eq!(
+            kernel.required_phys_memory(PageSize::Size4KiB),
+            0x2000 + 0x20_1000 + 0x1000
+        );
+        let (pr_hdr, data) = kernel.load_segments().nth(1).unwrap();
+        assert_eq!(data.len(), 0x800);
+        assert_eq!(
+            KernelFile::segment_phys_size(&pr_hdr, PageSize::Size2MiB),
+            2 * TWO_MIB
+        );
+
+        // The next segment must follow the BSS.
+        let mut fixture = kernel_fixture();
+        fixture.segments[1].p_memsz = two_mib + 0x1000;
+        let bytes = fixture.build();
+        assert!(matches!(
+            KernelFile::from_bytes(&bytes),
+            Err(KernelFileError::InvalidLoadSegments)
+        ));
+
+        // The end of a segment must not overflow.
+        let mut fixture = kernel_fixture();
+        fixture.segments[1].p_memsz = u64::MAX - 0x1000;
+        let bytes = fixture.build();
+        assert!(matches!(
+            KernelFile::from_bytes(&bytes),
+            Err(KernelFileError::InvalidLoadSegments)
+        ));
+
+        // The file size must not exceed the size in memory.
+        let mut fixture = kernel_fixture();
+        fixture.segments[1].p_memsz = 0x400;
+        let bytes = fixture.build();
+        assert!(matches!(
+            KernelFile::from_bytes(&bytes),
+            Err(KernelFileError::InvalidLoadSegments)
+        ));
+    }
+
+    #[test]
+    fn test_segment_data_outside_of_file() {
+        /// Sets `p_offset` of the second program header.
+        fn with_offset(mut bytes: Vec<u8>, offset: u64) -> Vec<u8> {
+            let phoff = u64::from_le_bytes(bytes[32..40].try_into().unwrap()) as usize;
+            let field = phoff + 56 + 8;
+            bytes[field..field + 8].copy_from_slice(&offset.to_le_bytes());
+            bytes
+        }
+
+        let bytes = kernel_fixture().build();
+        let len = bytes.len() as u64;
+        assert!(KernelFile::from_bytes(&with_offset(bytes.clone(), 0)).is_ok());
+        for offset in [len - 0x10, len, u64::MAX - 0x10] {
+            assert!(matches!(
+                KernelFile::from_bytes(&with_offset(bytes.clone(), offset)),
+                Err(KernelFileError::InvalidLoadSegments)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_entry_symbol_name() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert_eq!(kernel.entry_symbol_name(), None);
+
+        let bytes = kernel_fixture()
+            .symbol("other", LINK_ADDR + 0x100)
+            .symbol("kernel_entry", LINK_ADDR)
+            .build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert_eq!(kernel.entry_symbol_name(), Some("kernel_entry"));
+    }
+
+    #[test]
+    fn test_check_symbol_writable() {
+        let rw = LINK_ADDR + 2 * TWO_MIB as u64;
+        let bytes = kernel_fixture()
+            .object("HEAP_MEM", rw, 0x800)
+            .object("RODATA", LINK_ADDR + TWO_MIB as u64, 0x10)
+            .object("TOO_LARGE", rw + 0x800, 0x1000)
+            .object("HUGE", rw, u64::MAX)
+            .symbol("STACK_MEM", rw)
+            .build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+
+        assert_eq!(kernel.object_symbol_range("HEAP_MEM"), Some(rw..rw + 0x800));
+        assert_eq!(kernel.check_symbol_writable("HEAP_MEM"), Ok(()));
+        assert_eq!(
+            kernel.check_symbol_writable("RODATA"),
+            Err(SymbolError::NotWritable {
+                name: "RODATA".to_string(),
+                start: LINK_ADDR + TWO_MIB as u64,
+                end: LINK_ADDR + TWO_MIB as u64 + 0x10
+            })
+        );
+        assert!(matches!(
+            kernel.check_symbol_writable("TOO_LARGE"),
+            Err(SymbolError::NotWritable { .. })
+        ));
+        assert_eq!(kernel.object_symbol_range("HUGE"), None);
+        assert_eq!(
+            kernel.check_symbol_writable("HUGE"),
+            Err(SymbolError::InvalidSize {
+                name: "HUGE".to_string(),
+                start: rw,
+                size: u64::MAX
+            })
+        );
+        // Functions are not considered.
+        assert_eq!(
+            kernel.check_symbol_writable("STACK_MEM"),
+            Err(SymbolError::NotFound("STACK_MEM".to_string()))
+        );
+
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert_eq!(kernel.object_symbol_range("HEAP_MEM"), None);
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_verify_segments() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let mut hashes = kernel.segment_hashes();
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(kernel.verify_segments(&hashes), Ok(()));
+
+        hashes[1].hash ^= 1;
+        assert_eq!(
+            kernel.verify_segments(&hashes),
+            Err(VerifyError::Mismatch {
+                vaddr: LINK_ADDR + TWO_MIB as u64,
+                expected: hashes[1].hash,
+                actual: hashes[1].hash ^ 1,
+            })
+        );
+        assert_eq!(
+            kernel.verify_segments(&hashes[..1]),
+            Err(VerifyError::MissingHash(LINK_ADDR + TWO_MIB as u64))
+        );
+
+        hashes[1].vaddr = VirtAddress(0x1000);
+        assert_eq!(
+            kernel.verify_segments(&hashes),
+            Err(VerifyError::UnknownSegment(0x1000))
+        );
+    }
+
+    #[test]
+    fn test_relocate_to() {
+        let two_mib = TWO_MIB as u64;
+        let rw = LINK_ADDR + 2 * two_mib;
+        let fixture = kernel_fixture().rela(rw + 8, R_X86_64_RELATIVE, (LINK_ADDR + 0x10) as i64);
+        let bytes = fixture.build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let needed = 2 * TWO_MIB + 0x1000;
+        let new_base = LINK_ADDR + 4 * two_mib;
+
+        let mut out = vec![0xff; needed];
+        kernel.relocate_to(VirtAddress(new_base), &mut out).unwrap();
+        assert!(out[..0x1800].iter().all(|&b| b == 0xcc));
+        assert!(out[0x1800..TWO_MIB].iter().all(|&b| b == 0));
+        assert!(out[TWO_MIB..TWO_MIB + 0x800].iter().all(|&b| b == 0xaa));
+        let rw_data = &out[2 * TWO_MIB..];
+        assert!(rw_data[..8].iter().all(|&b| b == 0xbb));
+        assert_eq!(rw_data[8..16], (new_base + 0x10).to_le_bytes());
+        assert!(rw_data[16..].iter().all(|&b| b == 0xbb));
+
+        // Relocating to the link address keeps the addend as it is.
+        kernel
+            .relocate_to(VirtAddress(LINK_ADDR), &mut out)
+            .unwrap();
+        assert_eq!(
+            out[2 * TWO_MIB + 8..][..8],
+            (LINK_ADDR + 0x10).to_le_bytes()
+        );
+
+        assert!(matches!(
+            kernel.relocate_to(VirtAddress(new_base), &mut out[..needed - 1]),
+            Err(RelocError::OutputTooSmall { needed: n, len }) if n == needed && len == needed - 1
+        ));
+        assert!(matches!(
+            kernel.relocate_to(VirtAddress(new_base + 0x1000), &mut out),
+            Err(RelocError::MisalignedBase(_))
+        ));
+
+        // Without a .rela.dyn, only the link address works.
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        kernel
+            .relocate_to(VirtAddress(LINK_ADDR), &mut out)
+            .unwrap();
+        assert!(matches!(
+            kernel.relocate_to(VirtAddress(new_base), &mut out),
+            Err(RelocError::NotRelocatable)
+        ));
+    }
+
+    #[test]
+    fn test_relocate_to_rejects() {
+        let rw = LINK_ADDR + 2 * TWO_MIB as u64;
+        let mut out = vec![0; 2 * TWO_MIB + 0x1000];
+
+        let bytes = kernel_fixture().rela(rw, R_X86_64_64, 0).build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert!(matches!(
+            kernel.relocate_to(VirtAddress(LINK_ADDR), &mut out),
+            Err(RelocError::UnsupportedType { offset, typ: R_X86_64_64 }) if offset == rw
+        ));
+
+        // In the gap behind the RX segment and at the end of the RW segment.
+        for offset in [LINK_ADDR + 0x1800, rw + 0x1000 - 4] {
+            let bytes = kernel_fixture().rela(offset, R_X86_64_RELATIVE, 0).build();
+            let kernel = KernelFile::from_bytes(&bytes).unwrap();
+            assert!(matches!(
+                kernel.relocate_to(VirtAddress(LINK_ADDR), &mut out),
+                Err(RelocError::OutOfRange(o)) if o == offset
+            ));
+        }
+    }
 }
@@ -16,17 +16,238 @@ extern crate alloc;
 #[cfg(test)]
 extern crate std;
 
+mod boot_info;
+mod config;
+mod elf_header;
+mod error_chain;
 mod kernel_file;
+#[cfg(any(test, feature = "uefi"))]
+mod memory_type;
+mod page_table_pool;
+#[cfg(test)]
+mod test_utils;
+mod trampoline;
 
-pub use kernel_file::KernelFile;
+pub use boot_info::{create_boot_information, write_boot_information, write_memory_map};
+pub use config::{Config, ConfigError};
+pub use elf_header::{HeaderError, validate_elf_header};
+pub use error_chain::ErrorChain;
+pub use kernel_file::{
+    KernelFile, KernelFileError, ProgramHeaderInfo, RelocError, SegmentAlignment, SegmentHash,
+    SymbolError, VerifyError,
+};
+#[cfg(feature = "uefi")]
+pub use memory_type::MemoryMapEntryTypeExt;
+pub use page_table_pool::PageTablePool;
+pub use trampoline::{
+    TrampolineError, jump_to_kernel_trampoline, trampoline_page_bytes, verify_trampoline,
+};
 
+use core::ops::Range;
+use kernel_lib::{MemoryMapEntry, MemoryMapEntryType};
 use log::debug;
-use std::mem::ManuallyDrop;
-use std::ops::DerefMut;
+use thiserror::Error;
 use util::mem::AlignedBuffer;
-use util::paging::{PAGE_MASK, PageTable, PhysMappingDest, VirtAddress, map_address_step};
+use util::paging::{
+    AlignError, MapError, PAGE_MASK, PAGE_SIZE, PageSize, PageTable, PageTableEntryFlags,
+    PageTableMemory, PageTableStats, PhysAddress, PhysMappingDest, VirtAddress, map_address,
+    map_address_step, zero_frame,
+};
 use util::sizes::TWO_MIB;
 
+/// Page size used to map the LOAD segments of the kernel with
+/// [`SegmentPlacement::Aligned`].
+pub const KERNEL_PAGE_SIZE: PageSize = PageSize::Size2MiB;
+
+/// Placement of the kernel's LOAD segments in physical memory, see
+/// [`setup_page_tables`].
+///
+/// The virtual layout of the kernel is the same for both placements; only
+/// the physical footprint and the page tables differ.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SegmentPlacement {
+    /// Each segment starts at a new 2 MiB page and is mapped with 2 MiB
+    /// pages. This needs no level 1 page tables and few TLB entries, but
+    /// wastes up to 2 MiB of physical memory per segment, e.g., for a small
+    /// read-only segment.
+    #[default]
+    Aligned,
+    /// The segments are packed tightly, each starting at a new 4 KiB page,
+    /// and are mapped with 4 KiB pages. Only the begin of the kernel is
+    /// 2 MiB aligned. This saves physical memory at the cost of one level 1
+    /// page table per 2 MiB of the kernel's virtual range and more TLB
+    /// pressure.
+    Packed,
+}
+
+impl SegmentPlacement {
+    /// Returns the placement with the given name (`aligned` or `packed`),
+    /// e.g., in configuration files.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "aligned" => Some(Self::Aligned),
+            "packed" => Some(Self::Packed),
+            _ => None,
+        }
+    }
+
+    /// Returns the page size with which the segments are placed and mapped.
+    ///
+    /// This is the page size for [`KernelFile::required_phys_memory`].
+    #[must_use]
+    pub const fn page_size(self) -> PageSize {
+        match self {
+            Self::Aligned => KERNEL_PAGE_SIZE,
+            Self::Packed => PageSize::Size4KiB,
+        }
+    }
+
+    /// Returns the number of page tables the placement needs in addition to
+    /// [`PageTablePool::required_tables`].
+    #[must_use]
+    pub fn extra_page_tables(self, kernel: &KernelFile<'_>) -> usize {
+        match self {
+            Self::Aligned => 0,
+            // One level 1 table per 2 MiB region the virtual range touches.
+            Self::Packed => {
+                let two_mib = TWO_MIB as u64;
+                let first = kernel.virt_start().0 / two_mib;
+                let last = kernel.virt_end().0.div_ceil(two_mib);
+                (last - first) as usize
+            }
+        }
+    }
+}
+
+/// Possible errors of [`setup_page_tables`] and [`setup_direct_map`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum SetupError {
+    /// The kernel doesn't fit into the virtual window reserved for it.
+    #[error(
+        "kernel needs {needed:#x} bytes at runtime but only {window:#x} bytes are reserved for it"
+    )]
+    KernelExceedsWindow {
+        /// Bytes the kernel needs at runtime.
+        needed: usize,
+        /// Size of the kernel window.
+        window: usize,
+    },
+    /// The boot information is not page-aligned.
+    #[error("boot information is misaligned")]
+    BootInfoMisaligned(#[source] AlignError),
+    /// The destination of the kernel is not 2 MiB aligned.
+    #[error("kernel destination is misaligned")]
+    KernelDestinationMisaligned(#[source] AlignError),
+    /// The destination of the kernel is too small.
+    #[error("kernel destination has {len:#x} bytes but the kernel needs {needed:#x} bytes")]
+    KernelDestinationTooSmall {
+        /// Size of the destination.
+        len: usize,
+        /// Bytes the kernel needs at runtime.
+        needed: usize,
+    },
+    /// The page of the trampoline lies within the virtual range of a kernel
+    /// LOAD segment, so one mapping would replace the other.
+    #[error(
+        "trampoline page at {trampoline:#x} overlaps kernel LOAD segment at {segment_start:#x}..{segment_end:#x}"
+    )]
+    TrampolineOverlapsKernel {
+        /// The page-aligned virtual address of the trampoline.
+        trampoline: u64,
+        /// Start of the virtual range of the segment.
+        segment_start: u64,
+        /// End (exclusive) of the mapped virtual range of the segment.
+        segment_end: u64,
+    },
+    /// The trampoline page is in the same 512 GiB region as the kernel, i.e.,
+    /// it needs the level-4 entry the kernel's page tables already use.
+    #[error("trampoline page at {trampoline:#x} shares the level-4 entry {index} with the kernel")]
+    TrampolineSharesRootEntry {
+        /// The page-aligned virtual address of the trampoline.
+        trampoline: u64,
+        /// Index of the shared entry in the root page table.
+        index: usize,
+    },
+    /// The end of the direct map overflows the address space.
+    #[error("direct map at {hhdm_offset:#x} with {phys_end:#x} bytes overflows")]
+    DirectMapOverflow {
+        /// Virtual base address of the direct map.
+        hhdm_offset: u64,
+        /// End of the physical memory to map.
+        phys_end: u64,
+    },
+    /// The direct map doesn't lie within one canonical half of the address
+    /// space.
+    #[error("direct map at {start:#x}..{end:#x} is not canonical")]
+    DirectMapNotCanonical {
+        /// Start of the virtual range of the direct map.
+        start: u64,
+        /// End (exclusive) of the virtual range of the direct map.
+        end: u64,
+    },
+    /// The direct map is in the lower half of the address space, which is
+    /// used for identity mappings during the handoff.
+    #[error("direct map at {start:#x}..{end:#x} is not in the higher half")]
+    DirectMapNotHigherHalf {
+        /// Start of the virtual range of the direct map.
+        start: u64,
+        /// End (exclusive) of the virtual range of the direct map.
+        end: u64,
+    },
+    /// The direct map overlaps the virtual window of the kernel.
+    #[error("direct map at {start:#x}..{end:#x} overlaps the kernel window at {window_start:#x}")]
+    DirectMapOverlapsKernel {
+        /// Start of the virtual range of the direct map.
+        start: u64,
+        /// End (exclusive) of the virtual range of the direct map.
+        end: u64,
+        /// Start of the kernel window.
+        window_start: u64,
+    },
+    /// The direct map overlaps the boot information.
+    #[error("direct map at {start:#x}..{end:#x} overlaps the boot information at {boot_info:#x}")]
+    DirectMapOverlapsBootInfo {
+        /// Start of the virtual range of the direct map.
+        start: u64,
+        /// End (exclusive) of the virtual range of the direct map.
+        end: u64,
+        /// Virtual address of the boot information.
+        boot_info: u64,
+    },
+    /// A mapping failed, e.g., because the page-table pool is exhausted.
+    #[error("failed to map the kernel's address space")]
+    Map(#[from] MapError),
+}
+
+/// Result of a successful [`setup_page_tables`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageTableSetup {
+    /// Physical address of the root page table (the value for `cr3`).
+    pub cr3: PhysAddress,
+    /// Number of page tables per level that were allocated.
+    pub stats: PageTableStats,
+    /// Physical base address of the kernel, i.e., of its first LOAD segment.
+    /// This is 2 MiB aligned.
+    pub kernel_phys_base: PhysAddress,
+    /// Number of bytes of physical memory used by the kernel, starting at
+    /// [`Self::kernel_phys_base`].
+    pub kernel_phys_len: usize,
+}
+
+impl PageTableSetup {
+    /// Returns the memory map entry marking the kernel's physical memory as
+    /// [`MemoryMapEntryType::Kernel`].
+    #[must_use]
+    pub const fn kernel_memory_map_entry(&self) -> MemoryMapEntry {
+        MemoryMapEntry::with_default_flags(
+            self.kernel_phys_base.0,
+            self.kernel_phys_len as u64,
+            MemoryMapEntryType::Kernel,
+        )
+    }
+}
+
 /// Prepares the page-tables for the kernel in ELF format.
 ///
 /// Loads the kernels ELF segments into properly aligned memory and ensures that
@@ -35,7 +256,15 @@ use util::sizes::TWO_MIB;
 /// The memory behind `KernelFile` can be thread afterward, if not needed for
 /// other purposes.
 ///
-/// It uses the default Rust allocator to allocate the pages.
+/// The kernel is loaded into `kernel_dst`, if provided, which lets the caller
+/// choose the physical placement. It must be 2 MiB aligned and large enough
+/// for [`KernelFile::required_phys_memory`] with
+/// [`SegmentPlacement::page_size`]. Otherwise, the default Rust allocator is
+/// used to allocate the pages for the kernel. All page tables are allocated
+/// from `pool`.
+///
+/// `placement` selects whether the segments are 2 MiB aligned or packed in
+/// physical memory, see [`SegmentPlacement`].
 ///
 /// ## Page Table Format
 /// This uses x86_64 4-level page tables.
@@ -43,15 +272,61 @@ use util::sizes::TWO_MIB;
 /// Number of page tables:
 /// - 1x Level 4 (root/PML4)
 /// - 1x Level 3
-/// - 1x kernel RX+RW+RO (2 MiB huge pages)
+/// - 1x kernel RX+RW+RO (2 MiB huge pages), plus one level 1 table per 2 MiB
+///   with [`SegmentPlacement::Packed`]
 /// - 1x trampoline
+/// - boot information (shares tables with the trampoline where possible)
+///
+/// ## Kernel Window
+/// The kernel must fit into `max_kernel_window` bytes starting at its link
+/// address, so that it can't overrun into other reserved virtual regions.
+/// This is checked before any mapping is done.
+///
+/// ## Trampoline
+/// The trampoline page is identity-mapped. It must not overlap the virtual
+/// range of any kernel LOAD segment (rounded to 2 MiB pages), as both are
+/// higher-half. This is checked before any mapping is done and reported as
+/// [`SetupError::TrampolineOverlapsKernel`]. The trampoline also gets its own
+/// level-4 entry, so it must not be in the 512 GiB region of the kernel
+/// ([`SetupError::TrampolineSharesRootEntry`]).
+///
+/// ## Boot Information
+/// The boot information region at the page-aligned `boot_info_addr` is mapped
+/// read-only and non-executable at `boot_info_vaddr`, as it is an immutable
+/// contract between the loader and the kernel.
+///
+/// ## Return Value
+/// Returns the physical address of the root page table (the value for `cr3`),
+/// the number of page tables per level that were allocated, and the physical
+/// memory of the kernel, see [`PageTableSetup`].
+#[allow(clippy::too_many_arguments)]
+#[must_use = "the page tables are useless unless loaded into cr3"]
 pub fn setup_page_tables(
     kernel: &KernelFile<'_>,
     trampoline_addr: u64,
-) -> anyhow::Result<u64 /* addr of pml4 */> {
-    let mut pt_l4 = ManuallyDrop::new(Box::new(PageTable::ZERO));
-    let mut pt_l3 = ManuallyDrop::new(Box::new(PageTable::ZERO));
-    let mut pt_l2 = ManuallyDrop::new(Box::new(PageTable::ZERO));
+    boot_info_addr: PhysAddress,
+    boot_info_len: usize,
+    boot_info_vaddr: VirtAddress,
+    max_kernel_window: usize,
+    kernel_dst: Option<&'static mut [u8]>,
+    placement: SegmentPlacement,
+    pool: &mut PageTablePool,
+) -> Result<PageTableSetup, SetupError> {
+    if kernel.total_runtime_memsize(KERNEL_PAGE_SIZE) > max_kernel_window {
+        return Err(SetupError::KernelExceedsWindow {
+            needed: kernel.total_runtime_memsize(KERNEL_PAGE_SIZE),
+            window: max_kernel_window,
+        });
+    }
+    boot_info_addr
+        .require_aligned(PAGE_SIZE as u64)
+        .map_err(SetupError::BootInfoMisaligned)?;
+    check_trampoline_overlap(kernel, trampoline_addr)?;
+
+    let alloc = |pool: &mut PageTablePool| pool.alloc().ok_or(MapError::OutOfMemory);
+    let pt_l4 = alloc(pool)?;
+    let pt_l3 = alloc(pool)?;
+    let pt_l2 = alloc(pool)?;
 
     let vaddr = kernel.virt_start();
 
@@ -60,7 +335,7 @@ pub fn setup_page_tables(
         // map l4 -> l3
         map_address_step(
             vaddr,
-            pt_l4.deref_mut(),
+            pt_l4,
             PhysMappingDest::Page(pt_l3.as_page()),
             4,
             true,
@@ -70,7 +345,7 @@ pub fn setup_page_tables(
         // map l3 -> l2
         map_address_step(
             vaddr,
-            pt_l3.deref_mut(),
+            pt_l3,
             PhysMappingDest::Page(pt_l2.as_page()),
             3,
             true,
@@ -79,11 +354,25 @@ pub fn setup_page_tables(
         );
     }
 
-    // Huge page mappings for each segment of the kernel.
-    {
-        // An aligned buffer sufficient in size.
-        let dst_buffer = AlignedBuffer::<u8>::new(kernel.total_runtime_memsize(), TWO_MIB);
-        let mut dst_buffer = ManuallyDrop::new(dst_buffer);
+    // Mappings for each segment of the kernel.
+    let page_size = placement.page_size();
+    let kernel_phys_base = {
+        let dst_buffer: &mut [u8] = if let Some(dst) = kernel_dst {
+            PhysAddress(dst.as_ptr() as u64)
+                .require_aligned(TWO_MIB as u64)
+                .map_err(SetupError::KernelDestinationMisaligned)?;
+            if dst.len() < kernel.required_phys_memory(page_size) {
+                return Err(SetupError::KernelDestinationTooSmall {
+                    len: dst.len(),
+                    needed: kernel.required_phys_memory(page_size),
+                });
+            }
+            dst
+        } else {
+            // An aligned buffer sufficient in size. It becomes the memory of
+            // the kernel, so it is never freed.
+            AlignedBuffer::<u8>::new(kernel.required_phys_memory(page_size), TWO_MIB).leak()
+        };
 
         let mut dst_buffer_offset = 0;
         let n = kernel.load_segments().count();
@@ -92,14 +381,21 @@ pub fn setup_page_tables(
             let end = dst_buffer_offset + data.len();
             let phys_dst = &mut dst_buffer[dst_buffer_offset..end];
             phys_dst.copy_from_slice(data);
+            let phys_addr = phys_dst.as_ptr() as u64;
 
-            // Step 2/2: Create mapping to memory
+            // The destination might contain garbage, but the kernel expects
+            // the memory not backed by the file, e.g., the BSS, to be zeroed.
+            let phys_end = dst_buffer_offset + KernelFile::segment_phys_size(&pr_hdr, page_size);
+            let frames_start = end.next_multiple_of(PAGE_SIZE).min(phys_end);
+            dst_buffer[end..frames_start].fill(0);
+            let dst_base = PhysAddress(dst_buffer.as_ptr() as u64);
+            for frame in (frames_start..phys_end).step_by(PAGE_SIZE) {
+                // SAFETY: The frame is part of the kernel's memory, which is
+                // owned by the loader and not yet mapped.
+                unsafe { zero_frame(pool, dst_base + frame as u64, PageSize::Size4KiB)? };
+            }
 
-            let phys_addr = phys_dst.as_ptr() as u64;
-            assert!(
-                phys_addr.is_multiple_of(TWO_MIB as u64),
-                "{phys_addr} should be huge-page aligned"
-            );
+            // Step 2/2: Create mapping to memory
 
             let write = pr_hdr.p_flags & elf::abi::PF_W != 0;
             let execute = pr_hdr.p_flags & elf::abi::PF_X != 0;
@@ -109,58 +405,90 @@ pub fn setup_page_tables(
                 execute,
                 write
             );
-            map_address_step(
-                VirtAddress(pr_hdr.p_vaddr),
-                pt_l2.deref_mut(),
-                PhysMappingDest::Addr(phys_addr),
-                2,
-                write,
-                true,
-                !execute,
-            );
+            match placement {
+                SegmentPlacement::Aligned => {
+                    assert!(
+                        phys_addr.is_multiple_of(TWO_MIB as u64),
+                        "{phys_addr} should be huge-page aligned"
+                    );
+                    // Segments and their BSS may span multiple huge pages.
+                    let len = KernelFile::segment_phys_size(&pr_hdr, page_size) as u64;
+                    for offset in (0..len).step_by(TWO_MIB) {
+                        map_address_step(
+                            VirtAddress(pr_hdr.p_vaddr) + offset,
+                            pt_l2,
+                            PhysMappingDest::Addr(phys_addr + offset),
+                            2,
+                            write,
+                            true,
+                            !execute,
+                        );
+                    }
+                }
+                SegmentPlacement::Packed => {
+                    let flags = PageTableEntryFlags {
+                        write,
+                        execute_disable: !execute,
+                        ..Default::default()
+                    };
+                    let len = KernelFile::segment_phys_size(&pr_hdr, page_size) as u64;
+                    for offset in (0..len).step_by(PAGE_SIZE) {
+                        map_address(
+                            pt_l4,
+                            pool,
+                            VirtAddress(pr_hdr.p_vaddr) + offset,
+                            PhysAddress(phys_addr) + offset,
+                            PageSize::Size4KiB,
+                            flags.clone(),
+                        )?;
+                    }
+                }
+            }
 
-            dst_buffer_offset += data.len().next_multiple_of(TWO_MIB);
+            // Advance by the size in memory, not in the file, so that the BSS
+            // of this segment doesn't overlap the next one.
+            dst_buffer_offset += KernelFile::segment_phys_size(&pr_hdr, page_size);
         }
-    }
+        PhysAddress(dst_buffer.as_ptr() as u64)
+    };
 
     // trampoline setup
     {
         debug!("Mapping trampoline next: at {trampoline_addr:#x}");
         let trampoline_addr = VirtAddress(trampoline_addr);
-        let l4_index = trampoline_addr.index(4);
-        if pt_l4.0[l4_index].flags().present {
-            panic!("l4 already present; unexpected");
-        }
 
-        let mut pt_trampoline_l3 = ManuallyDrop::new(Box::new(PageTable::ZERO));
+        // The intermediate tables are writable, as other identity mappings,
+        // e.g., of the handoff stack, may share them. The trampoline page
+        // itself is read-only.
+        let pt_trampoline_l3 = alloc(pool)?;
         map_address_step(
             trampoline_addr,
-            pt_l4.deref_mut(),
+            pt_l4,
             PhysMappingDest::Page(pt_trampoline_l3.as_page()),
             4,
-            false,
+            true,
             false,
             false,
         );
 
-        let mut pt_trampoline_l2 = ManuallyDrop::new(Box::new(PageTable::ZERO));
+        let pt_trampoline_l2 = alloc(pool)?;
         map_address_step(
             trampoline_addr,
-            pt_trampoline_l3.deref_mut(),
+            pt_trampoline_l3,
             PhysMappingDest::Page(pt_trampoline_l2.as_page()),
             3,
-            false,
+            true,
             false,
             false,
         );
 
-        let mut pt_trampoline_l1 = ManuallyDrop::new(Box::new(PageTable::ZERO));
+        let pt_trampoline_l1 = alloc(pool)?;
         map_address_step(
             trampoline_addr,
-            pt_trampoline_l2.deref_mut(),
+            pt_trampoline_l2,
             PhysMappingDest::Page(pt_trampoline_l1.as_page()),
             2,
-            false,
+            true,
             false,
             false,
         );
@@ -168,7 +496,7 @@ pub fn setup_page_tables(
         let trampoline_addr_page = trampoline_addr.0 & !(PAGE_MASK as u64);
         map_address_step(
             trampoline_addr,
-            pt_trampoline_l1.deref_mut(),
+            pt_trampoline_l1,
             PhysMappingDest::Addr(trampoline_addr_page),
             1,
             false,
@@ -177,8 +505,815 @@ pub fn setup_page_tables(
         );
     }
 
-    Ok(pt_l4.as_page().as_ptr() as u64)
+    // boot information setup
+    {
+        let len = (boot_info_len as u64).next_multiple_of(PAGE_SIZE as u64);
+        debug!(
+            "Mapping boot information next: {:#x} -> {:#x}..{:#x}",
+            boot_info_vaddr.0,
+            boot_info_addr.0,
+            (boot_info_addr + len).0
+        );
+        let flags = PageTableEntryFlags {
+            write: false,
+            execute_disable: true,
+            ..Default::default()
+        };
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            map_address(
+                pt_l4,
+                pool,
+                boot_info_vaddr + offset,
+                boot_info_addr + offset,
+                PageSize::Size4KiB,
+                flags.clone(),
+            )?;
+        }
+    }
+
+    let stats = PageTableStats::collect(pt_l4, pool);
+    Ok(PageTableSetup {
+        cr3: PhysAddress(pt_l4.as_page().as_ptr() as u64),
+        stats,
+        kernel_phys_base,
+        kernel_phys_len: kernel.required_phys_memory(page_size),
+    })
+}
+
+/// Ensures that the trampoline page doesn't lie within the virtual range of a
+/// kernel LOAD segment and doesn't share the kernel's level-4 entry.
+fn check_trampoline_overlap(
+    kernel: &KernelFile<'_>,
+    trampoline_addr: u64,
+) -> Result<(), SetupError> {
+    let trampoline = trampoline_addr & !(PAGE_MASK as u64);
+    for (pr_hdr, _) in kernel.load_segments() {
+        let segment_start = pr_hdr.p_vaddr;
+        let segment_end = segment_start + pr_hdr.p_memsz.next_multiple_of(TWO_MIB as u64);
+        if (segment_start..segment_end).contains(&trampoline) {
+            return Err(SetupError::TrampolineOverlapsKernel {
+                trampoline,
+                segment_start,
+                segment_end,
+            });
+        }
+    }
+    let index = VirtAddress(trampoline).index(4);
+    if index == kernel.virt_start().index(4) {
+        return Err(SetupError::TrampolineSharesRootEntry { trampoline, index });
+    }
+    Ok(())
+}
+
+/// Identity-maps the stack at `stack_addr` with `len` bytes into the page
+/// tables with the given root and returns the initial stack pointer.
+///
+/// The trampoline switches to this stack together with the page tables, so
+/// that there is never a window with an unmapped stack before the kernel sets
+/// up its own stack. The stack is writable but non-executable. The returned
+/// stack pointer is 16-byte aligned.
+///
+/// # Panics
+/// Panics if `stack_addr` or `len` are not page-aligned.
+pub fn setup_handoff_stack(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    stack_addr: PhysAddress,
+    len: usize,
+) -> Result<VirtAddress, MapError> {
+    assert!(len.is_multiple_of(PAGE_SIZE));
+    debug!(
+        "Mapping handoff stack: {:#x}..{:#x} (identity-mapped)",
+        stack_addr.0,
+        (stack_addr + len as u64).0
+    );
+    let flags = PageTableEntryFlags {
+        write: true,
+        execute_disable: true,
+        ..Default::default()
+    };
+    for offset in (0..len as u64).step_by(PAGE_SIZE) {
+        map_address(
+            root,
+            mem,
+            VirtAddress(stack_addr.0) + offset,
+            stack_addr + offset,
+            PageSize::Size4KiB,
+            flags.clone(),
+        )?;
+    }
+    Ok(VirtAddress(stack_addr.0) + len as u64)
+}
+
+/// Maps the physical memory `0..phys_end` linearly at `hhdm_offset` into the
+/// page tables with the given root.
+///
+/// This is the higher-half direct map (HHDM) of physical memory. It uses
+/// 2 MiB pages and is writable but non-executable. `hhdm_offset` must be
+/// validated by [`Config::validate`].
+///
+/// Before any mapping is done, the virtual range of the direct map is checked
+/// to be canonical, in the higher half, and to neither overlap `kernel_window` nor the boot
+/// information at `boot_info_vaddr`, so that a bad `hhdm_offset` can't alias
+/// the kernel's mappings.
+pub fn setup_direct_map(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    hhdm_offset: u64,
+    phys_end: u64,
+    kernel_window: Range<u64>,
+    boot_info_vaddr: VirtAddress,
+) -> Result<(), SetupError> {
+    let overflow = SetupError::DirectMapOverflow {
+        hhdm_offset,
+        phys_end,
+    };
+    let phys_end = phys_end
+        .checked_next_multiple_of(TWO_MIB as u64)
+        .ok_or(overflow)?;
+    let end = hhdm_offset.checked_add(phys_end).ok_or(overflow)?;
+    check_direct_map_range(hhdm_offset..end, kernel_window, boot_info_vaddr)?;
+    debug!("Mapping direct map next: {hhdm_offset:#x} -> 0x0..{phys_end:#x}");
+    let flags = PageTableEntryFlags {
+        write: true,
+        execute_disable: true,
+        ..Default::default()
+    };
+    for phys in (0..phys_end).step_by(TWO_MIB) {
+        map_address(
+            root,
+            mem,
+            VirtAddress(hhdm_offset) + phys,
+            PhysAddress(phys),
+            PageSize::Size2MiB,
+            flags.clone(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Checks the virtual range of the direct map, see [`setup_direct_map`].
+fn check_direct_map_range(
+    range: Range<u64>,
+    kernel_window: Range<u64>,
+    boot_info_vaddr: VirtAddress,
+) -> Result<(), SetupError> {
+    let Range { start, end } = range;
+    if start == end {
+        return Ok(());
+    }
+    let last = end - 1;
+    let same_half = (start ^ last) & (1 << 63) == 0;
+    if !VirtAddress(start).is_canonical() || !VirtAddress(last).is_canonical() || !same_half {
+        return Err(SetupError::DirectMapNotCanonical { start, end });
+    }
+    if start & (1 << 63) == 0 {
+        return Err(SetupError::DirectMapNotHigherHalf { start, end });
+    }
+    if start < kernel_window.end && kernel_window.start < end {
+        return Err(SetupError::DirectMapOverlapsKernel {
+            start,
+            end,
+            window_start: kernel_window.start,
+        });
+    }
+    if (start..end).contains(&boot_info_vaddr.0) {
+        return Err(SetupError::DirectMapOverlapsBootInfo {
+            start,
+            end,
+            boot_info: boot_info_vaddr.0,
+        });
+    }
+    Ok(())
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::test_utils::{LINK_ADDR, kernel_fixture};
+    use util::paging::{IdentityMapped, translate};
+
+    const TEST_KERNEL_WINDOW: usize = 64 * 1024 * 1024;
+
+    fn test_kernel_window() -> Range<u64> {
+        LINK_ADDR..LINK_ADDR + TEST_KERNEL_WINDOW as u64
+    }
+
+    #[test]
+    fn test_boot_information_is_mapped_read_only() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new([util::paging::Page::ZERO; 2]);
+        let boot_info_addr = boot_info.as_ptr() as u64;
+        let boot_info_len = PAGE_SIZE + 16;
+        let boot_info_vaddr = kernel_lib::BOOT_INFO_VADDR;
+
+        let PageTableSetup { cr3, .. } = setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info_addr),
+            boot_info_len,
+            boot_info_vaddr,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        )
+        .unwrap();
+
+        // SAFETY: The loader's page tables are identity-mapped.
+        let root = unsafe { &*(cr3.0 as *const PageTable) };
+        for offset in [0, boot_info_len as u64 - 1] {
+            let vaddr = boot_info_vaddr + offset;
+            let translation = translate(root, &IdentityMapped, vaddr).unwrap();
+            assert_eq!(translation.phys, PhysAddress(boot_info_addr + offset));
+            assert!(!translation.flags.write);
+            assert!(translation.flags.execute_disable);
+        }
+        assert_eq!(
+            translate(root, &IdentityMapped, VirtAddress(boot_info_addr)),
+            None
+        );
+
+        let unaligned = setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info_addr + 8),
+            boot_info_len,
+            boot_info_vaddr,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        );
+        assert_eq!(
+            unaligned.unwrap_err(),
+            SetupError::BootInfoMisaligned(AlignError {
+                addr: PhysAddress(boot_info_addr + 8),
+                align: PAGE_SIZE as u64
+            })
+        );
+    }
+
+    #[test]
+    fn test_handoff_stack_is_writable() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        // The trampoline and the stack share the identity-mapped tables.
+        let pages = Box::new([util::paging::Page::ZERO; 3]);
+        let trampoline_addr = pages[0].as_ptr() as u64;
+        let stack_addr = PhysAddress(pages[1].as_ptr() as u64);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+        let mut pool = PageTablePool::new(16);
+
+        let PageTableSetup { cr3, .. } = setup_page_tables(
+            &kernel,
+            trampoline_addr,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut pool,
+        )
+        .unwrap();
+        // SAFETY: The loader's page tables are identity-mapped.
+        let root = unsafe { &mut *(cr3.0 as *mut PageTable) };
+        let stack_top = setup_handoff_stack(root, &mut pool, stack_addr, 2 * PAGE_SIZE).unwrap();
+
+        let translation = translate(root, &IdentityMapped, stack_top - 8).unwrap();
+        assert_eq!(translation.phys, PhysAddress((stack_top - 8).0));
+        assert!(translation.flags.write);
+        let translation = translate(root, &IdentityMapped, VirtAddress(trampoline_addr)).unwrap();
+        assert!(!translation.flags.write);
+        assert!(!translation.flags.execute_disable);
+    }
+
+    #[test]
+    fn test_kernel_exceeds_window() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+
+        let res = setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            2 * TWO_MIB,
+            None,
+            SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        );
+        assert_eq!(
+            res.unwrap_err(),
+            SetupError::KernelExceedsWindow {
+                needed: 0x600000,
+                window: 0x400000
+            }
+        );
+    }
+
+    #[test]
+    fn test_trampoline_overlaps_kernel() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let boot_info = Box::new(util::paging::Page::ZERO);
+
+        // Within the mapped 2 MiB page of the RX segment, but beyond its data.
+        let trampoline_addr = kernel.virt_start().0 + TWO_MIB as u64 - 0x10;
+        let res = setup_page_tables(
+            &kernel,
+            trampoline_addr,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        );
+        assert_eq!(
+            res.unwrap_err(),
+            SetupError::TrampolineOverlapsKernel {
+                trampoline: kernel.virt_start().0 + TWO_MIB as u64 - PAGE_SIZE as u64,
+                segment_start: kernel.virt_start().0,
+                segment_end: kernel.virt_start().0 + TWO_MIB as u64,
+            }
+        );
+
+        // Outside of the kernel, but in the same 512 GiB region.
+        let trampoline_addr = 0xffff_ff80_0000_1000;
+        let res = setup_page_tables(
+            &kernel,
+            trampoline_addr,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        );
+        assert_eq!(
+            res.unwrap_err(),
+            SetupError::TrampolineSharesRootEntry {
+                trampoline: trampoline_addr,
+                index: 511,
+            }
+        );
+    }
+
+    #[test]
+    fn test_direct_map() {
+        let config = Config::default();
+        let mut root = Box::new(PageTable::ZERO);
+        setup_direct_map(
+            &mut root,
+            &mut IdentityMapped,
+            config.hhdm_offset(),
+            0x500_0000,
+            test_kernel_window(),
+            kernel_lib::BOOT_INFO_VADDR,
+        )
+        .unwrap();
+
+        let translation = translate(
+            &root,
+            &IdentityMapped,
+            VirtAddress(config.hhdm_offset() + 0x123_4567),
+        )
+        .unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x123_4567));
+        assert_eq!(translation.page_size, PageSize::Size2MiB);
+        assert!(translation.flags.write);
+        assert!(translation.flags.execute_disable);
+
+        assert!(
+            translate(
+                &root,
+                &IdentityMapped,
+                VirtAddress(config.hhdm_offset() + 0x600_0000)
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_direct_map_checks() {
+        let direct_map = |hhdm_offset, phys_end| {
+            let mut root = Box::new(PageTable::ZERO);
+            setup_direct_map(
+                &mut root,
+                &mut IdentityMapped,
+                hhdm_offset,
+                phys_end,
+                test_kernel_window(),
+                kernel_lib::BOOT_INFO_VADDR,
+            )
+        };
+
+        assert_eq!(
+            direct_map(0xffff_ffff_c000_0000, 0x4000_0000),
+            Err(SetupError::DirectMapOverflow {
+                hhdm_offset: 0xffff_ffff_c000_0000,
+                phys_end: 0x4000_0000,
+            })
+        );
+        assert_eq!(
+            direct_map(0x0000_7fff_c000_0000, 0x8000_0000),
+            Err(SetupError::DirectMapNotCanonical {
+                start: 0x0000_7fff_c000_0000,
+                end: 0x0000_8000_4000_0000,
+            })
+        );
+        assert_eq!(
+            direct_map(0, 0x4000_0000),
+            Err(SetupError::DirectMapNotHigherHalf {
+                start: 0,
+                end: 0x4000_0000,
+            })
+        );
+        // Both ends are canonical, but in different halves.
+        assert_eq!(
+            direct_map(0, 0xffff_8000_0020_0000),
+            Err(SetupError::DirectMapNotCanonical {
+                start: 0,
+                end: 0xffff_8000_0020_0000,
+            })
+        );
+        assert_eq!(
+            direct_map(0xffff_ffff_8000_0000, 0x1000_0000),
+            Err(SetupError::DirectMapOverlapsKernel {
+                start: 0xffff_ffff_8000_0000,
+                end: 0xffff_ffff_9000_0000,
+                window_start: LINK_ADDR,
+            })
+        );
+        assert_eq!(
+            direct_map(0xffff_ffff_8000_0000, 0x20_0000),
+            Err(SetupError::DirectMapOverlapsBootInfo {
+                start: 0xffff_ffff_8000_0000,
+                end: 0xffff_ffff_8020_0000,
+                boot_info: kernel_lib::BOOT_INFO_VADDR.0,
+            })
+        );
+        // Ends right below the boot information.
+        assert_eq!(direct_map(0xffff_ffff_4000_0000, 0x4000_0000), Ok(()));
+    }
+
+    #[test]
+    fn test_page_tables_are_allocated_from_pool() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+        let phys_end = 0x500_0000;
+        let mut pool = PageTablePool::new(PageTablePool::required_tables(phys_end));
+
+        let PageTableSetup { cr3, stats, .. } = setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut pool,
+        )
+        .unwrap();
+        // The boot information shares the level 3 and level 2 table with the
+        // kernel.
+        assert_eq!(
+            stats,
+            PageTableStats {
+                l4: 1,
+                l3: 2,
+                l2: 2,
+                l1: 2
+            }
+        );
+        assert_eq!(stats.to_string(), "1 L4, 2 L3, 2 L2, 2 L1 = 28 KiB");
+        assert_eq!(stats.tables(), pool.len());
+
+        // SAFETY: The loader's page tables are identity-mapped.
+        let root = unsafe { &mut *(cr3.0 as *mut PageTable) };
+        setup_direct_map(
+            root,
+            &mut pool,
+            Config::DEFAULT_HHDM_OFFSET,
+            phys_end,
+            test_kernel_window(),
+            kernel_lib::BOOT_INFO_VADDR,
+        )
+        .unwrap();
+
+        let (base, size) = pool.region();
+        let region = base.0..base.0 + size as u64;
+        assert!(region.contains(&cr3.0));
+        // 3 for the kernel, 3 for the trampoline, 1 for the boot information,
+        // and 2 for the direct map.
+        assert_eq!(pool.len(), 9);
+
+        let mut exhausted = PageTablePool::new(2);
+        let res = setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut exhausted,
+        );
+        assert_eq!(res.unwrap_err(), SetupError::Map(MapError::OutOfMemory));
+    }
+
+    #[test]
+    fn test_kernel_destination() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+        // Every call gets its own leaked buffer, so no two slices alias.
+        let new_dst = |offset: usize, len: usize| {
+            let buffer = AlignedBuffer::<u8>::new(offset + len, TWO_MIB).leak();
+            &mut buffer[offset..]
+        };
+        let setup = |kernel_dst| {
+            setup_page_tables(
+                &kernel,
+                trampoline.as_ptr() as u64,
+                PhysAddress(boot_info.as_ptr() as u64),
+                PAGE_SIZE,
+                kernel_lib::BOOT_INFO_VADDR,
+                TEST_KERNEL_WINDOW,
+                Some(kernel_dst),
+                SegmentPlacement::Aligned,
+                &mut PageTablePool::new(16),
+            )
+        };
+
+        let len = kernel.required_phys_memory(KERNEL_PAGE_SIZE);
+        let dst = new_dst(0, len);
+        dst.fill(0xaa);
+        let dst_ptr = dst.as_mut_ptr();
+        let result = setup(dst).unwrap();
+        assert_eq!(result.kernel_phys_base, PhysAddress(dst_ptr as u64));
+        assert!(result.kernel_phys_base.0.is_multiple_of(TWO_MIB as u64));
+        assert_eq!(result.kernel_phys_len, len);
+        let entry = result.kernel_memory_map_entry();
+        assert_eq!(
+            (entry.from, entry.length),
+            (result.kernel_phys_base.0, len as u64)
+        );
+        assert_eq!(entry.typ, MemoryMapEntryType::Kernel);
+        // SAFETY: The loader's page tables are identity-mapped.
+        let root = unsafe { &*(result.cr3.0 as *const PageTable) };
+        let translation = translate(root, &IdentityMapped, kernel.virt_start()).unwrap();
+        assert_eq!(translation.phys, result.kernel_phys_base);
+        // SAFETY: The buffer is leaked and setup_page_tables() no longer
+        // accesses it.
+        let dst = unsafe { core::slice::from_raw_parts(dst_ptr, len) };
+        // Memory not backed by the file is zeroed.
+        assert!(dst[0x1800..TWO_MIB].iter().all(|&byte| byte == 0));
+
+        let dst = new_dst(PAGE_SIZE, len);
+        let dst_addr = dst.as_ptr() as u64;
+        assert_eq!(
+            setup(dst).unwrap_err(),
+            SetupError::KernelDestinationMisaligned(AlignError {
+                addr: PhysAddress(dst_addr),
+                align: TWO_MIB as u64
+            })
+        );
+        assert_eq!(
+            setup(new_dst(0, len - 1)).unwrap_err(),
+            SetupError::KernelDestinationTooSmall {
+                len: len - 1,
+                needed: len
+            }
+        );
+    }
+
+    #[test]
+    fn test_kernel_with_bss() {
+        let two_mib = TWO_MIB as u64;
+        let mut fixture = kernel_fixture();
+        // The read-only segment gets BSS spanning into a second 2 MiB page.
+        fixture.segments[1].p_memsz = two_mib + 0x1000;
+        fixture.segments[2].p_vaddr = LINK_ADDR + 3 * two_mib;
+        let bytes = fixture.build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let len = kernel.required_phys_memory(KERNEL_PAGE_SIZE);
+        assert_eq!(len, 4 * TWO_MIB);
+
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+        // One guard page behind the kernel to detect out-of-bounds writes.
+        let dst = AlignedBuffer::<u8>::new(len + PAGE_SIZE, TWO_MIB).leak();
+        dst.fill(0xaa);
+        let dst_addr = dst.as_ptr() as u64;
+        let (kernel_dst, guard) = dst.split_at_mut(len);
+
+        let setup = setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            TEST_KERNEL_WINDOW,
+            Some(kernel_dst),
+            SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        )
+        .unwrap();
+        assert_eq!(setup.kernel_phys_len, len);
+        assert!(guard.iter().all(|&byte| byte == 0xaa));
+
+        // SAFETY: The loader's page tables are identity-mapped.
+        let root = unsafe { &*(setup.cr3.0 as *const PageTable) };
+        let translation = translate(root, &IdentityMapped, VirtAddress(LINK_ADDR + 3 * two_mib));
+        assert_eq!(
+            translation.unwrap().phys,
+            PhysAddress(dst_addr + 3 * two_mib)
+        );
+        // The BSS in the second huge page of the read-only segment is mapped.
+        let translation = translate(
+            root,
+            &IdentityMapped,
+            VirtAddress(LINK_ADDR + 2 * two_mib + 0x10),
+        )
+        .unwrap();
+        assert_eq!(translation.phys, PhysAddress(dst_addr + 2 * two_mib + 0x10));
+        assert!(!translation.flags.write);
+        // SAFETY: The buffer is still alive.
+        let kernel_mem = unsafe { core::slice::from_raw_parts(dst_addr as *const u8, len) };
+        assert_eq!(kernel_mem[3 * TWO_MIB], 0xbb);
+        // The BSS is zeroed.
+        assert!(
+            kernel_mem[TWO_MIB + 0x800..3 * TWO_MIB]
+                .iter()
+                .all(|&byte| byte == 0)
+        );
+    }
+
+    #[test]
+    fn test_kernel_with_large_bss() {
+        // The RW segment holds the stack and heap statics of the kernel in its
+        // BSS, which span many huge pages.
+        let bss = 32 * 1024 * 1024 + 128 * 1024;
+        let mut fixture = kernel_fixture();
+        fixture.segments[2].p_memsz = bss;
+        let bytes = fixture.build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+
+        let setup = setup_page_tables(
+            &kernel,
+            trampoline.as_ptr() as u64,
+            PhysAddress(boot_info.as_ptr() as u64),
+            PAGE_SIZE,
+            kernel_lib::BOOT_INFO_VADDR,
+            TEST_KERNEL_WINDOW,
+            None,
+            SegmentPlacement::Aligned,
+            &mut PageTablePool::new(16),
+        )
+        .unwrap();
+
+        // SAFETY: The loader's page tables are identity-mapped.
+        let root = unsafe { &*(setup.cr3.0 as *const PageTable) };
+        let rw_start = LINK_ADDR + 2 * TWO_MIB as u64;
+        let last = VirtAddress(rw_start + bss - 1);
+        let translation = translate(root, &IdentityMapped, last).unwrap();
+        assert_eq!(
+            translation.phys,
+            setup.kernel_phys_base + (last.0 - LINK_ADDR)
+        );
+        assert!(translation.flags.write);
+        assert!(translation.flags.execute_disable);
+    }
+
+    #[test]
+    fn test_segment_placement() {
+        let bytes = kernel_fixture().build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+        let setup = |placement| {
+            let mut pool = PageTablePool::new(16);
+            let setup = setup_page_tables(
+                &kernel,
+                trampoline.as_ptr() as u64,
+                PhysAddress(boot_info.as_ptr() as u64),
+                PAGE_SIZE,
+                kernel_lib::BOOT_INFO_VADDR,
+                TEST_KERNEL_WINDOW,
+                None,
+                placement,
+                &mut pool,
+            )
+            .unwrap();
+            assert_eq!(setup.stats.tables(), pool.len());
+            setup
+        };
+
+        let aligned = setup(SegmentPlacement::Aligned);
+        let packed = setup(SegmentPlacement::Packed);
+        assert_eq!(aligned.kernel_phys_len, 3 * TWO_MIB);
+        assert_eq!(packed.kernel_phys_len, 0x2000 + 0x1000 + 0x1000);
+        assert!(packed.kernel_phys_base.0.is_multiple_of(TWO_MIB as u64));
+        assert_eq!(SegmentPlacement::Aligned.extra_page_tables(&kernel), 0);
+        assert_eq!(SegmentPlacement::Packed.extra_page_tables(&kernel), 3);
+        assert_eq!(packed.stats.l1, aligned.stats.l1 + 3);
+
+        // SAFETY: The loader's page tables are identity-mapped.
+        let root = unsafe { &*(packed.cr3.0 as *const PageTable) };
+        let base = packed.kernel_phys_base.0;
+        let two_mib = TWO_MIB as u64;
+        let expected = [
+            (LINK_ADDR + 0x1000, base + 0x1000, false, false),
+            (LINK_ADDR + two_mib, base + 0x2000, false, true),
+            (LINK_ADDR + 2 * two_mib, base + 0x3000, true, true),
+        ];
+        for (vaddr, phys, write, execute_disable) in expected {
+            let translation = translate(root, &IdentityMapped, VirtAddress(vaddr)).unwrap();
+            assert_eq!(translation.phys, PhysAddress(phys));
+            assert_eq!(translation.page_size, PageSize::Size4KiB);
+            assert_eq!(translation.flags.write, write);
+            assert_eq!(translation.flags.execute_disable, execute_disable);
+        }
+        // Only the mapped pages of each segment are present.
+        assert!(translate(root, &IdentityMapped, VirtAddress(LINK_ADDR + 0x2000)).is_none());
+        // SAFETY: The kernel memory is leaked.
+        let kernel_mem =
+            unsafe { core::slice::from_raw_parts(base as *const u8, packed.kernel_phys_len) };
+        assert_eq!(kernel_mem[0x17ff], 0xcc);
+        assert_eq!(kernel_mem[0x1800], 0);
+        assert_eq!(kernel_mem[0x2000], 0xaa);
+        assert_eq!(kernel_mem[0x3000], 0xbb);
+
+        assert_eq!(
+            SegmentPlacement::from_name("packed"),
+            Some(SegmentPlacement::Packed)
+        );
+        assert_eq!(SegmentPlacement::from_name("tight"), None);
+    }
+
+    #[test]
+    fn test_packed_segment_spanning_2mib_regions() {
+        let two_mib = TWO_MIB as u64;
+        let mut fixture = kernel_fixture();
+        // BSS reaching across the 2 MiB boundary into a fourth region.
+        fixture.segments[2].p_memsz = two_mib + 0x1000;
+        let bytes = fixture.build();
+        let kernel = KernelFile::from_bytes(&bytes).unwrap();
+        assert_eq!(SegmentPlacement::Packed.extra_page_tables(&kernel), 4);
+
+        let trampoline = Box::new(util::paging::Page::ZERO);
+        let boot_info = Box::new(util::paging::Page::ZERO);
+        let setup = |placement| {
+            setup_page_tables(
+                &kernel,
+                trampoline.as_ptr() as u64,
+                PhysAddress(boot_info.as_ptr() as u64),
+                PAGE_SIZE,
+                kernel_lib::BOOT_INFO_VADDR,
+                TEST_KERNEL_WINDOW,
+                None,
+                placement,
+                &mut PageTablePool::new(16),
+            )
+            .unwrap()
+        };
+        let aligned = setup(SegmentPlacement::Aligned);
+        let packed = setup(SegmentPlacement::Packed);
+        assert_eq!(packed.stats.l1, aligned.stats.l1 + 4);
+    }
+
+    #[test]
+    fn test_handoff_stack() {
+        let stack = Box::new([util::paging::Page::ZERO; 2]);
+        let stack_addr = PhysAddress(stack.as_ptr() as u64);
+        let mut root = Box::new(PageTable::ZERO);
+
+        let stack_top =
+            setup_handoff_stack(&mut root, &mut IdentityMapped, stack_addr, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(stack_top, VirtAddress(stack_addr.0 + 2 * PAGE_SIZE as u64));
+        assert!(stack_top.0.is_multiple_of(16));
+
+        let translation = translate(&root, &IdentityMapped, stack_top - 8).unwrap();
+        assert_eq!(translation.phys, PhysAddress((stack_top - 8).0));
+        assert!(translation.flags.write);
+        assert!(translation.flags.execute_disable);
+        assert!(translate(&root, &IdentityMapped, stack_top).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+//! Conversion of UEFI memory types to [`MemoryMapEntryType`].
+//!
+//! This lives here rather than in [`kernel_lib`], as the kernel must not
+//! depend on UEFI definitions. The conversion is only available with the
+//! `uefi` feature, so that other users of this library don't depend on the
+//! `uefi` crate.
+
+use kernel_lib::MemoryMapEntryType;
+
+/// Memory types of the UEFI specification (`EFI_MEMORY_TYPE`).
+mod uefi_type {
+    pub const RESERVED: u32 = 0;
+    pub const LOADER_CODE: u32 = 1;
+    pub const LOADER_DATA: u32 = 2;
+    pub const BOOT_SERVICES_CODE: u32 = 3;
+    pub const BOOT_SERVICES_DATA: u32 = 4;
+    pub const RUNTIME_SERVICES_CODE: u32 = 5;
+    pub const RUNTIME_SERVICES_DATA: u32 = 6;
+    pub const CONVENTIONAL: u32 = 7;
+    pub const UNUSABLE: u32 = 8;
+    pub const ACPI_RECLAIM: u32 = 9;
+    pub const ACPI_NON_VOLATILE: u32 = 10;
+    pub const MMIO: u32 = 11;
+    pub const MMIO_PORT_SPACE: u32 = 12;
+    pub const PAL_CODE: u32 = 13;
+    pub const PERSISTENT_MEMORY: u32 = 14;
+    pub const UNACCEPTED: u32 = 15;
+}
+
+/// Extension of [`MemoryMapEntryType`] for the UEFI loader.
+#[cfg(feature = "uefi")]
+pub trait MemoryMapEntryTypeExt {
+    /// Returns the type of memory map entries for UEFI memory of the type
+    /// `typ`.
+    ///
+    /// Memory of the loader itself becomes [`MemoryMapEntryType::LoaderData`],
+    /// as it is in use until the kernel took over. Memory of the boot
+    /// services is free once they were exited. Unrecognized types, such as
+    /// OEM-defined ones, become [`MemoryMapEntryType::Firmware`], so that the
+    /// kernel never uses them.
+    fn from_uefi(typ: uefi::boot::MemoryType) -> Self;
+}
+
+#[cfg(feature = "uefi")]
+impl MemoryMapEntryTypeExt for MemoryMapEntryType {
+    fn from_uefi(typ: uefi::boot::MemoryType) -> Self {
+        from_raw_uefi_type(typ.0)
+    }
+}
+
+/// Implementation of [`MemoryMapEntryTypeExt::from_uefi`] for the raw value
+/// of the UEFI memory type.
+const fn from_raw_uefi_type(typ: u32) -> MemoryMapEntryType {
+    use uefi_type::*;
+    match typ {
+        CONVENTIONAL | BOOT_SERVICES_CODE | BOOT_SERVICES_DATA => MemoryMapEntryType::AvailableRam,
+        LOADER_CODE | LOADER_DATA => MemoryMapEntryType::LoaderData,
+        ACPI_RECLAIM => MemoryMapEntryType::AcpiReclaimable,
+        RUNTIME_SERVICES_CODE | RUNTIME_SERVICES_DATA | ACPI_NON_VOLATILE | PAL_CODE => {
+            MemoryMapEntryType::Firmware
+        }
+        MMIO | MMIO_PORT_SPACE => MemoryMapEntryType::Mmio,
+        // Unaccepted memory must be accepted before it can be used, which
+        // the kernel doesn't support.
+        RESERVED | UNUSABLE | PERSISTENT_MEMORY | UNACCEPTED => MemoryMapEntryType::Reserved,
+        _ => MemoryMapEntryType::Firmware,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_uefi() {
+        let expected = [
+            (uefi_type::RESERVED, MemoryMapEntryType::Reserved),
+            (uefi_type::LOADER_CODE, MemoryMapEntryType::LoaderData),
+            (uefi_type::LOADER_DATA, MemoryMapEntryType::LoaderData),
+            (
+                uefi_type::BOOT_SERVICES_CODE,
+                MemoryMapEntryType::AvailableRam,
+            ),
+            (
+                uefi_type::BOOT_SERVICES_DATA,
+                MemoryMapEntryType::AvailableRam,
+            ),
+            (
+                uefi_type::RUNTIME_SERVICES_CODE,
+                MemoryMapEntryType::Firmware,
+            ),
+            (
+                uefi_type::RUNTIME_SERVICES_DATA,
+                MemoryMapEntryType::Firmware,
+            ),
+            (uefi_type::CONVENTIONAL, MemoryMapEntryType::AvailableRam),
+            (uefi_type::UNUSABLE, MemoryMapEntryType::Reserved),
+            (uefi_type::ACPI_RECLAIM, MemoryMapEntryType::AcpiReclaimable),
+            (uefi_type::ACPI_NON_VOLATILE, MemoryMapEntryType::Firmware),
+            (uefi_type::MMIO, MemoryMapEntryType::Mmio),
+            (uefi_type::MMIO_PORT_SPACE, MemoryMapEntryType::Mmio),
+            (uefi_type::PAL_CODE, MemoryMapEntryType::Firmware),
+            (uefi_type::PERSISTENT_MEMORY, MemoryMapEntryType::Reserved),
+            (uefi_type::UNACCEPTED, MemoryMapEntryType::Reserved),
+            // Unrecognized types: the end marker, OEM and OS loader ranges.
+            (16, MemoryMapEntryType::Firmware),
+            (0x7000_0000, MemoryMapEntryType::Firmware),
+            (0x8000_0000, MemoryMapEntryType::Firmware),
+        ];
+        for (typ, expected) in expected {
+            assert_eq!(from_raw_uefi_type(typ), expected, "type {typ}");
+        }
+    }
+}
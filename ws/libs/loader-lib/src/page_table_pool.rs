@@ -0,0 +1,131 @@
+//! Pool for the page tables the loader builds for the kernel.
+
+use util::paging::{PAGE_SIZE, PageTable, PageTableMemory, PhysAddress};
+use util::sizes::ONE_GIB;
+
+/// Contiguous region of page tables from which the loader allocates all page
+/// tables of the kernel's initial address space.
+///
+/// The tables must outlive the loader and are therefore leaked. As they live
+/// in a single region, the kernel can reclaim them as a whole once it switched
+/// to its own page tables. See [`Self::region`]. In debug builds, all tables
+/// of the pool count as leaked, see [`util::paging::leaked_table_count`].
+///
+/// Like [`util::paging::IdentityMapped`], this assumes that physical memory is
+/// identity-mapped.
+#[derive(Debug)]
+pub struct PageTablePool {
+    base: *mut PageTable,
+    capacity: usize,
+    next: usize,
+}
+
+impl PageTablePool {
+    /// Number of tables needed independent of the amount of physical memory:
+    /// up to three tables each for the kernel, the trampoline, the boot
+    /// information, and the handoff stack, plus the root table.
+    const FIXED_TABLES: usize = 13;
+
+    /// Creates a new pool with space for `capacity` page tables.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let tables = vec![PageTable::ZERO; capacity].into_boxed_slice();
+        let base = Box::leak(tables).as_mut_ptr();
+        #[cfg(debug_assertions)]
+        util::paging::record_leaked_tables(capacity);
+        Self {
+            base,
+            capacity,
+            next: 0,
+        }
+    }
+
+    /// Returns the number of page tables needed to map the kernel, the
+    /// trampoline, the boot information, the handoff stack, and a direct map
+    /// of the physical memory `0..phys_end` using 2 MiB pages.
+    #[must_use]
+    pub const fn required_tables(phys_end: u64) -> usize {
+        let l2_tables = phys_end.div_ceil(ONE_GIB as u64);
+        let l3_tables = l2_tables.div_ceil(512);
+        Self::FIXED_TABLES + l3_tables as usize + l2_tables as usize
+    }
+
+    /// Allocates a zeroed page table from the pool.
+    ///
+    /// Returns `None` if the pool is exhausted.
+    pub fn alloc(&mut self) -> Option<&'static mut PageTable> {
+        if self.next == self.capacity {
+            return None;
+        }
+        // SAFETY: The memory is leaked and each table is handed out only once.
+        let table = unsafe { &mut *self.base.add(self.next) };
+        debug_assert!(table.is_zero(), "table should be unused");
+        self.next += 1;
+        Some(table)
+    }
+
+    /// Returns the number of allocated page tables.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.next
+    }
+
+    /// Returns whether no page table was allocated yet.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.next == 0
+    }
+
+    /// Returns the physical base address and the size in bytes of the whole
+    /// pool, including unused tables.
+    ///
+    /// The kernel can reclaim this region after switching to its own page
+    /// tables.
+    #[must_use]
+    pub fn region(&self) -> (PhysAddress, usize) {
+        (PhysAddress(self.base as u64), self.capacity * PAGE_SIZE)
+    }
+}
+
+impl PageTableMemory for PageTablePool {
+    fn table_ptr(&self, phys: PhysAddress) -> Option<*mut PageTable> {
+        (phys.0 != 0).then_some(phys.0 as *mut PageTable)
+    }
+
+    fn alloc_table(&mut self) -> Option<PhysAddress> {
+        self.alloc()
+            .map(|table| PhysAddress(table.as_page().as_ptr() as u64))
+    }
+
+    unsafe fn frame_ptr(&mut self, phys: PhysAddress, _len: usize) -> Option<*mut u8> {
+        // The loader runs with identity-mapped physical memory.
+        (phys.0 != 0).then_some(phys.0 as *mut u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool() {
+        let mut pool = PageTablePool::new(2);
+        let (base, size) = pool.region();
+        assert!(base.0.is_multiple_of(PAGE_SIZE as u64));
+        assert_eq!(size, 2 * PAGE_SIZE);
+
+        let a = pool.alloc_table().unwrap();
+        let b = pool.alloc_table().unwrap();
+        assert_eq!(a, base);
+        assert_eq!(b, base + PAGE_SIZE as u64);
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.alloc_table(), None);
+    }
+
+    #[test]
+    fn test_required_tables() {
+        assert_eq!(PageTablePool::required_tables(0), 13);
+        assert_eq!(PageTablePool::required_tables(1), 15);
+        assert_eq!(PageTablePool::required_tables(4 * ONE_GIB as u64), 18);
+    }
+}
@@ -0,0 +1,296 @@
+//! Helpers for unit tests, such as a builder for ELF fixtures.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use elf::abi::{PF_R, PF_W, PF_X, PT_LOAD};
+use util::sizes::TWO_MIB;
+
+/// The link address expected by [`crate::KernelFile`].
+pub const LINK_ADDR: u64 = 0xffffffff88200000;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+/// File offset of the first segment's data.
+const DATA_OFFSET: usize = 0x1000;
+
+/// Description of a segment of an ELF fixture.
+#[derive(Clone, Debug)]
+pub struct SegmentSpec {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_vaddr: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+    pub data: Vec<u8>,
+}
+
+impl SegmentSpec {
+    /// Creates a LOAD segment with `memsz == filesz`.
+    pub fn load(p_flags: u32, p_vaddr: u64, data: Vec<u8>) -> Self {
+        Self {
+            p_type: PT_LOAD,
+            p_flags,
+            p_vaddr,
+            p_memsz: data.len() as u64,
+            p_align: TWO_MIB as u64,
+            data,
+        }
+    }
+}
+
+/// Builder for minimal ELF64 x86_64 files.
+#[derive(Clone, Debug)]
+pub struct ElfBuilder {
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_entry: u64,
+    pub segments: Vec<SegmentSpec>,
+    /// Symbols for the `.symtab`: name, type, value, and size.
+    pub symbols: Vec<(String, u8, u64, u64)>,
+    /// Relocations for the `.rela.dyn`: offset, type, and addend.
+    pub relas: Vec<(u64, u32, i64)>,
+}
+
+impl ElfBuilder {
+    /// Creates a builder for an executable without segments.
+    pub fn new(e_entry: u64) -> Self {
+        Self {
+            e_type: elf::abi::ET_EXEC,
+            e_machine: elf::abi::EM_X86_64,
+            e_entry,
+            segments: Vec::new(),
+            symbols: Vec::new(),
+            relas: Vec::new(),
+        }
+    }
+
+    /// Adds a segment.
+    pub fn segment(mut self, segment: SegmentSpec) -> Self {
+        self.segments.push(segment);
+        self
+    }
+
+    /// Adds a function symbol. Without symbols, the file has no section
+    /// headers, like a stripped kernel.
+    pub fn symbol(mut self, name: &str, value: u64) -> Self {
+        self.symbols
+            .push((name.into(), elf::abi::STT_FUNC, value, 0));
+        self
+    }
+
+    /// Adds a data symbol, e.g., for a static.
+    pub fn object(mut self, name: &str, value: u64, size: u64) -> Self {
+        self.symbols
+            .push((name.into(), elf::abi::STT_OBJECT, value, size));
+        self
+    }
+
+    /// Adds a relocation to the `.rela.dyn`.
+    pub fn rela(mut self, offset: u64, typ: u32, addend: i64) -> Self {
+        self.relas.push((offset, typ, addend));
+        self
+    }
+
+    /// Builds the ELF file.
+    ///
+    /// The segment data is placed page-aligned behind the headers. Symbols
+    /// and relocations are placed behind the segment data.
+    pub fn build(&self) -> Vec<u8> {
+        let mut offsets = Vec::new();
+        let mut next_offset = DATA_OFFSET;
+        for segment in &self.segments {
+            offsets.push(next_offset);
+            next_offset += segment.data.len().next_multiple_of(DATA_OFFSET);
+        }
+
+        let mut bytes = Vec::new();
+
+        // ELF header
+        bytes.extend_from_slice(&[0x7f, b'E', b'L', b'F']);
+        bytes.push(elf::abi::ELFCLASS64);
+        bytes.push(elf::abi::ELFDATA2LSB);
+        bytes.push(elf::abi::EV_CURRENT);
+        bytes.resize(16, 0);
+        bytes.extend_from_slice(&self.e_type.to_le_bytes());
+        bytes.extend_from_slice(&self.e_machine.to_le_bytes());
+        bytes.extend_from_slice(&(elf::abi::EV_CURRENT as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.e_entry.to_le_bytes());
+        bytes.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes()); // e_phoff
+        bytes.extend_from_slice(&0_u64.to_le_bytes()); // e_shoff
+        bytes.extend_from_slice(&0_u32.to_le_bytes()); // e_flags
+        bytes.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        bytes.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.segments.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&64_u16.to_le_bytes()); // e_shentsize
+        bytes.extend_from_slice(&0_u16.to_le_bytes()); // e_shnum
+        bytes.extend_from_slice(&0_u16.to_le_bytes()); // e_shstrndx
+        assert_eq!(bytes.len(), EHDR_SIZE);
+
+        // Program headers
+        for (segment, offset) in self.segments.iter().zip(&offsets) {
+            bytes.extend_from_slice(&segment.p_type.to_le_bytes());
+            bytes.extend_from_slice(&segment.p_flags.to_le_bytes());
+            bytes.extend_from_slice(&(*offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&segment.p_vaddr.to_le_bytes());
+            bytes.extend_from_slice(&segment.p_vaddr.to_le_bytes()); // p_paddr
+            bytes.extend_from_slice(&(segment.data.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&segment.p_memsz.to_le_bytes());
+            bytes.extend_from_slice(&segment.p_align.to_le_bytes());
+        }
+
+        // Segment data
+        for (segment, offset) in self.segments.iter().zip(&offsets) {
+            bytes.resize(*offset, 0);
+            bytes.extend_from_slice(&segment.data);
+        }
+
+        if !self.symbols.is_empty() || !self.relas.is_empty() {
+            self.append_sections(&mut bytes);
+        }
+
+        bytes
+    }
+
+    /// Appends a `.strtab` and a `.symtab` (if there are symbols), a
+    /// `.rela.dyn` (if there are relocations), a `.shstrtab`, and the section
+    /// headers for them.
+    fn append_sections(&self, bytes: &mut Vec<u8>) {
+        // name, type, offset, size, link, entsize
+        let mut sections = Vec::new();
+
+        if !self.symbols.is_empty() {
+            let strtab_offset = bytes.len();
+            bytes.push(0);
+            let mut name_offsets = Vec::new();
+            for (name, ..) in &self.symbols {
+                name_offsets.push(bytes.len() - strtab_offset);
+                bytes.extend_from_slice(name.as_bytes());
+                bytes.push(0);
+            }
+            let strtab_size = bytes.len() - strtab_offset;
+
+            bytes.resize(bytes.len().next_multiple_of(8), 0);
+            let symtab_offset = bytes.len();
+            bytes.resize(bytes.len() + SYM_SIZE, 0); // null symbol
+            for ((_, st_type, value, size), name_offset) in self.symbols.iter().zip(name_offsets) {
+                bytes.extend_from_slice(&(name_offset as u32).to_le_bytes());
+                bytes.push(elf::abi::STB_GLOBAL << 4 | st_type);
+                bytes.push(0); // st_other
+                bytes.extend_from_slice(&1_u16.to_le_bytes()); // st_shndx
+                bytes.extend_from_slice(&value.to_le_bytes());
+                bytes.extend_from_slice(&size.to_le_bytes());
+            }
+            let symtab_size = bytes.len() - symtab_offset;
+
+            // The `.strtab` directly follows the `.symtab`.
+            let strtab_index = sections.len() + 2;
+            sections.push((
+                ".symtab",
+                elf::abi::SHT_SYMTAB,
+                symtab_offset,
+                symtab_size,
+                strtab_index,
+                SYM_SIZE,
+            ));
+            sections.push((
+                ".strtab",
+                elf::abi::SHT_STRTAB,
+                strtab_offset,
+                strtab_size,
+                0,
+                0,
+            ));
+        }
+
+        if !self.relas.is_empty() {
+            bytes.resize(bytes.len().next_multiple_of(8), 0);
+            let rela_offset = bytes.len();
+            for (offset, typ, addend) in &self.relas {
+                bytes.extend_from_slice(&offset.to_le_bytes());
+                bytes.extend_from_slice(&u64::from(*typ).to_le_bytes()); // r_info, r_sym = 0
+                bytes.extend_from_slice(&addend.to_le_bytes());
+            }
+            let rela_size = bytes.len() - rela_offset;
+            sections.push((
+                ".rela.dyn",
+                elf::abi::SHT_RELA,
+                rela_offset,
+                rela_size,
+                0,
+                RELA_SIZE,
+            ));
+        }
+
+        let shstrtab_offset = bytes.len();
+        bytes.push(0);
+        let mut name_offsets = Vec::new();
+        for name in sections
+            .iter()
+            .map(|section| section.0)
+            .chain([".shstrtab"])
+        {
+            name_offsets.push(bytes.len() - shstrtab_offset);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(0);
+        }
+        let shstrtab_size = bytes.len() - shstrtab_offset;
+        sections.push((
+            ".shstrtab",
+            elf::abi::SHT_STRTAB,
+            shstrtab_offset,
+            shstrtab_size,
+            0,
+            0,
+        ));
+
+        // Section headers: null, followed by the sections from above
+        bytes.resize(bytes.len().next_multiple_of(8), 0);
+        let shdr_offset = bytes.len();
+        bytes.resize(bytes.len() + SHDR_SIZE, 0);
+        for ((_, sh_type, offset, size, link, entsize), name_offset) in
+            sections.iter().zip(name_offsets)
+        {
+            bytes.extend_from_slice(&(name_offset as u32).to_le_bytes());
+            bytes.extend_from_slice(&sh_type.to_le_bytes());
+            bytes.extend_from_slice(&0_u64.to_le_bytes()); // sh_flags
+            bytes.extend_from_slice(&0_u64.to_le_bytes()); // sh_addr
+            bytes.extend_from_slice(&(*offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&(*size as u64).to_le_bytes());
+            bytes.extend_from_slice(&(*link as u32).to_le_bytes());
+            bytes.extend_from_slice(&0_u32.to_le_bytes()); // sh_info
+            bytes.extend_from_slice(&8_u64.to_le_bytes()); // sh_addralign
+            bytes.extend_from_slice(&(*entsize as u64).to_le_bytes());
+        }
+
+        // Patch e_shoff, e_shnum, and e_shstrndx in the ELF header.
+        let shnum = sections.len() as u16 + 1;
+        bytes[0x28..0x30].copy_from_slice(&(shdr_offset as u64).to_le_bytes());
+        bytes[0x3c..0x3e].copy_from_slice(&shnum.to_le_bytes());
+        bytes[0x3e..0x40].copy_from_slice(&(shnum - 1).to_le_bytes());
+    }
+}
+
+/// Returns a builder for a kernel with the three LOAD segments `rx`, `ro`,
+/// and `rw`, each one 2 MiB page apart.
+pub fn kernel_fixture() -> ElfBuilder {
+    let two_mib = TWO_MIB as u64;
+    ElfBuilder::new(LINK_ADDR)
+        .segment(SegmentSpec::load(
+            PF_R | PF_X,
+            LINK_ADDR,
+            vec![0xcc; 0x1800],
+        ))
+        .segment(SegmentSpec::load(
+            PF_R,
+            LINK_ADDR + two_mib,
+            vec![0xaa; 0x800],
+        ))
+        .segment(SegmentSpec::load(
+            PF_R | PF_W,
+            LINK_ADDR + 2 * two_mib,
+            vec![0xbb; 0x1000],
+        ))
+}
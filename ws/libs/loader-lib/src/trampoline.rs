@@ -0,0 +1,280 @@
+//! The trampoline that switches to the page tables of the kernel and the
+//! verification of its machine code.
+//!
+//! The trampoline runs while `cr3` changes: it must not use the stack, as the
+//! stack of the loader is not mapped in the new page tables, and it must not
+//! reference data relative to `rip`, as only its own page is mapped. This
+//! module checks the instructions against a small allow list, so that an
+//! edit of the trampoline can't silently break these rules. The tests run
+//! this check on the actual trampoline.
+
+use kernel_lib::BootInformation;
+use thiserror::Error;
+use util::paging::{PAGE_SIZE, VirtAddress};
+
+/// Trampoline in UEFI loader to jump to kernel.
+///
+/// This is the only part of the loader that will be mapped in the initial page
+/// tables of the loader.
+///
+/// # Alignment
+/// The trampoline is aligned to `16` bytes to prevent its instructions from
+/// crossing a page boundary. The `n` (`16`) must be greater or equal to the
+/// size of the instructions.
+///
+/// The tests verify this, and that only stack-free, position-independent
+/// instructions are used; see [`verify_trampoline`].
+///
+/// # Arguments
+///
+/// The arguments passed using the SystemV ABI calling convention.
+/// - `new_cr3` (`rdi`): the new root page table
+/// - `kernel_addr` (`rsi`): the entry point of the kernel
+/// - `boot_info` (`rdx`): the boot information, passed to the kernel in `rdi`;
+///   must be [`kernel_lib::BOOT_INFO_VADDR`]
+/// - `stack_top` (`rcx`): the initial stack pointer, loaded into `rsp`
+///   together with the new page tables; must be 16-byte aligned and mapped in
+///   the new page tables
+///
+/// The stack of the loader is not mapped in the new page tables. Switching
+/// `cr3` and `rsp` back to back ensures that there is no window with an
+/// unmapped stack before the kernel sets up its own stack.
+///
+/// # Safety
+/// The new page tables must identity-map the page of the trampoline and map
+/// the kernel, the boot information, and the stack. This never returns.
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+pub unsafe extern "sysv64" fn jump_to_kernel_trampoline(
+    new_cr3: u64,
+    kernel_addr: VirtAddress,
+    boot_info: *const BootInformation,
+    stack_top: VirtAddress,
+) -> ! {
+    core::arch::naked_asm!(
+        // align:
+        ".balign 16",
+        "mov %rdi, %cr3",
+        "mov %rcx, %rsp",
+        "mov %rdx, %rdi",
+        "jmp *%rsi",
+        "ud2",
+        options(att_syntax)
+    )
+}
+
+/// Possible errors of [`verify_trampoline`].
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum TrampolineError {
+    /// An instruction is not on the allow list.
+    #[error("unexpected instruction at offset {offset} (first byte {byte:#04x})")]
+    UnexpectedInstruction {
+        /// Offset of the instruction from the begin of the trampoline.
+        offset: usize,
+        /// First byte of the instruction.
+        byte: u8,
+    },
+    /// The final `ud2` was not found before the end of the page, i.e., the
+    /// trampoline crosses a page boundary.
+    #[error("trampoline doesn't end with ud2 before the page boundary at offset {0}")]
+    CrossesPage(usize),
+}
+
+const REX_W: u8 = 0x48;
+const OPCODE_MOV_RM64_R64: u8 = 0x89;
+const OPCODE_GROUP_5: u8 = 0xff;
+const OPCODE_NOP: u8 = 0x90;
+const OPCODE_TWO_BYTE: u8 = 0x0f;
+const OPCODE2_MOV_CR_R64: u8 = 0x22;
+const OPCODE2_NOP_RM: u8 = 0x1f;
+const OPCODE2_UD2: u8 = 0x0b;
+const PREFIX_OPERAND_SIZE: u8 = 0x66;
+const PREFIX_CS: u8 = 0x2e;
+/// ModRM.reg of `jmp r/m64` in group 5.
+const GROUP_5_JMP: u8 = 4;
+/// ModRM.reg of `cr3` in `mov cr, r64`.
+const CR3: u8 = 3;
+
+/// Allowed instructions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Instruction {
+    /// Padding of the alignment directive.
+    Nop,
+    /// `mov %r64, %cr3`
+    MovToCr3,
+    /// `mov %r64, %r64`
+    MovRegReg,
+    /// `jmp *%r64`
+    JmpReg,
+    /// `ud2`
+    Ud2,
+}
+
+/// Verifies the machine code of the trampoline.
+///
+/// `code` starts at the first byte of the trampoline and ends at the end of
+/// its page. Only register-to-register moves, moves to `cr3`, indirect jumps
+/// via registers, and `ud2` are allowed, as well as the NOPs of alignment
+/// padding. Everything else, in particular `push`, `pop`, `call`, and
+/// memory operands, is rejected. The trampoline ends with the first `ud2`.
+///
+/// Returns the length of the trampoline in bytes.
+pub fn verify_trampoline(code: &[u8]) -> Result<usize, TrampolineError> {
+    let mut offset = 0;
+    while offset < code.len() {
+        let (instruction, len) =
+            decode(&code[offset..]).ok_or(TrampolineError::UnexpectedInstruction {
+                offset,
+                byte: code[offset],
+            })?;
+        offset += len;
+        if instruction == Instruction::Ud2 {
+            return Ok(offset);
+        }
+    }
+    Err(TrampolineError::CrossesPage(code.len()))
+}
+
+/// Returns the bytes from the trampoline at `addr` to the end of its page.
+///
+/// # Safety
+/// The page containing `addr` must be mapped and readable.
+#[must_use]
+pub const unsafe fn trampoline_page_bytes(addr: u64) -> &'static [u8] {
+    let len = PAGE_SIZE - (addr as usize % PAGE_SIZE);
+    // SAFETY: The caller guarantees that the page is readable.
+    unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+}
+
+/// Decodes the allowed instruction at the begin of `code` and returns it
+/// together with its length.
+fn decode(code: &[u8]) -> Option<(Instruction, usize)> {
+    match *code {
+        [OPCODE_NOP, ..] => Some((Instruction::Nop, 1)),
+        [REX_W, OPCODE_MOV_RM64_R64, modrm, ..] if is_register(modrm) => {
+            Some((Instruction::MovRegReg, 3))
+        }
+        [OPCODE_GROUP_5, modrm, ..] if is_register(modrm) && reg(modrm) == GROUP_5_JMP => {
+            Some((Instruction::JmpReg, 2))
+        }
+        [OPCODE_TWO_BYTE, OPCODE2_MOV_CR_R64, modrm, ..]
+            if is_register(modrm) && reg(modrm) == CR3 =>
+        {
+            Some((Instruction::MovToCr3, 3))
+        }
+        [OPCODE_TWO_BYTE, OPCODE2_UD2, ..] => Some((Instruction::Ud2, 2)),
+        _ => decode_multi_byte_nop(code).map(|len| (Instruction::Nop, len)),
+    }
+}
+
+/// Decodes the prefixed NOPs (`nopw`/`nopl` with a memory operand that is
+/// never accessed, or `xchg %ax, %ax`) that assemblers use for padding.
+fn decode_multi_byte_nop(code: &[u8]) -> Option<usize> {
+    let prefixes = code
+        .iter()
+        .take_while(|&&b| b == PREFIX_OPERAND_SIZE || b == PREFIX_CS)
+        .count();
+    let [OPCODE_TWO_BYTE, OPCODE2_NOP_RM, modrm, ref rest @ ..] = code[prefixes..] else {
+        // `xchg %ax, %ax`
+        return (code.get(prefixes) == Some(&OPCODE_NOP)).then_some(prefixes + 1);
+    };
+    if reg(modrm) != 0 {
+        return None;
+    }
+    let rm = modrm & 0b111;
+    let sib = usize::from(modrm >> 6 != 0b11 && rm == 0b100);
+    let disp = match modrm >> 6 {
+        0b00 if rm == 0b101 => 4,
+        0b00 | 0b11 => 0,
+        0b01 => 1,
+        _ => 4,
+    };
+    let len = prefixes + 3 + sib + disp;
+    (rest.len() >= sib + disp).then_some(len)
+}
+
+/// Returns whether the ModRM byte encodes a register operand.
+const fn is_register(modrm: u8) -> bool {
+    modrm >> 6 == 0b11
+}
+
+/// Returns the `reg` field of the ModRM byte.
+const fn reg(modrm: u8) -> u8 {
+    (modrm >> 3) & 0b111
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The trampoline of the UEFI loader as assembled by LLVM, with four
+    /// bytes of padding in front.
+    const TRAMPOLINE: &[u8] = &[
+        0x0f, 0x1f, 0x40, 0x00, // nopl 0x0(%rax)
+        0x0f, 0x22, 0xdf, // mov %rdi, %cr3
+        0x48, 0x89, 0xcc, // mov %rcx, %rsp
+        0x48, 0x89, 0xd7, // mov %rdx, %rdi
+        0xff, 0xe6, // jmp *%rsi
+        0x0f, 0x0b, // ud2
+    ];
+
+    #[test]
+    fn test_verify_trampoline() {
+        let mut page = TRAMPOLINE.to_vec();
+        page.resize(64, 0xcc);
+        assert_eq!(verify_trampoline(&page), Ok(TRAMPOLINE.len()));
+        // The page ends before the `ud2`.
+        let end = TRAMPOLINE.len() - 2;
+        assert_eq!(
+            verify_trampoline(&TRAMPOLINE[..end]),
+            Err(TrampolineError::CrossesPage(end))
+        );
+
+        // Other padding emitted by assemblers.
+        let padding: [&[u8]; 4] = [
+            &[0x90],
+            &[0x66, 0x90],
+            &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+            &[0x66, 0x2e, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ];
+        for padding in padding {
+            let code = [padding, &TRAMPOLINE[4..]].concat();
+            assert_eq!(verify_trampoline(&code), Ok(code.len()), "{padding:x?}");
+        }
+    }
+
+    #[test]
+    fn test_verify_actual_trampoline() {
+        // SAFETY: The trampoline is part of the test binary.
+        let code = unsafe { trampoline_page_bytes(jump_to_kernel_trampoline as *const () as u64) };
+        let len = verify_trampoline(code).unwrap();
+        // The 16-byte alignment keeps the instructions within one page.
+        let instructions = &TRAMPOLINE[4..];
+        assert!(instructions.len() <= 16);
+        assert_eq!(&code[len - instructions.len()..len], instructions);
+    }
+
+    #[test]
+    fn test_verify_trampoline_rejects() {
+        let rejected: [&[u8]; 6] = [
+            &[0x57],                         // push %rdi
+            &[0x5f],                         // pop %rdi
+            &[0xff, 0xd6],                   // call *%rsi
+            &[0xe8, 0x00, 0x00, 0x00, 0x00], // call rel32
+            // mov 0x0(%rip), %rdi
+            &[0x48, 0x8b, 0x3d, 0x00, 0x00, 0x00, 0x00],
+            // mov %rdi, (%rsp)
+            &[0x48, 0x89, 0x3c, 0x24],
+        ];
+        for code in rejected {
+            let code = [&TRAMPOLINE[..7], code, &TRAMPOLINE[7..]].concat();
+            assert_eq!(
+                verify_trampoline(&code),
+                Err(TrampolineError::UnexpectedInstruction {
+                    offset: 7,
+                    byte: code[7]
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Benchmark for [`map_scatter`] against independent [`map_address`] calls
+//! over [`FakePhysMemory`].
+//!
+//! Run with `cargo bench -p util`. Like the `map_range` benchmark, this uses
+//! a minimal harness with [`std::time::Instant`].
+//!
+//! The physical frames are contiguous while the virtual pages are scattered
+//! with a stride of two pages, so most consecutive pages share their level 1
+//! table. [`map_scatter`] only walks the upper levels once per 2 MiB region
+//! and should therefore be noticeably faster.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use util::paging::fake_memory::FakePhysMemory;
+use util::paging::{
+    PageSize, PageTable, PageTableEntryFlags, PhysAddress, VirtAddress, map_address, map_scatter,
+};
+
+const ITERATIONS: u32 = 20;
+const PAGES: u64 = 64 * 1024;
+
+fn mappings() -> Vec<(VirtAddress, PhysAddress)> {
+    (0..PAGES)
+        .map(|i| {
+            (
+                VirtAddress(0xffff_8000_0000_0000 + i * 2 * 0x1000),
+                PhysAddress(i * 0x1000),
+            )
+        })
+        .collect()
+}
+
+fn flags() -> PageTableEntryFlags {
+    PageTableEntryFlags {
+        write: true,
+        ..Default::default()
+    }
+}
+
+/// Maps all `mappings` into a fresh set of page tables and returns the
+/// duration of the mapping.
+fn map_once(mappings: &[(VirtAddress, PhysAddress)], scatter: bool) -> Duration {
+    let mut root = Box::new(PageTable::ZERO);
+    let mut mem = FakePhysMemory::new();
+    let begin = Instant::now();
+    if scatter {
+        map_scatter(&mut root, &mut mem, black_box(mappings), flags()).unwrap();
+    } else {
+        for &(vaddr, paddr) in black_box(mappings) {
+            map_address(
+                &mut root,
+                &mut mem,
+                vaddr,
+                paddr,
+                PageSize::Size4KiB,
+                flags(),
+            )
+            .unwrap();
+        }
+    }
+    let elapsed = begin.elapsed();
+    black_box((&root, &mem));
+    elapsed
+}
+
+fn bench(name: &str, mappings: &[(VirtAddress, PhysAddress)], scatter: bool) {
+    // Warm-up
+    map_once(mappings, scatter);
+    let best = (0..ITERATIONS)
+        .map(|_| map_once(mappings, scatter))
+        .min()
+        .unwrap();
+    let ns_per_page = best.as_nanos() as f64 / PAGES as f64;
+    println!("{name:<24} {PAGES:>8} pages  {best:>12.3?}  {ns_per_page:>8.2} ns/page");
+}
+
+fn main() {
+    let mappings = mappings();
+    bench("map_address (4 KiB)", &mappings, false);
+    bench("map_scatter (4 KiB)", &mappings, true);
+}
@@ -0,0 +1,183 @@
+//! Diagnostics of the CPU state.
+
+use core::fmt::{self, Display, Formatter};
+
+/// Flags of CR0, see [`ControlRegisters`].
+const CR0_FLAGS: &[(u64, &str)] = &[(1 << 0, "PE"), (1 << 16, "WP"), (1 << 31, "PG")];
+/// Flags of CR4, see [`ControlRegisters`].
+const CR4_FLAGS: &[(u64, &str)] = &[
+    (1 << 5, "PAE"),
+    (1 << 7, "PGE"),
+    (1 << 17, "PCIDE"),
+    (1 << 20, "SMEP"),
+    (1 << 21, "SMAP"),
+];
+/// Flags of the EFER MSR, see [`ControlRegisters`].
+const EFER_FLAGS: &[(u64, &str)] = &[(1 << 8, "LME"), (1 << 11, "NXE")];
+
+/// Raw values of the control registers relevant for paging and long mode.
+///
+/// The [`Display`] implementation shows the raw values and decodes the
+/// flags that matter most when debugging paging. Cleared flags are prefixed
+/// with `!`, e.g.:
+///
+/// ```text
+/// cr0=0x80010033 (PE WP PG) cr3=0x7e01000 cr4=0x668 (PAE !PGE !PCIDE !SMEP !SMAP) efer=0xd00 (LME NXE)
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ControlRegisters {
+    /// Value of CR0.
+    pub cr0: u64,
+    /// Value of CR3.
+    pub cr3: u64,
+    /// Value of CR4.
+    pub cr4: u64,
+    /// Value of the EFER MSR.
+    pub efer: u64,
+}
+
+impl ControlRegisters {
+    /// Reads the registers of the current CPU.
+    ///
+    /// # Safety
+    /// Must run in ring 0.
+    pub unsafe fn read() -> Self {
+        // SAFETY: The caller guarantees that we run in ring 0.
+        unsafe {
+            Self {
+                cr0: x86::controlregs::cr0().bits() as u64,
+                cr3: x86::controlregs::cr3(),
+                cr4: x86::controlregs::cr4().bits() as u64,
+                efer: x86::msr::rdmsr(x86::msr::IA32_EFER),
+            }
+        }
+    }
+}
+
+impl Display for ControlRegisters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "cr0={:#x} ", self.cr0)?;
+        write_flags(f, self.cr0, CR0_FLAGS)?;
+        write!(f, " cr3={:#x} cr4={:#x} ", self.cr3, self.cr4)?;
+        write_flags(f, self.cr4, CR4_FLAGS)?;
+        write!(f, " efer={:#x} ", self.efer)?;
+        write_flags(f, self.efer, EFER_FLAGS)
+    }
+}
+
+/// CR0.PG: paging is enabled.
+const CR0_PG: u64 = 1 << 31;
+/// CR4.PAE: physical address extension, required for long mode.
+const CR4_PAE: u64 = 1 << 5;
+/// EFER.LMA: long mode is active.
+const EFER_LMA: u64 = 1 << 10;
+
+impl ControlRegisters {
+    /// Checks that the CPU runs in long mode with paging enabled, as the
+    /// loader promises to the kernel.
+    ///
+    /// Returns a description of the first violated requirement, if any.
+    pub const fn check_long_mode_paging(&self) -> Result<(), &'static str> {
+        if self.efer & EFER_LMA == 0 {
+            Err("long mode is not active (EFER.LMA is clear)")
+        } else if self.cr0 & CR0_PG == 0 {
+            Err("paging is disabled (CR0.PG is clear)")
+        } else if self.cr4 & CR4_PAE == 0 {
+            Err("PAE is disabled (CR4.PAE is clear)")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Asserts that the CPU runs in long mode with paging enabled.
+///
+/// This turns a broken hand-over from the loader into an explicit panic
+/// rather than a fault on the first access to a higher-half address.
+///
+/// # Safety
+/// Must run in ring 0.
+///
+/// # Panics
+/// Panics if EFER.LMA, CR0.PG, or CR4.PAE is clear.
+pub unsafe fn assert_long_mode_paging() {
+    // SAFETY: The caller guarantees that we run in ring 0.
+    let regs = unsafe { ControlRegisters::read() };
+    if let Err(msg) = regs.check_long_mode_paging() {
+        panic!("CPU is not in the state the loader promised: {msg}; {regs}");
+    }
+}
+
+/// Writes the names of `flags` in parentheses, prefixing cleared ones with
+/// `!`.
+fn write_flags(f: &mut Formatter<'_>, value: u64, flags: &[(u64, &str)]) -> fmt::Result {
+    f.write_str("(")?;
+    for (i, &(mask, name)) in flags.iter().enumerate() {
+        let separator = if i == 0 { "" } else { " " };
+        let negation = if value & mask == 0 { "!" } else { "" };
+        write!(f, "{separator}{negation}{name}")?;
+    }
+    f.write_str(")")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_display() {
+        let regs = ControlRegisters {
+            cr0: 0x8001_0033,
+            cr3: 0x7e0_1000,
+            cr4: 0x0030_0020,
+            efer: 0x500,
+        };
+        assert_eq!(
+            regs.to_string(),
+            "cr0=0x80010033 (PE WP PG) cr3=0x7e01000 cr4=0x300020 (PAE !PGE !PCIDE SMEP SMAP) \
+             efer=0x500 (LME !NXE)"
+        );
+
+        let regs = ControlRegisters {
+            cr0: 0,
+            cr3: 0,
+            cr4: 0,
+            efer: 0,
+        };
+        assert_eq!(
+            regs.to_string(),
+            "cr0=0x0 (!PE !WP !PG) cr3=0x0 cr4=0x0 (!PAE !PGE !PCIDE !SMEP !SMAP) efer=0x0 (!LME !NXE)"
+        );
+    }
+
+    #[test]
+    fn test_check_long_mode_paging() {
+        let regs = ControlRegisters {
+            cr0: 0x8001_0033,
+            cr3: 0x7e0_1000,
+            cr4: 0x668,
+            efer: 0xd00,
+        };
+        assert_eq!(regs.check_long_mode_paging(), Ok(()));
+
+        let no_lma = ControlRegisters {
+            efer: 0x100,
+            ..regs
+        };
+        assert_eq!(
+            no_lma.check_long_mode_paging(),
+            Err("long mode is not active (EFER.LMA is clear)")
+        );
+        let no_pg = ControlRegisters { cr0: 0x33, ..regs };
+        assert_eq!(
+            no_pg.check_long_mode_paging(),
+            Err("paging is disabled (CR0.PG is clear)")
+        );
+        let no_pae = ControlRegisters { cr4: 0x648, ..regs };
+        assert_eq!(
+            no_pae.check_long_mode_paging(),
+            Err("PAE is disabled (CR4.PAE is clear)")
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use super::{PortIo, X86PortIo};
 use x86::io::outb;
 
 /// Driver to the Debug Connection (debugcon) device, which is typically
@@ -12,6 +13,23 @@ impl DebugCon {
     pub fn write(byte: u8) {
         unsafe { outb(Self::PORT, byte) }
     }
+
+    /// Returns whether the debugcon device is present.
+    ///
+    /// This is a best-effort check: reading the port of the device returns
+    /// the port number (`0xe9`), whereas an unused port typically reads as
+    /// `0xff`. A different device behind the port may still be mistaken for
+    /// debugcon.
+    pub fn is_present() -> bool {
+        // SAFETY: Reading the port has no side effects on debugcon and
+        // nothing else uses the port concurrently.
+        Self::is_present_on(&mut unsafe { X86PortIo::new() })
+    }
+
+    /// Like [`Self::is_present`], but uses the given port I/O.
+    pub fn is_present_on(io: &mut impl PortIo) -> bool {
+        io.read8(Self::PORT) == Self::PORT as u8
+    }
 }
 
 impl core::fmt::Write for DebugCon {
@@ -20,3 +38,18 @@ impl core::fmt::Write for DebugCon {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::MockPortIo;
+
+    #[test]
+    fn test_is_present() {
+        let mut io = MockPortIo::default().with_read(DebugCon::PORT, 0xe9);
+        assert!(DebugCon::is_present_on(&mut io));
+        let mut io = MockPortIo::default().with_read(DebugCon::PORT, 0xff);
+        assert!(!DebugCon::is_present_on(&mut io));
+        assert!(io.writes.is_empty());
+    }
+}
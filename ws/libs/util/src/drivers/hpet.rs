@@ -0,0 +1,186 @@
+//! Minimal driver for the High Precision Event Timer (HPET).
+//!
+//! The HPET is a memory-mapped block of 64-bit registers. Its physical base
+//! address is reported by the ACPI `HPET` table; the block is
+//! [`REGISTER_BLOCK_SIZE`] bytes large and must be mapped uncached before it
+//! is accessed. Only the capabilities and the main counter are supported so
+//! far, which is enough for a monotonic clock independent of the TSC.
+
+/// Size of the register block in bytes.
+pub const REGISTER_BLOCK_SIZE: usize = 0x400;
+/// Offset of the General Capabilities and ID Register.
+pub const REG_CAPABILITIES: usize = 0x0;
+/// Offset of the General Configuration Register.
+pub const REG_CONFIG: usize = 0x10;
+/// Offset of the Main Counter Value Register.
+pub const REG_MAIN_COUNTER: usize = 0xf0;
+
+/// Bit in [`REG_CONFIG`] that starts the main counter.
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+/// Longest valid counter period in femtoseconds (100 ns), as required by the
+/// specification.
+pub const MAX_PERIOD_FS: u32 = 100_000_000;
+
+const FS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// Parsed General Capabilities and ID Register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    /// Revision of the implemented function; must not be `0`.
+    pub revision: u8,
+    /// Number of comparators (timers).
+    pub num_timers: u8,
+    /// Whether the main counter is 64 bits wide. Otherwise, it is 32 bits
+    /// wide.
+    pub counter_64bit: bool,
+    /// Whether the legacy replacement routing is supported.
+    pub legacy_replacement: bool,
+    /// PCI vendor ID of the implementation.
+    pub vendor_id: u16,
+    /// Period of the main counter in femtoseconds.
+    pub period_fs: u32,
+}
+
+impl Capabilities {
+    /// Parses the raw value of [`REG_CAPABILITIES`].
+    #[must_use]
+    pub const fn from_raw(raw: u64) -> Self {
+        Self {
+            revision: raw as u8,
+            // The register holds the index of the last timer.
+            num_timers: ((raw >> 8) & 0x1f) as u8 + 1,
+            counter_64bit: raw & (1 << 13) != 0,
+            legacy_replacement: raw & (1 << 15) != 0,
+            vendor_id: (raw >> 16) as u16,
+            period_fs: (raw >> 32) as u32,
+        }
+    }
+
+    /// Returns whether the values are within the limits of the specification,
+    /// i.e., whether there is a functional HPET behind the registers.
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        self.revision != 0 && self.period_fs != 0 && self.period_fs <= MAX_PERIOD_FS
+    }
+
+    /// Returns the frequency of the main counter in Hz.
+    ///
+    /// # Panics
+    /// Panics if the period is `0`, see [`Self::is_valid`].
+    #[must_use]
+    pub const fn frequency_hz(&self) -> u64 {
+        FS_PER_SECOND / self.period_fs as u64
+    }
+}
+
+/// Driver for one HPET register block.
+#[derive(Debug)]
+pub struct Hpet {
+    base: *mut u64,
+}
+
+// SAFETY: The registers are a device memory region that is not tied to a
+// thread.
+unsafe impl Send for Hpet {}
+
+impl Hpet {
+    /// Creates a new driver for the register block at `base`.
+    ///
+    /// # Safety
+    /// `base` must be the mapped (virtual) address of [`REGISTER_BLOCK_SIZE`]
+    /// bytes of HPET registers, aligned to 8 bytes, that stay valid for the
+    /// lifetime of the driver.
+    pub const unsafe fn new(base: *mut u64) -> Self {
+        Self { base }
+    }
+
+    fn read(&self, offset: usize) -> u64 {
+        debug_assert!(offset < REGISTER_BLOCK_SIZE && offset.is_multiple_of(8));
+        // SAFETY: The offset is within the register block, see `Self::new`.
+        unsafe { self.base.add(offset / 8).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, value: u64) {
+        debug_assert!(offset < REGISTER_BLOCK_SIZE && offset.is_multiple_of(8));
+        // SAFETY: The offset is within the register block, see `Self::new`.
+        unsafe { self.base.add(offset / 8).write_volatile(value) }
+    }
+
+    /// Reads and parses the capabilities.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::from_raw(self.read(REG_CAPABILITIES))
+    }
+
+    /// Returns whether the main counter is running.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.read(REG_CONFIG) & CONFIG_ENABLE != 0
+    }
+
+    /// Starts the main counter, if it isn't running already.
+    pub fn enable(&mut self) {
+        let config = self.read(REG_CONFIG);
+        self.write(REG_CONFIG, config | CONFIG_ENABLE);
+    }
+
+    /// Returns the current value of the main counter.
+    ///
+    /// The counter increments once per [`Capabilities::period_fs`] while it is
+    /// enabled. On 32-bit counters, the upper half is always `0`.
+    #[must_use]
+    pub fn main_counter(&self) -> u64 {
+        self.read(REG_MAIN_COUNTER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Capabilities as reported by QEMU: 3 timers, 64-bit counter, legacy
+    /// replacement, vendor 0x8086, 10 ns period.
+    const QEMU_CAPABILITIES: u64 = 0x0098_9680_8086_a201;
+
+    #[test]
+    fn test_capabilities() {
+        let caps = Capabilities::from_raw(QEMU_CAPABILITIES);
+        assert_eq!(
+            caps,
+            Capabilities {
+                revision: 1,
+                num_timers: 3,
+                counter_64bit: true,
+                legacy_replacement: true,
+                vendor_id: 0x8086,
+                period_fs: 10_000_000,
+            }
+        );
+        assert!(caps.is_valid());
+        assert_eq!(caps.frequency_hz(), 100_000_000);
+
+        assert!(!Capabilities::from_raw(0).is_valid());
+        assert!(!Capabilities::from_raw(u64::MAX).is_valid());
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut registers = vec![0_u64; REGISTER_BLOCK_SIZE / 8];
+        registers[REG_CAPABILITIES / 8] = QEMU_CAPABILITIES;
+        registers[REG_CONFIG / 8] = 0b10;
+        registers[REG_MAIN_COUNTER / 8] = 0x1234_5678_9abc;
+        {
+            // SAFETY: The buffer has the size of the register block.
+            let mut hpet = unsafe { Hpet::new(registers.as_mut_ptr()) };
+            assert_eq!(hpet.capabilities().num_timers, 3);
+            assert_eq!(hpet.main_counter(), 0x1234_5678_9abc);
+            assert!(!hpet.is_enabled());
+            hpet.enable();
+            assert!(hpet.is_enabled());
+        }
+        // Other configuration bits are preserved.
+        assert_eq!(registers[REG_CONFIG / 8], 0b11);
+    }
+}
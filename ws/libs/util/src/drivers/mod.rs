@@ -1,5 +1,15 @@
 //! Collection of drivers.
 
 mod debugcon;
+pub mod hpet;
+pub mod pit;
+mod port_io;
+mod text_console;
+mod vga_text;
 
 pub use debugcon::DebugCon;
+#[cfg(test)]
+pub(crate) use port_io::MockPortIo;
+pub use port_io::{PortIo, X86PortIo};
+pub use text_console::{Color, TextConsole};
+pub use vga_text::VgaText;
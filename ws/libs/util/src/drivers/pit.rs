@@ -0,0 +1,99 @@
+//! Driver for the 8254 Programmable Interval Timer (PIT).
+//!
+//! Channel 2 is used as a one-shot timer with a known frequency, e.g., to
+//! calibrate other clocks. Unlike channel 0, it does not raise interrupts.
+//! Its gate and output are wired to [`PORT_B`], so it can be polled.
+//!
+//! The programming sequence for one delay is:
+//! 1. Set the gate of channel 2 (bit 0 of [`PORT_B`]) and disable the
+//!    PC speaker (bit 1).
+//! 2. Write [`CONTROL_WORD`] to [`PORT_COMMAND`]: channel 2, low byte then
+//!    high byte, mode 0 (interrupt on terminal count), binary counting.
+//! 3. Write the low and then the high byte of the divisor to
+//!    [`PORT_CHANNEL_2`]. This (re)starts the countdown.
+//! 4. Poll until the output of channel 2 (bit 5 of [`PORT_B`]) is set.
+
+use super::PortIo;
+
+/// Input frequency of the PIT in Hz.
+pub const FREQUENCY_HZ: u64 = 1_193_182;
+/// Data port of channel 2.
+pub const PORT_CHANNEL_2: u16 = 0x42;
+/// Mode/command port.
+pub const PORT_COMMAND: u16 = 0x43;
+/// Port B of the keyboard controller (NMI status and control) with the gate
+/// and output of channel 2.
+pub const PORT_B: u16 = 0x61;
+/// Control word for channel 2 in one-shot mode, see the module
+/// documentation.
+pub const CONTROL_WORD: u8 = 0b1011_0000;
+
+const PORT_B_GATE_2: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT_2: u8 = 1 << 5;
+
+/// Longest delay per countdown, as the divisor is limited to 16 bits.
+const MAX_CHUNK_MS: u16 = 50;
+
+/// Returns the divisor for a countdown of `ms` milliseconds.
+const fn divisor(ms: u16) -> u16 {
+    assert!(ms <= MAX_CHUNK_MS);
+    (FREQUENCY_HZ * ms as u64).div_ceil(1000) as u16
+}
+
+/// Busy-waits for `ms` milliseconds using channel 2 of the PIT.
+///
+/// Longer delays are split into multiple countdowns.
+pub fn busy_wait_ms(io: &mut impl PortIo, ms: u16) {
+    let mut remaining = ms;
+    while remaining > 0 {
+        let chunk = remaining.min(MAX_CHUNK_MS);
+        one_shot(io, divisor(chunk));
+        remaining -= chunk;
+    }
+}
+
+/// Counts down `divisor` ticks and polls until the countdown expired.
+fn one_shot(io: &mut impl PortIo, divisor: u16) {
+    let port_b = io.read8(PORT_B);
+    io.write8(PORT_B, (port_b & !PORT_B_SPEAKER) | PORT_B_GATE_2);
+
+    let [low, high] = divisor.to_le_bytes();
+    io.write8(PORT_COMMAND, CONTROL_WORD);
+    io.write8(PORT_CHANNEL_2, low);
+    io.write8(PORT_CHANNEL_2, high);
+
+    while io.read8(PORT_B) & PORT_B_OUT_2 == 0 {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::MockPortIo;
+
+    #[test]
+    fn test_busy_wait_ms() {
+        // Speaker enabled, countdown expires immediately.
+        let mut io = MockPortIo::default().with_read(PORT_B, PORT_B_OUT_2 | PORT_B_SPEAKER);
+        busy_wait_ms(&mut io, 60);
+
+        let gate = PORT_B_OUT_2 | PORT_B_GATE_2;
+        let [low_50, high_50] = 59660_u16.to_le_bytes();
+        let [low_10, high_10] = 11932_u16.to_le_bytes();
+        assert_eq!(
+            io.writes,
+            [
+                (PORT_B, gate),
+                (PORT_COMMAND, 0xb0),
+                (PORT_CHANNEL_2, low_50),
+                (PORT_CHANNEL_2, high_50),
+                (PORT_B, gate),
+                (PORT_COMMAND, 0xb0),
+                (PORT_CHANNEL_2, low_10),
+                (PORT_CHANNEL_2, high_10),
+            ]
+        );
+    }
+}
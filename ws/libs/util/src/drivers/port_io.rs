@@ -0,0 +1,69 @@
+/// Abstraction over x86 port I/O so that drivers can be tested without
+/// hardware.
+pub trait PortIo {
+    /// Reads one byte from `port`.
+    fn read8(&mut self, port: u16) -> u8;
+
+    /// Writes one byte to `port`.
+    fn write8(&mut self, port: u16, value: u8);
+}
+
+/// [`PortIo`] accessing the real I/O ports via `in` and `out`.
+#[derive(Debug)]
+pub struct X86PortIo(());
+
+impl X86PortIo {
+    /// Creates a new handle to the I/O ports.
+    ///
+    /// # Safety
+    /// The caller must ensure that port I/O is permitted and that the
+    /// accessed ports are not used concurrently by someone else.
+    pub const unsafe fn new() -> Self {
+        Self(())
+    }
+}
+
+impl PortIo for X86PortIo {
+    fn read8(&mut self, port: u16) -> u8 {
+        // SAFETY: See `Self::new`.
+        unsafe { x86::io::inb(port) }
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        // SAFETY: See `Self::new`.
+        unsafe { x86::io::outb(port, value) }
+    }
+}
+
+/// [`PortIo`] recording all writes. Reads return the last written value of
+/// the port, or the value of [`Self::with_read`].
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MockPortIo {
+    pub writes: alloc::vec::Vec<(u16, u8)>,
+    reads: alloc::vec::Vec<(u16, u8)>,
+}
+
+#[cfg(test)]
+impl MockPortIo {
+    /// Lets all reads of `port` return `value`.
+    pub fn with_read(mut self, port: u16, value: u8) -> Self {
+        self.reads.push((port, value));
+        self
+    }
+}
+
+#[cfg(test)]
+impl PortIo for MockPortIo {
+    fn read8(&mut self, port: u16) -> u8 {
+        self.reads
+            .iter()
+            .chain(self.writes.iter().rev())
+            .find(|(p, _)| *p == port)
+            .map_or(0, |(_, value)| *value)
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        self.writes.push((port, value));
+    }
+}
@@ -0,0 +1,45 @@
+use core::fmt;
+
+/// The 16 colors of text consoles, as in the VGA text mode palette.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+/// A text console with basic terminal control, such as [`super::VgaText`].
+pub trait TextConsole: fmt::Write {
+    /// Clears the screen with the current background color and moves the
+    /// cursor to the top-left corner.
+    fn clear(&mut self);
+
+    /// Moves the cursor to the given position. Positions outside the screen
+    /// are clamped.
+    fn set_cursor(&mut self, row: usize, column: usize);
+
+    /// Sets the colors for subsequently written characters.
+    fn set_color(&mut self, fg: Color, bg: Color);
+
+    /// Clears the screen and prints `msg` prominently, e.g., for a panic.
+    fn show_fatal(&mut self, msg: fmt::Arguments<'_>) {
+        self.set_color(Color::White, Color::Red);
+        self.clear();
+        // Nothing we can do about errors here.
+        let _ = self.write_fmt(msg);
+    }
+}
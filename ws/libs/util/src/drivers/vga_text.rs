@@ -0,0 +1,211 @@
+use super::{Color, TextConsole};
+
+/// Driver for the legacy VGA text buffer with 80x25 cells.
+///
+/// Each cell consists of the character in code page 437 and an attribute byte
+/// with the foreground and background color. On overflow of the last line, the
+/// content is scrolled up by one line.
+///
+/// The buffer is typically found at physical address [`VgaText::PHYS_ADDR`].
+/// It is only usable if that memory is mapped, e.g., identity-mapped in the
+/// loader or via the direct map in the kernel.
+#[derive(Debug)]
+pub struct VgaText {
+    buffer: *mut u16,
+    row: usize,
+    column: usize,
+    attribute: u8,
+}
+
+// SAFETY: The buffer is a device memory region that is not tied to a thread.
+unsafe impl Send for VgaText {}
+
+impl VgaText {
+    /// The typical physical address of the text buffer.
+    pub const PHYS_ADDR: u64 = 0xb8000;
+    /// Number of characters per line.
+    pub const WIDTH: usize = 80;
+    /// Number of lines.
+    pub const HEIGHT: usize = 25;
+    /// Light gray on black.
+    pub const DEFAULT_ATTRIBUTE: u8 = 0x07;
+
+    /// Creates a new driver writing to the text buffer at `buffer`, starting
+    /// in the top-left corner.
+    ///
+    /// # Safety
+    /// `buffer` must point to [`Self::WIDTH`] x [`Self::HEIGHT`] valid and
+    /// mapped cells that are not accessed by anyone else.
+    pub const unsafe fn new(buffer: *mut u16) -> Self {
+        Self {
+            buffer,
+            row: 0,
+            column: 0,
+            attribute: Self::DEFAULT_ATTRIBUTE,
+        }
+    }
+
+    /// Sets the attribute byte (colors) for subsequently written characters.
+    pub const fn set_attribute(&mut self, attribute: u8) {
+        self.attribute = attribute;
+    }
+
+    fn write_cell(&mut self, row: usize, column: usize, cell: u16) {
+        debug_assert!(row < Self::HEIGHT && column < Self::WIDTH);
+        // SAFETY: The index is within the buffer, see `Self::new`.
+        unsafe {
+            self.buffer
+                .add(row * Self::WIDTH + column)
+                .write_volatile(cell)
+        }
+    }
+
+    fn read_cell(&self, row: usize, column: usize) -> u16 {
+        debug_assert!(row < Self::HEIGHT && column < Self::WIDTH);
+        // SAFETY: The index is within the buffer, see `Self::new`.
+        unsafe { self.buffer.add(row * Self::WIDTH + column).read_volatile() }
+    }
+
+    fn blank(&self) -> u16 {
+        u16::from(self.attribute) << 8 | u16::from(b' ')
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < Self::HEIGHT {
+            self.row += 1;
+            return;
+        }
+
+        for row in 1..Self::HEIGHT {
+            for column in 0..Self::WIDTH {
+                let cell = self.read_cell(row, column);
+                self.write_cell(row - 1, column, cell);
+            }
+        }
+        for column in 0..Self::WIDTH {
+            self.write_cell(Self::HEIGHT - 1, column, self.blank());
+        }
+    }
+
+    /// Writes one byte. A `\n` starts a new line; lines longer than
+    /// [`Self::WIDTH`] wrap.
+    pub fn write_byte(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.new_line();
+            return;
+        }
+        if self.column == Self::WIDTH {
+            self.new_line();
+        }
+        let cell = u16::from(self.attribute) << 8 | u16::from(byte);
+        self.write_cell(self.row, self.column, cell);
+        self.column += 1;
+    }
+}
+
+impl TextConsole for VgaText {
+    fn clear(&mut self) {
+        for row in 0..Self::HEIGHT {
+            for column in 0..Self::WIDTH {
+                self.write_cell(row, column, self.blank());
+            }
+        }
+        self.row = 0;
+        self.column = 0;
+    }
+
+    fn set_cursor(&mut self, row: usize, column: usize) {
+        self.row = row.min(Self::HEIGHT - 1);
+        self.column = column.min(Self::WIDTH - 1);
+    }
+
+    fn set_color(&mut self, fg: Color, bg: Color) {
+        self.set_attribute((bg as u8) << 4 | fg as u8);
+    }
+}
+
+impl core::fmt::Write for VgaText {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            // Code page 437 matches ASCII only in the printable range.
+            let byte = match c {
+                ' '..='~' | '\n' => c as u8,
+                _ => 0xfe, // ■
+            };
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use core::fmt::Write;
+
+    fn char_at(buffer: &[u16], row: usize, column: usize) -> u8 {
+        buffer[row * VgaText::WIDTH + column] as u8
+    }
+
+    #[test]
+    fn test_wrap_and_scroll() {
+        let mut buffer = vec![0_u16; VgaText::WIDTH * VgaText::HEIGHT];
+        {
+            // SAFETY: The buffer has the expected size.
+            let mut vga = unsafe { VgaText::new(buffer.as_mut_ptr()) };
+
+            // Wraps after 80 characters.
+            for _ in 0..VgaText::WIDTH {
+                vga.write_char('a').unwrap();
+            }
+            vga.write_str("b\n").unwrap();
+            for row in 2..VgaText::HEIGHT - 1 {
+                writeln!(vga, "line {row}").unwrap();
+            }
+            vga.write_str("last").unwrap();
+        }
+
+        assert_eq!(char_at(&buffer, 0, 79), b'a');
+        assert_eq!(char_at(&buffer, 1, 0), b'b');
+        assert_eq!(buffer[0] >> 8, u16::from(VgaText::DEFAULT_ATTRIBUTE));
+        assert_eq!(char_at(&buffer, 24, 0), b'l');
+        assert_eq!(char_at(&buffer, 24, 3), b't');
+
+        {
+            // SAFETY: The buffer has the expected size.
+            let mut vga = unsafe { VgaText::new(buffer.as_mut_ptr()) };
+            vga.row = VgaText::HEIGHT - 1;
+            vga.column = 4;
+            vga.write_str("\nä").unwrap();
+        }
+
+        // Scrolled by one line.
+        assert_eq!(char_at(&buffer, 0, 0), b'b');
+        assert_eq!(char_at(&buffer, 23, 3), b't');
+        assert_eq!(char_at(&buffer, 24, 0), 0xfe);
+        assert_eq!(char_at(&buffer, 24, 1), b' ');
+    }
+
+    #[test]
+    fn test_text_console() {
+        let mut buffer = vec![0_u16; VgaText::WIDTH * VgaText::HEIGHT];
+        {
+            // SAFETY: The buffer has the expected size.
+            let mut vga = unsafe { VgaText::new(buffer.as_mut_ptr()) };
+            vga.write_str("boot log").unwrap();
+            vga.set_cursor(3, 100);
+            vga.write_str("xy").unwrap();
+            vga.show_fatal(format_args!("panic: {}", 42));
+        }
+
+        let red = u16::from(0x4f_u8) << 8;
+        assert_eq!(buffer[0], red | u16::from(b'p'));
+        assert_eq!(char_at(&buffer, 0, 7), b'4');
+        // The previous content is gone.
+        assert_eq!(buffer[VgaText::WIDTH * 3 + 79], red | u16::from(b' '));
+        assert_eq!(char_at(&buffer, 4, 0), b' ');
+        assert!(buffer.iter().all(|cell| cell >> 8 == red >> 8));
+    }
+}
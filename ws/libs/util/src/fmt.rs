@@ -0,0 +1,74 @@
+//! Formatting helpers for debugging.
+
+use core::fmt::{self, Write};
+
+/// Number of bytes per line of [`hexdump`].
+const BYTES_PER_LINE: usize = 16;
+
+/// Writes a classic hex dump of `bytes` to `writer`, 16 bytes per line:
+///
+/// ```text
+/// 0x0000000000001000: 7f 45 4c 46 02 01 01 00 00 00 00 00 00 00 00 00 |.ELF............|
+/// ```
+///
+/// Each line starts with the address of its first byte, counted from
+/// `base_addr`, and ends with the bytes as ASCII, with non-printable bytes
+/// shown as `.`. A short last line is padded, so that the ASCII columns
+/// align. Every line, including the last one, ends with `\n`.
+///
+/// This doesn't allocate, so it is usable in any environment.
+pub fn hexdump(bytes: &[u8], base_addr: u64, writer: &mut dyn Write) -> fmt::Result {
+    for (i, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let addr = base_addr.wrapping_add((i * BYTES_PER_LINE) as u64);
+        write!(writer, "{addr:#018x}:")?;
+        for byte in line {
+            write!(writer, " {byte:02x}")?;
+        }
+        for _ in line.len()..BYTES_PER_LINE {
+            writer.write_str("   ")?;
+        }
+        writer.write_str(" |")?;
+        for &byte in line {
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            writer.write_char(c)?;
+        }
+        writer.write_str("|\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn test_hexdump() {
+        let mut bytes = [0_u8; 32];
+        bytes[..4].copy_from_slice(b"\x7fELF");
+        bytes[16..32].copy_from_slice(b"Hello, world!\n\xff~");
+
+        let mut out = String::new();
+        hexdump(&bytes, 0x1000, &mut out).unwrap();
+        assert_eq!(
+            out,
+            "0x0000000000001000: 7f 45 4c 46 00 00 00 00 00 00 00 00 00 00 00 00 |.ELF............|\n\
+             0x0000000000001010: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a ff 7e |Hello, world!..~|\n"
+        );
+
+        let mut out = String::new();
+        hexdump(&bytes[..3], 0, &mut out).unwrap();
+        assert_eq!(
+            out,
+            "0x0000000000000000: 7f 45 4c                                        |.EL|\n"
+        );
+
+        let mut out = String::new();
+        hexdump(&[], 0, &mut out).unwrap();
+        assert_eq!(out, "");
+    }
+}
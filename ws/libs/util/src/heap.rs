@@ -0,0 +1,520 @@
+//! Heap allocator over a single span of memory, shared by the loader and the
+//! kernel.
+//!
+//! The binaries only differ in how they obtain the backing memory. They
+//! register an [`Allocator`] as `#[global_allocator]` and hand it the memory
+//! via [`Allocator::init_from_span`].
+//!
+//! Alternatively, a [`BumpAllocator`] serves the few allocations of the
+//! earliest boot phase from a small static buffer until the full heap is
+//! switched in.
+//!
+//! `GlobalAlloc` can only report failures as a null pointer, after which the
+//! allocation error handler panics without the layout. An [`AllocFailureHook`]
+//! can report the failed allocation before that, e.g., via the raw debugcon
+//! escape hatch, which works even if the logger needs the heap.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::fmt::{self, Display, Formatter};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+use linked_list_allocator::Heap;
+use spin::mutex::SpinMutex;
+use thiserror::Error;
+
+/// Byte freed memory is overwritten with in debug builds, so that reads via
+/// dangling pointers yield obviously wrong data.
+#[cfg(debug_assertions)]
+pub const POISON_BYTE: u8 = 0xde;
+
+/// Diagnostic for a failed allocation, see [`AllocFailureHook`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AllocFailure {
+    /// Requested size.
+    pub size: usize,
+    /// Requested alignment.
+    pub align: usize,
+}
+
+impl From<Layout> for AllocFailure {
+    fn from(layout: Layout) -> Self {
+        Self {
+            size: layout.size(),
+            align: layout.align(),
+        }
+    }
+}
+
+impl Display for AllocFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "alloc failed: size={} align={}", self.size, self.align)
+    }
+}
+
+/// Function called by the allocators of this module when an allocation fails,
+/// right before they return a null pointer.
+///
+/// The hook must not allocate.
+pub type AllocFailureHook = fn(AllocFailure);
+
+/// Global allocator managing a single span of memory.
+///
+/// Allocations fail until [`Allocator::init_from_span`] was called.
+pub struct Allocator {
+    heap: SpinMutex<Heap>,
+    failure_hook: Option<AllocFailureHook>,
+}
+
+impl Allocator {
+    /// Creates a new allocator without backing memory.
+    pub const fn new() -> Self {
+        Self {
+            heap: SpinMutex::new(Heap::empty()),
+            failure_hook: None,
+        }
+    }
+
+    /// Sets the hook that is called when an allocation fails.
+    #[must_use]
+    pub const fn with_failure_hook(mut self, hook: AllocFailureHook) -> Self {
+        self.failure_hook = Some(hook);
+        self
+    }
+
+    fn report_failure(&self, layout: Layout) {
+        if let Some(hook) = self.failure_hook {
+            hook(layout.into());
+        }
+    }
+
+    /// Hands the memory `base..base + size` to the allocator.
+    ///
+    /// # Panics
+    /// Panics if the allocator was already initialized.
+    ///
+    /// # Safety
+    /// The memory must be valid, exclusively owned by the allocator, and
+    /// live for the rest of the program.
+    pub unsafe fn init_from_span(&self, base: *mut u8, size: usize) {
+        let mut heap = self.heap.lock();
+        assert_eq!(heap.size(), 0, "heap should only be initialized once");
+        // SAFETY: Guaranteed by the caller.
+        unsafe { heap.init(base, size) }
+    }
+}
+
+impl Default for Allocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .heap
+            .lock()
+            .allocate_first_fit(layout)
+            .map_or(core::ptr::null_mut(), NonNull::as_ptr);
+        if ptr.is_null() {
+            self.report_failure(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Poison before the region is returned to the free list, as the
+        // allocator stores its own metadata in free regions.
+        #[cfg(debug_assertions)]
+        // SAFETY: The caller guarantees that `ptr` is valid for `layout`.
+        unsafe {
+            ptr.write_bytes(POISON_BYTE, layout.size());
+        }
+
+        // SAFETY: The caller guarantees that `ptr` was allocated by us.
+        unsafe {
+            self.heap
+                .lock()
+                .deallocate(NonNull::new_unchecked(ptr), layout)
+        }
+    }
+}
+
+/// Global allocator for the earliest boot phase, before any RAM besides the
+/// image of the binary is available.
+///
+/// # Switchover Protocol
+/// 1. Initially, allocations are served from a static buffer of `N` bytes by
+///    bumping a pointer. Freeing the most recent allocation moves the pointer
+///    back to its start, and freeing the last live allocation resets it.
+///    Other freed memory is only reused once all allocations from the buffer
+///    are freed. Once the buffer is exhausted, allocations fail.
+/// 2. Once RAM is mapped, [`BumpAllocator::switch_to_heap`] hands a span of
+///    memory to an inner [`Allocator`].
+/// 3. From then on, all allocations are served by the inner allocator.
+///    Memory from the static buffer is still freed to the buffer, which
+///    doesn't serve new allocations anymore. Hence, early allocations stay
+///    valid.
+///
+/// Unlike a statically backed [`Allocator`], this keeps the binary small, as
+/// only `N` bytes are reserved in its image.
+pub struct BumpAllocator<const N: usize> {
+    mem: UnsafeCell<[u8; N]>,
+    state: SpinMutex<BumpState>,
+    heap: Allocator,
+    switched: AtomicBool,
+}
+
+/// Bump pointer of a [`BumpAllocator`].
+#[derive(Debug)]
+struct BumpState {
+    /// Offset of the first unused byte of the buffer.
+    next: usize,
+    /// Number of allocations from the buffer that are not yet freed.
+    live: usize,
+}
+
+// SAFETY: Each byte of `mem` is handed out at most once at a time, guarded by
+// the lock of `state`.
+unsafe impl<const N: usize> Sync for BumpAllocator<N> {}
+
+impl<const N: usize> BumpAllocator<N> {
+    /// Creates a new allocator in the bump phase.
+    pub const fn new() -> Self {
+        Self {
+            mem: UnsafeCell::new([0; N]),
+            state: SpinMutex::new(BumpState { next: 0, live: 0 }),
+            heap: Allocator::new(),
+            switched: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets the hook that is called when an allocation fails, both in the
+    /// bump phase and after the switch.
+    #[must_use]
+    pub const fn with_failure_hook(mut self, hook: AllocFailureHook) -> Self {
+        self.heap = self.heap.with_failure_hook(hook);
+        self
+    }
+
+    /// Switches to a full heap over the memory `base..base + size`.
+    ///
+    /// # Panics
+    /// Panics if the allocator already switched.
+    ///
+    /// # Safety
+    /// See [`Allocator::init_from_span`].
+    pub unsafe fn switch_to_heap(&self, base: *mut u8, size: usize) {
+        // SAFETY: Guaranteed by the caller.
+        unsafe { self.heap.init_from_span(base, size) };
+        self.switched.store(true, Ordering::Release);
+    }
+
+    /// Returns the number of bytes used from the static buffer, including
+    /// padding.
+    pub fn bump_used(&self) -> usize {
+        self.state.lock().next
+    }
+
+    fn is_from_buffer(&self, ptr: *mut u8) -> bool {
+        let base = self.mem.get().cast::<u8>();
+        (base as usize..base as usize + N).contains(&(ptr as usize))
+    }
+
+    /// Frees memory from the buffer, see the switchover protocol of
+    /// [`BumpAllocator`].
+    fn release(&self, ptr: *mut u8, layout: Layout) {
+        let start = ptr as usize - self.mem.get() as usize;
+        let mut state = self.state.lock();
+        state.live -= 1;
+        if state.live == 0 {
+            state.next = 0;
+        } else if state.next == start + layout.size() {
+            state.next = start;
+        }
+    }
+
+    fn bump(&self, layout: Layout) -> *mut u8 {
+        let base = self.mem.get().cast::<u8>();
+        let mut state = self.state.lock();
+        let start = (base as usize + state.next).next_multiple_of(layout.align()) - base as usize;
+        let Some(end) = start.checked_add(layout.size()).filter(|&end| end <= N) else {
+            return core::ptr::null_mut();
+        };
+        state.next = end;
+        state.live += 1;
+        // SAFETY: `start` is within the buffer.
+        unsafe { base.add(start) }
+    }
+}
+
+impl<const N: usize> Default for BumpAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for BumpAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.switched.load(Ordering::Acquire) {
+            // SAFETY: Forwarded from the caller.
+            unsafe { self.heap.alloc(layout) }
+        } else {
+            let ptr = self.bump(layout);
+            if ptr.is_null() {
+                self.heap.report_failure(layout);
+            }
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.is_from_buffer(ptr) {
+            self.release(ptr, layout);
+        } else {
+            // SAFETY: Forwarded from the caller.
+            unsafe { self.heap.dealloc(ptr, layout) }
+        }
+    }
+}
+
+/// Possible errors of [`self_test`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Error)]
+pub enum HeapError {
+    /// An allocation returned a null pointer.
+    #[error("allocation of {size} bytes (align {align}) failed")]
+    AllocFailed {
+        /// Requested size.
+        size: usize,
+        /// Requested alignment.
+        align: usize,
+    },
+    /// An allocation is not aligned as requested.
+    #[error("allocation of {size} bytes at {addr:#x} is not aligned to {align}")]
+    Misaligned {
+        /// Address of the allocation.
+        addr: usize,
+        /// Requested size.
+        size: usize,
+        /// Requested alignment.
+        align: usize,
+    },
+    /// The contents of an allocation changed while other allocations were
+    /// made, e.g., because they overlap.
+    #[error("allocation at {addr:#x} was corrupted")]
+    Corrupted {
+        /// Address of the allocation.
+        addr: usize,
+    },
+}
+
+/// Sizes and alignments of the allocations done by [`self_test`].
+const SELF_TEST_LAYOUTS: [(usize, usize); 6] = [
+    (1, 1),
+    (24, 8),
+    (100, 16),
+    (256, 64),
+    (4096, 4096),
+    (3000, 8),
+];
+
+/// Checks `allocator` with a few allocations of varying sizes and alignments.
+///
+/// All allocations are alive at the same time and are filled with a
+/// distinct pattern, so that overlapping allocations are detected. All memory
+/// is freed again in reverse order of allocation, also on errors, so that
+/// even a [`BumpAllocator`] can reuse it. This needs about 16 KiB of free memory and
+/// doesn't use the global allocator itself.
+pub fn self_test(allocator: &impl GlobalAlloc) -> Result<(), HeapError> {
+    let mut ptrs = [core::ptr::null_mut::<u8>(); SELF_TEST_LAYOUTS.len()];
+    let layouts = SELF_TEST_LAYOUTS
+        .map(|(size, align)| Layout::from_size_align(size, align).expect("should be valid"));
+
+    let res = (|| {
+        for (i, layout) in layouts.iter().enumerate() {
+            // SAFETY: The layout has a non-zero size.
+            let ptr = unsafe { allocator.alloc(*layout) };
+            if ptr.is_null() {
+                return Err(HeapError::AllocFailed {
+                    size: layout.size(),
+                    align: layout.align(),
+                });
+            }
+            ptrs[i] = ptr;
+            if !ptr.addr().is_multiple_of(layout.align()) {
+                return Err(HeapError::Misaligned {
+                    addr: ptr.addr(),
+                    size: layout.size(),
+                    align: layout.align(),
+                });
+            }
+            // SAFETY: The memory was just allocated with this size.
+            unsafe { ptr.write_bytes(i as u8 + 1, layout.size()) };
+        }
+        for (i, (ptr, layout)) in ptrs.iter().zip(&layouts).enumerate() {
+            // SAFETY: The memory is allocated and initialized.
+            let mem = unsafe { core::slice::from_raw_parts(*ptr, layout.size()) };
+            if mem.iter().any(|&b| b != i as u8 + 1) {
+                return Err(HeapError::Corrupted { addr: ptr.addr() });
+            }
+        }
+        Ok(())
+    })();
+
+    for (ptr, layout) in ptrs.into_iter().zip(layouts).rev() {
+        if !ptr.is_null() {
+            // SAFETY: The memory was allocated with this layout.
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::Page;
+    use alloc::boxed::Box;
+    use core::sync::atomic::AtomicUsize;
+
+    /// Returns an allocator backed by leaked memory.
+    fn allocator(pages: usize) -> Allocator {
+        let mem = Box::leak(alloc::vec![Page::ZERO; pages].into_boxed_slice());
+        let allocator = Allocator::new();
+        unsafe { allocator.init_from_span(mem.as_mut_ptr().cast(), size_of_val(mem)) };
+        allocator
+    }
+
+    #[test]
+    fn test_uninitialized() {
+        let allocator = Allocator::new();
+        let ptr = unsafe { allocator.alloc(Layout::new::<u64>()) };
+        assert!(ptr.is_null());
+    }
+
+    #[test]
+    fn test_alloc_dealloc() {
+        let allocator = allocator(4);
+        let layout = Layout::from_size_align(256, 64).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr.align_offset(64), 0);
+        unsafe { ptr.write_bytes(0x42, layout.size()) };
+        unsafe { allocator.dealloc(ptr, layout) };
+
+        // All memory is free again.
+        let layout = Layout::from_size_align(4 * 4096, 4096).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_dealloc_poisons() {
+        let allocator = allocator(1);
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { ptr.write_bytes(0x42, layout.size()) };
+        unsafe { allocator.dealloc(ptr, layout) };
+
+        // The allocator's own metadata lives at the beginning of the freed
+        // region; all behind it must be poisoned.
+        let freed = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+        assert!(freed[64..].iter().all(|&b| b == POISON_BYTE));
+    }
+
+    #[test]
+    fn test_bump_allocator() {
+        let allocator = Box::new(BumpAllocator::<256>::new());
+        let small = unsafe { allocator.alloc(Layout::new::<u8>()) };
+        let aligned = unsafe { allocator.alloc(Layout::from_size_align(64, 64).unwrap()) };
+        assert!(!small.is_null());
+        assert_eq!(aligned.align_offset(64), 0);
+        assert!(aligned > small);
+
+        // Only the memory of the most recent allocation is reused right away.
+        let used = allocator.bump_used();
+        let small2 = unsafe { allocator.alloc(Layout::new::<u8>()) };
+        unsafe { allocator.dealloc(aligned, Layout::from_size_align(64, 64).unwrap()) };
+        assert_eq!(allocator.bump_used(), used + 1);
+        unsafe { allocator.dealloc(small2, Layout::new::<u8>()) };
+        assert_eq!(allocator.bump_used(), used);
+        assert!(unsafe { allocator.alloc(Layout::new::<[u8; 512]>()) }.is_null());
+        assert_eq!(allocator.bump_used(), used);
+
+        let mem = Box::leak(Box::new(Page::ZERO));
+        unsafe { allocator.switch_to_heap(mem.as_ptr_mut(), size_of::<Page>()) };
+        let layout = Layout::new::<[u8; 512]>();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr, mem.as_ptr_mut());
+        unsafe { allocator.dealloc(ptr, layout) };
+        // Early allocations are still freed to the buffer, not to the heap.
+        unsafe { allocator.dealloc(small, Layout::new::<u8>()) };
+        assert_eq!(allocator.bump_used(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "only be initialized once")]
+    fn test_init_twice() {
+        let allocator = allocator(1);
+        let mut mem = Box::new(Page::ZERO);
+        unsafe { allocator.init_from_span(mem.as_ptr_mut(), size_of::<Page>()) };
+    }
+
+    #[test]
+    fn test_self_test() {
+        assert_eq!(self_test(&allocator(8)), Ok(()));
+        assert_eq!(
+            self_test(&Allocator::new()),
+            Err(HeapError::AllocFailed { size: 1, align: 1 })
+        );
+        // Fails in the middle; the earlier allocations are freed again.
+        let allocator = allocator(1);
+        assert_eq!(
+            self_test(&allocator),
+            Err(HeapError::AllocFailed {
+                size: 4096,
+                align: 4096
+            })
+        );
+        assert_eq!(self_test(&allocator), self_test(&allocator));
+        let ptr = unsafe { allocator.alloc(Layout::from_size_align(2048, 8).unwrap()) };
+        assert!(!ptr.is_null());
+
+        // The bump allocator gets all memory back.
+        let allocator = Box::new(BumpAllocator::<{ 16 * 1024 }>::new());
+        for _ in 0..8 {
+            assert_eq!(self_test(&*allocator), Ok(()));
+            assert_eq!(allocator.bump_used(), 0);
+        }
+    }
+
+    #[test]
+    fn test_failure_hook() {
+        static FAILED: AtomicUsize = AtomicUsize::new(0);
+        fn hook(failure: AllocFailure) {
+            assert_eq!(
+                alloc::format!("{failure}"),
+                "alloc failed: size=8192 align=4096"
+            );
+            FAILED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mem = Box::leak(Box::new(Page::ZERO));
+        let allocator = Allocator::new().with_failure_hook(hook);
+        unsafe { allocator.init_from_span(mem.as_ptr_mut(), size_of::<Page>()) };
+        let ptr = unsafe { allocator.alloc(Layout::from_size_align(2048, 8).unwrap()) };
+        assert!(!ptr.is_null());
+        assert_eq!(FAILED.load(Ordering::Relaxed), 0);
+
+        let layout = Layout::from_size_align(8192, 4096).unwrap();
+        assert!(unsafe { allocator.alloc(layout) }.is_null());
+        assert_eq!(FAILED.load(Ordering::Relaxed), 1);
+
+        // Also in the bump phase of the bump allocator.
+        let allocator = Box::new(BumpAllocator::<256>::new().with_failure_hook(hook));
+        assert!(unsafe { allocator.alloc(layout) }.is_null());
+        assert_eq!(FAILED.load(Ordering::Relaxed), 2);
+    }
+}
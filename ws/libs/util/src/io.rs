@@ -0,0 +1,100 @@
+//! Helpers for writers.
+
+use core::fmt;
+
+/// Line-buffering adapter for a [`fmt::Write`].
+///
+/// Accumulates the written text in a buffer of `N` bytes and forwards it to
+/// the inner writer on each newline or when the buffer is full. This reduces
+/// the number of writes to the inner writer, which is useful if each write is
+/// expensive, e.g., a port I/O.
+///
+/// The buffer never splits a UTF-8 character. Call [`Self::flush`] after the
+/// last write to emit a trailing partial line; dropping the adapter discards
+/// it.
+#[derive(Debug)]
+pub struct LineBuffered<W: fmt::Write, const N: usize> {
+    inner: W,
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<W: fmt::Write, const N: usize> LineBuffered<W, N> {
+    /// Creates a new adapter around `inner`.
+    pub const fn new(inner: W) -> Self {
+        const { assert!(N >= 4, "buffer must fit any UTF-8 character") };
+        Self {
+            inner,
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Forwards the buffered text to the inner writer.
+    pub fn flush(&mut self) -> fmt::Result {
+        if self.len == 0 {
+            return Ok(());
+        }
+        // The buffer only ever contains complete UTF-8 characters.
+        let text = core::str::from_utf8(&self.buf[..self.len]).map_err(|_| fmt::Error)?;
+        self.len = 0;
+        self.inner.write_str(text)
+    }
+
+    /// Returns the inner writer. Text that is not yet flushed is discarded.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: fmt::Write, const N: usize> fmt::Write for LineBuffered<W, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.len + c.len_utf8() > N {
+                self.flush()?;
+            }
+            let encoded = c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += encoded.len();
+            if c == '\n' {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use core::fmt::Write;
+
+    /// Writer recording each write separately.
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Write for Recorder {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0.push(s.into());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_line_buffered() {
+        let mut writer = LineBuffered::<_, 4>::new(Recorder::default());
+        writer.write_str("hello\nworld").unwrap();
+        assert_eq!(writer.inner.0, ["hell", "o\n", "worl"]);
+        writer.flush().unwrap();
+        assert_eq!(writer.into_inner().0, ["hell", "o\n", "worl", "d"]);
+    }
+
+    #[test]
+    fn test_line_buffered_utf8() {
+        let mut writer = LineBuffered::<_, 4>::new(Recorder::default());
+        writer.write_str("aä€").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.into_inner().0, ["aä", "€"]);
+    }
+}
@@ -6,17 +6,17 @@ extern crate alloc;
 #[cfg(test)]
 extern crate std;
 
+pub mod cpu;
 pub mod drivers;
+pub mod fmt;
+pub mod heap;
+pub mod io;
 pub mod logging;
 pub mod mem;
 pub mod paging;
+pub mod sizes;
 pub mod sync;
-
-pub mod sizes {
-    pub const FOUR_K: usize = 4096;
-    pub const TWO_MIB: usize = 0x200000;
-    pub const ONE_GIB: usize = 0x40000000;
-}
+pub mod time;
 
 #[cfg(test)]
 mod tests {
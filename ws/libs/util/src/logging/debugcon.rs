@@ -1,9 +1,22 @@
 use crate::drivers::DebugCon;
-use crate::logging::fmt_and_write_msg;
+use crate::logging::{LogFormat, fmt_and_write_msg};
 use core::fmt::Write;
 use log::{Metadata, Record};
 
-pub struct DebugconLogger;
+/// Logger writing to the [`DebugCon`] device.
+///
+/// This never allocates, so it is usable before the heap is initialized.
+#[derive(Debug, Default)]
+pub struct DebugconLogger {
+    format: LogFormat,
+}
+
+impl DebugconLogger {
+    /// Creates a new logger writing messages in the given format.
+    pub const fn new(format: LogFormat) -> Self {
+        Self { format }
+    }
+}
 
 impl log::Log for DebugconLogger {
     fn enabled(&self, _metadata: &Metadata) -> bool {
@@ -11,9 +24,25 @@ impl log::Log for DebugconLogger {
     }
 
     fn log(&self, record: &Record) {
-        fmt_and_write_msg(&mut DebugCon, record).unwrap();
+        fmt_and_write_msg(&mut DebugCon, record, self.format).unwrap();
         DebugCon.write_char('\n').unwrap();
     }
 
     fn flush(&self) {}
 }
+
+/// Writes `s` directly to the [`DebugCon`] device, bypassing the [`log`]
+/// infrastructure.
+///
+/// This is the "print before anything works" escape hatch: it works from the
+/// very first instruction, before the logger is initialized, and never
+/// allocates. Use the [`log`] macros everywhere else.
+///
+/// Nothing is written if the device is not present, as [`DebugCon::PORT`] may belong
+/// to other hardware then.
+#[inline]
+pub fn raw_debugcon_print(s: &str) {
+    if DebugCon::is_present() {
+        s.bytes().for_each(DebugCon::write);
+    }
+}
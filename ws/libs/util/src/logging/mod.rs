@@ -1,25 +1,144 @@
 mod debugcon;
+mod vga_text;
 
 pub use debugcon::*;
+pub use vga_text::*;
 
+use crate::drivers::TextConsole;
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::fmt;
 use log::{LevelFilter, Log, Metadata, Record};
 use spin::Once as SyncOnceCell;
 
-/// Actually formats a [`log`] message properly and writes it to the
-/// corresponding destination specified by `writer`.
+/// Preset formats for log messages.
 ///
-/// This does not add a terminating newline.
-pub fn fmt_and_write_msg(writer: &mut dyn fmt::Write, record: &Record) -> core::fmt::Result {
-    write!(
-        writer,
-        "[{:>5} {}@{:03}]: {}",
-        record.level(),
-        record.file().unwrap_or("<unknown>"),
-        record.line().unwrap_or(0),
-        record.args()
-    )
+/// Each logger backend chooses its own format.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LogFormat {
+    /// Level, full file path, and line: `[ INFO src/foo.rs@007]: msg`
+    #[default]
+    Full,
+    /// Abbreviated level, file name, and line: `[I foo.rs@7]: msg`
+    Compact,
+    /// Level only, for narrow outputs: `[ INFO]: msg`
+    LevelOnly,
+}
+
+/// Kinds of the typed backends of a [`LoggerFacadeInner`].
+///
+/// Binaries can use this to select their backends at runtime by name.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BackendKind {
+    /// The [`DebugconLogger`].
+    Debugcon,
+    /// The [`VgaTextLogger`].
+    VgaText,
+    /// The stdout logger of the environment, if any.
+    Stdout,
+}
+
+impl BackendKind {
+    /// All backend kinds.
+    pub const ALL: [Self; 3] = [Self::Debugcon, Self::VgaText, Self::Stdout];
+
+    /// Returns the name of the backend, e.g., in configuration files.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Debugcon => "debugcon",
+            Self::VgaText => "vga_text",
+            Self::Stdout => "stdout",
+        }
+    }
+
+    /// Returns the backend kind with the given [name](Self::name), if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// Selects the backends with the given [names](BackendKind::name) among the
+/// `supported` ones, e.g., as listed in a configuration file.
+///
+/// Returns the selected kinds without duplicates, in the order of their first
+/// occurrence, and the names that are unknown or not supported, which the
+/// caller should report.
+pub fn select_backends<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    supported: &[BackendKind],
+) -> (Vec<BackendKind>, Vec<&'a str>) {
+    let mut kinds = Vec::new();
+    let mut ignored = Vec::new();
+    for name in names {
+        match BackendKind::from_name(name) {
+            Some(kind) if supported.contains(&kind) => {
+                if !kinds.contains(&kind) {
+                    kinds.push(kind);
+                }
+            }
+            _ => ignored.push(name),
+        }
+    }
+    (kinds, ignored)
+}
+
+/// Source of the id of the current CPU core, published by
+/// [`LoggerFacade::init`].
+static CORE_ID_SOURCE: SyncOnceCell<fn() -> u32> = SyncOnceCell::new();
+
+/// Actually formats a [`log`] message properly in the given [`LogFormat`] and
+/// writes it to the corresponding destination specified by `writer`.
+///
+/// If a core-id source was registered via
+/// [`LoggerFacadeInner::set_core_id_source`], the message is prefixed with
+/// the id of the current core, e.g., `[CPU0] `.
+///
+/// This does not add a terminating newline. This never allocates, so it can
+/// be used before the heap is initialized.
+pub fn fmt_and_write_msg(
+    writer: &mut dyn fmt::Write,
+    record: &Record,
+    format: LogFormat,
+) -> core::fmt::Result {
+    let core_id = CORE_ID_SOURCE.get().map(|source| source());
+    write_msg(writer, record, format, core_id)
+}
+
+/// Implementation of [`fmt_and_write_msg`] with an explicit core id.
+fn write_msg(
+    writer: &mut dyn fmt::Write,
+    record: &Record,
+    format: LogFormat,
+    core_id: Option<u32>,
+) -> core::fmt::Result {
+    if let Some(core_id) = core_id {
+        write!(writer, "[CPU{core_id}] ")?;
+    }
+    let file = record.file().unwrap_or("<unknown>");
+    let line = record.line().unwrap_or(0);
+    match format {
+        LogFormat::Full => write!(
+            writer,
+            "[{:>5} {}@{:03}]: {}",
+            record.level(),
+            file,
+            line,
+            record.args()
+        ),
+        LogFormat::Compact => {
+            let file_name = file.rsplit(['/', '\\']).next().unwrap_or(file);
+            let level = record.level().as_str();
+            write!(
+                writer,
+                "[{} {}@{}]: {}",
+                &level[..1],
+                file_name,
+                line,
+                record.args()
+            )
+        }
+        LogFormat::LevelOnly => write!(writer, "[{:>5}]: {}", record.level(), record.args()),
+    }
 }
 
 /// Logging facade for [`log`] over various optional logger backends.
@@ -38,10 +157,21 @@ impl LoggerFacade {
     ///
     /// This operation must only be called once.
     pub fn init<'a: 'static>(&'a self, inner: LoggerFacadeInner, max_level: LevelFilter) {
+        if let Some(source) = inner.core_id_source {
+            CORE_ID_SOURCE.call_once(|| source);
+        }
         self.0.call_once(|| inner);
         let _ = log::set_logger(self);
         log::set_max_level(max_level);
     }
+
+    /// Runs `f` for each backend that is a [`TextConsole`], e.g., to show a
+    /// panic prominently. Consoles that are currently in use are skipped.
+    pub fn with_text_consoles(&self, f: impl FnMut(&mut dyn TextConsole)) {
+        if let Some(inner) = self.0.get() {
+            inner.with_text_consoles(f);
+        }
+    }
 }
 
 impl Default for LoggerFacade {
@@ -64,16 +194,30 @@ impl Log for LoggerFacade {
     }
 }
 
+/// Maximum number of backends added via [`LoggerFacadeInner::add_backend`].
+pub const MAX_BACKENDS: usize = 4;
+
+/// The backends of a [`LoggerFacade`].
+///
+/// Apart from the typed slots for debugcon, VGA text mode, and stdout, up to
+/// [`MAX_BACKENDS`] further backends can be added. Each backend needs at most
+/// one allocation; the debugcon and VGA text backends need none.
 pub struct LoggerFacadeInner {
     debugcon: Option<DebugconLogger>,
+    vga_text: Option<VgaTextLogger>,
     stdout_logger: Option<Box<dyn Log>>,
+    backends: heapless::Vec<Box<dyn Log>, MAX_BACKENDS>,
+    core_id_source: Option<fn() -> u32>,
 }
 
 impl LoggerFacadeInner {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             debugcon: None,
+            vga_text: None,
             stdout_logger: None,
+            backends: heapless::Vec::new(),
+            core_id_source: None,
         }
     }
 
@@ -81,15 +225,60 @@ impl LoggerFacadeInner {
         self.debugcon = Some(debugcon);
     }
 
+    pub fn set_vga_text(&mut self, vga_text: VgaTextLogger) {
+        self.vga_text = Some(vga_text);
+    }
+
     pub fn set_stdout_logger(&mut self, stdout_logger: Box<dyn Log>) {
         self.stdout_logger = Some(stdout_logger);
     }
 
-    fn loggers(&self) -> [Option<&dyn Log>; 2] {
+    /// Sets the source of the id of the current CPU core, e.g., reading the
+    /// APIC id or a CPU-local variable.
+    ///
+    /// With a source, [`fmt_and_write_msg`] prefixes each message with the
+    /// core id, e.g., `[CPU0] `, so that interleaved messages of multiple
+    /// cores can be told apart. The source must not log.
+    pub fn set_core_id_source(&mut self, source: fn() -> u32) {
+        self.core_id_source = Some(source);
+    }
+
+    /// Adds a further backend.
+    ///
+    /// Returns the backend as error if there are already [`MAX_BACKENDS`]
+    /// backends.
+    pub fn add_backend(&mut self, logger: Box<dyn Log>) -> Result<(), Box<dyn Log>> {
+        self.backends.push(logger)
+    }
+
+    /// Runs `f` for each backend that is a [`TextConsole`]. Consoles that
+    /// are currently in use are skipped.
+    pub fn with_text_consoles(&self, mut f: impl FnMut(&mut dyn TextConsole)) {
+        if let Some(vga_text) = &self.vga_text {
+            vga_text.with_console(&mut f);
+        }
+    }
+
+    /// Returns the kinds of the typed backends that are set.
+    ///
+    /// Backends added via [`Self::add_backend`] are not included.
+    pub fn backend_kinds(&self) -> impl Iterator<Item = BackendKind> {
         [
-            self.stdout_logger.as_deref(),
-            self.debugcon.as_ref().map(|d| d as &dyn Log),
+            (BackendKind::Debugcon, self.debugcon.is_some()),
+            (BackendKind::VgaText, self.vga_text.is_some()),
+            (BackendKind::Stdout, self.stdout_logger.is_some()),
         ]
+        .into_iter()
+        .filter_map(|(kind, set)| set.then_some(kind))
+    }
+
+    fn loggers(&self) -> impl Iterator<Item = &dyn Log> {
+        self.stdout_logger
+            .as_deref()
+            .into_iter()
+            .chain(self.debugcon.as_ref().map(|d| d as &dyn Log))
+            .chain(self.vga_text.as_ref().map(|v| v as &dyn Log))
+            .chain(self.backends.iter().map(|b| b.as_ref()))
     }
 }
 
@@ -105,15 +294,15 @@ impl Log for LoggerFacadeInner {
     }
 
     fn log(&self, record: &Record) {
-        for logger in self.loggers().into_iter().flatten() {
-            if  logger.enabled(record.metadata()) {
-                    logger.log(record);
-                }
+        for logger in self.loggers() {
+            if logger.enabled(record.metadata()) {
+                logger.log(record);
+            }
         }
     }
 
     fn flush(&self) {
-        for logger in self.loggers().into_iter().flatten() {
+        for logger in self.loggers() {
             logger.flush();
         }
     }
@@ -121,10 +310,18 @@ impl Log for LoggerFacadeInner {
 
 #[cfg(test)]
 mod tests {
-    use crate::logging::test_support::StdErrLogger;
-    use crate::logging::{LoggerFacade, LoggerFacadeInner};
+    use crate::drivers::VgaText;
+    use crate::logging::test_support::{StdErrLogger, forbid_alloc};
+    use crate::logging::{
+        BackendKind, DebugconLogger, LogFormat, LoggerFacade, LoggerFacadeInner, MAX_BACKENDS,
+        VgaTextLogger, fmt_and_write_msg, select_backends,
+    };
     use alloc::boxed::Box;
-    use log::LevelFilter;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::fmt::Write;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use log::{Level, LevelFilter, Log, Metadata, Record};
 
     static TEST_LOGGER: LoggerFacade = LoggerFacade::new();
 
@@ -135,11 +332,209 @@ mod tests {
         TEST_LOGGER.init(logger_facade, LevelFilter::Trace);
         log::info!("hello from logger");
     }
+
+    /// Backend counting the log messages.
+    struct CountingLogger(std::sync::Arc<AtomicUsize>);
+
+    impl Log for CountingLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, _record: &Record) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn add_backends() {
+        let count = std::sync::Arc::new(AtomicUsize::new(0));
+        let mut inner = LoggerFacadeInner::new();
+        for _ in 0..MAX_BACKENDS {
+            assert!(
+                inner
+                    .add_backend(Box::new(CountingLogger(count.clone())))
+                    .is_ok()
+            );
+        }
+        assert!(
+            inner
+                .add_backend(Box::new(CountingLogger(count.clone())))
+                .is_err()
+        );
+
+        inner.log(&Record::builder().args(format_args!("hello")).build());
+        assert_eq!(count.load(Ordering::SeqCst), MAX_BACKENDS);
+    }
+
+    #[test]
+    fn backends_by_name() {
+        for kind in BackendKind::ALL {
+            assert_eq!(BackendKind::from_name(kind.name()), Some(kind));
+        }
+
+        let names = [
+            "stdout", "serial", "debugcon", "Debugcon", "debugcon", "vga_text",
+        ];
+        let supported = [BackendKind::Debugcon, BackendKind::Stdout];
+        let (kinds, ignored) = select_backends(names, &supported);
+        assert_eq!(kinds, [BackendKind::Stdout, BackendKind::Debugcon]);
+        assert_eq!(ignored, ["serial", "Debugcon", "vga_text"]);
+
+        let (kinds, ignored) = select_backends(names, &BackendKind::ALL);
+        assert_eq!(
+            kinds,
+            [
+                BackendKind::Stdout,
+                BackendKind::Debugcon,
+                BackendKind::VgaText
+            ]
+        );
+        assert_eq!(ignored, ["serial", "Debugcon"]);
+        assert_eq!(select_backends([], &supported), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn fmt_and_write_msg_is_alloc_free() {
+        let mut buf = heapless::String::<128>::new();
+        forbid_alloc(|| {
+            fmt_and_write_msg(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("hello {}", 42))
+                    .level(Level::Info)
+                    .file(Some("foo.rs"))
+                    .line(Some(7))
+                    .build(),
+                LogFormat::Full,
+            )
+            .unwrap();
+            buf.write_char('\n').unwrap();
+        });
+        assert_eq!(buf.as_str(), "[ INFO foo.rs@007]: hello 42\n");
+    }
+
+    /// The facade with the backends of the early kernel logger. The debugcon
+    /// backend writes to port I/O, so only its formatting is covered by
+    /// `fmt_and_write_msg_is_alloc_free`.
+    #[test]
+    fn facade_log_is_alloc_free() {
+        let mut buffer = vec![0_u16; VgaText::WIDTH * VgaText::HEIGHT];
+        let mut inner = LoggerFacadeInner::new();
+        {
+            // SAFETY: The buffer has the expected size and outlives the logger.
+            let vga = unsafe { VgaText::new(buffer.as_mut_ptr()) };
+            inner.set_vga_text(VgaTextLogger::new(vga, LogFormat::LevelOnly));
+        }
+        let debugcon = DebugconLogger::new(LogFormat::Full);
+
+        forbid_alloc(|| {
+            let metadata = Metadata::builder().level(Level::Info).build();
+            assert!(debugcon.enabled(&metadata));
+            assert!(inner.enabled(&metadata));
+            inner.log(
+                &Record::builder()
+                    .args(format_args!("hello {}", 42))
+                    .metadata(metadata)
+                    .build(),
+            );
+            inner.flush();
+        });
+        drop(inner);
+
+        let line = buffer[..17]
+            .iter()
+            .map(|&cell| cell as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(line, b"[ INFO]: hello 42");
+    }
+
+    fn fmt_preset(format: LogFormat) -> heapless::String<128> {
+        let mut buf = heapless::String::<128>::new();
+        fmt_and_write_msg(
+            &mut buf,
+            &Record::builder()
+                .args(format_args!("hello {}", 42))
+                .level(Level::Warn)
+                .file(Some("src/logging/foo.rs"))
+                .line(Some(7))
+                .build(),
+            format,
+        )
+        .unwrap();
+        buf
+    }
+
+    #[test]
+    fn fmt_and_write_msg_presets() {
+        assert_eq!(LogFormat::default(), LogFormat::Full);
+        assert_eq!(
+            fmt_preset(LogFormat::Full).as_str(),
+            "[ WARN src/logging/foo.rs@007]: hello 42"
+        );
+        assert_eq!(
+            fmt_preset(LogFormat::Compact).as_str(),
+            "[W foo.rs@7]: hello 42"
+        );
+        assert_eq!(
+            fmt_preset(LogFormat::LevelOnly).as_str(),
+            "[ WARN]: hello 42"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected allocation")]
+    fn forbid_alloc_catches_allocations() {
+        forbid_alloc(|| Box::new(42));
+    }
 }
 
 #[cfg(test)]
 pub mod test_support {
     use super::*;
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::cell::Cell;
+    use std::alloc::System;
+
+    std::thread_local! {
+        static ALLOC_FORBIDDEN: Cell<bool> = const { Cell::new(false) };
+        static ALLOC_SEEN: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Global allocator for the unit tests that records allocations made by
+    /// the current thread within [`forbid_alloc`].
+    ///
+    /// Thread-local, as the tests run in parallel. It must not panic itself,
+    /// as unwinding out of an allocator is undefined behavior.
+    struct RecordingAllocator;
+
+    unsafe impl GlobalAlloc for RecordingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if ALLOC_FORBIDDEN.get() {
+                ALLOC_SEEN.set(true);
+            }
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: RecordingAllocator = RecordingAllocator;
+
+    /// Runs `f` and panics if it allocated heap memory.
+    pub fn forbid_alloc<R>(f: impl FnOnce() -> R) -> R {
+        ALLOC_SEEN.set(false);
+        ALLOC_FORBIDDEN.set(true);
+        let ret = f();
+        ALLOC_FORBIDDEN.set(false);
+        assert!(!ALLOC_SEEN.get(), "unexpected allocation");
+        ret
+    }
 
     pub struct StdErrLogger;
 
@@ -156,7 +551,7 @@ pub mod test_support {
         }
 
         fn log(&self, record: &Record) {
-            fmt_and_write_msg(&mut StdErrLogger, record)
+            fmt_and_write_msg(&mut StdErrLogger, record, LogFormat::Full)
                 .expect("should not failed to format and write log message")
         }
 
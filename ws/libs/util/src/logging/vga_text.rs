@@ -0,0 +1,48 @@
+use crate::drivers::{TextConsole, VgaText};
+use crate::logging::{LogFormat, fmt_and_write_msg};
+use core::fmt::Write;
+use log::{Metadata, Record};
+use spin::Mutex;
+
+/// Logger writing to the legacy VGA text buffer via [`VgaText`].
+///
+/// This is the last resort on legacy BIOS systems or VMs without a framebuffer
+/// or serial port. The text buffer must be mapped; see [`VgaText`]. This never
+/// allocates.
+#[derive(Debug)]
+pub struct VgaTextLogger {
+    vga: Mutex<VgaText>,
+    format: LogFormat,
+}
+
+impl VgaTextLogger {
+    /// Creates a new logger writing messages in the given format.
+    pub const fn new(vga: VgaText, format: LogFormat) -> Self {
+        Self {
+            vga: Mutex::new(vga),
+            format,
+        }
+    }
+
+    /// Runs `f` with the underlying console, e.g., to clear the screen.
+    ///
+    /// Returns `None` without running `f` if the console is in use, e.g.,
+    /// because a panic occurred while logging.
+    pub fn with_console<R>(&self, f: impl FnOnce(&mut dyn TextConsole) -> R) -> Option<R> {
+        self.vga.try_lock().map(|mut vga| f(&mut *vga))
+    }
+}
+
+impl log::Log for VgaTextLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut vga = self.vga.lock();
+        fmt_and_write_msg(&mut *vga, record, self.format).unwrap();
+        vga.write_char('\n').unwrap();
+    }
+
+    fn flush(&self) {}
+}
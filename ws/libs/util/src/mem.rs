@@ -1,7 +1,8 @@
 use core::alloc::Layout;
+use core::mem::ManuallyDrop;
 use core::ops::{Deref, DerefMut, Index, IndexMut};
 use core::ops::{Range, RangeInclusive};
-use core::slice;
+use core::{ptr, slice};
 
 /// An aligned buffer. Similar to `Box<[T]>` but with guaranteed alignment.
 #[derive(Debug)]
@@ -17,11 +18,24 @@ impl<T: Default + Clone> AlignedBuffer<T> {
     /// # Arguments
     /// - `size`: Amount of items
     /// - `alignment`: Alignment. Must be power of two.
+    ///
+    /// # Panics
+    /// Panics if `alignment` is not a power of two.
     pub fn new(capacity: usize, alignment: usize) -> Self {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment must be a power of two, got {alignment}"
+        );
         let size = capacity * size_of::<T>();
         let layout = Layout::from_size_align(size, alignment).unwrap();
-        // SAFETY: We trust the allocator.
-        let heap_ptr = unsafe { alloc::alloc::alloc(layout) }.cast::<T>();
+        let heap_ptr = if size == 0 {
+            // Zero-sized allocations are not allowed; any aligned non-null
+            // pointer is valid for zero bytes.
+            ptr::without_provenance_mut(alignment)
+        } else {
+            // SAFETY: We trust the allocator and the size is not zero.
+            unsafe { alloc::alloc::alloc(layout) }.cast::<T>()
+        };
         // init data
         {
             // SAFETY: The allocation is big enough and the ptr is valid.
@@ -36,6 +50,26 @@ impl<T: Default + Clone> AlignedBuffer<T> {
     }
 }
 
+impl<T> AlignedBuffer<T> {
+    /// Consumes the buffer and returns its items as slice that lives as long
+    /// as needed, usually `'static`. Mirrors [`Box::leak`].
+    ///
+    /// The allocation is intentionally never freed. This is for memory that
+    /// outlives the code that created it, such as the memory of the kernel
+    /// that the loader hands over.
+    ///
+    /// [`Box::leak`]: alloc::boxed::Box::leak
+    pub fn leak<'a>(self) -> &'a mut [T]
+    where
+        T: 'a,
+    {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: The allocation is big enough and the ptr is valid. As the
+        // buffer is never dropped, the memory is never freed.
+        unsafe { slice::from_raw_parts_mut(this.heap_ptr, this.capacity) }
+    }
+}
+
 impl<T> Deref for AlignedBuffer<T> {
     type Target = [T];
 
@@ -96,15 +130,38 @@ impl<T> IndexMut<RangeInclusive<usize>> for AlignedBuffer<T> {
 
 impl<T> Drop for AlignedBuffer<T> {
     fn drop(&mut self) {
+        if self.layout.size() == 0 {
+            return;
+        }
         // SAFETY: Allocation was done with same properties.
         unsafe { alloc::alloc::dealloc(self.heap_ptr.cast(), self.layout) }
     }
 }
 
+/// Returns how many bytes of a downward-growing stack have been used so far.
+///
+/// `bytes` yields the `len` bytes of the stack memory from its bottom (lowest
+/// address) to its top, previously filled with `pattern`. It is only consumed
+/// up to the first byte that differs from `pattern`, so that callers can read
+/// the memory lazily, e.g., volatile from a stack that is in use. Everything
+/// above that byte is considered used. A byte that was overwritten with the
+/// value of `pattern` by chance makes the result slightly too small, so this
+/// is a high-water mark for diagnostics, not an exact number.
+#[must_use]
+pub fn stack_used_bytes(len: usize, bytes: impl IntoIterator<Item = u8>, pattern: u8) -> usize {
+    let untouched = bytes
+        .into_iter()
+        .take(len)
+        .take_while(|&b| b == pattern)
+        .count();
+    len - untouched
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::sizes::TWO_MIB;
+    use alloc::vec;
 
     // Main test here is that miri accepts the test.
     #[test]
@@ -118,4 +175,93 @@ mod tests {
 
         assert_eq!(buf.as_ptr().align_offset(TWO_MIB), 0);
     }
+
+    /// Property test over random capacities and alignments, including the
+    /// edge cases of an empty buffer and sizes one byte short of or exactly
+    /// at the alignment.
+    #[test]
+    fn test_aligned_buffer_random() {
+        // xorshift64; a fixed seed keeps failures reproducible.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut cases = vec![
+            (0, TWO_MIB),
+            (1, TWO_MIB),
+            (TWO_MIB - 1, TWO_MIB),
+            (TWO_MIB, TWO_MIB),
+            (TWO_MIB + 1, TWO_MIB),
+        ];
+        for _ in 0..64 {
+            let capacity = (next() % (3 * TWO_MIB as u64)) as usize;
+            let alignment = 1 << (next() % 22);
+            cases.push((capacity, alignment));
+        }
+
+        for (capacity, alignment) in cases {
+            let mut buf = AlignedBuffer::<u8>::new(capacity, alignment);
+            assert_eq!(buf.len(), capacity);
+            assert_eq!(buf.as_ptr().align_offset(alignment), 0);
+            assert!(buf.iter().all(|&b| b == 0));
+
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8;
+            }
+            let range = buf.as_ptr_range();
+            assert_eq!(range.end as usize - range.start as usize, capacity);
+            assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+        }
+    }
+
+    // Main test here is that miri accepts the test.
+    #[test]
+    fn test_aligned_buffer_leak() {
+        let leaked: &'static mut [u64] = {
+            let mut buf = AlignedBuffer::<u64>::new(4, TWO_MIB);
+            buf[1] = 42;
+            buf.leak()
+        };
+        leaked[3] = 73;
+        assert_eq!(leaked, &[0, 42, 0, 73]);
+        assert_eq!(leaked.as_ptr().align_offset(TWO_MIB), 0);
+
+        // SAFETY: The memory is never used again; this just keeps miri from
+        // reporting the intentional leak.
+        let layout = Layout::from_size_align(4 * size_of::<u64>(), TWO_MIB).unwrap();
+        unsafe { alloc::alloc::dealloc(leaked.as_mut_ptr().cast(), layout) };
+    }
+
+    #[test]
+    fn test_stack_used_bytes() {
+        let used = |stack: &[u8]| stack_used_bytes(stack.len(), stack.iter().copied(), 0xaa);
+        assert_eq!(used(&[]), 0);
+        assert_eq!(used(&[0xaa; 16]), 0);
+        assert_eq!(used(&[0; 16]), 16);
+
+        let mut stack = [0xaa_u8; 64];
+        stack[48..].fill(0x12);
+        assert_eq!(used(&stack), 16);
+        // Pattern bytes above the high-water mark still count as used.
+        stack[56] = 0xaa;
+        assert_eq!(used(&stack), 16);
+        stack[20] = 0;
+        assert_eq!(used(&stack), 44);
+
+        // Only the bytes up to the high-water mark are read.
+        let mut read = 0;
+        let bytes = stack.iter().inspect(|_| read += 1).copied();
+        assert_eq!(stack_used_bytes(stack.len(), bytes, 0xaa), 44);
+        assert_eq!(read, 21);
+    }
+
+    #[test]
+    #[should_panic(expected = "alignment must be a power of two, got 3")]
+    fn test_aligned_buffer_invalid_alignment() {
+        let _ = AlignedBuffer::<u8>::new(8, 3);
+    }
 }
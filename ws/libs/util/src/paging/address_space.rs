@@ -0,0 +1,881 @@
+//! Owned view on a hierarchy of page tables.
+
+use super::pcid::{self, Pcid, PcidAllocator, PcidAssignment};
+use super::{
+    FrameInit, LEVEL_BITS, MapError, PAGE_BITS, PageSize, PageTable, PageTableEntryFlags,
+    PageTableMemory, PhysAddress, Translation, VirtAddress, map_address, map_demand_zero,
+    resolve_demand_zero, split_huge_page, translate, try_merge_huge_page, zero_frame,
+};
+
+/// Index of the first root-table entry of the higher half.
+const HIGHER_HALF_INDEX: usize = 256;
+
+/// An address space, i.e., a hierarchy of 4-level page tables together with
+/// the [`PageTableMemory`] they live in.
+#[derive(Debug)]
+pub struct AddressSpace<M: PageTableMemory> {
+    root: PhysAddress,
+    mem: M,
+    /// PCID of the last activation, see [`Self::activate`].
+    pcid: Option<PcidAssignment>,
+}
+
+impl<M: PageTableMemory> AddressSpace<M> {
+    /// Creates a new, empty address space with a root table allocated from
+    /// `mem`.
+    pub fn new(mut mem: M) -> Result<Self, MapError> {
+        let root = mem.alloc_table().ok_or(MapError::OutOfMemory)?;
+        Ok(Self::from_root(root, mem))
+    }
+
+    /// Creates an address space from an existing root table in `mem`.
+    pub const fn from_root(root: PhysAddress, mem: M) -> Self {
+        Self {
+            root,
+            mem,
+            pcid: None,
+        }
+    }
+
+    /// Returns the physical address of the root table (the value for `cr3`).
+    pub const fn root(&self) -> PhysAddress {
+        self.root
+    }
+
+    /// Returns the memory backing the page tables.
+    pub const fn mem(&self) -> &M {
+        &self.mem
+    }
+
+    /// Returns the memory backing the page tables.
+    pub const fn mem_mut(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Creates a new address space that shares the higher half with this one.
+    ///
+    /// A fresh root table is allocated from `mem`. Its upper 256 entries
+    /// (the higher half, e.g., the kernel) are copied, so both address spaces
+    /// reference the same level 3 tables; its lower 256 entries (the lower
+    /// half, e.g., user mappings) are empty. This is the usual way to map
+    /// the kernel into each process.
+    ///
+    /// Mappings made later below the shared entries are visible in both
+    /// address spaces. New entries in the upper half of either root table
+    /// are not, so all higher-half level 3 tables should exist beforehand.
+    ///
+    /// `mem` is cloned for the new address space, so it must be a handle to
+    /// the same physical memory, such as [`super::IdentityMapped`].
+    pub fn new_with_shared_higher_half(&mut self) -> Result<Self, MapError>
+    where
+        M: Clone,
+    {
+        let src = self.root_table()?;
+        let root = self.mem.alloc_table().ok_or(MapError::OutOfMemory)?;
+        let dst = self
+            .mem
+            .table_ptr(root)
+            .ok_or(MapError::InvalidTableAddress(root))?;
+        // SAFETY: Both pointers were returned by `mem`, and the new table is
+        // not referenced anywhere else yet.
+        let (src, dst) = unsafe { (&*src, &mut *dst) };
+        dst.0[HIGHER_HALF_INDEX..].copy_from_slice(&src.0[HIGHER_HALF_INDEX..]);
+        Ok(Self::from_root(root, self.mem.clone()))
+    }
+
+    /// Loads `cr3` with this address space.
+    ///
+    /// With `pcids`, the address space is tagged with a PCID from the
+    /// allocator. The first activation with a PCID flushes the TLB entries
+    /// of that PCID. Later activations keep them, unless the allocator has
+    /// handed out the PCID again in the meantime, in which case a new one is
+    /// assigned. Without `pcids`, i.e., if PCIDs are not enabled, the whole
+    /// TLB is flushed, apart from global pages.
+    ///
+    /// If mappings are removed or restricted while the address space is not
+    /// active, [`Self::flush_on_next_activate`] must be called, as the TLB
+    /// may still hold the old translations.
+    ///
+    /// # Safety
+    /// The address space must map the executing code, the stack, and all
+    /// other memory in use, and `pcids` must only be `Some` if PCIDs are
+    /// enabled, see [`pcid::enable`]. The allocator must be the one of the
+    /// current CPU.
+    pub unsafe fn activate(&mut self, pcids: Option<&mut PcidAllocator>) {
+        let value = match pcids {
+            Some(pcids) => {
+                let (pcid, no_flush) = self.pcid_for_activation(pcids);
+                pcid::cr3_value(self.root, pcid, no_flush)
+            }
+            None => self.root.0,
+        };
+        // SAFETY: The caller guarantees that the address space is complete.
+        unsafe { x86::controlregs::cr3_write(value) };
+    }
+
+    /// Makes the next [`Self::activate`] flush the TLB entries of this
+    /// address space.
+    pub const fn flush_on_next_activate(&mut self) {
+        self.pcid = None;
+    }
+
+    /// Returns the PCID for the next activation and whether its TLB entries
+    /// can be kept.
+    fn pcid_for_activation(&mut self, pcids: &mut PcidAllocator) -> (Pcid, bool) {
+        match self.pcid {
+            Some(assignment) if pcids.is_current(&assignment) => (assignment.pcid, true),
+            _ => {
+                let assignment = pcids.alloc();
+                self.pcid = Some(assignment);
+                (assignment.pcid, false)
+            }
+        }
+    }
+
+    fn root_table(&self) -> Result<*mut PageTable, MapError> {
+        self.mem
+            .table_ptr(self.root)
+            .ok_or(MapError::InvalidTableAddress(self.root))
+    }
+
+    /// Maps a single page. See [`map_address`].
+    pub fn map(
+        &mut self,
+        vaddr: VirtAddress,
+        paddr: PhysAddress,
+        page_size: PageSize,
+        flags: PageTableEntryFlags,
+    ) -> Result<(), MapError> {
+        let root = self.root_table()?;
+        // SAFETY: The pointer was returned by `mem` and no other reference
+        // to the root table exists.
+        let root = unsafe { &mut *root };
+        map_address(root, &mut self.mem, vaddr, paddr, page_size, flags)
+    }
+
+    /// Maps `len` bytes starting at `vaddr` linearly to `paddr` using pages of
+    /// the given size. See [`map_address`].
+    ///
+    /// With [`FrameInit::Zero`], each frame is zeroed before it is mapped.
+    /// [`FrameInit::for_flags`] provides the usual choice.
+    ///
+    /// # Panics
+    /// Panics if `vaddr`, `paddr`, or `len` are not aligned to the page size.
+    ///
+    /// # Safety
+    /// With [`FrameInit::Zero`], the caller must own the frames
+    /// `paddr..paddr + len`, see [`zero_frame`].
+    pub unsafe fn map_range(
+        &mut self,
+        vaddr: VirtAddress,
+        paddr: PhysAddress,
+        len: u64,
+        page_size: PageSize,
+        flags: PageTableEntryFlags,
+        init: FrameInit,
+    ) -> Result<(), MapError> {
+        let step = page_size.size() as u64;
+        assert!(len.is_multiple_of(step));
+        for offset in (0..len).step_by(step as usize) {
+            if init == FrameInit::Zero {
+                // SAFETY: The caller owns the frames.
+                unsafe { zero_frame(&mut self.mem, paddr + offset, page_size)? };
+            }
+            self.map(vaddr + offset, paddr + offset, page_size, flags.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Reserves `len` bytes starting at `vaddr` as demand-zero pages of the
+    /// given size. See [`map_demand_zero`].
+    ///
+    /// # Panics
+    /// Panics if `vaddr` or `len` are not aligned to the page size.
+    pub fn map_range_demand_zero(
+        &mut self,
+        vaddr: VirtAddress,
+        len: u64,
+        page_size: PageSize,
+        flags: PageTableEntryFlags,
+    ) -> Result<(), MapError> {
+        let step = page_size.size() as u64;
+        assert!(len.is_multiple_of(step));
+        let root = self.root_table()?;
+        // SAFETY: The pointer was returned by `mem` and no other reference
+        // to the root table exists.
+        let root = unsafe { &mut *root };
+        for offset in (0..len).step_by(step as usize) {
+            map_demand_zero(
+                root,
+                &mut self.mem,
+                vaddr + offset,
+                page_size,
+                flags.clone(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a demand-zero page on first touch. See
+    /// [`resolve_demand_zero`].
+    ///
+    /// # Safety
+    /// See [`resolve_demand_zero`].
+    pub unsafe fn resolve_demand_zero(
+        &mut self,
+        vaddr: VirtAddress,
+        alloc_frame: impl FnOnce(PageSize) -> Option<PhysAddress>,
+    ) -> Result<PageSize, MapError> {
+        let root = self.root_table()?;
+        // SAFETY: The pointer was returned by `mem` and no other reference
+        // to the root table exists.
+        let root = unsafe { &mut *root };
+        // SAFETY: The caller owns frames from `alloc_frame`.
+        unsafe { resolve_demand_zero(root, &mut self.mem, vaddr, alloc_frame) }
+    }
+
+    /// Splits the 2 MiB page mapping `vaddr` into 4 KiB pages. See
+    /// [`split_huge_page`].
+    pub fn split_huge_page(&mut self, vaddr: VirtAddress) -> Result<(), MapError> {
+        let root = self.root_table()?;
+        // SAFETY: The pointer was returned by `mem` and no other reference
+        // to the root table exists.
+        let root = unsafe { &mut *root };
+        split_huge_page(root, &mut self.mem, vaddr)
+    }
+
+    /// Merges the 4 KiB pages of the 2 MiB region containing `vaddr` into a
+    /// 2 MiB page. See [`try_merge_huge_page`].
+    pub fn try_merge_huge_page(&mut self, vaddr: VirtAddress) -> Option<PhysAddress> {
+        let root = self.root_table().ok()?;
+        // SAFETY: The pointer was returned by `mem` and no other reference
+        // to the root table exists.
+        let root = unsafe { &mut *root };
+        try_merge_huge_page(root, &self.mem, vaddr)
+    }
+
+    /// Translates a virtual address. See [`translate`].
+    pub fn translate(&self, vaddr: VirtAddress) -> Option<Translation> {
+        let root = self.root_table().ok()?;
+        // SAFETY: The pointer was returned by `mem`.
+        let root = unsafe { &*root };
+        translate(root, &self.mem, vaddr)
+    }
+
+    /// Returns an iterator over all present leaf mappings (4 KiB, 2 MiB, and
+    /// 1 GiB pages) in ascending order of their virtual address.
+    ///
+    /// The flags are the effective flags, as in [`Translation::flags`].
+    /// Tables that are not accessible via `mem` are skipped.
+    ///
+    /// The iterator doesn't allocate. Its cost is O(present entries), plus
+    /// scanning the 512 entries of each visited table. Use
+    /// [`Iterator::take`] to cap the cost for large address spaces.
+    pub fn iter_mappings(
+        &self,
+    ) -> impl Iterator<Item = (VirtAddress, PhysAddress, PageTableEntryFlags, PageSize)> + '_ {
+        let root = self.root_table().ok();
+        let mut iter = MappingIter {
+            mem: &self.mem,
+            stack: [Frame::EMPTY; 4],
+            depth: 0,
+        };
+        if let Some(root) = root {
+            iter.stack[0] = Frame {
+                table: root,
+                index: 0,
+                vaddr: 0,
+                flags: PageTableEntryFlags {
+                    write: true,
+                    superuser: true,
+                    ..Default::default()
+                },
+            };
+            iter.depth = 1;
+        }
+        iter
+    }
+}
+
+/// Position within a single page table during the iteration.
+#[derive(Clone, Debug)]
+struct Frame {
+    table: *const PageTable,
+    /// Index of the next entry to look at.
+    index: usize,
+    /// Virtual base address covered by the table.
+    vaddr: u64,
+    /// Effective flags of all parent levels.
+    flags: PageTableEntryFlags,
+}
+
+impl Frame {
+    const EMPTY: Self = Self {
+        table: core::ptr::null(),
+        index: 0,
+        vaddr: 0,
+        flags: PageTableEntryFlags {
+            present: false,
+            write: false,
+            superuser: false,
+            write_through: false,
+            cache_disable: false,
+            accessed: false,
+            dirty: false,
+            hugepage: false,
+            demand_zero: false,
+            execute_disable: false,
+        },
+    };
+}
+
+/// Depth-first iterator over the leaf entries of an [`AddressSpace`].
+struct MappingIter<'a, M: PageTableMemory> {
+    mem: &'a M,
+    /// One frame per level, starting with level 4.
+    stack: [Frame; 4],
+    depth: usize,
+}
+
+impl<M: PageTableMemory> Iterator for MappingIter<'_, M> {
+    type Item = (VirtAddress, PhysAddress, PageTableEntryFlags, PageSize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.depth > 0 {
+            let level = 5 - self.depth;
+            let frame = &mut self.stack[self.depth - 1];
+            if frame.index == 512 {
+                self.depth -= 1;
+                continue;
+            }
+            let index = frame.index;
+            frame.index += 1;
+
+            // SAFETY: The pointer was returned by `mem`.
+            let entry = unsafe { &*frame.table }[index];
+            let flags = entry.flags();
+            if !flags.present {
+                continue;
+            }
+
+            let shift = (level - 1) * LEVEL_BITS + PAGE_BITS;
+            let vaddr = frame.vaddr | ((index as u64) << shift);
+            let effective = PageTableEntryFlags {
+                write: frame.flags.write && flags.write,
+                superuser: frame.flags.superuser && flags.superuser,
+                execute_disable: frame.flags.execute_disable || flags.execute_disable,
+                ..flags.clone()
+            };
+
+            if level == 1 || (level <= 3 && flags.hugepage) {
+                return Some((
+                    VirtAddress(sign_extend(vaddr)),
+                    PhysAddress(entry.addr()),
+                    effective,
+                    PageSize::from_level(level),
+                ));
+            }
+
+            if let Some(table) = self.mem.table_ptr(PhysAddress(entry.addr())) {
+                self.stack[self.depth] = Frame {
+                    table,
+                    index: 0,
+                    vaddr,
+                    flags: effective,
+                };
+                self.depth += 1;
+            }
+        }
+        None
+    }
+}
+
+/// Makes a 48-bit virtual address canonical.
+const fn sign_extend(vaddr: u64) -> u64 {
+    (((vaddr << 16) as i64) >> 16) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::fake_memory::FakePhysMemory;
+    use crate::paging::{CacheType, IdentityMapped, PAGE_SIZE, Page};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_map_and_translate() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        space
+            .map(
+                VirtAddress(0x4000_0000),
+                PhysAddress(0x20_0000),
+                PageSize::Size2MiB,
+                PageTableEntryFlags::default(),
+            )
+            .unwrap();
+        let translation = space.translate(VirtAddress(0x4000_1234)).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x20_1234));
+        assert_eq!(space.translate(VirtAddress(0x1000)), None);
+    }
+
+    #[test]
+    fn test_map_range() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        // SAFETY: The frames are fake memory.
+        unsafe {
+            space.map_range(
+                VirtAddress(0x40_0000),
+                PhysAddress(0x8000),
+                0x3000,
+                PageSize::Size4KiB,
+                PageTableEntryFlags::default(),
+                FrameInit::Keep,
+            )
+        }
+        .unwrap();
+        assert_eq!(space.iter_mappings().count(), 3);
+        let translation = space.translate(VirtAddress(0x40_2fff)).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0xafff));
+    }
+
+    #[test]
+    fn test_map_range_zeroes_frames() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let rw = PageTableEntryFlags {
+            write: true,
+            ..Default::default()
+        };
+        let init = FrameInit::for_flags(&rw);
+        assert_eq!(init, FrameInit::Zero);
+        // Uncached mappings are likely MMIO and must not be written.
+        let mmio = rw.clone().with_cache_type(CacheType::Uncacheable);
+        assert_eq!(FrameInit::for_flags(&mmio), FrameInit::Keep);
+        // SAFETY: The frames are fake memory.
+        unsafe {
+            space.map_range(
+                VirtAddress(0x40_0000),
+                PhysAddress(0x8000),
+                0x2000,
+                PageSize::Size4KiB,
+                rw,
+                init,
+            )
+        }
+        .unwrap();
+        for paddr in [0x8000, 0x9000] {
+            let frame = space.mem().frame(PhysAddress(paddr)).unwrap();
+            assert!(frame.iter().all(|&b| b == 0));
+        }
+
+        // Read-only mappings keep the data.
+        let ro = PageTableEntryFlags::default();
+        let init = FrameInit::for_flags(&ro);
+        assert_eq!(init, FrameInit::Keep);
+        // SAFETY: The frames are fake memory.
+        unsafe { space.mem_mut().frame_ptr(PhysAddress(0xa000), PAGE_SIZE) }.unwrap();
+        // SAFETY: The frames are fake memory.
+        unsafe {
+            space.map_range(
+                VirtAddress(0x50_0000),
+                PhysAddress(0xa000),
+                0x1000,
+                PageSize::Size4KiB,
+                ro,
+                init,
+            )
+        }
+        .unwrap();
+        let frame = space.mem().frame(PhysAddress(0xa000)).unwrap();
+        assert!(frame.iter().all(|&b| b == FakePhysMemory::STALE_BYTE));
+    }
+
+    #[test]
+    fn test_map_range_inaccessible_frame() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let paddr = FakePhysMemory::BASE;
+        assert_eq!(
+            // SAFETY: The frames are fake memory.
+            unsafe {
+                space.map_range(
+                    VirtAddress(0x40_0000),
+                    paddr,
+                    0x1000,
+                    PageSize::Size4KiB,
+                    PageTableEntryFlags::default(),
+                    FrameInit::Zero,
+                )
+            },
+            Err(MapError::InaccessibleFrame(paddr))
+        );
+        assert_eq!(space.iter_mappings().count(), 0);
+    }
+
+    #[test]
+    fn test_iter_mappings() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let rw = PageTableEntryFlags {
+            write: true,
+            ..Default::default()
+        };
+        let nx = PageTableEntryFlags {
+            execute_disable: true,
+            ..Default::default()
+        };
+        let mappings = [
+            (0x1000, 0x5000, rw.clone(), PageSize::Size4KiB),
+            (0x2000, 0x6000, nx.clone(), PageSize::Size4KiB),
+            (0x4000_0000, 0x20_0000, rw.clone(), PageSize::Size2MiB),
+            (0x80_0000_0000, 0x4000_0000, nx.clone(), PageSize::Size1GiB),
+            (
+                0xffff_ffff_8000_0000,
+                0x40_0000,
+                rw.clone(),
+                PageSize::Size2MiB,
+            ),
+        ];
+        for (vaddr, paddr, flags, page_size) in mappings.iter().cloned() {
+            space
+                .map(VirtAddress(vaddr), PhysAddress(paddr), page_size, flags)
+                .unwrap();
+        }
+
+        let actual = space.iter_mappings().collect::<Vec<_>>();
+        assert_eq!(actual.len(), mappings.len());
+        for ((vaddr, paddr, flags, page_size), actual) in mappings.iter().zip(&actual) {
+            assert_eq!(actual.0, VirtAddress(*vaddr));
+            assert_eq!(actual.1, PhysAddress(*paddr));
+            assert_eq!(actual.2.write, flags.write);
+            assert_eq!(actual.2.execute_disable, flags.execute_disable);
+            assert!(actual.2.present);
+            assert_eq!(actual.3, *page_size);
+        }
+    }
+
+    #[test]
+    fn test_iter_mappings_empty() {
+        let space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        assert_eq!(space.iter_mappings().count(), 0);
+        let space = AddressSpace::from_root(PhysAddress(0x1000), FakePhysMemory::new());
+        assert_eq!(space.iter_mappings().count(), 0);
+    }
+
+    #[test]
+    fn test_demand_zero() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let rw = PageTableEntryFlags {
+            write: true,
+            execute_disable: true,
+            ..Default::default()
+        };
+        let vaddr = VirtAddress(0x60_0000);
+        space
+            .map_range_demand_zero(vaddr, 0x2000, PageSize::Size4KiB, rw.clone())
+            .unwrap();
+        assert_eq!(space.translate(vaddr), None);
+        assert_eq!(space.iter_mappings().count(), 0);
+
+        // Fails without a frame and keeps the entry.
+        assert_eq!(
+            // SAFETY: The frames are fake memory.
+            unsafe { space.resolve_demand_zero(vaddr, |_| None) },
+            Err(MapError::OutOfMemory)
+        );
+        let mut requested = None;
+        // SAFETY: The frames are fake memory.
+        let page_size = unsafe {
+            space.resolve_demand_zero(vaddr + 0x10, |page_size| {
+                requested = Some(page_size);
+                Some(PhysAddress(0x8000))
+            })
+        }
+        .unwrap();
+        assert_eq!(page_size, PageSize::Size4KiB);
+        assert_eq!(requested, Some(PageSize::Size4KiB));
+
+        let translation = space.translate(vaddr).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x8000));
+        assert_eq!(
+            translation.flags.ignoring_ad(),
+            PageTableEntryFlags {
+                present: true,
+                ..rw
+            }
+        );
+        let frame = space.mem().frame(PhysAddress(0x8000)).unwrap();
+        assert!(frame.iter().all(|&b| b == 0));
+        // The other page is still lazy.
+        assert_eq!(space.translate(vaddr + 0x1000), None);
+
+        // Mapped and unmapped pages are not demand-zero.
+        for vaddr in [vaddr, VirtAddress(0x1000), VirtAddress(0x7f_f000)] {
+            assert_eq!(
+                // SAFETY: The frames are fake memory.
+                unsafe { space.resolve_demand_zero(vaddr, |_| unreachable!()) },
+                Err(MapError::NotDemandZero(vaddr))
+            );
+        }
+    }
+
+    #[test]
+    fn test_demand_zero_huge_page() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let vaddr = VirtAddress(0xffff_8000_0020_0000);
+        space
+            .map_range_demand_zero(vaddr, 0x20_0000, PageSize::Size2MiB, Default::default())
+            .unwrap();
+        // SAFETY: The frames are fake memory.
+        let page_size =
+            unsafe { space.resolve_demand_zero(vaddr + 0x1234, |_| Some(PhysAddress(0x20_0000))) }
+                .unwrap();
+        assert_eq!(page_size, PageSize::Size2MiB);
+        let translation = space.translate(vaddr + 0x1234).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x20_1234));
+        assert_eq!(translation.page_size, PageSize::Size2MiB);
+        assert!(!translation.flags.demand_zero);
+    }
+
+    #[test]
+    fn test_split_huge_page() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let vaddr = VirtAddress(0xffff_8000_0040_0000);
+        let flags = PageTableEntryFlags {
+            write: true,
+            execute_disable: true,
+            ..Default::default()
+        }
+        .with_cache_type(CacheType::WriteThrough);
+        space
+            .map(vaddr, PhysAddress(0x60_0000), PageSize::Size2MiB, flags)
+            .unwrap();
+        let offsets = [0, 0x1234, 0x10_0000, 0x1f_ffff];
+        let before = offsets.map(|offset| space.translate(vaddr + offset).unwrap());
+        let tables = space.mem().table_count();
+
+        space.split_huge_page(vaddr + 0x1234).unwrap();
+        assert_eq!(space.mem().table_count(), tables + 1);
+        for (offset, before) in offsets.into_iter().zip(before) {
+            let after = space.translate(vaddr + offset).unwrap();
+            assert_eq!(after.page_size, PageSize::Size4KiB);
+            assert_eq!(after.phys, before.phys);
+            assert_eq!(
+                after.flags,
+                PageTableEntryFlags {
+                    hugepage: false,
+                    ..before.flags
+                }
+            );
+        }
+
+        // Now a single page can be changed.
+        space
+            .map(
+                vaddr,
+                PhysAddress(0x60_0000),
+                PageSize::Size4KiB,
+                Default::default(),
+            )
+            .unwrap();
+        assert!(!space.translate(vaddr).unwrap().flags.write);
+        assert!(space.translate(vaddr + 0x1000).unwrap().flags.write);
+
+        assert_eq!(
+            space.split_huge_page(vaddr),
+            Err(MapError::NotHugePage(vaddr))
+        );
+        let unmapped = VirtAddress(0x20_0000);
+        assert_eq!(
+            space.split_huge_page(unmapped),
+            Err(MapError::NotHugePage(unmapped))
+        );
+    }
+
+    #[test]
+    fn test_try_merge_huge_page() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let vaddr = VirtAddress(0xffff_8000_0040_0000);
+        let paddr = PhysAddress(0x60_0000);
+        let flags = PageTableEntryFlags {
+            write: true,
+            ..Default::default()
+        };
+        // SAFETY: The frames are fake memory.
+        unsafe {
+            space.map_range(
+                vaddr,
+                paddr,
+                0x20_0000,
+                PageSize::Size4KiB,
+                flags.clone(),
+                FrameInit::Keep,
+            )
+        }
+        .unwrap();
+        let before = space.translate(vaddr + 0x1234).unwrap();
+
+        let l1 = space.try_merge_huge_page(vaddr + 0x1234).unwrap();
+        assert!(space.mem().table_ptr(l1).is_some());
+        let after = space.translate(vaddr + 0x1234).unwrap();
+        assert_eq!(after.page_size, PageSize::Size2MiB);
+        assert_eq!(after.phys, before.phys);
+        assert_eq!(
+            after.flags,
+            PageTableEntryFlags {
+                hugepage: true,
+                ..before.flags
+            }
+        );
+        // A huge page can't be merged again.
+        assert_eq!(space.try_merge_huge_page(vaddr), None);
+    }
+
+    #[test]
+    fn test_try_merge_huge_page_not_mergeable() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let vaddr = VirtAddress(0xffff_8000_0040_0000);
+        let paddr = PhysAddress(0x60_0000);
+        // SAFETY: The frames are fake memory.
+        unsafe {
+            space.map_range(
+                vaddr,
+                paddr,
+                0x20_0000,
+                PageSize::Size4KiB,
+                Default::default(),
+                FrameInit::Keep,
+            )
+        }
+        .unwrap();
+        // Swap two frames, so that the range is no longer contiguous.
+        space
+            .map(
+                vaddr,
+                paddr + 0x1000,
+                PageSize::Size4KiB,
+                Default::default(),
+            )
+            .unwrap();
+        space
+            .map(
+                vaddr + 0x1000,
+                paddr,
+                PageSize::Size4KiB,
+                Default::default(),
+            )
+            .unwrap();
+        assert_eq!(space.try_merge_huge_page(vaddr), None);
+        assert_eq!(
+            space.translate(vaddr).unwrap().page_size,
+            PageSize::Size4KiB
+        );
+
+        // Restoring the order makes it mergeable, unless the flags differ.
+        space
+            .map(vaddr, paddr, PageSize::Size4KiB, Default::default())
+            .unwrap();
+        let write = PageTableEntryFlags {
+            write: true,
+            ..Default::default()
+        };
+        space
+            .map(vaddr + 0x1000, paddr + 0x1000, PageSize::Size4KiB, write)
+            .unwrap();
+        assert_eq!(space.try_merge_huge_page(vaddr), None);
+        space
+            .map(
+                vaddr + 0x1000,
+                paddr + 0x1000,
+                PageSize::Size4KiB,
+                Default::default(),
+            )
+            .unwrap();
+        assert!(space.try_merge_huge_page(vaddr).is_some());
+
+        // Not mapped at all.
+        assert_eq!(space.try_merge_huge_page(VirtAddress(0x20_0000)), None);
+    }
+
+    #[test]
+    fn test_pcid_for_activation() {
+        let mut pcids = PcidAllocator::new(0);
+        let mut a = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let mut b = AddressSpace::new(FakePhysMemory::new()).unwrap();
+
+        let (pcid_a, no_flush) = a.pcid_for_activation(&mut pcids);
+        assert!(!no_flush);
+        let (pcid_b, no_flush) = b.pcid_for_activation(&mut pcids);
+        assert!(!no_flush);
+        assert_ne!(pcid_a, pcid_b);
+        // Switching back keeps the TLB entries.
+        assert_eq!(a.pcid_for_activation(&mut pcids), (pcid_a, true));
+
+        a.flush_on_next_activate();
+        let (pcid, no_flush) = a.pcid_for_activation(&mut pcids);
+        assert_ne!(pcid, pcid_a);
+        assert!(!no_flush);
+
+        // Once the PCIDs wrap around, the old assignment of `b` is stale.
+        while pcids.generation() == 0 {
+            pcids.alloc();
+        }
+        let (pcid, no_flush) = b.pcid_for_activation(&mut pcids);
+        assert!(!no_flush);
+        assert_eq!(b.pcid_for_activation(&mut pcids), (pcid, true));
+
+        // The assignment of another CPU's allocator is not reused.
+        let mut other_cpu = PcidAllocator::new(1);
+        let (_, no_flush) = b.pcid_for_activation(&mut other_cpu);
+        assert!(!no_flush);
+    }
+
+    #[test]
+    fn test_new_with_shared_higher_half() {
+        // The address spaces must share the memory, which `FakePhysMemory`
+        // can't, so this uses real heap memory.
+        let mut kernel = AddressSpace::new(IdentityMapped).unwrap();
+        let higher = VirtAddress(0xffff_8000_0000_0000);
+        let lower = VirtAddress(0x40_0000);
+        let frame = Box::leak(Box::new(Page::ZERO));
+        let paddr = PhysAddress(frame.as_ptr() as u64);
+        for vaddr in [higher, lower] {
+            kernel
+                .map(vaddr, paddr, PageSize::Size4KiB, Default::default())
+                .unwrap();
+        }
+
+        let mut process = kernel.new_with_shared_higher_half().unwrap();
+        assert_ne!(process.root(), kernel.root());
+        let root = |space: &AddressSpace<IdentityMapped>| unsafe { *space.root_table().unwrap() };
+        let (kernel_root, process_root) = (root(&kernel), root(&process));
+        assert_eq!(
+            kernel_root.0[HIGHER_HALF_INDEX..],
+            process_root.0[HIGHER_HALF_INDEX..]
+        );
+        assert!(kernel_root[lower.index(4)].flags().present);
+        assert!(
+            process_root.0[..HIGHER_HALF_INDEX]
+                .iter()
+                .all(|entry| entry.0 == 0)
+        );
+
+        assert_eq!(process.translate(higher), kernel.translate(higher));
+        assert_eq!(process.translate(lower), None);
+
+        // Mappings below the shared entries are visible in both.
+        let shared = higher + 0x1000;
+        process
+            .map(shared, paddr, PageSize::Size4KiB, Default::default())
+            .unwrap();
+        assert_eq!(kernel.translate(shared).unwrap().phys, paddr);
+        // Lower-half mappings are independent.
+        process
+            .map(
+                lower + 0x1000,
+                paddr,
+                PageSize::Size4KiB,
+                Default::default(),
+            )
+            .unwrap();
+        assert_eq!(kernel.translate(lower + 0x1000), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Memory types of mappings, selected via the PWT and PCD bits.
+
+use super::PageTableEntryFlags;
+
+/// Memory (cache) type of a mapping.
+///
+/// The type is selected by the PAT entry that the PWT (`write_through`) and
+/// PCD (`cache_disable`) bits of the leaf entry index. The PAT bit of the
+/// entry is always clear, so only PAT entries 0 to 3 are used:
+///
+/// | Type                   | PCD | PWT | PAT entry | Default PAT | [`Self::PAT_MSR_VALUE`] |
+/// |------------------------|-----|-----|-----------|-------------|-------------------------|
+/// | [`Self::WriteBack`]      | 0   | 0   | 0         | WB          | WB                      |
+/// | [`Self::WriteThrough`]   | 0   | 1   | 1         | WT          | WT                      |
+/// | [`Self::Uncacheable`]    | 1   | 0   | 2         | UC-         | UC                      |
+/// | [`Self::WriteCombining`] | 1   | 1   | 3         | UC          | WC                      |
+///
+/// The types are only exact once [`Self::PAT_MSR_VALUE`] is written to the
+/// `IA32_PAT` MSR. With the default PAT after reset, uncacheable memory is
+/// UC- and write-combining memory is UC, i.e., strictly uncached. Both are
+/// correct for device memory, just slower.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CacheType {
+    /// Regular cacheable memory; the type for RAM.
+    #[default]
+    WriteBack,
+    /// Reads are cached, writes go through to memory.
+    WriteThrough,
+    /// No caching at all; the type for MMIO registers.
+    Uncacheable,
+    /// Uncached, but writes may be combined and delayed; the type for
+    /// framebuffers.
+    WriteCombining,
+}
+
+impl CacheType {
+    /// Value for the `IA32_PAT` MSR so that PAT entries 0 to 3 match the
+    /// types of the table above. Entries 4 to 7 keep their defaults.
+    pub const PAT_MSR_VALUE: u64 = {
+        const WB: u64 = 0x06;
+        const WT: u64 = 0x04;
+        const UC: u64 = 0x00;
+        const WC: u64 = 0x01;
+        const UC_MINUS: u64 = 0x07;
+        WB | WT << 8 | UC << 16 | WC << 24 | WB << 32 | WT << 40 | UC_MINUS << 48 | UC << 56
+    };
+
+    /// Returns the values of the PWT (`write_through`) and PCD
+    /// (`cache_disable`) bits.
+    pub const fn pwt_pcd(self) -> (bool, bool) {
+        match self {
+            Self::WriteBack => (false, false),
+            Self::WriteThrough => (true, false),
+            Self::Uncacheable => (false, true),
+            Self::WriteCombining => (true, true),
+        }
+    }
+
+    /// Returns the type selected by the PWT and PCD bits of `flags`.
+    pub const fn from_flags(flags: &PageTableEntryFlags) -> Self {
+        match (flags.write_through, flags.cache_disable) {
+            (false, false) => Self::WriteBack,
+            (true, false) => Self::WriteThrough,
+            (false, true) => Self::Uncacheable,
+            (true, true) => Self::WriteCombining,
+        }
+    }
+}
+
+impl PageTableEntryFlags {
+    /// Returns the flags with the PWT and PCD bits set for the given cache
+    /// type.
+    #[must_use]
+    pub const fn with_cache_type(self, cache_type: CacheType) -> Self {
+        let (write_through, cache_disable) = cache_type.pwt_pcd();
+        Self {
+            write_through,
+            cache_disable,
+            ..self
+        }
+    }
+
+    /// Returns the cache type selected by these flags.
+    pub const fn cache_type(&self) -> CacheType {
+        CacheType::from_flags(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::fake_memory::FakePhysMemory;
+    use crate::paging::{AddressSpace, PageSize, PageTableEntry, PhysAddress, VirtAddress};
+
+    #[test]
+    fn test_flags() {
+        let rw = PageTableEntryFlags {
+            write: true,
+            ..Default::default()
+        };
+        let expected = [
+            (CacheType::WriteBack, false, false),
+            (CacheType::WriteThrough, true, false),
+            (CacheType::Uncacheable, false, true),
+            (CacheType::WriteCombining, true, true),
+        ];
+        for (cache_type, write_through, cache_disable) in expected {
+            let flags = rw.clone().with_cache_type(cache_type);
+            assert_eq!(
+                flags,
+                PageTableEntryFlags {
+                    write_through,
+                    cache_disable,
+                    ..rw.clone()
+                }
+            );
+            assert_eq!(flags.cache_type(), cache_type);
+            // Switching back clears the bits again.
+            assert_eq!(flags.with_cache_type(CacheType::WriteBack), rw);
+        }
+        assert_eq!(
+            PageTableEntryFlags::default().cache_type(),
+            CacheType::default()
+        );
+    }
+
+    #[test]
+    fn test_mapping() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let vaddr = VirtAddress(0xfee0_0000);
+        let flags = PageTableEntryFlags::default().with_cache_type(CacheType::Uncacheable);
+        space
+            .map(vaddr, PhysAddress(0xfee0_0000), PageSize::Size4KiB, flags)
+            .unwrap();
+        let translation = space.translate(vaddr).unwrap();
+        assert_eq!(translation.flags.cache_type(), CacheType::Uncacheable);
+
+        let entry = PageTableEntry::new(0x1000, translation.flags);
+        assert_eq!(
+            entry.0 & PageTableEntry::BIT_CACHE_DISABLE,
+            PageTableEntry::BIT_CACHE_DISABLE
+        );
+        assert_eq!(entry.0 & PageTableEntry::BIT_WRITE_THROUGH, 0);
+    }
+
+    #[test]
+    fn test_pat_msr_value() {
+        assert_eq!(CacheType::PAT_MSR_VALUE, 0x0007_0406_0100_0406);
+    }
+}
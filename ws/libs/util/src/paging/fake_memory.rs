@@ -0,0 +1,176 @@
+//! Fake physical memory for testing page-table code on the host.
+
+use super::{PAGE_SIZE, PageTable, PageTableEntry, PageTableMemory, PhysAddress, VirtAddress};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// [`PageTableMemory`] simulating physical memory for page tables.
+///
+/// Unlike with [`super::IdentityMapped`], the physical addresses of the
+/// tables differ from their addresses on the heap. This way, code that
+/// mixes up physical addresses and pointers fails instead of working by
+/// accident.
+///
+/// Data frames below [`Self::BASE`] are backed lazily on first access via
+/// [`PageTableMemory::frame_ptr`] and are filled with [`Self::STALE_BYTE`],
+/// to simulate stale contents of RAM.
+#[derive(Debug, Default)]
+pub struct FakePhysMemory {
+    tables: Vec<*mut PageTable>,
+    frames: Vec<(PhysAddress, Box<[u8]>)>,
+}
+
+impl FakePhysMemory {
+    /// Physical address of the first page table.
+    pub const BASE: PhysAddress = PhysAddress(0x1_0000_0000);
+
+    /// Initial value of each byte of a data frame.
+    pub const STALE_BYTE: u8 = 0xa5;
+
+    /// Creates a new fake memory without any page tables.
+    pub const fn new() -> Self {
+        Self {
+            tables: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Returns the data frame starting at `phys`, if it was accessed before.
+    pub fn frame(&self, phys: PhysAddress) -> Option<&[u8]> {
+        self.frames
+            .iter()
+            .find(|(base, _)| *base == phys)
+            .map(|(_, frame)| &**frame)
+    }
+
+    /// Returns the number of allocated page tables.
+    pub fn table_count(&self) -> usize {
+        self.tables.len()
+    }
+}
+
+/// A corruption that [`FakePhysMemory::inject`] applies to a page-table
+/// entry, to test how walkers cope with malformed tables.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Corruption {
+    /// Replaces the entry with one that only has the present bit set, i.e.,
+    /// that points to physical address zero.
+    PresentOnly,
+    /// Sets the huge-page bit.
+    HugePage,
+    /// Sets the given bits of the physical address, e.g., to make the
+    /// address of a huge page unaligned or to point it outside of the page
+    /// tables. Bits outside of [`PageTableEntry::BITS_PHYS_ADDR`] are
+    /// ignored.
+    AddressBits(u64),
+}
+
+impl Corruption {
+    const fn apply(self, entry: PageTableEntry) -> PageTableEntry {
+        match self {
+            Self::PresentOnly => PageTableEntry(PageTableEntry::BIT_PRESENT),
+            Self::HugePage => PageTableEntry(entry.0 | PageTableEntry::BIT_HUGEPAGE),
+            Self::AddressBits(bits) => PageTableEntry(entry.0 | (bits & ADDR_MASK)),
+        }
+    }
+}
+
+/// Mask of [`PageTableEntry::BITS_PHYS_ADDR`].
+const ADDR_MASK: u64 = ((1 << 52) - 1) & !(PAGE_SIZE as u64 - 1);
+
+impl FakePhysMemory {
+    /// Applies `corruption` to the entry of the given level that is used to
+    /// translate `vaddr`, walking the tree starting at `root`.
+    ///
+    /// Returns the previous entry, or `None` if the walk ends before
+    /// `level`, e.g., because an entry on the way is not present.
+    pub fn inject(
+        &self,
+        root: &mut PageTable,
+        vaddr: VirtAddress,
+        level: usize,
+        corruption: Corruption,
+    ) -> Option<PageTableEntry> {
+        assert!((1..=4).contains(&level));
+        let mut table: *mut PageTable = root;
+        for walk_level in (level + 1..=4).rev() {
+            // SAFETY: The pointer is either `root` or one of our tables.
+            let table_ref = unsafe { &*table };
+            let entry = table_ref[vaddr.index(walk_level)];
+            if !entry.is_present() || entry.is_huge() {
+                return None;
+            }
+            table = self.table_ptr(PhysAddress(entry.addr()))?;
+        }
+        // SAFETY: The pointer is either `root` or one of our tables.
+        let table_ref = unsafe { &mut *table };
+        let entry = &mut table_ref[vaddr.index(level)];
+        let old = *entry;
+        *entry = corruption.apply(old);
+        Some(old)
+    }
+}
+
+impl PageTableMemory for FakePhysMemory {
+    fn table_ptr(&self, phys: PhysAddress) -> Option<*mut PageTable> {
+        let offset = phys.0.checked_sub(Self::BASE.0)?;
+        if !offset.is_multiple_of(PAGE_SIZE as u64) {
+            return None;
+        }
+        let index = usize::try_from(offset / PAGE_SIZE as u64).ok()?;
+        self.tables.get(index).copied()
+    }
+
+    fn alloc_table(&mut self) -> Option<PhysAddress> {
+        let phys = Self::BASE + (self.tables.len() * PAGE_SIZE) as u64;
+        self.tables.push(Box::into_raw(Box::new(PageTable::ZERO)));
+        Some(phys)
+    }
+
+    unsafe fn frame_ptr(&mut self, phys: PhysAddress, len: usize) -> Option<*mut u8> {
+        let end = phys.0.checked_add(len as u64)?;
+        if end > Self::BASE.0 {
+            return None;
+        }
+        let index = match self.frames.iter().position(|(base, _)| *base == phys) {
+            Some(index) if self.frames[index].1.len() >= len => index,
+            Some(_) => return None,
+            None => {
+                self.frames
+                    .push((phys, alloc::vec![Self::STALE_BYTE; len].into_boxed_slice()));
+                self.frames.len() - 1
+            }
+        };
+        Some(self.frames[index].1.as_mut_ptr())
+    }
+}
+
+impl Drop for FakePhysMemory {
+    fn drop(&mut self) {
+        for table in self.tables.drain(..) {
+            // SAFETY: The pointer was created by `Box::into_raw`.
+            drop(unsafe { Box::from_raw(table) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_ptr() {
+        let mut mem = FakePhysMemory::new();
+        let first = mem.alloc_table().unwrap();
+        let second = mem.alloc_table().unwrap();
+        assert_eq!(first, FakePhysMemory::BASE);
+        assert_eq!(second - first, PAGE_SIZE as u64);
+        assert_eq!(mem.table_count(), 2);
+
+        let ptr = mem.table_ptr(second).unwrap();
+        assert_ne!(ptr as u64, second.0);
+        assert_eq!(mem.table_ptr(second + 8), None);
+        assert_eq!(mem.table_ptr(second + PAGE_SIZE as u64), None);
+        assert_eq!(mem.table_ptr(PhysAddress(0)), None);
+    }
+}
@@ -0,0 +1,1682 @@
+//! Module for x86_64 4-level paging.
+
+use crate::sizes::{ONE_GIB, TWO_MIB};
+use alloc::boxed::Box;
+use core::ops::{Add, Index, IndexMut, RangeInclusive, Sub};
+#[cfg(all(debug_assertions, not(test)))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::debug;
+use thiserror::Error;
+
+mod address_space;
+mod cache_type;
+#[cfg(any(test, feature = "fake-memory"))]
+pub mod fake_memory;
+mod number;
+pub mod pcid;
+mod stats;
+
+pub use address_space::AddressSpace;
+pub use cache_type::CacheType;
+pub use number::{FrameNumber, PageNumber};
+pub use stats::PageTableStats;
+
+pub const PAGE_SIZE: usize = 4096;
+pub const PAGE_MASK: usize = 0xfff;
+const PAGE_BITS: usize = 12;
+const PAGE_BITS_MASK: usize = bit_ops::bitops_usize::create_mask(PAGE_BITS);
+const LEVEL_BITS: usize = 9;
+const LEVEL_BITS_MASK: usize = bit_ops::bitops_usize::create_mask(LEVEL_BITS);
+/// Maximum physical address with 4-level paging.
+const LIMIT_MAX_PHYS_BITS: usize = bit_ops::bitops_usize::create_mask(52);
+
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct VirtAddress(pub u64);
+
+impl VirtAddress {
+    /// Returns the index into the page table for the given level.
+    ///
+    /// The level must be either `1`, `2`, `3`, or `4`. This is only checked in
+    /// debug builds, as this is on the hot path of every page-table walk.
+    #[inline]
+    pub fn index(&self, level: usize) -> usize {
+        debug_assert!(level > 0);
+        debug_assert!(level <= 4);
+        let shift = (level - 1) * LEVEL_BITS + PAGE_BITS;
+        let shift = shift as u64;
+        let index = (self.0 >> shift) & (LEVEL_BITS_MASK as u64);
+        index as usize
+    }
+
+    /// Returns whether the address is canonical for 4-level paging, i.e.,
+    /// whether bits 48 to 63 are copies of bit 47.
+    pub const fn is_canonical(&self) -> bool {
+        let upper = self.0 >> 47;
+        upper == 0 || upper == 0x1ffff
+    }
+}
+
+impl From<u64> for VirtAddress {
+    fn from(value: u64) -> VirtAddress {
+        Self(value)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct PhysAddress(pub u64);
+
+impl PhysAddress {
+    /// Returns whether the address is within the 52-bit physical address
+    /// space of 4-level paging.
+    pub const fn is_valid(&self) -> bool {
+        self.0 <= LIMIT_MAX_PHYS_BITS as u64
+    }
+
+    /// Returns the address if it is a multiple of `align`, or an
+    /// [`AlignError`] otherwise.
+    ///
+    /// This is for checks of input that must not panic, such as in the boot
+    /// path. `align` must be non-zero.
+    pub const fn require_aligned(self, align: u64) -> Result<Self, AlignError> {
+        if self.0.is_multiple_of(align) {
+            Ok(self)
+        } else {
+            Err(AlignError { addr: self, align })
+        }
+    }
+}
+
+/// A [`PhysAddress`] is not aligned as required.
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash, Error)]
+#[error("address {:#x} is not aligned to {align:#x}", .addr.0)]
+pub struct AlignError {
+    /// The misaligned address.
+    pub addr: PhysAddress,
+    /// The required alignment.
+    pub align: u64,
+}
+
+impl From<u64> for PhysAddress {
+    fn from(value: u64) -> PhysAddress {
+        Self(value)
+    }
+}
+
+/// Implements `Add<u64>`, `Sub<u64>`, and `Sub<Self>` (the difference in
+/// bytes) for an address type.
+///
+/// Overflows panic in debug builds; `$valid` is checked for the result in
+/// debug builds.
+macro_rules! impl_address_ops {
+    ($name:ident, $valid:expr) => {
+        impl Add<u64> for $name {
+            type Output = Self;
+
+            fn add(self, rhs: u64) -> Self {
+                let addr = Self(self.0 + rhs);
+                debug_assert!($valid(&addr), "{addr:x?} is out of range");
+                addr
+            }
+        }
+
+        impl Sub<u64> for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: u64) -> Self {
+                let addr = Self(self.0 - rhs);
+                debug_assert!($valid(&addr), "{addr:x?} is out of range");
+                addr
+            }
+        }
+
+        impl Sub for $name {
+            type Output = u64;
+
+            fn sub(self, rhs: Self) -> u64 {
+                self.0 - rhs.0
+            }
+        }
+    };
+}
+
+impl_address_ops!(VirtAddress, VirtAddress::is_canonical);
+impl_address_ops!(PhysAddress, PhysAddress::is_valid);
+
+/// The size of a page mapped by a leaf entry of the page tables.
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash)]
+pub enum PageSize {
+    /// 4 KiB page, mapped by a level 1 entry.
+    Size4KiB,
+    /// 2 MiB huge page, mapped by a level 2 entry.
+    Size2MiB,
+    /// 1 GiB huge page, mapped by a level 3 entry.
+    Size1GiB,
+}
+
+impl PageSize {
+    /// Returns the size in bytes.
+    pub const fn size(self) -> usize {
+        match self {
+            Self::Size4KiB => PAGE_SIZE,
+            Self::Size2MiB => TWO_MIB,
+            Self::Size1GiB => ONE_GIB,
+        }
+    }
+
+    /// Returns the level of the page table holding the leaf entry.
+    pub const fn level(self) -> usize {
+        match self {
+            Self::Size4KiB => 1,
+            Self::Size2MiB => 2,
+            Self::Size1GiB => 3,
+        }
+    }
+
+    /// Returns the page size of a leaf entry in the given level.
+    ///
+    /// The level must be either `1`, `2`, or `3`.
+    pub const fn from_level(level: usize) -> Self {
+        match level {
+            1 => Self::Size4KiB,
+            2 => Self::Size2MiB,
+            3 => Self::Size1GiB,
+            _ => panic!("invalid level for a leaf entry"),
+        }
+    }
+}
+
+/// Companion for [`PageTableEntry`].
+#[derive(Clone, Debug, Default, PartialOrd, Ord, Eq, PartialEq, Hash)]
+pub struct PageTableEntryFlags {
+    pub present: bool,
+    pub write: bool,
+    pub superuser: bool,
+    pub write_through: bool,
+    pub cache_disable: bool,
+    /// Set by the CPU on access.
+    pub accessed: bool,
+    /// Set by the CPU on writes. Only valid for entries mapping a page.
+    pub dirty: bool,
+    pub hugepage: bool,
+    /// Software-defined: the entry is not present but reserves a page that
+    /// is allocated and zeroed on first touch. See [`map_demand_zero`].
+    pub demand_zero: bool,
+    pub execute_disable: bool,
+}
+
+impl PageTableEntryFlags {
+    /// Returns whether these flags grant every access that `requested` asks
+    /// for, i.e., whether they are at most as restrictive.
+    ///
+    /// Only the presence and the write, user (`superuser`), and execute
+    /// permissions are considered.
+    pub const fn permits(&self, requested: &Self) -> bool {
+        (self.present || !requested.present)
+            && (self.write || !requested.write)
+            && (self.superuser || !requested.superuser)
+            && (!self.execute_disable || requested.execute_disable)
+    }
+
+    /// Returns the flags without the accessed and dirty bits, which the CPU
+    /// sets on its own.
+    #[must_use]
+    pub const fn ignoring_ad(self) -> Self {
+        Self {
+            accessed: false,
+            dirty: false,
+            ..self
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct PageTableEntry(pub u64);
+
+impl PageTableEntry {
+    pub const BIT_PRESENT: u64 = 1 << 0;
+    pub const BIT_WRITE: u64 = 1 << 1;
+    pub const BIT_SUPERUSER: u64 = 1 << 2;
+    pub const BIT_WRITE_THROUGH: u64 = 1 << 3;
+    pub const BIT_CACHE_DISABLE: u64 = 1 << 4;
+    pub const BIT_ACCESSED: u64 = 1 << 5;
+    pub const BIT_DIRTY: u64 = 1 << 6;
+    /// Huge page (page size) bit. Only valid in levels 2 and 3.
+    pub const BIT_HUGEPAGE: u64 = 1 << 7;
+    /// Software-defined bit (ignored by the CPU) marking a non-present entry
+    /// as demand-zero. See [`map_demand_zero`].
+    pub const BIT_DEMAND_ZERO: u64 = 1 << 9;
+    pub const BITS_PHYS_ADDR: RangeInclusive<u64> = 12..=51;
+    pub const BIT_EXECUTE_DISABLE: u64 = 1 << 63;
+
+    /// Creates a new entry referencing `phys_addr` with the given flags.
+    ///
+    /// `phys_addr` must be page-aligned and within the 52-bit physical
+    /// address space. This is an invariant of the callers, which either
+    /// validate caller input (see [`map_address`]) or use addresses of their
+    /// own page-aligned allocations. It is therefore only checked in debug
+    /// builds, as this is on the hot path of every mapping.
+    pub fn new(phys_addr: u64, flags: PageTableEntryFlags) -> Self {
+        // Start with zero
+        let mut value: u64 = 0;
+
+        if flags.present {
+            value |= Self::BIT_PRESENT;
+        }
+        if flags.write {
+            value |= Self::BIT_WRITE;
+        }
+        if flags.superuser {
+            value |= Self::BIT_SUPERUSER;
+        }
+        if flags.write_through {
+            value |= Self::BIT_WRITE_THROUGH;
+        }
+        if flags.cache_disable {
+            value |= Self::BIT_CACHE_DISABLE;
+        }
+        if flags.accessed {
+            value |= Self::BIT_ACCESSED;
+        }
+        if flags.dirty {
+            value |= Self::BIT_DIRTY;
+        }
+        if flags.hugepage {
+            value |= Self::BIT_HUGEPAGE;
+        }
+        if flags.demand_zero {
+            value |= Self::BIT_DEMAND_ZERO;
+        }
+
+        debug_assert_eq!(phys_addr & PAGE_BITS_MASK as u64, 0);
+        debug_assert_eq!(phys_addr & (!LIMIT_MAX_PHYS_BITS as u64), 0);
+
+        value |= phys_addr;
+
+        if flags.execute_disable {
+            value |= Self::BIT_EXECUTE_DISABLE;
+        }
+
+        Self(value)
+    }
+
+    /// Returns whether the present bit is set.
+    ///
+    /// Cheaper than [`Self::flags`], e.g., for page-table walks.
+    pub const fn is_present(&self) -> bool {
+        self.0 & Self::BIT_PRESENT != 0
+    }
+
+    /// Returns whether the huge-page bit is set. In level 1 entries, this is
+    /// the PAT bit.
+    ///
+    /// Cheaper than [`Self::flags`], e.g., for page-table walks.
+    pub const fn is_huge(&self) -> bool {
+        self.0 & Self::BIT_HUGEPAGE != 0
+    }
+
+    /// Returns the underlying flags.
+    pub fn flags(&self) -> PageTableEntryFlags {
+        let mut flags = PageTableEntryFlags::default();
+
+        if self.0 & Self::BIT_PRESENT != 0 {
+            flags.present = true;
+        }
+        if self.0 & Self::BIT_WRITE != 0 {
+            flags.write = true;
+        }
+        if self.0 & Self::BIT_SUPERUSER != 0 {
+            flags.superuser = true;
+        }
+        if self.0 & Self::BIT_WRITE_THROUGH != 0 {
+            flags.write_through = true;
+        }
+        if self.0 & Self::BIT_CACHE_DISABLE != 0 {
+            flags.cache_disable = true;
+        }
+        if self.0 & Self::BIT_ACCESSED != 0 {
+            flags.accessed = true;
+        }
+        if self.0 & Self::BIT_DIRTY != 0 {
+            flags.dirty = true;
+        }
+        if self.0 & Self::BIT_HUGEPAGE != 0 {
+            flags.hugepage = true;
+        }
+        if self.0 & Self::BIT_DEMAND_ZERO != 0 {
+            flags.demand_zero = true;
+        }
+        if self.0 & Self::BIT_EXECUTE_DISABLE != 0 {
+            flags.execute_disable = true;
+        }
+
+        flags
+    }
+
+    /// Returns the phys addr this is pointing to.
+    pub fn addr(&self) -> u64 /* phys addr */ {
+        let len = Self::BITS_PHYS_ADDR.end() - Self::BITS_PHYS_ADDR.start() + 1;
+        let mask = bit_ops::bitops_u64::create_mask(len);
+        self.0 & (mask << Self::BITS_PHYS_ADDR.start())
+    }
+}
+
+/// Generic page (backing memory).
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Debug)]
+#[repr(C, align(4096))]
+pub struct Page(pub [u8; PAGE_SIZE]);
+
+impl Page {
+    pub const ZERO: Self = Self([0; PAGE_SIZE]);
+
+    pub fn as_ptr(&self) -> *const u8 {
+        let ptr = &raw const *self;
+        ptr.cast()
+    }
+
+    pub fn as_ptr_mut(&mut self) -> *mut u8 {
+        let ptr = &raw mut *self;
+        ptr.cast()
+    }
+
+    pub fn as_page_table(&self) -> &PageTable {
+        // SAFETY: same ABI and all bit patterns are valid
+        unsafe { core::mem::transmute(self) }
+    }
+
+    pub fn as_page_table_mut(&mut self) -> &mut PageTable {
+        // SAFETY: same ABI and all bit patterns are valid
+        unsafe { core::mem::transmute(self) }
+    }
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+/// Generic page table (backing memory).
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Debug)]
+#[repr(C, align(4096))]
+pub struct PageTable(pub [PageTableEntry; 512]);
+
+impl PageTable {
+    pub const ZERO: Self = Self([PageTableEntry(0); 512]);
+
+    /// Returns the entry at the given index or `None` if the index is out of
+    /// range.
+    ///
+    /// This is the non-panicking alternative to indexing.
+    pub fn entry(&self, index: usize) -> Option<&PageTableEntry> {
+        self.0.get(index)
+    }
+
+    /// Returns the entry at the given index or `None` if the index is out of
+    /// range.
+    ///
+    /// This is the non-panicking alternative to indexing.
+    pub fn entry_mut(&mut self, index: usize) -> Option<&mut PageTableEntry> {
+        self.0.get_mut(index)
+    }
+
+    pub fn as_page(&self) -> &Page {
+        // SAFETY: same ABI and all bit patterns are valid
+        unsafe { core::mem::transmute(self) }
+    }
+
+    pub fn as_page_mut(&mut self) -> &mut Page {
+        // SAFETY: same ABI and all bit patterns are valid
+        unsafe { core::mem::transmute(self) }
+    }
+
+    /// Returns whether all entries are zero, as for a freshly allocated table.
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|entry| entry.0 == 0)
+    }
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.0[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.0[index]
+    }
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Debug)]
+pub enum PhysMappingDest<'a> {
+    Page(&'a Page),
+    Ptr(*const u8),
+    PtrMut(*mut u8),
+    Addr(u64),
+}
+
+impl<'a> From<&'a Page> for PhysMappingDest<'a> {
+    fn from(page: &'a Page) -> Self {
+        Self::Page(page)
+    }
+}
+
+impl From<*const u8> for PhysMappingDest<'_> {
+    fn from(ptr: *const u8) -> Self {
+        Self::Ptr(ptr)
+    }
+}
+
+impl From<*mut u8> for PhysMappingDest<'_> {
+    fn from(ptr: *mut u8) -> Self {
+        Self::PtrMut(ptr)
+    }
+}
+
+impl From<u64> for PhysMappingDest<'_> {
+    fn from(addr: u64) -> Self {
+        Self::Addr(addr)
+    }
+}
+
+impl PhysMappingDest<'_> {
+    pub fn to_addr(&self) -> u64 {
+        match self {
+            PhysMappingDest::Addr(addr) => *addr,
+            PhysMappingDest::Page(page) => page.as_ptr() as u64,
+            PhysMappingDest::Ptr(ptr) => *ptr as u64,
+            PhysMappingDest::PtrMut(ptr) => *ptr as u64,
+        }
+    }
+}
+
+/// Access to the memory backing the page tables.
+///
+/// Page tables reference each other by physical addresses. Walkers use this
+/// to access the table behind a physical address and to allocate new tables.
+pub trait PageTableMemory {
+    /// Returns a pointer to the page table at the given physical address.
+    ///
+    /// Returns `None` if the address does not refer to accessible page-table
+    /// memory.
+    fn table_ptr(&self, phys: PhysAddress) -> Option<*mut PageTable>;
+
+    /// Allocates a new zeroed page table and returns its physical address.
+    fn alloc_table(&mut self) -> Option<PhysAddress>;
+
+    /// Returns a pointer to the `len` bytes of (data) memory at the given
+    /// physical address, e.g., to zero a frame before it is mapped.
+    ///
+    /// Returns `None` if the memory is not accessible. By default, no memory
+    /// apart from the page tables is accessible.
+    ///
+    /// # Safety
+    /// The returned pointer is meant for writes. The caller must ensure that
+    /// `phys..phys + len` is RAM it owns, i.e., that is neither MMIO nor in
+    /// use elsewhere.
+    unsafe fn frame_ptr(&mut self, phys: PhysAddress, len: usize) -> Option<*mut u8> {
+        let _ = (phys, len);
+        None
+    }
+}
+
+/// Number of leaked tables, see [`leaked_table_count`].
+#[cfg(all(debug_assertions, not(test)))]
+static LEAKED_TABLES: AtomicUsize = AtomicUsize::new(0);
+
+// Per thread in the tests, so that tests running in parallel don't disturb
+// each other's counts.
+#[cfg(all(debug_assertions, test))]
+std::thread_local! {
+    static LEAKED_TABLES: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+/// Returns the number of page tables allocated and leaked so far, by
+/// [`IdentityMapped::alloc_table`] and as reported via
+/// [`record_leaked_tables`].
+///
+/// This helps to detect leaks, e.g., the kernel can check that it reclaimed
+/// as many tables as the loader leaked. Only available in debug builds.
+#[cfg(debug_assertions)]
+pub fn leaked_table_count() -> usize {
+    #[cfg(not(test))]
+    return LEAKED_TABLES.load(Ordering::Relaxed);
+    #[cfg(test)]
+    return LEAKED_TABLES.get();
+}
+
+/// Adds `count` to the [`leaked_table_count`].
+///
+/// Other [`PageTableMemory`] implementations that leak their tables, such as
+/// a pool that is handed over to the kernel, report them via this. Only
+/// available in debug builds.
+#[cfg(debug_assertions)]
+pub fn record_leaked_tables(count: usize) {
+    #[cfg(not(test))]
+    LEAKED_TABLES.fetch_add(count, Ordering::Relaxed);
+    #[cfg(test)]
+    LEAKED_TABLES.set(LEAKED_TABLES.get() + count);
+}
+
+/// [`PageTableMemory`] for environments where physical memory is
+/// identity-mapped, such as the UEFI loader.
+///
+/// New tables are allocated on the heap and leaked, as they must outlive the
+/// code creating them. In debug builds, they are counted, see
+/// [`leaked_table_count`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IdentityMapped;
+
+impl PageTableMemory for IdentityMapped {
+    fn table_ptr(&self, phys: PhysAddress) -> Option<*mut PageTable> {
+        (phys.0 != 0).then_some(phys.0 as *mut PageTable)
+    }
+
+    fn alloc_table(&mut self) -> Option<PhysAddress> {
+        let table = Box::leak(Box::new(PageTable::ZERO));
+        #[cfg(debug_assertions)]
+        record_leaked_tables(1);
+        Some(PhysAddress(table.as_page().as_ptr() as u64))
+    }
+
+    unsafe fn frame_ptr(&mut self, phys: PhysAddress, _len: usize) -> Option<*mut u8> {
+        (phys.0 != 0).then_some(phys.0 as *mut u8)
+    }
+}
+
+/// Possible errors of [`map_address`].
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash, Error)]
+pub enum MapError {
+    /// A new page table couldn't be allocated.
+    #[error("out of memory for new page tables")]
+    OutOfMemory,
+    /// An entry references a page table that is not accessible.
+    #[error("entry references inaccessible page table at {:#x}", .0.0)]
+    InvalidTableAddress(PhysAddress),
+    /// A huge page already maps the region in which a smaller page should
+    /// be mapped.
+    #[error("huge page at level {level} is in the path of {:#x}", .vaddr.0)]
+    HugePageInPath {
+        /// The address that should be mapped.
+        vaddr: VirtAddress,
+        /// The level of the entry mapping the huge page.
+        level: usize,
+    },
+    /// The memory of a frame to initialize is not accessible.
+    #[error("frame at {:#x} is not accessible", .0.0)]
+    InaccessibleFrame(PhysAddress),
+    /// An entry on the way is malformed, e.g., it has the huge-page bit set
+    /// at level 4.
+    #[error("malformed entry at level {level} in the path of {:#x}", .vaddr.0)]
+    MalformedEntry {
+        /// The address that should be mapped.
+        vaddr: VirtAddress,
+        /// The level of the malformed entry.
+        level: usize,
+    },
+    /// The address is not covered by a demand-zero entry.
+    #[error("{:#x} is not covered by a demand-zero entry", .0.0)]
+    NotDemandZero(VirtAddress),
+    /// The address is not mapped by a 2 MiB page.
+    #[error("{:#x} is not mapped by a 2 MiB page", .0.0)]
+    NotHugePage(VirtAddress),
+}
+
+/// Returns whether a present entry of the given level can't be interpreted
+/// consistently: level 4 entries must not map huge pages, and huge pages must
+/// be aligned to their size.
+fn is_malformed(entry: PageTableEntry, level: usize) -> bool {
+    match level {
+        4 => entry.is_huge(),
+        2 | 3 if entry.is_huge() => !entry
+            .addr()
+            .is_multiple_of(PageSize::from_level(level).size() as u64),
+        _ => false,
+    }
+}
+
+/// How the destination frames of a new mapping are initialized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FrameInit {
+    /// The frames are mapped as they are, e.g., for pre-populated data such
+    /// as kernel segments or for MMIO.
+    Keep,
+    /// The frames are zeroed before they are mapped, so that stale contents
+    /// of freshly allocated RAM don't leak into the new mapping.
+    Zero,
+}
+
+impl FrameInit {
+    /// Returns the default for a mapping with the given flags: writable
+    /// write-back mappings are zeroed, as they typically back fresh data.
+    /// Uncached mappings are kept, as they typically map MMIO.
+    pub const fn for_flags(flags: &PageTableEntryFlags) -> Self {
+        if flags.write && matches!(flags.cache_type(), CacheType::WriteBack) {
+            Self::Zero
+        } else {
+            Self::Keep
+        }
+    }
+}
+
+/// Zeroes the frame of the given page size at `paddr`.
+///
+/// Fails with [`MapError::InaccessibleFrame`] if `mem` doesn't provide access
+/// to the frame.
+///
+/// # Safety
+/// The caller must own the frame, see [`PageTableMemory::frame_ptr`].
+pub unsafe fn zero_frame(
+    mem: &mut impl PageTableMemory,
+    paddr: PhysAddress,
+    page_size: PageSize,
+) -> Result<(), MapError> {
+    let len = page_size.size();
+    // SAFETY: The caller owns the frame.
+    let ptr = unsafe { mem.frame_ptr(paddr, len) }.ok_or(MapError::InaccessibleFrame(paddr))?;
+    // SAFETY: `mem` guarantees that the memory is accessible.
+    unsafe { core::ptr::write_bytes(ptr, 0, len) };
+    Ok(())
+}
+
+/// Maps a single page of the given size.
+///
+/// Walks the page tables starting at `root` and allocates missing
+/// intermediate page tables via `mem`. Existing intermediate entries are
+/// reused as they are. The leaf entry uses `flags`; the `present` and
+/// `hugepage` flags are set automatically. The cache type is
+/// [`CacheType::WriteBack`] unless `flags` select another one via
+/// [`PageTableEntryFlags::with_cache_type`], as needed for MMIO.
+///
+/// Fails with [`MapError::HugePageInPath`] if an existing huge page covers
+/// `vaddr` at a level above the leaf, as its frame must not be interpreted
+/// as a page table. Malformed entries on the way fail with
+/// [`MapError::MalformedEntry`] and are left untouched.
+///
+/// # Panics
+/// Panics if `vaddr` or `paddr` are not aligned to the page size or if `paddr`
+/// exceeds the 52-bit physical address space. These checks of caller input
+/// are also done in release builds.
+pub fn map_address(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    vaddr: VirtAddress,
+    paddr: PhysAddress,
+    page_size: PageSize,
+    flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+    let alignment = page_size.size() as u64;
+    assert!(vaddr.0.is_multiple_of(alignment));
+    assert!(paddr.0.is_multiple_of(alignment));
+    assert!(paddr.is_valid());
+
+    let table = walk_to_leaf_table(root, mem, vaddr, page_size.level())?;
+    let flags = PageTableEntryFlags {
+        present: true,
+        hugepage: page_size != PageSize::Size4KiB,
+        ..flags
+    };
+    // SAFETY: The pointer is either `root` or was returned by `mem`.
+    let table_ref = unsafe { &mut *table };
+    table_ref[vaddr.index(page_size.level())] = PageTableEntry::new(paddr.0, flags);
+    Ok(())
+}
+
+/// Reserves a page of the given size at `vaddr` that is allocated and zeroed
+/// lazily on first touch, rather than eagerly.
+///
+/// This writes a non-present leaf entry with [`PageTableEntry::BIT_DEMAND_ZERO`]
+/// set. The entry keeps `flags` for the later mapping; its address bits are
+/// `0`. Intermediate tables are created as in [`map_address`], which also
+/// fails in the same cases.
+///
+/// # Page-Fault Handler Contract
+/// An access to the page raises a page fault with the present bit of the
+/// error code cleared. The handler then calls [`resolve_demand_zero`] with
+/// the faulting address (`cr2`):
+/// - On success, the page is mapped to a fresh zeroed frame and the handler
+///   returns to retry the access. No TLB flush is needed, as the CPU doesn't
+///   cache non-present entries.
+/// - [`MapError::NotDemandZero`] means the fault is a genuine one.
+/// - Any other error, e.g., [`MapError::OutOfMemory`], is fatal.
+///
+/// The handler must not touch the faulting page itself before it is
+/// resolved and must serialize resolving with other modifications of the
+/// same page tables.
+///
+/// # Panics
+/// Panics if `vaddr` is not aligned to the page size.
+pub fn map_demand_zero(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    vaddr: VirtAddress,
+    page_size: PageSize,
+    flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+    assert!(vaddr.0.is_multiple_of(page_size.size() as u64));
+
+    let table = walk_to_leaf_table(root, mem, vaddr, page_size.level())?;
+    let flags = PageTableEntryFlags {
+        present: false,
+        hugepage: page_size != PageSize::Size4KiB,
+        demand_zero: true,
+        ..flags
+    };
+    // SAFETY: The pointer is either `root` or was returned by `mem`.
+    let table_ref = unsafe { &mut *table };
+    table_ref[vaddr.index(page_size.level())] = PageTableEntry::new(0, flags);
+    Ok(())
+}
+
+/// Resolves the demand-zero entry covering `vaddr`, see [`map_demand_zero`].
+///
+/// Obtains a frame of the entry's page size from `alloc_frame`, zeroes it via
+/// `mem`, and maps it with the flags stored in the entry. Returns the page
+/// size of the new mapping.
+///
+/// Fails with [`MapError::NotDemandZero`] if `vaddr` is not covered by a
+/// demand-zero entry, including when it is already mapped, and with
+/// [`MapError::OutOfMemory`] if `alloc_frame` returns `None`. On failure, the
+/// page tables are left untouched.
+///
+/// # Panics
+/// Panics if the frame returned by `alloc_frame` is not aligned to the page
+/// size.
+///
+/// # Safety
+/// `alloc_frame` must return frames that the caller owns, as they are zeroed,
+/// see [`zero_frame`].
+pub unsafe fn resolve_demand_zero(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    vaddr: VirtAddress,
+    alloc_frame: impl FnOnce(PageSize) -> Option<PhysAddress>,
+) -> Result<PageSize, MapError> {
+    let mut table: *mut PageTable = root;
+    for level in (1..=4).rev() {
+        // SAFETY: The pointer is either `root` or was returned by `mem`.
+        let table_ref = unsafe { &mut *table };
+        let index = vaddr.index(level);
+        let entry = table_ref[index];
+        let flags = entry.flags();
+        if !flags.present {
+            if !flags.demand_zero || level == 4 {
+                return Err(MapError::NotDemandZero(vaddr));
+            }
+            let page_size = PageSize::from_level(level);
+            let paddr = alloc_frame(page_size).ok_or(MapError::OutOfMemory)?;
+            assert!(paddr.0.is_multiple_of(page_size.size() as u64));
+            assert!(paddr.is_valid());
+            // SAFETY: The caller owns frames from `alloc_frame`.
+            unsafe { zero_frame(mem, paddr, page_size)? };
+
+            let flags = PageTableEntryFlags {
+                present: true,
+                demand_zero: false,
+                ..flags
+            };
+            table_ref[index] = PageTableEntry::new(paddr.0, flags);
+            return Ok(page_size);
+        }
+        if level == 1 || flags.hugepage || is_malformed(entry, level) {
+            return Err(MapError::NotDemandZero(vaddr));
+        }
+        let next = PhysAddress(entry.addr());
+        table = mem
+            .table_ptr(next)
+            .ok_or(MapError::InvalidTableAddress(next))?;
+    }
+    unreachable!("level 1 entries are always leaves")
+}
+
+/// Splits the 2 MiB page mapping `vaddr` into 512 4 KiB pages, e.g., to
+/// change the permissions of a single 4 KiB page within it.
+///
+/// Allocates a new level 1 table via `mem` whose entries map the same
+/// physical range with the same flags as the huge page, including the
+/// accessed and dirty bits. The level 2 entry then references the new table
+/// with full permissions, so the effective permissions are unchanged.
+///
+/// Fails with [`MapError::NotHugePage`] if `vaddr` is not mapped by a 2 MiB
+/// page, and with [`MapError::OutOfMemory`] if no table can be allocated. On
+/// failure, the page tables are left untouched.
+///
+/// The caller must flush the TLB for the whole 2 MiB region afterwards, as
+/// the CPU may still cache the huge page.
+pub fn split_huge_page(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    vaddr: VirtAddress,
+) -> Result<(), MapError> {
+    let mut table: *mut PageTable = root;
+    for level in (3..=4).rev() {
+        // SAFETY: The pointer is either `root` or was returned by `mem`.
+        let table_ref = unsafe { &*table };
+        let entry = table_ref[vaddr.index(level)];
+        if !entry.is_present() || entry.is_huge() || is_malformed(entry, level) {
+            return Err(MapError::NotHugePage(vaddr));
+        }
+        let next = PhysAddress(entry.addr());
+        table = mem
+            .table_ptr(next)
+            .ok_or(MapError::InvalidTableAddress(next))?;
+    }
+
+    // SAFETY: The pointer was returned by `mem`.
+    let l2 = unsafe { &mut *table };
+    let index = vaddr.index(2);
+    let entry = l2[index];
+    let flags = entry.flags();
+    if !flags.present || !flags.hugepage || is_malformed(entry, 2) {
+        return Err(MapError::NotHugePage(vaddr));
+    }
+
+    let l1_addr = mem.alloc_table().ok_or(MapError::OutOfMemory)?;
+    let l1_ptr = mem
+        .table_ptr(l1_addr)
+        .ok_or(MapError::InvalidTableAddress(l1_addr))?;
+    // SAFETY: The table was just allocated by `mem`.
+    let l1 = unsafe { &mut *l1_ptr };
+    let leaf_flags = PageTableEntryFlags {
+        hugepage: false,
+        ..flags
+    };
+    for (i, l1_entry) in l1.0.iter_mut().enumerate() {
+        let paddr = entry.addr() + (i * PAGE_SIZE) as u64;
+        *l1_entry = PageTableEntry::new(paddr, leaf_flags.clone());
+    }
+
+    let table_flags = PageTableEntryFlags {
+        present: true,
+        write: true,
+        superuser: true,
+        ..Default::default()
+    };
+    l2[index] = PageTableEntry::new(l1_addr.0, table_flags);
+    Ok(())
+}
+
+/// Merges the 512 4 KiB pages of the 2 MiB region containing `vaddr` back
+/// into a single 2 MiB page; the inverse of [`split_huge_page`].
+///
+/// This is only possible if all entries of the level 1 table are present
+/// and map a contiguous, 2 MiB aligned physical range with the same flags,
+/// ignoring the accessed and dirty bits. The huge page gets these flags,
+/// restricted by the permissions of the level 2 entry, and the accessed and
+/// dirty bits if any 4 KiB page had them.
+///
+/// Returns the physical address of the now unused level 1 table, so that the
+/// caller can reclaim it, or `None` if the pages can't be merged. In that
+/// case, the page tables are left untouched.
+///
+/// The caller must flush the TLB for the whole 2 MiB region afterwards.
+pub fn try_merge_huge_page(
+    root: &mut PageTable,
+    mem: &impl PageTableMemory,
+    vaddr: VirtAddress,
+) -> Option<PhysAddress> {
+    let mut table: *mut PageTable = root;
+    for level in (3..=4).rev() {
+        // SAFETY: The pointer is either `root` or was returned by `mem`.
+        let table_ref = unsafe { &*table };
+        let entry = table_ref[vaddr.index(level)];
+        if !entry.is_present() || entry.is_huge() || is_malformed(entry, level) {
+            return None;
+        }
+        table = mem.table_ptr(PhysAddress(entry.addr()))?;
+    }
+
+    // SAFETY: The pointer was returned by `mem`.
+    let l2 = unsafe { &mut *table };
+    let index = vaddr.index(2);
+    let l2_entry = l2[index];
+    let l2_flags = l2_entry.flags();
+    if !l2_flags.present || l2_flags.hugepage {
+        return None;
+    }
+    let l1_addr = PhysAddress(l2_entry.addr());
+    // SAFETY: The pointer was returned by `mem`.
+    let l1 = unsafe { &*mem.table_ptr(l1_addr)? };
+
+    let base = l1[0].addr();
+    // The huge-page bit of level 1 entries is the PAT bit, which the huge
+    // page can't express at the same position.
+    let flags = l1[0].flags().ignoring_ad();
+    if !base.is_multiple_of(TWO_MIB as u64) || !flags.present || flags.hugepage {
+        return None;
+    }
+    let mut accessed = false;
+    let mut dirty = false;
+    for (i, entry) in l1.0.iter().enumerate() {
+        let entry_flags = entry.flags();
+        if entry.addr() != base + (i * PAGE_SIZE) as u64
+            || entry_flags.clone().ignoring_ad() != flags
+        {
+            return None;
+        }
+        accessed |= entry_flags.accessed;
+        dirty |= entry_flags.dirty;
+    }
+
+    let flags = PageTableEntryFlags {
+        write: flags.write && l2_flags.write,
+        superuser: flags.superuser && l2_flags.superuser,
+        execute_disable: flags.execute_disable || l2_flags.execute_disable,
+        accessed,
+        dirty,
+        hugepage: true,
+        ..flags
+    };
+    l2[index] = PageTableEntry::new(base, flags);
+    Some(l1_addr)
+}
+
+/// Walks the page tables from `root` down to the table holding the leaf
+/// entry for `vaddr` at `leaf_level`, allocating missing intermediate tables.
+///
+/// See [`map_address`] for the handling of existing entries.
+fn walk_to_leaf_table(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    vaddr: VirtAddress,
+    leaf_level: usize,
+) -> Result<*mut PageTable, MapError> {
+    let mut table: *mut PageTable = root;
+    for level in (leaf_level + 1..=4).rev() {
+        // SAFETY: The pointer is either `root` or was returned by `mem`.
+        let table_ref = unsafe { &mut *table };
+        let index = vaddr.index(level);
+        let entry = table_ref[index];
+        let next = if entry.is_present() {
+            if is_malformed(entry, level) {
+                return Err(MapError::MalformedEntry { vaddr, level });
+            }
+            if entry.is_huge() {
+                return Err(MapError::HugePageInPath { vaddr, level });
+            }
+            PhysAddress(entry.addr())
+        } else {
+            let next = mem.alloc_table().ok_or(MapError::OutOfMemory)?;
+            let flags = PageTableEntryFlags {
+                present: true,
+                write: true,
+                superuser: true,
+                ..Default::default()
+            };
+            table_ref[index] = PageTableEntry::new(next.0, flags);
+            next
+        };
+        table = mem
+            .table_ptr(next)
+            .ok_or(MapError::InvalidTableAddress(next))?;
+    }
+
+    Ok(table)
+}
+
+/// Maps each virtual 4 KiB page to the corresponding physical frame of
+/// `mappings`, all with the same `flags`.
+///
+/// This is equivalent to calling [`map_address`] for each pair, but reuses
+/// the level 1 table of the previous pair if both lie in the same 2 MiB
+/// region, so consecutive virtual addresses don't walk the upper levels again.
+/// The mappings are done in order; on error, the previous ones stay.
+///
+/// # Panics
+/// Panics under the same conditions as [`map_address`].
+pub fn map_scatter(
+    root: &mut PageTable,
+    mem: &mut impl PageTableMemory,
+    mappings: &[(VirtAddress, PhysAddress)],
+    flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+    let flags = PageTableEntryFlags {
+        present: true,
+        hugepage: false,
+        ..flags
+    };
+    // 2 MiB region and level 1 table of the previous mapping.
+    let mut cached: Option<(u64, *mut PageTable)> = None;
+    for &(vaddr, paddr) in mappings {
+        assert!(vaddr.0.is_multiple_of(PAGE_SIZE as u64));
+        assert!(paddr.0.is_multiple_of(PAGE_SIZE as u64));
+        assert!(paddr.is_valid());
+
+        let region = vaddr.0 / TWO_MIB as u64;
+        let table = match cached {
+            Some((cached_region, table)) if cached_region == region => table,
+            _ => {
+                let table = walk_to_leaf_table(root, mem, vaddr, 1)?;
+                cached = Some((region, table));
+                table
+            }
+        };
+        // SAFETY: The pointer was returned by `mem`.
+        let table_ref = unsafe { &mut *table };
+        table_ref[vaddr.index(1)] = PageTableEntry::new(paddr.0, flags.clone());
+    }
+    Ok(())
+}
+
+/// Result of a successful [`translate`].
+#[derive(Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash)]
+pub struct Translation {
+    /// The physical address the virtual address maps to.
+    pub phys: PhysAddress,
+    /// The effective flags of the mapping.
+    ///
+    /// `write` and `superuser` are only set if all levels allow them, and
+    /// `execute_disable` is set if any level disallows execution.
+    pub flags: PageTableEntryFlags,
+    /// The size of the page containing the address.
+    pub page_size: PageSize,
+}
+
+/// Translates a virtual address to a physical address by walking the page
+/// tables starting at `root`.
+///
+/// Returns `None` if the address is not mapped, the page tables reference
+/// inaccessible memory, or an entry on the way is malformed (a huge page at
+/// level 4 or an unaligned huge page). The huge-page bit of level 1 entries
+/// is the PAT bit and doesn't affect the translation.
+pub fn translate(
+    root: &PageTable,
+    mem: &impl PageTableMemory,
+    vaddr: VirtAddress,
+) -> Option<Translation> {
+    let mut table: *const PageTable = root;
+    let mut write = true;
+    let mut superuser = true;
+    let mut execute_disable = false;
+    for level in (1..=4).rev() {
+        // SAFETY: The pointer is either `root` or was returned by `mem`.
+        let table_ref = unsafe { &*table };
+        let entry = table_ref[vaddr.index(level)];
+        let flags = entry.flags();
+        if !flags.present || is_malformed(entry, level) {
+            return None;
+        }
+
+        write &= flags.write;
+        superuser &= flags.superuser;
+        execute_disable |= flags.execute_disable;
+
+        if level == 1 || flags.hugepage {
+            let page_size = PageSize::from_level(level);
+            let offset = vaddr.0 & (page_size.size() as u64 - 1);
+            return Some(Translation {
+                phys: PhysAddress(entry.addr() + offset),
+                flags: PageTableEntryFlags {
+                    write,
+                    superuser,
+                    execute_disable,
+                    hugepage: level != 1,
+                    ..flags
+                },
+                page_size,
+            });
+        }
+
+        table = mem.table_ptr(PhysAddress(entry.addr()))?;
+    }
+    unreachable!("level 1 entries are always leaves")
+}
+
+/// Performs a single mapping step.
+///
+/// Maps the virtual address for the given level with the given physical
+/// addresses for the page table and the physical destination.
+///
+/// # Panics
+/// Panics if a huge page is requested for a level other than `2` or `3` or if
+/// the destination is not aligned to the page size of that level. As these
+/// are caller input, they are also checked in release builds. The page
+/// alignment of the destination of a regular entry is only checked in debug
+/// builds; see [`PageTableEntry::new`].
+pub fn map_address_step(
+    addr: VirtAddress,
+    phys_src: &mut PageTable,
+    phys_dest: PhysMappingDest<'_>,
+    level: usize,
+    write: bool,
+    hugepage: bool,
+    execute_disable: bool,
+) {
+    if hugepage {
+        assert!(level == 2 || level == 3);
+        if level == 2 {
+            assert!(phys_dest.to_addr().is_multiple_of(TWO_MIB as u64));
+        } else if level == 3 {
+            assert!(phys_dest.to_addr().is_multiple_of(ONE_GIB as u64));
+        }
+    }
+
+    let index = addr.index(level);
+    let phys_dest = phys_dest.to_addr();
+    let flags = PageTableEntryFlags {
+        present: true,
+        write,
+        superuser: true,
+        write_through: false,
+        cache_disable: false,
+        accessed: false,
+        dirty: false,
+        hugepage,
+        demand_zero: false,
+        execute_disable,
+    };
+    debug!(
+        "Mapping step: level={level}: table @ {:#x}#{:03} (phys) => {:#x} (phys)",
+        phys_src.as_page().as_ptr() as u64,
+        index,
+        phys_dest
+    );
+    debug!("  write={}, hugepage={}", flags.write, flags.hugepage);
+    let entry = PageTableEntry::new(phys_dest, flags);
+    phys_src.0[index] = entry;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::fake_memory::Corruption;
+
+    #[test]
+    fn test_abi() {
+        assert_eq!(size_of::<Page>(), PAGE_SIZE);
+        assert_eq!(align_of::<Page>(), PAGE_SIZE);
+        assert_eq!(size_of::<PageTableEntry>(), 8);
+        assert_eq!(align_of::<PageTableEntry>(), 8);
+    }
+
+    #[test]
+    fn test_virt_address_index() {
+        let addr = VirtAddress(0xffff_eeee_dead_beef);
+        assert_eq!(addr.index(4), 477);
+        assert_eq!(addr.index(3), 443);
+        assert_eq!(addr.index(2), 245);
+        assert_eq!(addr.index(1), 219);
+    }
+
+    #[test]
+    fn test_address_ops() {
+        assert_eq!(PhysAddress(0x1000) + 0x234, PhysAddress(0x1234));
+        assert_eq!(PhysAddress(0x1234) - 0x234, PhysAddress(0x1000));
+        assert_eq!(PhysAddress(0x3000) - PhysAddress(0x1000), 0x2000);
+        assert_eq!(
+            VirtAddress(0xffff_8000_0000_0000) + 0x1000,
+            VirtAddress(0xffff_8000_0000_1000)
+        );
+        assert_eq!(VirtAddress(0x2000) - VirtAddress(0x1000), 0x1000);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overflow")]
+    fn test_address_add_overflow() {
+        let _ = VirtAddress(u64::MAX) + 1;
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overflow")]
+    fn test_address_difference_underflow() {
+        let _ = PhysAddress(0x1000) - PhysAddress(0x2000);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of range")]
+    fn test_address_add_out_of_range() {
+        let _ = VirtAddress(0x0000_7fff_ffff_f000) + 0x1000;
+    }
+
+    #[test]
+    #[should_panic(expected = "paddr.is_valid()")]
+    fn test_map_address_invalid_paddr() {
+        let mut root = Box::new(PageTable::ZERO);
+        let _ = map_address(
+            &mut root,
+            &mut fake_memory::FakePhysMemory::new(),
+            VirtAddress(0x1000),
+            PhysAddress(1 << 52),
+            PageSize::Size4KiB,
+            PageTableEntryFlags::default(),
+        );
+    }
+
+    #[test]
+    fn test_require_aligned() {
+        let addr = PhysAddress(0x20_0000);
+        assert_eq!(addr.require_aligned(TWO_MIB as u64), Ok(addr));
+        assert_eq!(addr.require_aligned(1), Ok(addr));
+        let addr = PhysAddress(0x20_1000);
+        let err = addr.require_aligned(TWO_MIB as u64).unwrap_err();
+        assert_eq!(
+            err,
+            AlignError {
+                addr,
+                align: TWO_MIB as u64
+            }
+        );
+        assert_eq!(
+            alloc::format!("{err}"),
+            "address 0x201000 is not aligned to 0x200000"
+        );
+    }
+
+    #[test]
+    fn test_virt_address_is_canonical() {
+        assert!(VirtAddress(0).is_canonical());
+        assert!(VirtAddress(0x0000_7fff_ffff_ffff).is_canonical());
+        assert!(VirtAddress(0xffff_8000_0000_0000).is_canonical());
+        assert!(!VirtAddress(0x0000_8000_0000_0000).is_canonical());
+        assert!(!VirtAddress(0xfff7_8000_0000_0000).is_canonical());
+    }
+
+    #[test]
+    fn test_page_table_entry_addr() {
+        let flags = PageTableEntryFlags {
+            present: true,
+            execute_disable: true,
+            ..Default::default()
+        };
+        let entry = PageTableEntry::new(0x000f_ffff_ffff_f000, flags);
+        assert_eq!(entry.addr(), 0x000f_ffff_ffff_f000);
+    }
+
+    #[test]
+    fn test_map_and_translate() {
+        let mut root = Box::new(PageTable::ZERO);
+        let mut mem = IdentityMapped;
+        let flags = PageTableEntryFlags {
+            write: true,
+            execute_disable: true,
+            ..Default::default()
+        };
+
+        let vaddr = VirtAddress(0xffff_8000_0020_0000);
+        map_address(
+            &mut root,
+            &mut mem,
+            vaddr,
+            PhysAddress(0x4000),
+            PageSize::Size4KiB,
+            flags.clone(),
+        )
+        .unwrap();
+        map_address(
+            &mut root,
+            &mut mem,
+            VirtAddress(0x4000_0000),
+            PhysAddress(0x20_0000),
+            PageSize::Size2MiB,
+            PageTableEntryFlags::default(),
+        )
+        .unwrap();
+
+        let translation = translate(&root, &mem, VirtAddress(vaddr.0 + 0x123)).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x4123));
+        assert_eq!(translation.page_size, PageSize::Size4KiB);
+        assert!(translation.flags.write);
+        assert!(translation.flags.execute_disable);
+
+        let translation = translate(&root, &mem, VirtAddress(0x4010_0000)).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x30_0000));
+        assert_eq!(translation.page_size, PageSize::Size2MiB);
+        assert!(!translation.flags.write);
+
+        assert_eq!(translate(&root, &mem, VirtAddress(vaddr.0 + 0x1000)), None);
+        assert_eq!(translate(&root, &mem, VirtAddress(0)), None);
+    }
+
+    #[test]
+    fn test_map_address_huge_page_in_path() {
+        let mut root = Box::new(PageTable::ZERO);
+        let mut mem = IdentityMapped;
+        map_address(
+            &mut root,
+            &mut mem,
+            VirtAddress(0x4000_0000),
+            PhysAddress(0x20_0000),
+            PageSize::Size2MiB,
+            PageTableEntryFlags::default(),
+        )
+        .unwrap();
+
+        let vaddr = VirtAddress(0x4010_0000);
+        assert_eq!(
+            map_address(
+                &mut root,
+                &mut mem,
+                vaddr,
+                PhysAddress(0x1000),
+                PageSize::Size4KiB,
+                PageTableEntryFlags::default(),
+            ),
+            Err(MapError::HugePageInPath { vaddr, level: 2 })
+        );
+        // The huge page is untouched.
+        let translation = translate(&root, &mem, vaddr).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x30_0000));
+        assert_eq!(translation.page_size, PageSize::Size2MiB);
+    }
+
+    #[test]
+    fn test_page_table_entry() {
+        let mut table = PageTable::ZERO;
+        table[511] = PageTableEntry(0x1000);
+        assert_eq!(table.entry(511), Some(&PageTableEntry(0x1000)));
+        assert_eq!(table.entry(512), None);
+        assert_eq!(table.entry_mut(512), None);
+
+        *table.entry_mut(0).unwrap() = PageTableEntry(0x2000);
+        assert_eq!(table[0], PageTableEntry(0x2000));
+    }
+
+    #[test]
+    fn test_page_table_entry_predicates() {
+        let bits = [
+            0,
+            PageTableEntry::BIT_PRESENT,
+            PageTableEntry::BIT_HUGEPAGE,
+            PageTableEntry::BIT_PRESENT | PageTableEntry::BIT_HUGEPAGE,
+            PageTableEntry::BIT_PRESENT | PageTableEntry::BIT_WRITE | 0x20_0000,
+            PageTableEntry::BIT_DEMAND_ZERO | PageTableEntry::BIT_EXECUTE_DISABLE,
+            u64::MAX,
+        ];
+        for value in bits {
+            let entry = PageTableEntry(value);
+            assert_eq!(entry.is_present(), entry.flags().present, "{value:#x}");
+            assert_eq!(entry.is_huge(), entry.flags().hugepage, "{value:#x}");
+        }
+        assert!(!PageTableEntry(0).is_present());
+        assert!(PageTableEntry(PageTableEntry::BIT_HUGEPAGE).is_huge());
+    }
+
+    #[test]
+    fn test_flags_permits() {
+        let read_only = PageTableEntryFlags {
+            present: true,
+            execute_disable: true,
+            ..Default::default()
+        };
+        let write = PageTableEntryFlags {
+            write: true,
+            ..read_only.clone()
+        };
+        let execute = PageTableEntryFlags {
+            execute_disable: false,
+            ..read_only.clone()
+        };
+        assert!(read_only.permits(&read_only));
+        assert!(!read_only.permits(&write));
+        assert!(!read_only.permits(&execute));
+        assert!(write.permits(&read_only));
+        assert!(execute.permits(&read_only));
+        assert!(!PageTableEntryFlags::default().permits(&read_only));
+    }
+
+    #[test]
+    fn test_flags_ignoring_ad() {
+        let flags = PageTableEntryFlags {
+            present: true,
+            write: true,
+            ..Default::default()
+        };
+        let entry = PageTableEntry::new(
+            0x1000,
+            PageTableEntryFlags {
+                accessed: true,
+                dirty: true,
+                ..flags.clone()
+            },
+        );
+        assert_eq!(entry.0 & 0xfff, 0x63);
+        assert_ne!(entry.flags(), flags);
+        assert_eq!(entry.flags().ignoring_ad(), flags);
+    }
+
+    /// Page-table tree in fake memory with a 4 KiB page at
+    /// [`FAULT_VADDR_4K`] and a 2 MiB page at [`FAULT_VADDR_2M`].
+    fn fault_injection_tree() -> (Box<PageTable>, fake_memory::FakePhysMemory) {
+        let mut root = Box::new(PageTable::ZERO);
+        let mut mem = fake_memory::FakePhysMemory::new();
+        for (vaddr, paddr, page_size) in [
+            (FAULT_VADDR_4K, 0x5000, PageSize::Size4KiB),
+            (FAULT_VADDR_2M, 0x20_0000, PageSize::Size2MiB),
+        ] {
+            map_address(
+                &mut root,
+                &mut mem,
+                vaddr,
+                PhysAddress(paddr),
+                page_size,
+                PageTableEntryFlags::default(),
+            )
+            .unwrap();
+        }
+        (root, mem)
+    }
+
+    const FAULT_VADDR_4K: VirtAddress = VirtAddress(0xffff_8000_0020_1000);
+    const FAULT_VADDR_2M: VirtAddress = VirtAddress(0x4000_0000);
+
+    fn map_4k(
+        root: &mut PageTable,
+        mem: &mut fake_memory::FakePhysMemory,
+        vaddr: VirtAddress,
+    ) -> Result<(), MapError> {
+        map_address(
+            root,
+            mem,
+            vaddr,
+            PhysAddress(0x9000),
+            PageSize::Size4KiB,
+            PageTableEntryFlags::default(),
+        )
+    }
+
+    #[test]
+    fn test_fault_present_only_entry() {
+        let (mut root, mut mem) = fault_injection_tree();
+        let old = mem
+            .inject(&mut root, FAULT_VADDR_4K, 3, Corruption::PresentOnly)
+            .unwrap();
+        assert!(old.flags().present);
+
+        assert_eq!(translate(&root, &mem, FAULT_VADDR_4K), None);
+        let tables = mem.table_count();
+        assert_eq!(
+            map_4k(&mut root, &mut mem, FAULT_VADDR_4K + 0x1000),
+            Err(MapError::InvalidTableAddress(PhysAddress(0)))
+        );
+        assert_eq!(mem.table_count(), tables);
+        // Unrelated mappings still work.
+        assert!(translate(&root, &mem, FAULT_VADDR_2M).is_some());
+    }
+
+    #[test]
+    fn test_fault_table_outside_of_memory() {
+        let (mut root, mut mem) = fault_injection_tree();
+        let old = mem
+            .inject(
+                &mut root,
+                FAULT_VADDR_4K,
+                2,
+                Corruption::AddressBits(1 << 40),
+            )
+            .unwrap();
+
+        assert_eq!(translate(&root, &mem, FAULT_VADDR_4K), None);
+        assert_eq!(
+            map_4k(&mut root, &mut mem, FAULT_VADDR_4K + 0x1000),
+            Err(MapError::InvalidTableAddress(PhysAddress(
+                old.addr() | 1 << 40
+            )))
+        );
+    }
+
+    #[test]
+    fn test_fault_huge_page_bit_at_level_1() {
+        let (mut root, mut mem) = fault_injection_tree();
+        mem.inject(&mut root, FAULT_VADDR_4K, 1, Corruption::HugePage)
+            .unwrap();
+
+        // At level 1, the bit is the PAT bit and doesn't change the size.
+        let translation = translate(&root, &mem, FAULT_VADDR_4K + 0x123).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x5123));
+        assert_eq!(translation.page_size, PageSize::Size4KiB);
+        assert!(!translation.flags.hugepage);
+
+        map_4k(&mut root, &mut mem, FAULT_VADDR_4K + 0x1000).unwrap();
+        let translation = translate(&root, &mem, FAULT_VADDR_4K + 0x1000).unwrap();
+        assert_eq!(translation.phys, PhysAddress(0x9000));
+    }
+
+    #[test]
+    fn test_fault_huge_page_bit_at_level_4() {
+        let (mut root, mut mem) = fault_injection_tree();
+        mem.inject(&mut root, FAULT_VADDR_4K, 4, Corruption::HugePage)
+            .unwrap();
+
+        assert_eq!(translate(&root, &mem, FAULT_VADDR_4K), None);
+        let vaddr = FAULT_VADDR_4K + 0x1000;
+        assert_eq!(
+            map_4k(&mut root, &mut mem, vaddr),
+            Err(MapError::MalformedEntry { vaddr, level: 4 })
+        );
+    }
+
+    #[test]
+    fn test_fault_unaligned_huge_page() {
+        let (mut root, mut mem) = fault_injection_tree();
+        mem.inject(
+            &mut root,
+            FAULT_VADDR_2M,
+            2,
+            Corruption::AddressBits(0x1000),
+        )
+        .unwrap();
+
+        assert_eq!(translate(&root, &mem, FAULT_VADDR_2M), None);
+        let vaddr = FAULT_VADDR_2M + 0x1000;
+        assert_eq!(
+            map_4k(&mut root, &mut mem, vaddr),
+            Err(MapError::MalformedEntry { vaddr, level: 2 })
+        );
+        // A huge page at level 3 must be 1 GiB aligned.
+        let (mut root, mem) = fault_injection_tree();
+        mem.inject(&mut root, FAULT_VADDR_2M, 3, Corruption::HugePage)
+            .unwrap();
+        assert_eq!(translate(&root, &mem, FAULT_VADDR_2M), None);
+    }
+
+    #[test]
+    fn test_fault_injection_requires_path() {
+        let (mut root, mem) = fault_injection_tree();
+        assert_eq!(
+            mem.inject(&mut root, VirtAddress(0x1000), 1, Corruption::HugePage),
+            None
+        );
+        // Level 1 below a 2 MiB page doesn't exist.
+        assert_eq!(
+            mem.inject(&mut root, FAULT_VADDR_2M, 1, Corruption::HugePage),
+            None
+        );
+        assert_eq!(translate(&root, &mem, VirtAddress(0x1000)), None);
+    }
+
+    #[test]
+    fn test_map_scatter() {
+        let mappings = [
+            (VirtAddress(0x1000), PhysAddress(0x7000)),
+            (VirtAddress(0x2000), PhysAddress(0x3000)),
+            // Next 2 MiB region and back again.
+            (VirtAddress(0x20_0000), PhysAddress(0x8000)),
+            (VirtAddress(0x3000), PhysAddress(0x4000)),
+            (VirtAddress(0xffff_8000_0000_0000), PhysAddress(0x9000)),
+        ];
+        let flags = PageTableEntryFlags {
+            write: true,
+            ..Default::default()
+        };
+
+        let mut root = Box::new(PageTable::ZERO);
+        let mut mem = fake_memory::FakePhysMemory::new();
+        map_scatter(&mut root, &mut mem, &mappings, flags.clone()).unwrap();
+
+        let mut expected_root = Box::new(PageTable::ZERO);
+        let mut expected_mem = fake_memory::FakePhysMemory::new();
+        for (vaddr, paddr) in mappings {
+            map_address(
+                &mut expected_root,
+                &mut expected_mem,
+                vaddr,
+                paddr,
+                PageSize::Size4KiB,
+                flags.clone(),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(mem.table_count(), expected_mem.table_count());
+        for (vaddr, paddr) in mappings {
+            let translation = translate(&root, &mem, vaddr).unwrap();
+            assert_eq!(translation.phys, paddr);
+            assert_eq!(translation.page_size, PageSize::Size4KiB);
+            assert_eq!(
+                Some(translation),
+                translate(&expected_root, &expected_mem, vaddr)
+            );
+        }
+        assert_eq!(translate(&root, &mem, VirtAddress(0x4000)), None);
+    }
+
+    #[test]
+    fn test_map_scatter_huge_page_in_path() {
+        let (mut root, mut mem) = fault_injection_tree();
+        let vaddr = FAULT_VADDR_2M + 0x1000;
+        let mappings = [
+            (VirtAddress(0x1000), PhysAddress(0x7000)),
+            (vaddr, PhysAddress(0x8000)),
+        ];
+        assert_eq!(
+            map_scatter(
+                &mut root,
+                &mut mem,
+                &mappings,
+                PageTableEntryFlags::default()
+            ),
+            Err(MapError::HugePageInPath { vaddr, level: 2 })
+        );
+        // Mappings before the failing one stay.
+        assert!(translate(&root, &mem, VirtAddress(0x1000)).is_some());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_leaked_table_count() {
+        let before = leaked_table_count();
+        let a = IdentityMapped.alloc_table().unwrap();
+        let b = IdentityMapped.alloc_table().unwrap();
+        assert_ne!(a, b);
+        assert_eq!(leaked_table_count(), before + 2);
+        let table = unsafe { &*IdentityMapped.table_ptr(a).unwrap() };
+        assert!(table.is_zero());
+
+        record_leaked_tables(16);
+        assert_eq!(leaked_table_count(), before + 18);
+    }
+}
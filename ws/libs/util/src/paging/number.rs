@@ -0,0 +1,142 @@
+//! Numbers of 4 KiB physical frames and virtual pages.
+//!
+//! Unlike raw addresses, these can't be off by a multiple of [`PAGE_SIZE`],
+//! which makes them suitable as keys in frame allocators and trackers.
+
+use super::{PAGE_SIZE, PhysAddress, VirtAddress};
+use core::ops::Add;
+
+/// Number of a 4 KiB physical frame, i.e., its address divided by
+/// [`PAGE_SIZE`].
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct FrameNumber(pub u64);
+
+impl FrameNumber {
+    /// Returns the number of the frame starting at `addr`.
+    ///
+    /// # Panics
+    /// Panics if `addr` is not page-aligned.
+    pub const fn from_phys(addr: PhysAddress) -> Self {
+        assert!(
+            addr.0.is_multiple_of(PAGE_SIZE as u64),
+            "address should be page-aligned"
+        );
+        Self(addr.0 / PAGE_SIZE as u64)
+    }
+
+    /// Returns the physical base address of the frame.
+    pub const fn to_phys(self) -> PhysAddress {
+        PhysAddress(self.0 * PAGE_SIZE as u64)
+    }
+}
+
+impl PhysAddress {
+    /// Returns the number of the frame starting at this address.
+    ///
+    /// # Panics
+    /// Panics if the address is not page-aligned.
+    pub const fn frame_number(self) -> FrameNumber {
+        FrameNumber::from_phys(self)
+    }
+}
+
+impl From<FrameNumber> for PhysAddress {
+    fn from(frame: FrameNumber) -> Self {
+        frame.to_phys()
+    }
+}
+
+impl Add<u64> for FrameNumber {
+    type Output = Self;
+
+    fn add(self, rhs: u64) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+/// Number of a 4 KiB virtual page, i.e., its address divided by
+/// [`PAGE_SIZE`].
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct PageNumber(pub u64);
+
+impl PageNumber {
+    /// Returns the number of the page starting at `addr`.
+    ///
+    /// # Panics
+    /// Panics if `addr` is not page-aligned.
+    pub const fn from_virt(addr: VirtAddress) -> Self {
+        assert!(
+            addr.0.is_multiple_of(PAGE_SIZE as u64),
+            "address should be page-aligned"
+        );
+        Self(addr.0 / PAGE_SIZE as u64)
+    }
+
+    /// Returns the virtual base address of the page.
+    pub const fn to_virt(self) -> VirtAddress {
+        VirtAddress(self.0 * PAGE_SIZE as u64)
+    }
+}
+
+impl VirtAddress {
+    /// Returns the number of the page starting at this address.
+    ///
+    /// # Panics
+    /// Panics if the address is not page-aligned.
+    pub const fn page_number(self) -> PageNumber {
+        PageNumber::from_virt(self)
+    }
+}
+
+impl From<PageNumber> for VirtAddress {
+    fn from(page: PageNumber) -> Self {
+        page.to_virt()
+    }
+}
+
+impl Add<u64> for PageNumber {
+    type Output = Self;
+
+    fn add(self, rhs: u64) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for addr in [0, 0x1000, 0x20_0000, 0xf_ffff_ffff_f000] {
+            let frame = PhysAddress(addr).frame_number();
+            assert_eq!(frame.to_phys(), PhysAddress(addr));
+        }
+        for addr in [0, 0x1000, 0x7fff_ffff_f000, 0xffff_8000_0000_0000] {
+            let page = VirtAddress(addr).page_number();
+            assert_eq!(page.to_virt(), VirtAddress(addr));
+        }
+
+        assert_eq!(PhysAddress(0x3000).frame_number(), FrameNumber(3));
+        assert_eq!(FrameNumber(3) + 2, FrameNumber(5));
+        assert_eq!(PhysAddress::from(FrameNumber(5)), PhysAddress(0x5000));
+        assert_eq!(
+            VirtAddress::from(VirtAddress(0x1000).page_number() + 1),
+            VirtAddress(0x2000)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "page-aligned")]
+    fn test_frame_number_unaligned() {
+        let _ = PhysAddress(0x1800).frame_number();
+    }
+
+    #[test]
+    #[should_panic(expected = "page-aligned")]
+    fn test_page_number_unaligned() {
+        let _ = VirtAddress(0xfff).page_number();
+    }
+}
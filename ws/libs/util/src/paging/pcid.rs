@@ -0,0 +1,193 @@
+//! Process-context identifiers (PCIDs).
+//!
+//! With PCIDs enabled (`CR4.PCIDE`), the CPU tags TLB entries with the PCID
+//! in the lower 12 bits of `cr3`. Loading `cr3` with bit 63 set then keeps
+//! the TLB entries of all PCIDs, so switching back to an address space
+//! doesn't start with a cold TLB. See [`super::AddressSpace::activate`].
+
+use super::PhysAddress;
+
+/// Bit of `cr3` that keeps the TLB entries of the loaded PCID.
+const CR3_NO_FLUSH: u64 = 1 << 63;
+
+/// Bit of CPUID leaf 1 ECX that reports PCID support.
+const CPUID_1_ECX_PCID: u32 = 1 << 17;
+
+/// A process-context identifier in the range `1..=4095`.
+///
+/// PCID `0` is the one in use when PCIDs are disabled; it is never
+/// allocated, so it stays with the boot page tables.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pcid(u16);
+
+impl Pcid {
+    /// The largest PCID.
+    pub const MAX: u16 = 4095;
+
+    /// Returns the raw value.
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+/// A PCID together with the CPU and the generation of the [`PcidAllocator`]
+/// that assigned it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PcidAssignment {
+    /// The assigned PCID.
+    pub pcid: Pcid,
+    /// The CPU of the allocator. PCIDs are per CPU, so the assignment is
+    /// meaningless on other CPUs.
+    pub cpu: u32,
+    /// The generation it belongs to. The assignment is stale once the
+    /// allocator moved on to a newer generation.
+    pub generation: u64,
+}
+
+/// Allocator of PCIDs for one CPU.
+///
+/// PCIDs are handed out in order. Once all are used, the allocator starts
+/// over with a new generation. This invalidates all previous assignments,
+/// as their PCIDs are handed out again. This is pure bookkeeping that
+/// doesn't touch the hardware.
+#[derive(Debug)]
+pub struct PcidAllocator {
+    cpu: u32,
+    next: u16,
+    generation: u64,
+}
+
+impl PcidAllocator {
+    /// Creates a new allocator for the CPU with the given id.
+    ///
+    /// Each CPU must have its own allocator with a unique id.
+    pub const fn new(cpu: u32) -> Self {
+        Self {
+            cpu,
+            next: 1,
+            generation: 0,
+        }
+    }
+
+    /// Returns the id of the CPU of the allocator.
+    pub const fn cpu(&self) -> u32 {
+        self.cpu
+    }
+
+    /// Returns the current generation.
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns whether `assignment` is still valid, i.e., it is from this
+    /// allocator and its PCID wasn't handed out again since.
+    pub const fn is_current(&self, assignment: &PcidAssignment) -> bool {
+        assignment.cpu == self.cpu && assignment.generation == self.generation
+    }
+
+    /// Assigns the next PCID.
+    pub const fn alloc(&mut self) -> PcidAssignment {
+        if self.next > Pcid::MAX {
+            self.next = 1;
+            self.generation += 1;
+        }
+        let pcid = Pcid(self.next);
+        self.next += 1;
+        PcidAssignment {
+            pcid,
+            cpu: self.cpu,
+            generation: self.generation,
+        }
+    }
+}
+
+/// Returns the value for `cr3` with PCIDs enabled.
+///
+/// With `no_flush`, the CPU keeps the TLB entries tagged with `pcid`.
+/// Otherwise, it flushes them.
+pub const fn cr3_value(root: PhysAddress, pcid: Pcid, no_flush: bool) -> u64 {
+    let value = root.0 | pcid.0 as u64;
+    if no_flush {
+        value | CR3_NO_FLUSH
+    } else {
+        value
+    }
+}
+
+/// Returns whether the CPU supports PCIDs.
+pub fn is_supported() -> bool {
+    let leaf = core::arch::x86_64::__cpuid(1);
+    leaf.ecx & CPUID_1_ECX_PCID != 0
+}
+
+/// Returns whether PCIDs are enabled.
+pub fn is_enabled() -> bool {
+    // SAFETY: Reading CR4 has no side effects.
+    let cr4 = unsafe { x86::controlregs::cr4() };
+    cr4.contains(x86::controlregs::Cr4::CR4_ENABLE_PCID)
+}
+
+/// Enables PCIDs, if the CPU supports them. Returns whether they are
+/// enabled.
+///
+/// # Safety
+/// Must run in long mode with the lower 12 bits of `cr3` cleared, as the CPU
+/// otherwise raises a general protection fault.
+pub unsafe fn enable() -> bool {
+    if !is_supported() {
+        return false;
+    }
+    // SAFETY: The CPU supports PCIDs, and the caller guarantees the rest.
+    unsafe {
+        let cr4 = x86::controlregs::cr4();
+        x86::controlregs::cr4_write(cr4 | x86::controlregs::Cr4::CR4_ENABLE_PCID);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc() {
+        let mut pcids = PcidAllocator::new(0);
+        let first = pcids.alloc();
+        assert_eq!(first.pcid.get(), 1);
+        assert_eq!(pcids.alloc().pcid.get(), 2);
+        for _ in 3..=Pcid::MAX {
+            assert_eq!(pcids.alloc().generation, 0);
+        }
+        assert!(pcids.is_current(&first));
+
+        // All PCIDs are used; the next one starts a new generation.
+        let wrapped = pcids.alloc();
+        assert_eq!(wrapped.pcid, first.pcid);
+        assert_eq!(wrapped.generation, 1);
+        assert!(!pcids.is_current(&first));
+        assert!(pcids.is_current(&wrapped));
+    }
+
+    #[test]
+    fn test_alloc_per_cpu() {
+        let mut cpu0 = PcidAllocator::new(0);
+        let mut cpu1 = PcidAllocator::new(1);
+        let on_cpu0 = cpu0.alloc();
+        let on_cpu1 = cpu1.alloc();
+        // Same PCID and generation, but for different CPUs.
+        assert_eq!(on_cpu0.pcid, on_cpu1.pcid);
+        assert_eq!(on_cpu0.generation, on_cpu1.generation);
+        assert!(cpu0.is_current(&on_cpu0));
+        assert!(!cpu0.is_current(&on_cpu1));
+        assert!(cpu1.is_current(&on_cpu1));
+        assert!(!cpu1.is_current(&on_cpu0));
+    }
+
+    #[test]
+    fn test_cr3_value() {
+        let root = PhysAddress(0x1234_5000);
+        let pcid = Pcid(0xabc);
+        assert_eq!(cr3_value(root, pcid, false), 0x1234_5abc);
+        assert_eq!(cr3_value(root, pcid, true), 0x8000_0000_1234_5abc);
+    }
+}
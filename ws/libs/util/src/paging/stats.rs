@@ -0,0 +1,126 @@
+//! Statistics about a hierarchy of page tables.
+
+use super::{PAGE_SIZE, PageTable, PageTableMemory, PhysAddress};
+use core::fmt::{self, Display, Formatter};
+
+/// Number of page tables per level of a hierarchy of 4-level page tables.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PageTableStats {
+    /// Number of level 4 tables (the root).
+    pub l4: usize,
+    /// Number of level 3 tables.
+    pub l3: usize,
+    /// Number of level 2 tables.
+    pub l2: usize,
+    /// Number of level 1 tables.
+    pub l1: usize,
+}
+
+impl PageTableStats {
+    /// Counts the page tables reachable from `root`.
+    ///
+    /// Tables that are not accessible via `mem` are not counted.
+    pub fn collect(root: &PageTable, mem: &impl PageTableMemory) -> Self {
+        let mut stats = Self::default();
+        stats.visit(root, mem, 4);
+        stats
+    }
+
+    fn visit(&mut self, table: &PageTable, mem: &impl PageTableMemory, level: usize) {
+        match level {
+            4 => self.l4 += 1,
+            3 => self.l3 += 1,
+            2 => self.l2 += 1,
+            _ => {
+                self.l1 += 1;
+                return;
+            }
+        }
+        for entry in table.0.iter() {
+            if !entry.is_present() || entry.is_huge() {
+                continue;
+            }
+            if let Some(next) = mem.table_ptr(PhysAddress(entry.addr())) {
+                // SAFETY: The pointer was returned by `mem`.
+                self.visit(unsafe { &*next }, mem, level - 1);
+            }
+        }
+    }
+
+    /// Returns the total number of page tables.
+    pub const fn tables(&self) -> usize {
+        self.l4 + self.l3 + self.l2 + self.l1
+    }
+
+    /// Returns the memory consumed by the page tables in bytes.
+    pub const fn size(&self) -> usize {
+        self.tables() * PAGE_SIZE
+    }
+}
+
+impl Display for PageTableStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} L4, {} L3, {} L2, {} L1 = {} KiB",
+            self.l4,
+            self.l3,
+            self.l2,
+            self.l1,
+            self.size() / 1024
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::fake_memory::FakePhysMemory;
+    use crate::paging::{AddressSpace, PageSize, PageTableEntryFlags, VirtAddress};
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_collect() {
+        let mut space = AddressSpace::new(FakePhysMemory::new()).unwrap();
+        let flags = PageTableEntryFlags::default();
+        space
+            .map(
+                VirtAddress(0x1000),
+                PhysAddress(0x1000),
+                PageSize::Size4KiB,
+                flags.clone(),
+            )
+            .unwrap();
+        space
+            .map(
+                VirtAddress(0x20_0000),
+                PhysAddress(0x20_0000),
+                PageSize::Size2MiB,
+                flags.clone(),
+            )
+            .unwrap();
+        space
+            .map(
+                VirtAddress(0xffff_8000_0000_0000),
+                PhysAddress(0),
+                PageSize::Size1GiB,
+                flags,
+            )
+            .unwrap();
+
+        // SAFETY: The root table was allocated by the fake memory.
+        let root = unsafe { &*space.mem().table_ptr(space.root()).unwrap() };
+        let stats = PageTableStats::collect(root, space.mem());
+        assert_eq!(
+            stats,
+            PageTableStats {
+                l4: 1,
+                l3: 2,
+                l2: 1,
+                l1: 1
+            }
+        );
+        assert_eq!(stats.tables(), space.mem().table_count());
+        assert_eq!(stats.to_string(), "1 L4, 2 L3, 1 L2, 1 L1 = 20 KiB");
+    }
+}
@@ -0,0 +1,72 @@
+//! Common sizes and conversions between bytes and pages.
+
+/// Size of a 4 KiB page in bytes.
+pub const FOUR_K: usize = 4096;
+/// Size of a 2 MiB huge page in bytes.
+pub const TWO_MIB: usize = 0x200000;
+/// Size of a 1 GiB huge page in bytes.
+pub const ONE_GIB: usize = 0x40000000;
+
+/// Returns the number of 4 KiB pages needed for `bytes`, rounding up.
+#[must_use]
+pub const fn bytes_to_pages(bytes: usize) -> usize {
+    bytes.div_ceil(FOUR_K)
+}
+
+/// Returns the size of `pages` 4 KiB pages in bytes.
+#[must_use]
+pub const fn pages_to_bytes(pages: usize) -> usize {
+    pages * FOUR_K
+}
+
+/// Returns the number of 2 MiB pages needed for `bytes`, rounding up.
+#[must_use]
+pub const fn bytes_to_two_mib_pages(bytes: usize) -> usize {
+    bytes.div_ceil(TWO_MIB)
+}
+
+/// Returns the size of `pages` 2 MiB pages in bytes.
+#[must_use]
+pub const fn two_mib_pages_to_bytes(pages: usize) -> usize {
+    pages * TWO_MIB
+}
+
+/// Returns the number of 1 GiB pages needed for `bytes`, rounding up.
+#[must_use]
+pub const fn bytes_to_one_gib_pages(bytes: usize) -> usize {
+    bytes.div_ceil(ONE_GIB)
+}
+
+/// Returns the size of `pages` 1 GiB pages in bytes.
+#[must_use]
+pub const fn one_gib_pages_to_bytes(pages: usize) -> usize {
+    pages * ONE_GIB
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages() {
+        assert_eq!(bytes_to_pages(0), 0);
+        assert_eq!(bytes_to_pages(1), 1);
+        assert_eq!(bytes_to_pages(FOUR_K), 1);
+        assert_eq!(bytes_to_pages(FOUR_K + 1), 2);
+        assert_eq!(pages_to_bytes(3), 3 * 4096);
+        assert_eq!(pages_to_bytes(bytes_to_pages(5000)), 8192);
+    }
+
+    #[test]
+    fn test_huge_pages() {
+        assert_eq!(bytes_to_two_mib_pages(0), 0);
+        assert_eq!(bytes_to_two_mib_pages(TWO_MIB), 1);
+        assert_eq!(bytes_to_two_mib_pages(TWO_MIB + 1), 2);
+        assert_eq!(two_mib_pages_to_bytes(2), 0x400000);
+
+        assert_eq!(bytes_to_one_gib_pages(1), 1);
+        assert_eq!(bytes_to_one_gib_pages(ONE_GIB), 1);
+        assert_eq!(bytes_to_one_gib_pages(4 * ONE_GIB + 1), 5);
+        assert_eq!(one_gib_pages_to_bytes(2), 0x80000000);
+    }
+}
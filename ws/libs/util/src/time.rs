@@ -0,0 +1,42 @@
+//! Time measurement.
+
+use crate::drivers::{PortIo, pit};
+
+/// Duration of the calibration in milliseconds.
+const CALIBRATION_MS: u16 = 10;
+
+/// Calibrates a free-running counter, typically the TSC, against the PIT and
+/// returns its ticks per microsecond.
+///
+/// `read_counter` is called once before and once after busy-waiting for a
+/// fixed time with [`pit::busy_wait_ms`]. Returns at least `1`.
+pub fn calibrate(io: &mut impl PortIo, mut read_counter: impl FnMut() -> u64) -> u64 {
+    let begin = read_counter();
+    pit::busy_wait_ms(io, CALIBRATION_MS);
+    let end = read_counter();
+
+    let elapsed_us = u64::from(CALIBRATION_MS) * 1000;
+    (end.wrapping_sub(begin) / elapsed_us).max(1)
+}
+
+/// Calibrates the TSC against the PIT and returns its ticks per microsecond.
+///
+/// See [`calibrate`].
+pub fn calibrate_tsc(io: &mut impl PortIo) -> u64 {
+    // SAFETY: rdtsc is available on every x86_64 CPU.
+    calibrate(io, || unsafe { core::arch::x86_64::_rdtsc() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::MockPortIo;
+
+    #[test]
+    fn test_calibrate() {
+        let mut io = MockPortIo::default().with_read(pit::PORT_B, 1 << 5);
+        let mut counter = [30_000 + 1234, 1234].into_iter().rev();
+        let ticks_per_us = calibrate(&mut io, || counter.next().unwrap());
+        assert_eq!(ticks_per_us, 3);
+    }
+}
@@ -0,0 +1,47 @@
+//! Tests the registration of a core-id source via [`LoggerFacade::init`].
+//!
+//! This is a separate test binary, as the source is registered process-wide
+//! and would prefix the messages of all other logging tests.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+use util::logging::{LogFormat, LoggerFacade, LoggerFacadeInner, fmt_and_write_msg};
+
+static LOGGER: LoggerFacade = LoggerFacade::new();
+static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Backend recording each formatted message.
+struct RecordingLogger;
+
+impl Log for RecordingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut msg = String::new();
+        fmt_and_write_msg(&mut msg, record, LogFormat::LevelOnly).unwrap();
+        MESSAGES.lock().unwrap().push(msg);
+    }
+
+    fn flush(&self) {}
+}
+
+fn core_id() -> u32 {
+    3
+}
+
+#[test]
+fn core_id_prefix() {
+    let mut inner = LoggerFacadeInner::new();
+    inner.set_stdout_logger(Box::new(RecordingLogger));
+    inner.set_core_id_source(core_id);
+    LOGGER.init(inner, LevelFilter::Trace);
+
+    log::info!("hello");
+    log::warn!("from core {}", core_id());
+    assert_eq!(
+        *MESSAGES.lock().unwrap(),
+        ["[CPU3] [ INFO]: hello", "[CPU3] [ WARN]: from core 3"]
+    );
+}